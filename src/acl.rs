@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+
+use sha2::{Digest, Sha256};
+
+use crate::{client::Client, commands::Command, glob};
+
+pub const DEFAULT_USER: &str = "default";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Category {
+    All,
+    Read,
+    Write,
+}
+
+impl Category {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "all" => Some(Category::All),
+            "read" => Some(Category::Read),
+            "write" => Some(Category::Write),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Category::All => "all",
+            Category::Read => "read",
+            Category::Write => "write",
+        }
+    }
+
+    fn contains(self, command: &Command) -> bool {
+        match self {
+            Category::All => true,
+            Category::Read => !command.is_write(),
+            Category::Write => command.is_write(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum CommandRule {
+    Category(Category, bool),
+    Command(String, bool),
+}
+
+/// A user with the commands and keys it may access. Rules use the Redis ACL
+/// syntax: `on`/`off`, `>password`, `nopass`, `+cmd`/`-cmd`,
+/// `+@category`/`-@category` (`all`, `read`, `write`), `~pattern` and
+/// `allkeys`. Command rules are applied in order, so the last matching rule
+/// decides.
+#[derive(Debug, Clone)]
+pub struct User {
+    name: String,
+    enabled: bool,
+    nopass: bool,
+    passwords: Vec<String>,
+    commands: Vec<CommandRule>,
+    keys: Vec<String>,
+}
+
+impl User {
+    /// Parses `<name> <rule>...`. A user starts disabled with no password,
+    /// no commands and no keys, like a freshly created Redis ACL user.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut tokens = spec.split_whitespace();
+        let name = tokens
+            .next()
+            .ok_or_else(|| "Empty user definition".to_string())?;
+
+        let mut user = User {
+            name: name.to_string(),
+            enabled: false,
+            nopass: false,
+            passwords: Vec::new(),
+            commands: Vec::new(),
+            keys: Vec::new(),
+        };
+
+        for rule in tokens {
+            user.apply_rule(rule)?;
+        }
+
+        Ok(user)
+    }
+
+    fn default_user(password: Option<&str>) -> Self {
+        let mut user = User::parse("default on +@all ~*").expect("valid default user");
+        match password {
+            Some(password) => user.passwords.push(hash_password(password)),
+            None => user.nopass = true,
+        }
+        user
+    }
+
+    fn apply_rule(&mut self, rule: &str) -> Result<(), String> {
+        match rule {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "allkeys" => self.keys = vec!["*".into()],
+            "resetkeys" => self.keys.clear(),
+            "allcommands" => self.commands = vec![CommandRule::Category(Category::All, true)],
+            "nocommands" => self.commands = vec![CommandRule::Category(Category::All, false)],
+            _ => {
+                if let Some(password) = rule.strip_prefix('>') {
+                    self.nopass = false;
+                    self.passwords.push(hash_password(password));
+                } else if let Some(pattern) = rule.strip_prefix('~') {
+                    self.keys.push(pattern.to_string());
+                } else if let Some(command) = rule.strip_prefix('+') {
+                    self.commands.push(parse_command_rule(command, true)?);
+                } else if let Some(command) = rule.strip_prefix('-') {
+                    self.commands.push(parse_command_rule(command, false)?);
+                } else {
+                    return Err(format!("Invalid ACL rule: {}", rule));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn can_run(&self, command: &Command) -> bool {
+        let name = command.name();
+        self.commands
+            .iter()
+            .rev()
+            .find_map(|rule| match rule {
+                CommandRule::Category(category, allow) => {
+                    category.contains(command).then_some(*allow)
+                }
+                CommandRule::Command(rule_name, allow) => {
+                    rule_name.eq_ignore_ascii_case(name).then_some(*allow)
+                }
+            })
+            .unwrap_or(false)
+    }
+
+    fn can_access(&self, key: &str) -> bool {
+        self.keys.iter().any(|pattern| glob::matches(pattern, key))
+    }
+
+    /// Describes the user in the `ACL LIST` format.
+    pub fn describe(&self) -> String {
+        let mut parts = vec![
+            format!("user {}", self.name),
+            if self.enabled { "on" } else { "off" }.to_string(),
+        ];
+
+        if self.nopass {
+            parts.push("nopass".into());
+        }
+        parts.extend(self.passwords.iter().map(|hash| format!("#{}", hash)));
+        parts.extend(self.keys.iter().map(|pattern| format!("~{}", pattern)));
+        parts.extend(self.commands.iter().map(|rule| match rule {
+            CommandRule::Category(category, allow) => {
+                format!("{}@{}", if *allow { '+' } else { '-' }, category.name())
+            }
+            CommandRule::Command(name, allow) => {
+                format!("{}{}", if *allow { '+' } else { '-' }, name)
+            }
+        }));
+
+        parts.join(" ")
+    }
+}
+
+fn parse_command_rule(rule: &str, allow: bool) -> Result<CommandRule, String> {
+    match rule.strip_prefix('@') {
+        Some(category) => Category::parse(category)
+            .map(|category| CommandRule::Category(category, allow))
+            .ok_or_else(|| format!("Unknown ACL category: {}", category)),
+        None => Ok(CommandRule::Command(rule.to_lowercase(), allow)),
+    }
+}
+
+fn hash_password(password: &str) -> String {
+    hex::encode(Sha256::digest(password.as_bytes()))
+}
+
+pub struct Acl {
+    users: HashMap<String, User>,
+}
+
+impl Acl {
+    /// Builds the user table from the configured users. When no `default`
+    /// user is configured, one with full access is added, protected by
+    /// `requirepass` if set, so existing setups keep working unchanged.
+    pub fn new(users: &[User], requirepass: Option<&str>) -> Self {
+        let mut users: HashMap<String, User> = users
+            .iter()
+            .map(|user| (user.name.clone(), user.clone()))
+            .collect();
+
+        users
+            .entry(DEFAULT_USER.to_string())
+            .or_insert_with(|| User::default_user(requirepass));
+
+        Self { users }
+    }
+
+    /// The user new connections are authenticated as, if the default user
+    /// can be used without a password.
+    pub fn initial_user(&self) -> Option<String> {
+        self.users
+            .get(DEFAULT_USER)
+            .filter(|user| user.enabled && user.nopass)
+            .map(|user| user.name.clone())
+    }
+
+    pub fn authenticate(&self, username: &str, password: &str) -> bool {
+        self.users.get(username).is_some_and(|user| {
+            user.enabled && (user.nopass || user.passwords.contains(&hash_password(password)))
+        })
+    }
+
+    /// Checks that the client's user may run the command and access every
+    /// key it names, returning the error reply to send otherwise.
+    pub fn check(&self, client: &Client, command: &Command) -> Result<(), String> {
+        if matches!(
+            command,
+            Command::Auth { .. } | Command::Hello { .. } | Command::Reset | Command::Quit
+        ) {
+            return Ok(());
+        }
+
+        let Some(user) = client.user.as_ref().and_then(|name| self.users.get(name)) else {
+            return Err("NOAUTH Authentication required.".into());
+        };
+
+        if !user.can_run(command) {
+            return Err(format!(
+                "NOPERM User {} has no permissions to run the '{}' command",
+                user.name,
+                command.name().to_lowercase()
+            ));
+        }
+
+        if !command.keys().iter().all(|key| user.can_access(key)) {
+            return Err("NOPERM No permissions to access a key".into());
+        }
+
+        Ok(())
+    }
+
+    pub fn users(&self) -> impl Iterator<Item = &User> {
+        self.users.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_round_trips_rules() {
+        let user = User::parse("reader on nopass ~cache:* -@all +get +@read -dump").unwrap();
+        assert_eq!(
+            user.describe(),
+            "user reader on nopass ~cache:* -@all +get +@read -dump"
+        );
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        assert!(User::parse("user on +@bogus").is_err());
+        assert!(User::parse("user on sometimes").is_err());
+    }
+}
@@ -0,0 +1,146 @@
+use std::str::FromStr;
+
+/// The unit BITPOS range bounds are given in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Byte,
+    Bit,
+}
+
+/// Finds the first bit equal to `bit` between `start` and `end` (inclusive,
+/// negative values counting from the end) in the given `unit`, numbering bits
+/// from the most significant bit of the first byte, as BITPOS does.
+///
+/// Without an explicit `end`, the value is treated as padded with zero bytes
+/// on the right, so looking for a clear bit in a value made only of set bits
+/// returns the first bit past its end. With an explicit `end`, or when
+/// looking for a set bit, a failed search returns -1.
+pub fn bitpos(bytes: &[u8], bit: u8, start: i64, end: Option<i64>, unit: Unit) -> i64 {
+    let len = match unit {
+        Unit::Byte => bytes.len() as i64,
+        Unit::Bit => bytes.len() as i64 * 8,
+    };
+    let end_given = end.is_some();
+    let resolve = |index: i64| {
+        if index < 0 {
+            (len + index).max(0)
+        } else {
+            index
+        }
+    };
+    let start = resolve(start);
+    let end = resolve(end.unwrap_or(-1)).min(len - 1);
+    if start > end {
+        return -1;
+    }
+
+    let (first, last) = match unit {
+        Unit::Byte => (start * 8, end * 8 + 7),
+        Unit::Bit => (start, end),
+    };
+    // Whole bytes holding only the other bit value can be skipped at once.
+    let skip = if bit == 1 { 0x00 } else { 0xff };
+
+    let mut pos = first;
+    while pos <= last {
+        let byte = bytes[(pos / 8) as usize];
+        if pos % 8 == 0 && pos + 7 <= last && byte == skip {
+            pos += 8;
+            continue;
+        }
+        if (byte >> (7 - pos % 8)) & 1 == bit {
+            return pos;
+        }
+        pos += 1;
+    }
+
+    if bit == 0 && !end_given {
+        last + 1
+    } else {
+        -1
+    }
+}
+
+/// The operations BITOP combines strings with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitOp {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+impl FromStr for BitOp {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "AND" => Ok(BitOp::And),
+            "OR" => Ok(BitOp::Or),
+            "XOR" => Ok(BitOp::Xor),
+            "NOT" => Ok(BitOp::Not),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Combines `sources` byte by byte as BITOP does. Sources shorter than the
+/// longest one are zero-padded to its length, which is the length of the
+/// result. NOT inverts its single source.
+pub fn bitop(op: BitOp, sources: &[Vec<u8>]) -> Vec<u8> {
+    let len = sources.iter().map(Vec::len).max().unwrap_or(0);
+    (0..len)
+        .map(|i| {
+            let mut bytes = sources
+                .iter()
+                .map(|source| source.get(i).copied().unwrap_or(0));
+            let first = bytes.next().unwrap_or(0);
+            match op {
+                BitOp::And => bytes.fold(first, |a, b| a & b),
+                BitOp::Or => bytes.fold(first, |a, b| a | b),
+                BitOp::Xor => bytes.fold(first, |a, b| a ^ b),
+                BitOp::Not => !first,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bitpos() {
+        assert_eq!(bitpos(b"\xff\xf0\x00", 0, 0, None, Unit::Byte), 12);
+        assert_eq!(bitpos(b"\x00\xff\xf0", 1, 0, None, Unit::Byte), 8);
+        assert_eq!(bitpos(b"\x00\xff\xf0", 1, 2, None, Unit::Byte), 16);
+        assert_eq!(bitpos(b"\x00\xff\xf0", 1, 2, Some(-1), Unit::Byte), 16);
+        assert_eq!(bitpos(b"\x00\xff\xf0", 1, 7, Some(15), Unit::Bit), 8);
+        assert_eq!(bitpos(b"\x00\xff\xf0", 1, 7, Some(-3), Unit::Bit), 8);
+        assert_eq!(bitpos(b"\x00\x00\x00", 1, 0, None, Unit::Byte), -1);
+    }
+
+    #[test]
+    fn test_bitpos_clear_bit_in_all_ones() {
+        // Without an end the value is zero-padded on the right...
+        assert_eq!(bitpos(b"\xff\xff", 0, 0, None, Unit::Byte), 16);
+        assert_eq!(bitpos(b"\xff\xff", 0, 1, None, Unit::Byte), 16);
+        // ...but an explicit range that holds no clear bit finds nothing.
+        assert_eq!(bitpos(b"\xff\xff", 0, 0, Some(-1), Unit::Byte), -1);
+        assert_eq!(bitpos(b"\xff\xff", 0, 0, Some(15), Unit::Bit), -1);
+        assert_eq!(bitpos(b"\xff\xff", 0, 2, None, Unit::Byte), -1);
+        assert_eq!(bitpos(b"", 0, 0, None, Unit::Byte), -1);
+    }
+
+    #[test]
+    fn test_bitop_pads_shorter_sources() {
+        let sources = [b"\xff\x0f".to_vec(), b"\xf0".to_vec()];
+        assert_eq!(bitop(BitOp::And, &sources), b"\xf0\x00");
+        assert_eq!(bitop(BitOp::Or, &sources), b"\xff\x0f");
+        assert_eq!(bitop(BitOp::Xor, &sources), b"\x0f\x0f");
+        assert_eq!(bitop(BitOp::Not, &sources[1..]), b"\x0f");
+        assert!(bitop(BitOp::Or, &[Vec::new(), Vec::new()]).is_empty());
+        assert_eq!("xor".parse(), Ok(BitOp::Xor));
+        assert!("nand".parse::<BitOp>().is_err());
+    }
+}
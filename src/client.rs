@@ -0,0 +1,148 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use tokio::sync::{
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+    Notify,
+};
+
+use crate::acl::Acl;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Per-connection state.
+#[derive(Debug)]
+pub struct Client {
+    pub id: u64,
+    /// The authenticated ACL user, or `None` until the connection
+    /// authenticates when the default user requires a password.
+    pub user: Option<String>,
+    /// The name set with `HELLO ... SETNAME`, if any.
+    pub name: Option<String>,
+    /// The database selected with SELECT, 0 until then.
+    pub db: usize,
+    /// The channels and patterns the connection is subscribed to.
+    pub channels: HashSet<String>,
+    pub patterns: HashSet<String>,
+    /// The shard channels the connection is subscribed to, a namespace of
+    /// their own.
+    pub shard_channels: HashSet<String>,
+    /// Whether CLIENT TRACKING is on, so the keys the connection reads are
+    /// recorded for invalidation.
+    pub tracking: bool,
+    /// Queue of encoded published messages, registered with every
+    /// subscription and drained by the connection handler.
+    pub mailbox: Mailbox,
+    pub messages: UnboundedReceiver<Vec<u8>>,
+}
+
+/// The sending end of a connection's queue of published messages. It counts
+/// the bytes not yet written to the connection, so a subscriber that stops
+/// reading can be cut off instead of growing the queue without bound.
+#[derive(Debug, Clone)]
+pub struct Mailbox {
+    sender: UnboundedSender<Vec<u8>>,
+    pending: Arc<AtomicUsize>,
+    overflowed: Arc<Notify>,
+    /// Whether the connection speaks RESP3, so messages are queued as push
+    /// frames rather than arrays.
+    resp3: Arc<AtomicBool>,
+}
+
+impl Mailbox {
+    /// Queues an encoded message, unless that would leave more than `limit`
+    /// bytes pending, in which case the connection is told to disconnect.
+    /// Returns whether the message was queued.
+    pub fn deliver(&self, message: &[u8], limit: Option<usize>) -> bool {
+        let pending = self.pending.fetch_add(message.len(), Ordering::Relaxed) + message.len();
+        if limit.is_some_and(|limit| pending > limit) {
+            self.pending.fetch_sub(message.len(), Ordering::Relaxed);
+            self.overflowed.notify_one();
+            return false;
+        }
+        // A closed queue belongs to a client that is disconnecting.
+        self.sender.send(message.to_vec()).is_ok()
+    }
+
+    /// Marks a message taken off the queue as written to the connection.
+    pub fn written(&self, message: &[u8]) {
+        self.pending.fetch_sub(message.len(), Ordering::Relaxed);
+    }
+
+    pub fn resp3(&self) -> bool {
+        self.resp3.load(Ordering::Relaxed)
+    }
+
+    /// Resolves once a message has been refused for crossing the limit.
+    pub async fn overflowed(&self) {
+        self.overflowed.notified().await
+    }
+}
+
+impl Client {
+    pub fn new(acl: &Acl) -> Self {
+        let (sender, messages) = mpsc::unbounded_channel();
+        let mailbox = Mailbox {
+            sender,
+            pending: Arc::new(AtomicUsize::new(0)),
+            overflowed: Arc::new(Notify::new()),
+            resp3: Arc::new(AtomicBool::new(false)),
+        };
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            user: acl.initial_user(),
+            name: None,
+            db: 0,
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            shard_channels: HashSet::new(),
+            tracking: false,
+            mailbox,
+            messages,
+        }
+    }
+
+    /// Returns the connection to the state of a freshly accepted one, as
+    /// RESET does. Callers drop its subscriptions and tracking first.
+    pub fn reset(&mut self, acl: &Acl) {
+        *self = Self::new(acl);
+    }
+
+    /// The RESP version negotiated with HELLO: 2 unless the client asked
+    /// for 3.
+    pub fn protocol(&self) -> i64 {
+        if self.mailbox.resp3() {
+            3
+        } else {
+            2
+        }
+    }
+
+    pub fn set_protocol(&mut self, protover: i64) {
+        self.mailbox.resp3.store(protover == 3, Ordering::Relaxed);
+    }
+
+    /// The channels and patterns subscribed to. Shard channels are counted
+    /// apart, as Redis does.
+    pub fn subscription_count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    /// Whether the connection is in subscribe mode.
+    pub fn is_subscribed(&self) -> bool {
+        self.subscription_count() > 0 || !self.shard_channels.is_empty()
+    }
+
+    /// Whether only the subscription commands, PING, QUIT and RESET are
+    /// accepted: in subscribe mode under RESP2, where a reply couldn't be
+    /// told apart from a message. RESP3 messages are push frames, so RESP3
+    /// connections keep running any command.
+    pub fn in_subscribe_context(&self) -> bool {
+        self.is_subscribed() && self.protocol() == 2
+    }
+}
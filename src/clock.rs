@@ -0,0 +1,49 @@
+use std::{fmt::Debug, time::SystemTime};
+
+#[cfg(test)]
+use std::{sync::Mutex, time::Duration};
+
+/// The source of the current time for expiry and access tracking, so tests
+/// can move time forward instead of sleeping.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// The system's wall clock.
+#[derive(Debug, Default)]
+pub struct RealClock;
+
+impl Clock for RealClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that stands still until advanced.
+#[cfg(test)]
+#[derive(Debug)]
+pub struct MockClock {
+    now: Mutex<SystemTime>,
+}
+
+#[cfg(test)]
+impl MockClock {
+    /// Starts at the current wall-clock time, so deadlines computed from
+    /// either clock stay comparable.
+    pub fn new() -> Self {
+        Self {
+            now: Mutex::new(SystemTime::now()),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap()
+    }
+}
@@ -0,0 +1,48 @@
+use crc::{Crc, CRC_16_XMODEM};
+
+/// Number of hash slots the key space is divided into, as in Redis Cluster.
+pub const SLOTS: u16 = 16384;
+
+const CRC16: Crc<u16> = Crc::<u16>::new(&CRC_16_XMODEM);
+
+/// The hash slot `key` maps to: the CRC16 of the key modulo 16384. When the
+/// key holds a non-empty hash tag, the part between its first `{` and the
+/// next `}`, only the tag is hashed, so keys sharing a tag land in the same
+/// slot.
+pub fn key_slot(key: &[u8]) -> u16 {
+    CRC16.checksum(hash_tag(key).unwrap_or(key)) % SLOTS
+}
+
+fn hash_tag(key: &[u8]) -> Option<&[u8]> {
+    let start = key.iter().position(|&b| b == b'{')? + 1;
+    let len = key[start..].iter().position(|&b| b == b'}')?;
+    (len > 0).then(|| &key[start..start + len])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_slot_matches_redis() {
+        assert_eq!(key_slot(b"foo"), 12182);
+        assert_eq!(key_slot(b"somekey"), 11058);
+        assert_eq!(key_slot(b"123456789"), 12739);
+        assert_eq!(key_slot(b""), 0);
+    }
+
+    #[test]
+    fn test_hash_tags_co_locate_keys() {
+        let slot = key_slot(b"{user1000}.following");
+        assert_eq!(slot, key_slot(b"{user1000}.followers"));
+        assert_eq!(slot, key_slot(b"user1000"));
+        assert_eq!(key_slot(b"foo{bar}{zap}"), key_slot(b"bar"));
+        // An empty or unterminated tag hashes the whole key.
+        assert_eq!(
+            key_slot(b"foo{}{bar}"),
+            CRC16.checksum(b"foo{}{bar}") % SLOTS
+        );
+        assert_eq!(key_slot(b"{user1000"), CRC16.checksum(b"{user1000") % SLOTS);
+        assert_eq!(key_slot(b"foo{{bar}}zap"), key_slot(b"{bar"));
+    }
+}
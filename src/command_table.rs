@@ -0,0 +1,321 @@
+use crate::resp::Resp;
+
+/// Static metadata about a command: how many arguments it takes, its flags
+/// and where its keys are. Arity validation and COMMAND INFO both read it.
+#[derive(Debug)]
+pub struct CommandSpec {
+    pub name: &'static str,
+    /// The number of arguments including the command name; negative means
+    /// at least that many.
+    pub arity: i32,
+    pub flags: &'static [&'static str],
+    /// Position of the first and last key argument (negative counts from
+    /// the end) and the step between keys, all 0 for keyless commands.
+    pub first_key: i32,
+    pub last_key: i32,
+    pub step: i32,
+}
+
+impl CommandSpec {
+    /// Whether a call with `argc` arguments, the name included, satisfies
+    /// the arity.
+    pub fn accepts(&self, argc: usize) -> bool {
+        let arity = self.arity.unsigned_abs() as usize;
+        if self.arity < 0 {
+            argc >= arity
+        } else {
+            argc == arity
+        }
+    }
+
+    /// The COMMAND INFO entry: name, arity, flags, first key, last key and
+    /// key step.
+    pub fn info(&self) -> Resp {
+        Resp::Array(vec![
+            Resp::BulkString(self.name.to_lowercase()),
+            Resp::Integer(self.arity.into()),
+            Resp::Array(
+                self.flags
+                    .iter()
+                    .map(|flag| Resp::SimpleString(flag.to_string()))
+                    .collect(),
+            ),
+            Resp::Integer(self.first_key.into()),
+            Resp::Integer(self.last_key.into()),
+            Resp::Integer(self.step.into()),
+        ])
+    }
+}
+
+/// Looks up a command by its uppercase name.
+pub fn lookup(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS.iter().find(|spec| spec.name == name)
+}
+
+const fn keyless(name: &'static str, arity: i32, flags: &'static [&'static str]) -> CommandSpec {
+    CommandSpec {
+        name,
+        arity,
+        flags,
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+    }
+}
+
+/// A command whose only key is its first argument.
+const fn single_key(name: &'static str, arity: i32, flags: &'static [&'static str]) -> CommandSpec {
+    CommandSpec {
+        name,
+        arity,
+        flags,
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+    }
+}
+
+const READ: &[&str] = &["readonly"];
+const READ_FAST: &[&str] = &["readonly", "fast"];
+const WRITE: &[&str] = &["write", "denyoom"];
+const WRITE_FAST: &[&str] = &["write", "denyoom", "fast"];
+const ADMIN: &[&str] = &["admin", "noscript"];
+const PUBSUB: &[&str] = &["pubsub", "noscript"];
+
+pub const COMMANDS: &[CommandSpec] = &[
+    keyless("ACL", -2, ADMIN),
+    keyless("AUTH", -2, &["noscript", "fast"]),
+    keyless("BGSAVE", 1, ADMIN),
+    CommandSpec {
+        name: "BITOP",
+        arity: -4,
+        flags: WRITE,
+        first_key: 2,
+        last_key: -1,
+        step: 1,
+    },
+    single_key("BITPOS", -3, READ),
+    keyless("CLIENT", -2, &["noscript", "stale"]),
+    keyless("CLUSTER", -2, &["stale"]),
+    keyless("COMMAND", -1, &["loading", "stale"]),
+    keyless("CHANGEFEED", -1, ADMIN),
+    keyless("CONFIG", -2, ADMIN),
+    CommandSpec {
+        name: "COPY",
+        arity: -3,
+        flags: WRITE,
+        first_key: 1,
+        last_key: 2,
+        step: 1,
+    },
+    keyless("DEBUG", -2, ADMIN),
+    single_key("DECR", 2, WRITE_FAST),
+    // Deleting can only free memory, so it isn't refused over maxmemory.
+    CommandSpec {
+        name: "DEL",
+        arity: -2,
+        flags: &["write"],
+        first_key: 1,
+        last_key: -1,
+        step: 1,
+    },
+    single_key("DUMP", 2, READ),
+    keyless("ECHO", 2, &["fast"]),
+    keyless("EVAL", -3, &["noscript", "movablekeys"]),
+    keyless("EVALSHA", -3, &["noscript", "movablekeys"]),
+    single_key("EXPIRETIME", 2, READ_FAST),
+    single_key("GET", 2, READ_FAST),
+    single_key("GETSET", 3, WRITE),
+    single_key("HGET", 3, READ_FAST),
+    single_key("HMGET", -3, READ_FAST),
+    single_key("HMSET", -4, WRITE_FAST),
+    single_key("HRANDFIELD", -2, READ),
+    single_key("HSCAN", -3, READ),
+    single_key("HSET", -4, WRITE_FAST),
+    single_key("INCR", 2, WRITE_FAST),
+    keyless("HELLO", -1, &["noscript", "loading", "stale", "fast"]),
+    keyless("INFO", -1, &["loading", "stale"]),
+    keyless("LATENCY", -2, ADMIN),
+    CommandSpec {
+        name: "LCS",
+        arity: -3,
+        flags: READ,
+        first_key: 1,
+        last_key: 2,
+        step: 1,
+    },
+    single_key("LINSERT", 5, WRITE),
+    single_key("LLEN", 2, READ_FAST),
+    CommandSpec {
+        name: "LMOVE",
+        arity: 5,
+        flags: WRITE,
+        first_key: 1,
+        last_key: 2,
+        step: 1,
+    },
+    keyless("LMPOP", -4, &["write", "movablekeys"]),
+    single_key("LPOP", -2, &["write", "fast"]),
+    single_key("LPOS", -3, READ),
+    single_key("LPUSH", -3, WRITE_FAST),
+    single_key("LRANGE", 4, READ),
+    single_key("LREM", 4, &["write"]),
+    single_key("LTRIM", 4, &["write"]),
+    keyless("MEMORY", -2, READ),
+    CommandSpec {
+        name: "MIGRATE",
+        arity: -6,
+        flags: &["write", "movablekeys"],
+        first_key: 3,
+        last_key: 3,
+        step: 1,
+    },
+    CommandSpec {
+        name: "MSETNX",
+        arity: -3,
+        flags: WRITE,
+        first_key: 1,
+        last_key: -1,
+        step: 2,
+    },
+    keyless("OBJECT", -2, READ),
+    single_key("PEXPIRETIME", 2, READ_FAST),
+    single_key("PFADD", -2, WRITE_FAST),
+    CommandSpec {
+        name: "PFCOUNT",
+        arity: -2,
+        flags: READ,
+        first_key: 1,
+        last_key: -1,
+        step: 1,
+    },
+    CommandSpec {
+        name: "PFMERGE",
+        arity: -2,
+        flags: WRITE,
+        first_key: 1,
+        last_key: -1,
+        step: 1,
+    },
+    keyless("PING", -1, &["fast", "stale"]),
+    keyless("PSUBSCRIBE", -2, PUBSUB),
+    keyless("PSYNC", -3, ADMIN),
+    keyless("PUBLISH", 3, &["pubsub", "fast"]),
+    keyless("PUBSUB", -2, &["pubsub"]),
+    keyless("PUNSUBSCRIBE", -1, PUBSUB),
+    keyless("QUIT", -1, &["noscript", "fast"]),
+    keyless("RANDOMKEY", -1, READ),
+    keyless("REPLICAOF", 3, ADMIN),
+    keyless("RESET", 1, &["noscript", "fast"]),
+    single_key("RESTORE", -4, WRITE),
+    single_key("RPOP", -2, &["write", "fast"]),
+    CommandSpec {
+        name: "RPOPLPUSH",
+        arity: 3,
+        flags: WRITE,
+        first_key: 1,
+        last_key: 2,
+        step: 1,
+    },
+    single_key("RPUSH", -3, WRITE_FAST),
+    single_key("SADD", -3, WRITE_FAST),
+    keyless("SAVE", 1, ADMIN),
+    keyless("SCAN", -2, READ),
+    single_key("SCARD", 2, READ_FAST),
+    keyless("SCRIPT", -2, &["noscript"]),
+    CommandSpec {
+        name: "SDIFF",
+        arity: -2,
+        flags: READ,
+        first_key: 1,
+        last_key: -1,
+        step: 1,
+    },
+    CommandSpec {
+        name: "SDIFFSTORE",
+        arity: -3,
+        flags: WRITE,
+        first_key: 1,
+        last_key: -1,
+        step: 1,
+    },
+    keyless("SELECT", 2, &["loading", "stale", "fast"]),
+    single_key("SET", -3, WRITE),
+    CommandSpec {
+        name: "SINTER",
+        arity: -2,
+        flags: READ,
+        first_key: 1,
+        last_key: -1,
+        step: 1,
+    },
+    // The keys follow their count, which COMMAND INFO can't describe.
+    keyless("SINTERCARD", -3, &["readonly", "movablekeys"]),
+    CommandSpec {
+        name: "SINTERSTORE",
+        arity: -3,
+        flags: WRITE,
+        first_key: 1,
+        last_key: -1,
+        step: 1,
+    },
+    single_key("SISMEMBER", 3, READ_FAST),
+    keyless("SLAVEOF", 3, ADMIN),
+    single_key("SMEMBERS", 2, READ),
+    keyless("SPUBLISH", 3, &["pubsub", "fast"]),
+    single_key("SREM", -3, &["write", "fast"]),
+    keyless("SSUBSCRIBE", -2, PUBSUB),
+    keyless("SUBSCRIBE", -2, PUBSUB),
+    CommandSpec {
+        name: "SUNION",
+        arity: -2,
+        flags: READ,
+        first_key: 1,
+        last_key: -1,
+        step: 1,
+    },
+    CommandSpec {
+        name: "SUNIONSTORE",
+        arity: -3,
+        flags: WRITE,
+        first_key: 1,
+        last_key: -1,
+        step: 1,
+    },
+    keyless("SUNSUBSCRIBE", -1, PUBSUB),
+    keyless("SYNC", 1, ADMIN),
+    keyless("UNSUBSCRIBE", -1, PUBSUB),
+    keyless("WAIT", 3, &["noscript"]),
+    keyless("WAITAOF", 4, &["noscript"]),
+    single_key("XADD", -5, WRITE_FAST),
+    single_key("XLEN", 2, READ_FAST),
+    single_key("XRANGE", -4, READ),
+    single_key("ZADD", -4, WRITE_FAST),
+    single_key("ZCARD", 2, READ_FAST),
+    single_key("ZCOUNT", 4, READ_FAST),
+    keyless("ZMPOP", -4, &["write", "movablekeys"]),
+    single_key("ZRANGE", -4, READ),
+    single_key("ZRANGEBYSCORE", -4, READ),
+    single_key("ZREM", -3, &["write", "fast"]),
+    single_key("ZREMRANGEBYRANK", 4, &["write"]),
+    single_key("ZREMRANGEBYSCORE", 4, &["write"]),
+    single_key("ZSCORE", 3, READ_FAST),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accepts() {
+        let get = lookup("GET").unwrap();
+        assert!(get.accepts(2));
+        assert!(!get.accepts(1) && !get.accepts(3));
+
+        let set = lookup("SET").unwrap();
+        assert!(!set.accepts(2));
+        assert!(set.accepts(3) && set.accepts(5));
+
+        assert!(lookup("NOSUCH").is_none());
+    }
+}
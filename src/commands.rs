@@ -1,6 +1,40 @@
-use std::time::Duration;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    io,
+    str::FromStr,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
 
-use crate::{resp::Resp, Error, Result, Storage};
+use bytes::Bytes;
+use tracing::{debug, error};
+
+use crate::{
+    acl,
+    bitmap::{self, Unit},
+    client::Client,
+    cluster,
+    command_table::{self, COMMANDS},
+    glob,
+    help::{self, Subcommands},
+    hyperloglog::HyperLogLog,
+    info, lcs,
+    list::{self, End},
+    logging::{self, LogLevel},
+    migrate,
+    persistence::{
+        self,
+        dump::{self, DecodeError},
+        TypeError, Value, ValueEntry,
+    },
+    resp::Resp,
+    scan::{self, ScanOptions},
+    scripting,
+    set::{self, Combine, Set},
+    stream::{Stream, StreamId},
+    zset::{self, Extreme, ScoreBound, SortedSet},
+    Error, Result, Storage,
+};
 
 #[derive(Debug)]
 pub enum Command {
@@ -8,10 +42,369 @@ pub enum Command {
     Echo(String),
     Set {
         key: String,
-        value: String,
+        value: Bytes,
         expiry: Option<Duration>,
     },
     Get(String),
+    GetSet {
+        key: String,
+        value: Bytes,
+    },
+    MSetNx(Vec<(String, Bytes)>),
+    Del(Vec<String>),
+    Incr(String),
+    Decr(String),
+    HSet {
+        key: String,
+        fields: Vec<(String, String)>,
+    },
+    HMSet {
+        key: String,
+        fields: Vec<(String, String)>,
+    },
+    HGet {
+        key: String,
+        field: String,
+    },
+    HMGet {
+        key: String,
+        fields: Vec<String>,
+    },
+    HRandField {
+        key: String,
+        count: Option<i64>,
+        withvalues: bool,
+    },
+    PfAdd {
+        key: String,
+        elements: Vec<String>,
+    },
+    PfCount(Vec<String>),
+    PfMerge {
+        dest: String,
+        sources: Vec<String>,
+    },
+    XAdd {
+        key: String,
+        /// `None` for `*`, letting the server generate the ID.
+        id: Option<StreamId>,
+        fields: Vec<(String, String)>,
+    },
+    XLen(String),
+    XRange {
+        key: String,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+    },
+    LPush {
+        key: String,
+        elements: Vec<String>,
+    },
+    RPush {
+        key: String,
+        elements: Vec<String>,
+    },
+    /// `count` is `None` when the command pops a single element and
+    /// replies with it rather than with an array.
+    LPop {
+        key: String,
+        count: Option<usize>,
+    },
+    RPop {
+        key: String,
+        count: Option<usize>,
+    },
+    LLen(String),
+    LRem {
+        key: String,
+        count: i64,
+        element: String,
+    },
+    LRange {
+        key: String,
+        start: i64,
+        stop: i64,
+    },
+    LTrim {
+        key: String,
+        start: i64,
+        stop: i64,
+    },
+    LInsert {
+        key: String,
+        /// Whether the element goes before the pivot rather than after it.
+        before: bool,
+        pivot: String,
+        element: String,
+    },
+    RPopLPush {
+        source: String,
+        destination: String,
+    },
+    LMove {
+        source: String,
+        destination: String,
+        from: End,
+        to: End,
+    },
+    LMPop {
+        keys: Vec<String>,
+        end: End,
+        count: usize,
+    },
+    LPos {
+        key: String,
+        element: String,
+        rank: i64,
+        /// `None` when the command replies with the first index rather than
+        /// an array; 0 returns every match.
+        count: Option<usize>,
+        maxlen: usize,
+    },
+    SAdd {
+        key: String,
+        members: Vec<String>,
+    },
+    SRem {
+        key: String,
+        members: Vec<String>,
+    },
+    SMembers(String),
+    SIsMember {
+        key: String,
+        member: String,
+    },
+    SCard(String),
+    SInterCard {
+        keys: Vec<String>,
+        /// 0 counts the whole intersection.
+        limit: usize,
+    },
+    SInter(Vec<String>),
+    SUnion(Vec<String>),
+    SDiff(Vec<String>),
+    SInterStore {
+        destination: String,
+        keys: Vec<String>,
+    },
+    SUnionStore {
+        destination: String,
+        keys: Vec<String>,
+    },
+    SDiffStore {
+        destination: String,
+        keys: Vec<String>,
+    },
+    ZAdd {
+        key: String,
+        members: Vec<(String, f64)>,
+    },
+    ZScore {
+        key: String,
+        member: String,
+    },
+    ZRange {
+        key: String,
+        start: i64,
+        stop: i64,
+        with_scores: bool,
+    },
+    ZRangeByScore {
+        key: String,
+        min: ScoreBound,
+        max: ScoreBound,
+        with_scores: bool,
+        offset: usize,
+        /// `None` returns every member past the offset.
+        count: Option<usize>,
+    },
+    ZCard(String),
+    ZCount {
+        key: String,
+        min: ScoreBound,
+        max: ScoreBound,
+    },
+    ZRem {
+        key: String,
+        members: Vec<String>,
+    },
+    ZRemRangeByScore {
+        key: String,
+        min: ScoreBound,
+        max: ScoreBound,
+    },
+    ZRemRangeByRank {
+        key: String,
+        start: i64,
+        stop: i64,
+    },
+    ZMPop {
+        keys: Vec<String>,
+        extreme: Extreme,
+        count: usize,
+    },
+    Scan(ScanOptions),
+    /// RANDOMKEY, optionally restricted to keys of one type.
+    RandomKey(Option<String>),
+    HScan {
+        key: String,
+        options: ScanOptions,
+    },
+    BitOp {
+        op: bitmap::BitOp,
+        destination: String,
+        keys: Vec<String>,
+    },
+    BitPos {
+        key: String,
+        bit: u8,
+        start: i64,
+        end: Option<i64>,
+        unit: Unit,
+    },
+    Lcs {
+        key1: String,
+        key2: String,
+        len: bool,
+        idx: bool,
+        min_match_len: usize,
+        with_match_len: bool,
+    },
+    Dump(String),
+    /// The Unix time at which a key expires, in seconds or, for
+    /// PEXPIRETIME, milliseconds.
+    ExpireTime(String),
+    PExpireTime(String),
+    Restore {
+        key: String,
+        ttl: u64,
+        serialized: String,
+        replace: bool,
+    },
+    /// Copies a key, into the database `db` if given rather than the
+    /// client's.
+    Copy {
+        source: String,
+        destination: String,
+        db: Option<i64>,
+        replace: bool,
+    },
+    Migrate {
+        host: String,
+        port: u16,
+        key: String,
+        timeout: Duration,
+        copy: bool,
+        replace: bool,
+    },
+    /// `MEMORY USAGE key [SAMPLES count]`; 0 samples measures every element.
+    MemoryUsage {
+        key: String,
+        samples: usize,
+    },
+    ObjectEncoding(String),
+    ObjectIdleTime(String),
+    ObjectRefCount(String),
+    /// The hash slot a key maps to in Redis Cluster.
+    ClusterKeySlot(String),
+    /// `CLIENT TRACKING ON|OFF`.
+    ClientTracking(bool),
+    ReplicaOf(Option<(String, u16)>),
+    Sync,
+    /// Streams every write to the connection, optionally starting with the
+    /// dataset as a snapshot.
+    ChangeFeed {
+        snapshot: bool,
+    },
+    Wait {
+        numreplicas: usize,
+        timeout: u64,
+    },
+    WaitAof {
+        numlocal: usize,
+        numreplicas: usize,
+        timeout: u64,
+    },
+    /// `HELLO [protover [AUTH user pass] [SETNAME name]]`.
+    Hello {
+        protover: Option<i64>,
+        auth: Option<(String, String)>,
+        setname: Option<String>,
+    },
+    Auth {
+        user: Option<String>,
+        password: String,
+    },
+    AclWhoAmI,
+    AclList,
+    Eval {
+        script: String,
+        keys: Vec<String>,
+        args: Vec<String>,
+    },
+    EvalSha {
+        sha1: String,
+        keys: Vec<String>,
+        args: Vec<String>,
+    },
+    ScriptLoad(String),
+    ScriptExists(Vec<String>),
+    ScriptFlush,
+    Subscribe(Vec<String>),
+    /// Unsubscribes from the given channels, or from all of them if empty.
+    Unsubscribe(Vec<String>),
+    PSubscribe(Vec<String>),
+    PUnsubscribe(Vec<String>),
+    Publish {
+        channel: String,
+        message: String,
+    },
+    SSubscribe(Vec<String>),
+    /// Unsubscribes from the given shard channels, or from all of them if
+    /// empty.
+    SUnsubscribe(Vec<String>),
+    SPublish {
+        channel: String,
+        message: String,
+    },
+    PubSubChannels(Option<String>),
+    PubSubNumSub(Vec<String>),
+    PubSubNumPat,
+    DebugSetActiveExpire(bool),
+    /// Overrides the reported used memory; 0 clears the override.
+    DebugSetMemoryUsage(usize),
+    DebugReload,
+    Save,
+    BgSave,
+    DebugChangeReplId,
+    DebugSleep(Duration),
+    DebugObject(String),
+    /// `DEBUG STRINGMATCH-LEN`: whether `string` matches the glob `pattern`.
+    DebugStringMatch {
+        pattern: String,
+        string: String,
+    },
+    LatencyLatest,
+    LatencyHistory(String),
+    /// `LATENCY RESET`; no events means all of them.
+    LatencyReset(Vec<String>),
+    Reset,
+    /// Asks the server to close the connection once it has replied.
+    Quit,
+    /// `SELECT`, with the index as given; it is checked against the number
+    /// of databases when it runs.
+    Select(i64),
+    Info(Option<String>),
+    ConfigResetStat,
+    ConfigSetLogLevel(LogLevel),
+    /// `COMMAND` and `COMMAND INFO`; no names means every command.
+    DescribeCommands(Vec<String>),
+    CountCommands,
+    /// `COMMAND GETKEYS`, holding the command whose keys are requested.
+    GetKeys(Box<Command>),
+    /// The `HELP` subcommand of a container command.
+    Help(&'static Subcommands),
 }
 
 impl Command {
@@ -22,10 +415,21 @@ impl Command {
                     return Err(Error::Command("Empty command".into()));
                 }
 
-                let command = match items.remove(0) {
-                    Resp::BulkString(cmd) => cmd.to_uppercase(),
-                    _ => return Err(Error::Command("Invalid command format".into())),
+                let first = items.remove(0);
+                let name = match first.as_str() {
+                    Some(name) => name,
+                    None => return Err(Error::Command("Invalid command format".into())),
                 };
+                let command = name.to_uppercase();
+
+                if let Some(spec) = command_table::lookup(&command) {
+                    if !spec.accepts(items.len() + 1) {
+                        return Err(Error::Command(format!(
+                            "Wrong number of arguments for '{}' command",
+                            command.to_lowercase()
+                        )));
+                    }
+                }
 
                 match command.as_str() {
                     "PING" => Ok(Command::Ping),
@@ -36,94 +440,3993 @@ impl Command {
                         _ => Err(Error::Command("Wrong number of SET arguments".into())),
                     },
                     "GET" => Self::get(items),
-                    _ => Err(Error::Command(format!("Unknown command: {}", command))),
+                    "GETSET" => Self::getset(items),
+                    "MSETNX" => Self::msetnx(items),
+                    "DEL" => {
+                        let keys = Self::string_args(items, "DEL")?;
+                        if keys.is_empty() {
+                            return Err(Error::Command("DEL requires at least one key".into()));
+                        }
+                        Ok(Command::Del(keys))
+                    }
+                    "INCR" | "DECR" => Self::counter(&command, items),
+                    "LCS" => Self::lcs(items),
+                    "BITOP" => Self::bitop(items),
+                    "BITPOS" => Self::bitpos(items),
+                    "HSET" => {
+                        let (key, fields) = Self::hash_fields(items, "HSET")?;
+                        Ok(Command::HSet { key, fields })
+                    }
+                    "HMSET" => {
+                        let (key, fields) = Self::hash_fields(items, "HMSET")?;
+                        Ok(Command::HMSet { key, fields })
+                    }
+                    "HGET" => Self::hget(items),
+                    "HMGET" => Self::hmget(items),
+                    "HRANDFIELD" => Self::hrandfield(items),
+                    "PFADD" | "PFCOUNT" | "PFMERGE" => Self::hyperloglog(&command, items),
+                    "XADD" => Self::xadd(items),
+                    "XLEN" => {
+                        let mut args = Self::string_args(items, "XLEN")?.into_iter();
+                        match (args.next(), args.next()) {
+                            (Some(key), None) => Ok(Command::XLen(key)),
+                            _ => Err(Error::Command("XLEN requires exactly one argument".into())),
+                        }
+                    }
+                    "XRANGE" => Self::xrange(items),
+                    "LPUSH" | "RPUSH" => Self::push(&command, items),
+                    "LPOP" | "RPOP" => Self::pop(&command, items),
+                    "LLEN" => {
+                        let mut args = Self::string_args(items, "LLEN")?.into_iter();
+                        match (args.next(), args.next()) {
+                            (Some(key), None) => Ok(Command::LLen(key)),
+                            _ => Err(Error::Command("LLEN requires exactly one argument".into())),
+                        }
+                    }
+                    "LRANGE" | "LTRIM" => Self::list_range(&command, items),
+                    "LREM" => Self::lrem(items),
+                    "LINSERT" => Self::linsert(items),
+                    "RPOPLPUSH" => {
+                        let mut args = Self::string_args(items, "RPOPLPUSH")?.into_iter();
+                        match (args.next(), args.next(), args.next()) {
+                            (Some(source), Some(destination), None) => Ok(Command::RPopLPush {
+                                source,
+                                destination,
+                            }),
+                            _ => Err(Error::Command(
+                                "RPOPLPUSH requires exactly two arguments".into(),
+                            )),
+                        }
+                    }
+                    "LMOVE" => Self::lmove(items),
+                    "LMPOP" => {
+                        let (keys, end, count) = Self::mpop("LMPOP", items)?;
+                        Ok(Command::LMPop { keys, end, count })
+                    }
+                    "LPOS" => Self::lpos(items),
+                    "SADD" | "SREM" => Self::set_members(&command, items),
+                    "SMEMBERS" | "SCARD" => {
+                        let mut args = Self::string_args(items, &command)?.into_iter();
+                        match (args.next(), args.next()) {
+                            (Some(key), None) if command == "SMEMBERS" => {
+                                Ok(Command::SMembers(key))
+                            }
+                            (Some(key), None) => Ok(Command::SCard(key)),
+                            _ => Err(Error::Command(format!(
+                                "{} requires exactly one argument",
+                                command
+                            ))),
+                        }
+                    }
+                    "SISMEMBER" => {
+                        let mut args = Self::string_args(items, "SISMEMBER")?.into_iter();
+                        match (args.next(), args.next(), args.next()) {
+                            (Some(key), Some(member), None) => {
+                                Ok(Command::SIsMember { key, member })
+                            }
+                            _ => Err(Error::Command(
+                                "SISMEMBER requires exactly two arguments".into(),
+                            )),
+                        }
+                    }
+                    "SINTERCARD" => Self::sintercard(items),
+                    "SINTER" | "SUNION" | "SDIFF" | "SINTERSTORE" | "SUNIONSTORE"
+                    | "SDIFFSTORE" => Self::set_combine(&command, items),
+                    "ZADD" => Self::zadd(items),
+                    "ZSCORE" => {
+                        let mut args = Self::string_args(items, "ZSCORE")?.into_iter();
+                        match (args.next(), args.next(), args.next()) {
+                            (Some(key), Some(member), None) => Ok(Command::ZScore { key, member }),
+                            _ => Err(Error::Command(
+                                "ZSCORE requires exactly two arguments".into(),
+                            )),
+                        }
+                    }
+                    "ZRANGE" => Self::zrange(items),
+                    "ZRANGEBYSCORE" => Self::zrangebyscore(items),
+                    "ZCARD" => {
+                        let mut args = Self::string_args(items, "ZCARD")?.into_iter();
+                        match (args.next(), args.next()) {
+                            (Some(key), None) => Ok(Command::ZCard(key)),
+                            _ => Err(Error::Command("ZCARD requires exactly one argument".into())),
+                        }
+                    }
+                    "ZCOUNT" => {
+                        let mut args = Self::string_args(items, "ZCOUNT")?.into_iter();
+                        let (Some(key), Some(min), Some(max), None) =
+                            (args.next(), args.next(), args.next(), args.next())
+                        else {
+                            return Err(Error::Command(
+                                "ZCOUNT requires exactly three arguments".into(),
+                            ));
+                        };
+                        let (min, max) = Self::score_range(&min, &max)?;
+                        Ok(Command::ZCount { key, min, max })
+                    }
+                    "ZREM" => {
+                        let mut args = Self::string_args(items, "ZREM")?.into_iter();
+                        let key = args.next();
+                        let members: Vec<String> = args.collect();
+                        match key {
+                            Some(key) if !members.is_empty() => Ok(Command::ZRem { key, members }),
+                            _ => Err(Error::Command("Wrong number of ZREM arguments".into())),
+                        }
+                    }
+                    "ZREMRANGEBYSCORE" | "ZREMRANGEBYRANK" => Self::zremrange(&command, items),
+                    "ZMPOP" => {
+                        let (keys, extreme, count) = Self::mpop("ZMPOP", items)?;
+                        Ok(Command::ZMPop {
+                            keys,
+                            extreme,
+                            count,
+                        })
+                    }
+                    "SCAN" => {
+                        let args = Self::string_args(items, "SCAN")?.into_iter();
+                        Ok(Command::Scan(ScanOptions::parse(args, "SCAN", true)?))
+                    }
+                    "RANDOMKEY" => {
+                        let args = Self::string_args(items, "RANDOMKEY")?;
+                        match args.as_slice() {
+                            [] => Ok(Command::RandomKey(None)),
+                            [option, type_name] if option.eq_ignore_ascii_case("TYPE") => {
+                                Ok(Command::RandomKey(Some(type_name.clone())))
+                            }
+                            _ => Err(Error::Command("Invalid RANDOMKEY arguments".into())),
+                        }
+                    }
+                    "HSCAN" => {
+                        let mut args = Self::string_args(items, "HSCAN")?.into_iter();
+                        let key = args.next().ok_or_else(|| {
+                            Error::Command("Wrong number of HSCAN arguments".into())
+                        })?;
+                        let options = ScanOptions::parse(args, "HSCAN", false)?;
+                        Ok(Command::HScan { key, options })
+                    }
+                    "DUMP" => Self::dump(items),
+                    "EXPIRETIME" | "PEXPIRETIME" => {
+                        let mut args = Self::string_args(items, &command)?.into_iter();
+                        match (args.next(), args.next(), command.as_str()) {
+                            (Some(key), None, "EXPIRETIME") => Ok(Command::ExpireTime(key)),
+                            (Some(key), None, _) => Ok(Command::PExpireTime(key)),
+                            _ => Err(Error::Command(format!(
+                                "{} requires exactly one argument",
+                                command
+                            ))),
+                        }
+                    }
+                    "RESTORE" => Self::restore(items),
+                    "COPY" => Self::copy(items),
+                    "MIGRATE" => Self::migrate(items),
+                    "MEMORY" => Self::memory(items),
+                    "OBJECT" => Self::object(items),
+                    "CLUSTER" => Self::cluster(items),
+                    "CLIENT" => Self::client(items),
+                    "REPLICAOF" | "SLAVEOF" => Self::replicaof(items),
+                    "SYNC" | "PSYNC" => Ok(Command::Sync),
+                    "CHANGEFEED" => Self::changefeed(items),
+                    "WAIT" => Self::wait(items),
+                    "WAITAOF" => Self::waitaof(items),
+                    "AUTH" => Self::auth(items),
+                    "HELLO" => Self::hello(items),
+                    "RESET" if items.is_empty() => Ok(Command::Reset),
+                    "RESET" => Err(Error::Command("RESET takes no arguments".into())),
+                    "QUIT" => Ok(Command::Quit),
+                    "SELECT" => {
+                        let args = Self::string_args(items, "SELECT")?;
+                        let index = args[0].parse().map_err(|_| {
+                            Error::Command("value is not an integer or out of range".into())
+                        })?;
+                        Ok(Command::Select(index))
+                    }
+                    "ACL" => Self::acl(items),
+                    "EVAL" => {
+                        let (script, keys, args) = Self::eval_args(items, "EVAL")?;
+                        Ok(Command::Eval { script, keys, args })
+                    }
+                    "EVALSHA" => {
+                        let (sha1, keys, args) = Self::eval_args(items, "EVALSHA")?;
+                        Ok(Command::EvalSha { sha1, keys, args })
+                    }
+                    "SCRIPT" => Self::script(items),
+                    "SUBSCRIBE" | "UNSUBSCRIBE" | "PSUBSCRIBE" | "PUNSUBSCRIBE" | "SSUBSCRIBE"
+                    | "SUNSUBSCRIBE" => Self::subscription(&command, items),
+                    "PUBLISH" | "SPUBLISH" => {
+                        let mut args = Self::string_args(items, &command)?.into_iter();
+                        match (args.next(), args.next(), args.next()) {
+                            (Some(channel), Some(message), None) if command == "PUBLISH" => {
+                                Ok(Command::Publish { channel, message })
+                            }
+                            (Some(channel), Some(message), None) => {
+                                Ok(Command::SPublish { channel, message })
+                            }
+                            _ => Err(Error::Command(format!(
+                                "{} requires a channel and a message",
+                                command
+                            ))),
+                        }
+                    }
+                    "PUBSUB" => Self::pubsub(items),
+                    "DEBUG" => Self::debug(items),
+                    "LATENCY" => Self::latency(items),
+                    "SAVE" => Ok(Command::Save),
+                    "BGSAVE" => Ok(Command::BgSave),
+                    "INFO" => Self::info(items),
+                    "COMMAND" => Self::command_args(items),
+                    "CONFIG" => Self::config(items),
+                    _ => Err(unknown_command(name, &items)),
                 }
             }
             _ => Err(Error::Command("Invalid command format".into())),
         }
     }
 
-    pub async fn execute(&self, storage: &Storage) -> Resp {
+    /// Canonical uppercase name of the command.
+    pub fn name(&self) -> &'static str {
         match self {
-            Command::Ping => Resp::SimpleString("PONG".into()),
-            Command::Echo(message) => Resp::SimpleString(message.clone()),
-            Command::Set { key, value, expiry } => {
-                match storage.set(key.clone(), value.clone(), *expiry).await {
-                    Ok(()) => Resp::SimpleString("OK".into()),
-                    Err(_) => Resp::Error("ERR failed to set value".into()),
-                }
+            Command::Ping => "PING",
+            Command::Echo(_) => "ECHO",
+            Command::Set { .. } => "SET",
+            Command::Get(_) => "GET",
+            Command::GetSet { .. } => "GETSET",
+            Command::MSetNx(_) => "MSETNX",
+            Command::Del(_) => "DEL",
+            Command::Incr(_) => "INCR",
+            Command::Decr(_) => "DECR",
+            Command::Lcs { .. } => "LCS",
+            Command::BitOp { .. } => "BITOP",
+            Command::BitPos { .. } => "BITPOS",
+            Command::HSet { .. } => "HSET",
+            Command::HMSet { .. } => "HMSET",
+            Command::HGet { .. } => "HGET",
+            Command::HMGet { .. } => "HMGET",
+            Command::HRandField { .. } => "HRANDFIELD",
+            Command::PfAdd { .. } => "PFADD",
+            Command::PfCount(_) => "PFCOUNT",
+            Command::PfMerge { .. } => "PFMERGE",
+            Command::XAdd { .. } => "XADD",
+            Command::XLen(_) => "XLEN",
+            Command::XRange { .. } => "XRANGE",
+            Command::LPush { .. } => "LPUSH",
+            Command::RPush { .. } => "RPUSH",
+            Command::LPop { .. } => "LPOP",
+            Command::RPop { .. } => "RPOP",
+            Command::LLen(_) => "LLEN",
+            Command::LRem { .. } => "LREM",
+            Command::LRange { .. } => "LRANGE",
+            Command::LTrim { .. } => "LTRIM",
+            Command::LInsert { .. } => "LINSERT",
+            Command::RPopLPush { .. } => "RPOPLPUSH",
+            Command::LMPop { .. } => "LMPOP",
+            Command::LMove { .. } => "LMOVE",
+            Command::LPos { .. } => "LPOS",
+            Command::SAdd { .. } => "SADD",
+            Command::SRem { .. } => "SREM",
+            Command::SMembers(_) => "SMEMBERS",
+            Command::SIsMember { .. } => "SISMEMBER",
+            Command::SCard(_) => "SCARD",
+            Command::SInterCard { .. } => "SINTERCARD",
+            Command::SInter(_) => "SINTER",
+            Command::SUnion(_) => "SUNION",
+            Command::SDiff(_) => "SDIFF",
+            Command::SInterStore { .. } => "SINTERSTORE",
+            Command::SUnionStore { .. } => "SUNIONSTORE",
+            Command::SDiffStore { .. } => "SDIFFSTORE",
+            Command::ZAdd { .. } => "ZADD",
+            Command::ZScore { .. } => "ZSCORE",
+            Command::ZRange { .. } => "ZRANGE",
+            Command::ZRangeByScore { .. } => "ZRANGEBYSCORE",
+            Command::ZCard(_) => "ZCARD",
+            Command::ZCount { .. } => "ZCOUNT",
+            Command::ZRem { .. } => "ZREM",
+            Command::ZRemRangeByScore { .. } => "ZREMRANGEBYSCORE",
+            Command::ZRemRangeByRank { .. } => "ZREMRANGEBYRANK",
+            Command::ZMPop { .. } => "ZMPOP",
+            Command::Scan(_) => "SCAN",
+            Command::RandomKey(_) => "RANDOMKEY",
+            Command::HScan { .. } => "HSCAN",
+            Command::Dump(_) => "DUMP",
+            Command::ExpireTime(_) => "EXPIRETIME",
+            Command::PExpireTime(_) => "PEXPIRETIME",
+            Command::Restore { .. } => "RESTORE",
+            Command::Copy { .. } => "COPY",
+            Command::Migrate { .. } => "MIGRATE",
+            Command::MemoryUsage { .. } => "MEMORY",
+            Command::ObjectEncoding(_)
+            | Command::ObjectIdleTime(_)
+            | Command::ObjectRefCount(_) => "OBJECT",
+            Command::ClusterKeySlot(_) => "CLUSTER",
+            Command::ClientTracking(_) => "CLIENT",
+            Command::ReplicaOf(_) => "REPLICAOF",
+            Command::Sync => "SYNC",
+            Command::ChangeFeed { .. } => "CHANGEFEED",
+            Command::Wait { .. } => "WAIT",
+            Command::WaitAof { .. } => "WAITAOF",
+            Command::Auth { .. } => "AUTH",
+            Command::Hello { .. } => "HELLO",
+            Command::AclWhoAmI | Command::AclList => "ACL",
+            Command::Eval { .. } => "EVAL",
+            Command::EvalSha { .. } => "EVALSHA",
+            Command::ScriptLoad(_) | Command::ScriptExists(_) | Command::ScriptFlush => "SCRIPT",
+            Command::Subscribe(_) => "SUBSCRIBE",
+            Command::Unsubscribe(_) => "UNSUBSCRIBE",
+            Command::PSubscribe(_) => "PSUBSCRIBE",
+            Command::PUnsubscribe(_) => "PUNSUBSCRIBE",
+            Command::Publish { .. } => "PUBLISH",
+            Command::SSubscribe(_) => "SSUBSCRIBE",
+            Command::SUnsubscribe(_) => "SUNSUBSCRIBE",
+            Command::SPublish { .. } => "SPUBLISH",
+            Command::PubSubChannels(_) | Command::PubSubNumSub(_) | Command::PubSubNumPat => {
+                "PUBSUB"
             }
-            Command::Get(key) => match storage.get(&key).await {
-                Ok(Some(value)) => Resp::BulkString(value),
-                Ok(None) => Resp::Null,
-                Err(_) => Resp::Error("ERR failed to get value".into()),
-            },
+            Command::DebugSetActiveExpire(_)
+            | Command::DebugSetMemoryUsage(_)
+            | Command::DebugReload
+            | Command::DebugChangeReplId
+            | Command::DebugSleep(_)
+            | Command::DebugObject(_)
+            | Command::DebugStringMatch { .. } => "DEBUG",
+            Command::LatencyLatest | Command::LatencyHistory(_) | Command::LatencyReset(_) => {
+                "LATENCY"
+            }
+            Command::Save => "SAVE",
+            Command::BgSave => "BGSAVE",
+            Command::Reset => "RESET",
+            Command::Quit => "QUIT",
+            Command::Select(_) => "SELECT",
+            Command::Info(_) => "INFO",
+            Command::DescribeCommands(_) | Command::CountCommands | Command::GetKeys(_) => {
+                "COMMAND"
+            }
+            Command::ConfigResetStat | Command::ConfigSetLogLevel(_) => "CONFIG",
+            Command::Help(subcommands) => subcommands.command,
         }
     }
 
-    fn get(mut items: Vec<Resp>) -> Result<Command> {
-        if items.len() != 1 {
-            return Err(Error::Command("GET requires exactly one argument".into()));
-        }
-        if let Resp::BulkString(key) = items.remove(0) {
-            Ok(Command::Get(key))
-        } else {
-            Err(Error::Command("Invalid GET argument".into()))
+    /// The key names the command reads or writes.
+    pub fn keys(&self) -> Vec<&str> {
+        match self {
+            Command::Set { key, .. }
+            | Command::Get(key)
+            | Command::GetSet { key, .. }
+            | Command::Incr(key)
+            | Command::Decr(key)
+            | Command::HSet { key, .. }
+            | Command::HMSet { key, .. }
+            | Command::HGet { key, .. }
+            | Command::HMGet { key, .. }
+            | Command::HRandField { key, .. }
+            | Command::HScan { key, .. }
+            | Command::PfAdd { key, .. }
+            | Command::XAdd { key, .. }
+            | Command::XLen(key)
+            | Command::XRange { key, .. }
+            | Command::LPush { key, .. }
+            | Command::RPush { key, .. }
+            | Command::LPop { key, .. }
+            | Command::RPop { key, .. }
+            | Command::LLen(key)
+            | Command::LRem { key, .. }
+            | Command::LRange { key, .. }
+            | Command::LTrim { key, .. }
+            | Command::LInsert { key, .. }
+            | Command::LPos { key, .. }
+            | Command::SAdd { key, .. }
+            | Command::SRem { key, .. }
+            | Command::SMembers(key)
+            | Command::SIsMember { key, .. }
+            | Command::SCard(key)
+            | Command::ZAdd { key, .. }
+            | Command::ZScore { key, .. }
+            | Command::ZRange { key, .. }
+            | Command::ZRangeByScore { key, .. }
+            | Command::ZCard(key)
+            | Command::ZCount { key, .. }
+            | Command::ZRem { key, .. }
+            | Command::ZRemRangeByScore { key, .. }
+            | Command::ZRemRangeByRank { key, .. }
+            | Command::BitPos { key, .. }
+            | Command::Dump(key)
+            | Command::ExpireTime(key)
+            | Command::PExpireTime(key)
+            | Command::Restore { key, .. }
+            | Command::Migrate { key, .. }
+            | Command::MemoryUsage { key, .. }
+            | Command::ObjectEncoding(key)
+            | Command::ObjectIdleTime(key)
+            | Command::ObjectRefCount(key)
+            | Command::DebugObject(key) => vec![key],
+            Command::MSetNx(pairs) => pairs.iter().map(|(key, _)| key.as_str()).collect(),
+            Command::Lcs { key1, key2, .. } => vec![key1, key2],
+            Command::RPopLPush {
+                source,
+                destination,
+            }
+            | Command::LMove {
+                source,
+                destination,
+                ..
+            }
+            | Command::Copy {
+                source,
+                destination,
+                ..
+            } => vec![source, destination],
+            Command::Del(keys)
+            | Command::PfCount(keys)
+            | Command::SInterCard { keys, .. }
+            | Command::LMPop { keys, .. }
+            | Command::ZMPop { keys, .. }
+            | Command::SInter(keys)
+            | Command::SUnion(keys)
+            | Command::SDiff(keys) => keys.iter().map(String::as_str).collect(),
+            Command::SInterStore { destination, keys }
+            | Command::SUnionStore { destination, keys }
+            | Command::SDiffStore { destination, keys }
+            | Command::BitOp {
+                destination, keys, ..
+            } => std::iter::once(destination)
+                .chain(keys)
+                .map(String::as_str)
+                .collect(),
+            Command::PfMerge { dest, sources } => std::iter::once(dest)
+                .chain(sources)
+                .map(String::as_str)
+                .collect(),
+            Command::Eval { keys, .. } | Command::EvalSha { keys, .. } => {
+                keys.iter().map(String::as_str).collect()
+            }
+            Command::Ping
+            | Command::Echo(_)
+            | Command::ReplicaOf(_)
+            | Command::Sync
+            | Command::ChangeFeed { .. }
+            | Command::Wait { .. }
+            | Command::WaitAof { .. }
+            | Command::Auth { .. }
+            | Command::Hello { .. }
+            | Command::AclWhoAmI
+            | Command::AclList
+            | Command::ScriptLoad(_)
+            | Command::ScriptExists(_)
+            | Command::ScriptFlush
+            | Command::Subscribe(_)
+            | Command::Unsubscribe(_)
+            | Command::PSubscribe(_)
+            | Command::PUnsubscribe(_)
+            | Command::Publish { .. }
+            | Command::SSubscribe(_)
+            | Command::SUnsubscribe(_)
+            | Command::SPublish { .. }
+            | Command::PubSubChannels(_)
+            | Command::PubSubNumSub(_)
+            | Command::PubSubNumPat
+            | Command::DebugSetActiveExpire(_)
+            | Command::DebugSetMemoryUsage(_)
+            | Command::DebugReload
+            | Command::DebugChangeReplId
+            | Command::ClientTracking(_)
+            | Command::ClusterKeySlot(_)
+            | Command::DebugSleep(_)
+            | Command::DebugStringMatch { .. }
+            | Command::LatencyLatest
+            | Command::LatencyHistory(_)
+            | Command::LatencyReset(_)
+            | Command::Save
+            | Command::BgSave
+            | Command::Reset
+            | Command::Quit
+            | Command::Select(_)
+            | Command::Info(_)
+            | Command::DescribeCommands(_)
+            | Command::CountCommands
+            | Command::GetKeys(_)
+            | Command::Help(_)
+            | Command::ConfigResetStat
+            | Command::ConfigSetLogLevel(_)
+            | Command::RandomKey(_)
+            | Command::Scan(_) => Vec::new(),
         }
     }
 
-    fn echo(mut items: Vec<Resp>) -> Result<Command> {
-        if items.len() != 1 {
-            return Err(Error::Command("ECHO requires exactly one argument".into()));
-        }
-        if let Resp::BulkString(message) = items.remove(0) {
-            Ok(Command::Echo(message))
-        } else {
-            Err(Error::Command("Invalid ECHO argument".into()))
-        }
+    /// Whether the command may grow the dataset, and so is refused once it
+    /// is over `maxmemory`: those flagged `denyoom` in the command table.
+    fn denies_oom(&self) -> bool {
+        command_table::lookup(self.name()).is_some_and(|spec| spec.flags.contains(&"denyoom"))
     }
 
-    fn set(mut items: Vec<Resp>) -> Result<Self> {
-        if let (Resp::BulkString(key), Resp::BulkString(value)) = (items.remove(0), items.remove(0))
-        {
-            Ok(Self::Set {
-                key,
-                value,
-                expiry: None,
-            })
-        } else {
-            Err(Error::Command("Invalid SET arguments".into()))
+    /// Whether the command modifies the dataset.
+    pub fn is_write(&self) -> bool {
+        match self {
+            Command::Set { .. }
+            | Command::GetSet { .. }
+            | Command::MSetNx(_)
+            | Command::Del(_)
+            | Command::Incr(_)
+            | Command::Decr(_)
+            | Command::HSet { .. }
+            | Command::HMSet { .. }
+            | Command::PfAdd { .. }
+            | Command::PfMerge { .. }
+            | Command::XAdd { .. }
+            | Command::LPush { .. }
+            | Command::RPush { .. }
+            | Command::LPop { .. }
+            | Command::RPop { .. }
+            | Command::LRem { .. }
+            | Command::LTrim { .. }
+            | Command::LInsert { .. }
+            | Command::RPopLPush { .. }
+            | Command::LMove { .. }
+            | Command::SAdd { .. }
+            | Command::SRem { .. }
+            | Command::SInterStore { .. }
+            | Command::SUnionStore { .. }
+            | Command::SDiffStore { .. }
+            | Command::BitOp { .. }
+            | Command::ZAdd { .. }
+            | Command::ZRem { .. }
+            | Command::ZRemRangeByScore { .. }
+            | Command::ZRemRangeByRank { .. }
+            | Command::LMPop { .. }
+            | Command::ZMPop { .. }
+            | Command::Restore { .. }
+            | Command::Copy { .. }
+            | Command::Migrate { .. } => true,
+            Command::Ping
+            | Command::Echo(_)
+            | Command::Get(_)
+            | Command::Lcs { .. }
+            | Command::BitPos { .. }
+            | Command::HGet { .. }
+            | Command::HMGet { .. }
+            | Command::HRandField { .. }
+            | Command::Scan(_)
+            | Command::RandomKey(_)
+            | Command::HScan { .. }
+            | Command::PfCount(_)
+            | Command::XLen(_)
+            | Command::XRange { .. }
+            | Command::LLen(_)
+            | Command::LRange { .. }
+            | Command::LPos { .. }
+            | Command::SMembers(_)
+            | Command::SIsMember { .. }
+            | Command::SCard(_)
+            | Command::SInterCard { .. }
+            | Command::SInter(_)
+            | Command::SUnion(_)
+            | Command::SDiff(_)
+            | Command::ZScore { .. }
+            | Command::ZRange { .. }
+            | Command::ZRangeByScore { .. }
+            | Command::ZCard(_)
+            | Command::ZCount { .. }
+            | Command::Dump(_)
+            | Command::ExpireTime(_)
+            | Command::PExpireTime(_)
+            | Command::MemoryUsage { .. }
+            | Command::ObjectEncoding(_)
+            | Command::ObjectIdleTime(_)
+            | Command::ObjectRefCount(_)
+            | Command::ReplicaOf(_)
+            | Command::Sync
+            | Command::ChangeFeed { .. }
+            | Command::Wait { .. }
+            | Command::WaitAof { .. }
+            | Command::Auth { .. }
+            | Command::Hello { .. }
+            | Command::AclWhoAmI
+            | Command::AclList
+            | Command::ScriptLoad(_)
+            | Command::ScriptExists(_)
+            | Command::ScriptFlush
+            | Command::Subscribe(_)
+            | Command::Unsubscribe(_)
+            | Command::PSubscribe(_)
+            | Command::PUnsubscribe(_)
+            | Command::Publish { .. }
+            | Command::SSubscribe(_)
+            | Command::SUnsubscribe(_)
+            | Command::SPublish { .. }
+            | Command::PubSubChannels(_)
+            | Command::PubSubNumSub(_)
+            | Command::PubSubNumPat
+            | Command::DebugSetActiveExpire(_)
+            | Command::DebugSetMemoryUsage(_)
+            | Command::DebugReload
+            | Command::DebugChangeReplId
+            | Command::ClientTracking(_)
+            | Command::ClusterKeySlot(_)
+            | Command::DebugSleep(_)
+            | Command::DebugObject(_)
+            | Command::DebugStringMatch { .. }
+            | Command::LatencyLatest
+            | Command::LatencyHistory(_)
+            | Command::LatencyReset(_)
+            | Command::Save
+            | Command::BgSave
+            | Command::Reset
+            | Command::Quit
+            | Command::Select(_)
+            | Command::Info(_)
+            | Command::DescribeCommands(_)
+            | Command::CountCommands
+            | Command::GetKeys(_)
+            | Command::Help(_)
+            | Command::ConfigResetStat
+            | Command::ConfigSetLogLevel(_) => false,
+            // Scripts are checked command by command as they run.
+            Command::Eval { .. } | Command::EvalSha { .. } => false,
         }
     }
 
-    fn set_with_expiry(mut items: Vec<Resp>) -> Result<Self> {
-        let (key, value, opt, px) = match (
-            items.remove(0),
-            items.remove(0),
-            items.remove(0),
-            items.remove(0),
+    pub async fn execute(&self, storage: &Storage, client: &mut Client) -> Resp {
+        // Scripts and read-modify-write commands hold the lock
+        // exclusively so no other command can interleave with them.
+        let start = Instant::now();
+        let reply = if matches!(self, Command::Wait { .. } | Command::WaitAof { .. }) {
+            // Waiting for acknowledgments can take until the timeout, and
+            // holding the lock meanwhile would stall every client behind the
+            // next exclusive command.
+            self.run(storage, client).await
+        } else if matches!(
+            self,
+            Command::Eval { .. }
+                | Command::EvalSha { .. }
+                | Command::GetSet { .. }
+                | Command::MSetNx(_)
+                | Command::Restore { .. }
+                | Command::Copy { .. }
+                | Command::Incr(_)
+                | Command::Decr(_)
+                | Command::HSet { .. }
+                | Command::HMSet { .. }
+                | Command::PfAdd { .. }
+                | Command::PfMerge { .. }
+                | Command::XAdd { .. }
+                | Command::LPush { .. }
+                | Command::RPush { .. }
+                | Command::LPop { .. }
+                | Command::RPop { .. }
+                | Command::LRem { .. }
+                | Command::LTrim { .. }
+                | Command::LInsert { .. }
+                | Command::RPopLPush { .. }
+                | Command::LMove { .. }
+                | Command::SAdd { .. }
+                | Command::SRem { .. }
+                | Command::SInterStore { .. }
+                | Command::SUnionStore { .. }
+                | Command::SDiffStore { .. }
+                | Command::BitOp { .. }
+                | Command::ZAdd { .. }
+                | Command::ZRem { .. }
+                | Command::ZRemRangeByScore { .. }
+                | Command::ZRemRangeByRank { .. }
+                | Command::LMPop { .. }
+                | Command::ZMPop { .. }
+                | Command::Migrate { .. }
+                | Command::DebugReload
         ) {
-            (
-                Resp::BulkString(k),
-                Resp::BulkString(v),
-                Resp::BulkString(o),
-                Resp::BulkString(p),
-            ) => (k, v, o, p),
-            _ => return Err(Error::Command("Invalid SET arguments".into())),
+            let _guard = storage.script_lock().write().await;
+            self.run(storage, client).await
+        } else {
+            let _guard = storage.script_lock().read().await;
+            self.run(storage, client).await
         };
+        let elapsed = start.elapsed();
+        let fast =
+            command_table::lookup(self.name()).is_some_and(|spec| spec.flags.contains(&"fast"));
+        let event = if fast { "fast-command" } else { "command" };
+        storage.latency().record(self.name(), event, elapsed);
+        // Counted once run, like Redis, so INFO doesn't count itself.
+        storage.stats().record_command();
+        reply
+    }
 
-        if opt.to_uppercase() != "PX" {
-            return Err(Error::Command("Invalid SET option".into()));
+    /// Executes the command without taking the script lock. Used directly by
+    /// scripts, which already hold it.
+    pub async fn run(&self, storage: &Storage, client: &mut Client) -> Resp {
+        debug!("Executing {}", self.name());
+        let storage = &*storage.select(client.db);
+
+        if client.in_subscribe_context() && !self.allowed_when_subscribed() {
+            return Resp::Error(format!(
+                "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+                self.name().to_lowercase()
+            ));
         }
 
-        let ms = px
-            .parse::<u64>()
-            .map_err(|_| Error::Command("Invalid PX value".into()))?;
+        if let Err(e) = storage.acl().check(client, self) {
+            return Resp::Error(e);
+        }
 
-        Ok(Self::Set {
-            key,
-            value,
-            expiry: Some(Duration::from_millis(ms)),
-        })
+        if self.is_write() && storage.is_read_only() {
+            return Resp::Error("READONLY You can't write against a read only replica.".into());
+        }
+        if self.is_write() && storage.aof_failing() {
+            return Resp::Error("MISCONF Errors writing to the AOF file".into());
+        }
+        // Keys are tracked before they are read, so a write racing with the
+        // read still invalidates them.
+        if client.tracking && !self.is_write() {
+            for key in self.keys() {
+                storage.tracking().track(client, key);
+            }
+        }
+        // Commands that may grow the dataset first make room as the
+        // maxmemory policy allows, and are refused if that isn't enough.
+        if storage.over_maxmemory() && self.denies_oom() {
+            match storage.evict().await {
+                Ok(true) => {}
+                Ok(false) => {
+                    return Resp::Error(
+                        "OOM command not allowed when used memory > 'maxmemory'.".into(),
+                    )
+                }
+                Err(e) => return storage_error("evict keys", e),
+            }
+        }
+
+        match self {
+            Command::Ping if client.in_subscribe_context() => Resp::Array(vec![
+                Resp::BulkString("pong".into()),
+                Resp::BulkString(String::new()),
+            ]),
+            Command::Ping => Resp::SimpleString("PONG".into()),
+            Command::Echo(message) => Resp::SimpleString(message.clone()),
+            Command::Set { key, value, expiry } => {
+                match storage.set(key.clone(), value.clone(), *expiry).await {
+                    Ok(_) => Resp::SimpleString("OK".into()),
+                    Err(e) => storage_error("set value", e),
+                }
+            }
+            // Like the legacy command, GETSET drops any TTL the key had. The
+            // type check and the swap are atomic under the exclusive lock.
+            Command::GetSet { key, value } => {
+                if let Some(Err(e)) =
+                    storage.lookup(key, |entry| entry.as_string(storage.now()).map(drop))
+                {
+                    return e.into();
+                }
+                match storage.set(key.clone(), value.clone(), None).await {
+                    Ok(previous) => match previous.as_ref().map(Value::as_string) {
+                        Some(Ok(previous)) => Resp::bulk(previous.into_owned()),
+                        Some(Err(e)) => e.into(),
+                        None => Resp::Null,
+                    },
+                    Err(e) => storage_error("set value", e),
+                }
+            }
+            Command::Incr(key) => increment(storage, key, 1).await,
+            Command::Decr(key) => increment(storage, key, -1).await,
+            Command::MSetNx(pairs) => {
+                if pairs
+                    .iter()
+                    .any(|(key, _)| storage.get_entry(key).is_some())
+                {
+                    return Resp::Integer(0);
+                }
+                for (key, value) in pairs {
+                    if let Err(e) = storage.set(key.clone(), value.clone(), None).await {
+                        return storage_error("set value", e);
+                    }
+                }
+                Resp::Integer(1)
+            }
+            Command::Del(keys) => {
+                let mut deleted = 0;
+                for key in keys {
+                    match storage.delete(key.clone()).await {
+                        Ok(true) => deleted += 1,
+                        Ok(false) => {}
+                        Err(e) => return storage_error("delete key", e),
+                    }
+                }
+                Resp::Integer(deleted)
+            }
+            Command::Lcs {
+                key1,
+                key2,
+                len,
+                idx,
+                min_match_len,
+                with_match_len,
+            } => {
+                let (a, b) = match (read_string(storage, key1), read_string(storage, key2)) {
+                    (Ok(a), Ok(b)) => (a.unwrap_or_default(), b.unwrap_or_default()),
+                    (Err(e), _) | (_, Err(e)) => return e,
+                };
+                let result = lcs::lcs(&a, &b);
+
+                if *idx {
+                    let range = |(start, end): (usize, usize)| {
+                        Resp::Array(vec![Resp::Integer(start as i64), Resp::Integer(end as i64)])
+                    };
+                    let matches = result
+                        .matches
+                        .iter()
+                        .filter(|m| m.len() >= *min_match_len)
+                        .map(|m| {
+                            let mut entry = vec![range(m.a), range(m.b)];
+                            if *with_match_len {
+                                entry.push(Resp::Integer(m.len() as i64));
+                            }
+                            Resp::Array(entry)
+                        })
+                        .collect();
+                    Resp::Array(vec![
+                        Resp::BulkString("matches".into()),
+                        Resp::Array(matches),
+                        Resp::BulkString("len".into()),
+                        Resp::Integer(result.sequence.len() as i64),
+                    ])
+                } else if *len {
+                    Resp::Integer(result.sequence.len() as i64)
+                } else {
+                    Resp::bulk(result.sequence)
+                }
+            }
+            // The sources are read and the destination written under the
+            // exclusive lock, so the result reflects a single point in time.
+            Command::BitOp {
+                op,
+                destination,
+                keys,
+            } => {
+                let mut sources = Vec::with_capacity(keys.len());
+                for key in keys {
+                    match read_string(storage, key) {
+                        Ok(value) => sources.push(value.unwrap_or_default()),
+                        Err(e) => return e,
+                    }
+                }
+                let result = bitmap::bitop(*op, &sources);
+                let len = result.len() as i64;
+                // An empty result deletes the destination, as in Redis.
+                let stored = if result.is_empty() {
+                    storage.delete(destination.clone()).await.map(drop)
+                } else {
+                    storage
+                        .set(destination.clone(), result.into(), None)
+                        .await
+                        .map(drop)
+                };
+                match stored {
+                    Ok(()) => Resp::Integer(len),
+                    Err(e) => storage_error("store value", e),
+                }
+            }
+            Command::BitPos {
+                key,
+                bit,
+                start,
+                end,
+                unit,
+            } => match read_string(storage, key) {
+                Ok(Some(value)) => Resp::Integer(bitmap::bitpos(&value, *bit, *start, *end, *unit)),
+                // A missing key is an empty string, zero-padded on the right.
+                Ok(None) => Resp::Integer(if *bit == 1 { -1 } else { 0 }),
+                Err(e) => e,
+            },
+            Command::Get(key) => match read_string(storage, key) {
+                Ok(Some(value)) => Resp::bulk(value),
+                Ok(None) => Resp::Null,
+                Err(e) => e,
+            },
+            Command::HSet { key, fields } | Command::HMSet { key, fields } => {
+                if let Some(Err(e)) =
+                    storage.lookup(key, |entry| entry.as_hash(storage.now()).map(drop))
+                {
+                    return e.into();
+                }
+                match storage.hset(key.clone(), fields.clone()).await {
+                    Ok(added) if matches!(self, Command::HSet { .. }) => {
+                        Resp::Integer(added as i64)
+                    }
+                    Ok(_) => Resp::SimpleString("OK".into()),
+                    Err(e) => storage_error("set value", e),
+                }
+            }
+            Command::HGet { key, field } => {
+                match storage.read(key, |value| {
+                    value.as_hash().map(|hash| hash.get(field).cloned())
+                }) {
+                    Some(Ok(Some(value))) => Resp::BulkString(value),
+                    Some(Ok(None)) | None => Resp::Null,
+                    Some(Err(e)) => e.into(),
+                }
+            }
+            Command::HRandField {
+                key,
+                count,
+                withvalues,
+            } => {
+                let picked = storage.read(key, |value| {
+                    value.as_hash().map(|hash| random_fields(hash, *count))
+                });
+                let picked = match picked {
+                    Some(Ok(picked)) => picked,
+                    Some(Err(e)) => return e.into(),
+                    None if count.is_some() => return Resp::Array(Vec::new()),
+                    None => return Resp::Null,
+                };
+
+                if count.is_none() {
+                    return picked
+                        .into_iter()
+                        .next()
+                        .map_or(Resp::Null, |(field, _)| Resp::BulkString(field));
+                }
+                Resp::Array(
+                    picked
+                        .into_iter()
+                        .flat_map(|(field, value)| {
+                            let value = withvalues.then_some(Resp::BulkString(value));
+                            std::iter::once(Resp::BulkString(field)).chain(value)
+                        })
+                        .collect(),
+                )
+            }
+            Command::PfAdd { key, elements } => {
+                let (mut hll, created) = match read_hyperloglog(storage, key) {
+                    Ok(Some(hll)) => (hll, false),
+                    Ok(None) => (HyperLogLog::new(), true),
+                    Err(e) => return e,
+                };
+                let mut changed = created;
+                for element in elements {
+                    changed |= hll.add(element.as_bytes());
+                }
+                if !changed {
+                    return Resp::Integer(0);
+                }
+                match storage
+                    .overwrite(key.clone(), Value::String(hll.to_value().into()))
+                    .await
+                {
+                    Ok(()) => Resp::Integer(1),
+                    Err(e) => storage_error("set value", e),
+                }
+            }
+            Command::PfCount(keys) => {
+                let mut union = HyperLogLog::new();
+                for key in keys {
+                    match read_hyperloglog(storage, key) {
+                        Ok(Some(hll)) => union.merge(&hll),
+                        Ok(None) => {}
+                        Err(e) => return e,
+                    }
+                }
+                Resp::Integer(union.count() as i64)
+            }
+            Command::PfMerge { dest, sources } => {
+                let mut union = HyperLogLog::new();
+                for key in std::iter::once(dest).chain(sources) {
+                    match read_hyperloglog(storage, key) {
+                        Ok(Some(hll)) => union.merge(&hll),
+                        Ok(None) => {}
+                        Err(e) => return e,
+                    }
+                }
+                match storage
+                    .overwrite(dest.clone(), Value::String(union.to_value().into()))
+                    .await
+                {
+                    Ok(()) => Resp::SimpleString("OK".into()),
+                    Err(e) => storage_error("set value", e),
+                }
+            }
+            Command::XAdd { key, id, fields } => {
+                let last_id = match storage
+                    .lookup(key, |entry| entry.value.as_stream().map(Stream::last_id))
+                {
+                    Some(Ok(last_id)) => Some(last_id),
+                    Some(Err(e)) => return e.into(),
+                    None => None,
+                };
+
+                let id = match (id, last_id) {
+                    (Some(id), Some(last_id)) if *id <= last_id => {
+                        return Resp::Error(
+                            "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+                                .into(),
+                        )
+                    }
+                    (Some(id), _) => *id,
+                    (None, last_id) => {
+                        let now_ms = storage
+                            .now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_millis() as u64;
+                        match last_id.unwrap_or(StreamId::MIN).successor(now_ms) {
+                            Some(id) => id,
+                            None => {
+                                return Resp::Error(
+                                    "ERR The stream has exhausted the last possible ID, unable to add more items"
+                                        .into(),
+                                )
+                            }
+                        }
+                    }
+                };
+
+                match storage.xadd(key.clone(), id, fields.clone()).await {
+                    Ok(()) => Resp::BulkString(id.to_string()),
+                    Err(e) => storage_error("append entry", e),
+                }
+            }
+            Command::XLen(key) => {
+                match storage.read(key, |value| value.as_stream().map(Stream::len)) {
+                    Some(Ok(len)) => Resp::Integer(len as i64),
+                    Some(Err(e)) => e.into(),
+                    None => Resp::Integer(0),
+                }
+            }
+            Command::XRange {
+                key,
+                start,
+                end,
+                count,
+            } => {
+                let entries = storage.read(key, |value| {
+                    value.as_stream().map(|stream| {
+                        stream
+                            .range(*start, *end)
+                            .take(count.unwrap_or(usize::MAX))
+                            .map(|(id, fields)| {
+                                Resp::Array(vec![
+                                    Resp::BulkString(id.to_string()),
+                                    Resp::Array(
+                                        fields
+                                            .iter()
+                                            .flat_map(|(field, value)| {
+                                                [
+                                                    Resp::BulkString(field.clone()),
+                                                    Resp::BulkString(value.clone()),
+                                                ]
+                                            })
+                                            .collect(),
+                                    ),
+                                ])
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                });
+                match entries {
+                    Some(Ok(entries)) => Resp::Array(entries),
+                    Some(Err(e)) => e.into(),
+                    None => Resp::Array(Vec::new()),
+                }
+            }
+            Command::LPush { key, elements } | Command::RPush { key, elements } => {
+                if let Some(Err(e)) =
+                    storage.lookup(key, |entry| entry.as_list(storage.now()).map(drop))
+                {
+                    return e.into();
+                }
+                let end = match self {
+                    Command::LPush { .. } => End::Left,
+                    _ => End::Right,
+                };
+                match storage.push(key.clone(), end, elements.clone()).await {
+                    Ok(len) => Resp::Integer(len as i64),
+                    Err(e) => storage_error("push elements", e),
+                }
+            }
+            Command::LPop { key, count } | Command::RPop { key, count } => {
+                if let Some(Err(e)) =
+                    storage.lookup(key, |entry| entry.as_list(storage.now()).map(drop))
+                {
+                    return e.into();
+                }
+                let end = match self {
+                    Command::LPop { .. } => End::Left,
+                    _ => End::Right,
+                };
+                let popped = match storage.pop(key.clone(), end, count.unwrap_or(1)).await {
+                    Ok(Some(popped)) => popped,
+                    Ok(None) => return Resp::Null,
+                    Err(e) => return storage_error("pop elements", e),
+                };
+                match count {
+                    Some(_) => Resp::Array(popped.into_iter().map(Resp::BulkString).collect()),
+                    None => popped
+                        .into_iter()
+                        .next()
+                        .map_or(Resp::Null, Resp::BulkString),
+                }
+            }
+            Command::LLen(key) => {
+                match storage.read(key, |value| value.as_list().map(|list| list.len())) {
+                    Some(Ok(len)) => Resp::Integer(len as i64),
+                    Some(Err(e)) => e.into(),
+                    None => Resp::Integer(0),
+                }
+            }
+            Command::LRem {
+                key,
+                count,
+                element,
+            } => {
+                let mut list = match storage.lookup(key, |entry| entry.value.as_list().cloned()) {
+                    Some(Ok(list)) => list,
+                    Some(Err(e)) => return e.into(),
+                    None => return Resp::Integer(0),
+                };
+                let removed = list::remove(&mut list, element, *count);
+                if removed > 0 {
+                    if let Err(e) = storage.store_list(key.clone(), list).await {
+                        return storage_error("remove elements", e);
+                    }
+                }
+                Resp::Integer(removed as i64)
+            }
+            Command::LRange { key, start, stop } => {
+                let elements = storage.read(key, |value| {
+                    value.as_list().map(|list| {
+                        list::range(list.len(), *start, *stop)
+                            .map(|range| {
+                                list.range(range)
+                                    .map(|element| Resp::BulkString(element.clone()))
+                                    .collect()
+                            })
+                            .unwrap_or_default()
+                    })
+                });
+                match elements {
+                    Some(Ok(elements)) => Resp::Array(elements),
+                    Some(Err(e)) => e.into(),
+                    None => Resp::Array(Vec::new()),
+                }
+            }
+            Command::LTrim { key, start, stop } => {
+                let mut list = match storage.lookup(key, |entry| entry.value.as_list().cloned()) {
+                    Some(Ok(list)) => list,
+                    Some(Err(e)) => return e.into(),
+                    None => return Resp::SimpleString("OK".into()),
+                };
+                let len = list.len();
+                match list::range(len, *start, *stop) {
+                    Some(range) => {
+                        list.truncate(range.end() + 1);
+                        list.drain(..range.start());
+                    }
+                    None => list.clear(),
+                }
+                if list.len() < len {
+                    if let Err(e) = storage.store_list(key.clone(), list).await {
+                        return storage_error("trim list", e);
+                    }
+                }
+                Resp::SimpleString("OK".into())
+            }
+            Command::LInsert {
+                key,
+                before,
+                pivot,
+                element,
+            } => {
+                let mut list = match storage.lookup(key, |entry| entry.value.as_list().cloned()) {
+                    Some(Ok(list)) => list,
+                    Some(Err(e)) => return e.into(),
+                    None => return Resp::Integer(0),
+                };
+                let Some(index) = list.iter().position(|candidate| candidate == pivot) else {
+                    return Resp::Integer(-1);
+                };
+                // Shifts the elements after the insertion point, which is
+                // linear in the list's length like the rewrite journaled
+                // below, and like LINSERT in Redis.
+                list.insert(if *before { index } else { index + 1 }, element.clone());
+                let len = list.len();
+                match storage.store_list(key.clone(), list).await {
+                    Ok(()) => Resp::Integer(len as i64),
+                    Err(e) => storage_error("insert element", e),
+                }
+            }
+            Command::RPopLPush {
+                source,
+                destination,
+            } => lmove(storage, source, destination, End::Right, End::Left).await,
+            Command::LMove {
+                source,
+                destination,
+                from,
+                to,
+            } => lmove(storage, source, destination, *from, *to).await,
+            Command::LPos {
+                key,
+                element,
+                rank,
+                count,
+                maxlen,
+            } => {
+                let positions = match storage.read(key, |value| {
+                    value.as_list().map(|list| {
+                        list::positions(list, element, *rank, count.unwrap_or(1), *maxlen)
+                    })
+                }) {
+                    Some(Ok(positions)) => positions,
+                    Some(Err(e)) => return e.into(),
+                    None => Vec::new(),
+                };
+                match count {
+                    Some(_) => Resp::Array(
+                        positions
+                            .into_iter()
+                            .map(|index| Resp::Integer(index as i64))
+                            .collect(),
+                    ),
+                    None => positions
+                        .first()
+                        .map_or(Resp::Null, |&index| Resp::Integer(index as i64)),
+                }
+            }
+            Command::SAdd { key, members } => {
+                if let Some(Err(e)) =
+                    storage.lookup(key, |entry| entry.as_set(storage.now()).map(drop))
+                {
+                    return e.into();
+                }
+                match storage.sadd(key.clone(), members.clone()).await {
+                    Ok(added) => Resp::Integer(added as i64),
+                    Err(e) => storage_error("add members", e),
+                }
+            }
+            Command::SRem { key, members } => {
+                if let Some(Err(e)) =
+                    storage.lookup(key, |entry| entry.as_set(storage.now()).map(drop))
+                {
+                    return e.into();
+                }
+                match storage.srem(key.clone(), members.clone()).await {
+                    Ok(removed) => Resp::Integer(removed as i64),
+                    Err(e) => storage_error("remove members", e),
+                }
+            }
+            Command::SMembers(key) => {
+                let members = storage.read(key, |value| {
+                    value.as_set().map(|set| {
+                        set.iter()
+                            .map(|member| Resp::BulkString(member.into_owned()))
+                            .collect()
+                    })
+                });
+                match members {
+                    Some(Ok(members)) => Resp::Array(members),
+                    Some(Err(e)) => e.into(),
+                    None => Resp::Array(Vec::new()),
+                }
+            }
+            Command::SIsMember { key, member } => {
+                match storage.read(key, |value| value.as_set().map(|set| set.contains(member))) {
+                    Some(Ok(found)) => Resp::Integer(found.into()),
+                    Some(Err(e)) => e.into(),
+                    None => Resp::Integer(0),
+                }
+            }
+            Command::SCard(key) => match storage.read(key, |value| value.as_set().map(Set::len)) {
+                Some(Ok(len)) => Resp::Integer(len as i64),
+                Some(Err(e)) => e.into(),
+                None => Resp::Integer(0),
+            },
+            Command::SInterCard { keys, limit } => sintercard(storage, keys, *limit),
+            Command::SInter(keys) | Command::SUnion(keys) | Command::SDiff(keys) => {
+                let combine = match self {
+                    Command::SInter(_) => Combine::Inter,
+                    Command::SUnion(_) => Combine::Union,
+                    _ => Combine::Diff,
+                };
+                match combine_sets(storage, combine, keys) {
+                    Ok(set) => Resp::Array(
+                        set.iter()
+                            .map(|member| Resp::BulkString(member.into_owned()))
+                            .collect(),
+                    ),
+                    Err(reply) => reply,
+                }
+            }
+            Command::SInterStore { destination, keys }
+            | Command::SUnionStore { destination, keys }
+            | Command::SDiffStore { destination, keys } => {
+                let combine = match self {
+                    Command::SInterStore { .. } => Combine::Inter,
+                    Command::SUnionStore { .. } => Combine::Union,
+                    _ => Combine::Diff,
+                };
+                if let Some(Err(e)) =
+                    storage.lookup(destination, |entry| entry.as_set(storage.now()).map(drop))
+                {
+                    return e.into();
+                }
+                let set = match combine_sets(storage, combine, keys) {
+                    Ok(set) => set,
+                    Err(reply) => return reply,
+                };
+                let len = set.len();
+                match storage.store_set(destination.clone(), set).await {
+                    Ok(()) => Resp::Integer(len as i64),
+                    Err(e) => storage_error("store set", e),
+                }
+            }
+            Command::ZAdd { key, members } => {
+                if let Some(Err(e)) =
+                    storage.lookup(key, |entry| entry.as_sorted_set(storage.now()).map(drop))
+                {
+                    return e.into();
+                }
+                match storage.zadd(key.clone(), members.clone()).await {
+                    Ok(added) => Resp::Integer(added as i64),
+                    Err(e) => storage_error("add members", e),
+                }
+            }
+            Command::ZScore { key, member } => {
+                match storage.read(key, |value| {
+                    value.as_sorted_set().map(|set| set.score(member))
+                }) {
+                    Some(Ok(Some(score))) => Resp::BulkString(zset::format_score(score)),
+                    Some(Ok(None)) | None => Resp::Null,
+                    Some(Err(e)) => e.into(),
+                }
+            }
+            Command::ZRange {
+                key,
+                start,
+                stop,
+                with_scores,
+            } => {
+                let members = storage.read(key, |value| {
+                    value.as_sorted_set().map(|set| {
+                        let Some(range) = list::range(set.len(), *start, *stop) else {
+                            return Vec::new();
+                        };
+                        let members = set.iter().skip(*range.start()).take(range.count());
+                        scored_members(members, *with_scores)
+                    })
+                });
+                match members {
+                    Some(Ok(members)) => Resp::Array(members),
+                    Some(Err(e)) => e.into(),
+                    None => Resp::Array(Vec::new()),
+                }
+            }
+            Command::ZRangeByScore {
+                key,
+                min,
+                max,
+                with_scores,
+                offset,
+                count,
+            } => {
+                let members = storage.read(key, |value| {
+                    value.as_sorted_set().map(|set| {
+                        let members = set
+                            .range_by_score(*min, *max)
+                            .skip(*offset)
+                            .take(count.unwrap_or(usize::MAX));
+                        scored_members(members, *with_scores)
+                    })
+                });
+                match members {
+                    Some(Ok(members)) => Resp::Array(members),
+                    Some(Err(e)) => e.into(),
+                    None => Resp::Array(Vec::new()),
+                }
+            }
+            Command::ZCard(key) => {
+                match storage.read(key, |value| value.as_sorted_set().map(SortedSet::len)) {
+                    Some(Ok(len)) => Resp::Integer(len as i64),
+                    Some(Err(e)) => e.into(),
+                    None => Resp::Integer(0),
+                }
+            }
+            Command::ZCount { key, min, max } => {
+                match storage.read(key, |value| {
+                    value.as_sorted_set().map(|set| set.count(*min, *max))
+                }) {
+                    Some(Ok(count)) => Resp::Integer(count as i64),
+                    Some(Err(e)) => e.into(),
+                    None => Resp::Integer(0),
+                }
+            }
+            Command::ZRem { key, members } => {
+                let members = members.clone();
+                zremrange(storage, key, |_| members).await
+            }
+            Command::ZRemRangeByScore { key, min, max } => {
+                zremrange(storage, key, |set| {
+                    set.range_by_score(*min, *max)
+                        .map(|(member, _)| member.to_string())
+                        .collect()
+                })
+                .await
+            }
+            Command::ZRemRangeByRank { key, start, stop } => {
+                zremrange(storage, key, |set| {
+                    let Some(range) = list::range(set.len(), *start, *stop) else {
+                        return Vec::new();
+                    };
+                    set.iter()
+                        .skip(*range.start())
+                        .take(range.count())
+                        .map(|(member, _)| member.to_string())
+                        .collect()
+                })
+                .await
+            }
+            Command::LMPop { keys, end, count } => {
+                // Pops from the first key holding a list, failing on any
+                // other type met before it.
+                for key in keys {
+                    match storage.lookup(key, |entry| {
+                        entry.as_list(storage.now()).map(|list| list.is_some())
+                    }) {
+                        Some(Err(e)) => return e.into(),
+                        Some(Ok(true)) => {}
+                        _ => continue,
+                    }
+                    return match storage.pop(key.clone(), *end, *count).await {
+                        Ok(popped) => Resp::Array(vec![
+                            Resp::BulkString(key.clone()),
+                            Resp::Array(
+                                popped
+                                    .unwrap_or_default()
+                                    .into_iter()
+                                    .map(Resp::BulkString)
+                                    .collect(),
+                            ),
+                        ]),
+                        Err(e) => storage_error("pop elements", e),
+                    };
+                }
+                Resp::Null
+            }
+            Command::ZMPop {
+                keys,
+                extreme,
+                count,
+            } => {
+                for key in keys {
+                    match storage.lookup(key, |entry| {
+                        entry.as_sorted_set(storage.now()).map(|set| set.is_some())
+                    }) {
+                        Some(Err(e)) => return e.into(),
+                        Some(Ok(true)) => {}
+                        _ => continue,
+                    }
+                    return match storage.zpop(key.clone(), *extreme, *count).await {
+                        Ok(popped) => Resp::Array(vec![
+                            Resp::BulkString(key.clone()),
+                            Resp::Array(
+                                popped
+                                    .into_iter()
+                                    .map(|(member, score)| {
+                                        Resp::Array(vec![
+                                            Resp::BulkString(member),
+                                            Resp::BulkString(zset::format_score(score)),
+                                        ])
+                                    })
+                                    .collect(),
+                            ),
+                        ]),
+                        Err(e) => storage_error("pop members", e),
+                    };
+                }
+                Resp::Null
+            }
+            Command::Scan(options) => {
+                let (next, keys) = storage.scan(options);
+                scan_reply(next, keys.into_iter().map(Resp::BulkString).collect())
+            }
+            Command::RandomKey(type_name) => match storage.random_key(type_name.as_deref()) {
+                Some(key) => Resp::BulkString(key),
+                None => Resp::Null,
+            },
+            Command::HScan { key, options } => {
+                let page = storage.read(key, |value| {
+                    value.as_hash().map(|hash| {
+                        let (next, pairs) = scan::page(
+                            hash.iter(),
+                            options.cursor,
+                            options.count,
+                            options.pattern.as_deref(),
+                            |(field, _)| field.as_str(),
+                        );
+                        let pairs = pairs
+                            .into_iter()
+                            .flat_map(|(field, value)| {
+                                [
+                                    Resp::BulkString(field.clone()),
+                                    Resp::BulkString(value.clone()),
+                                ]
+                            })
+                            .collect::<Vec<_>>();
+                        (next, pairs)
+                    })
+                });
+                match page {
+                    Some(Ok((next, pairs))) => scan_reply(next, pairs),
+                    Some(Err(e)) => e.into(),
+                    None => scan_reply(0, Vec::new()),
+                }
+            }
+            Command::HMGet { key, fields } => {
+                let values = storage.read(key, |value| {
+                    value.as_hash().map(|hash| {
+                        fields
+                            .iter()
+                            .map(|field| hash.get(field).cloned())
+                            .collect::<Vec<_>>()
+                    })
+                });
+                match values {
+                    Some(Ok(values)) => Resp::Array(
+                        values
+                            .into_iter()
+                            .map(|value| value.map_or(Resp::Null, Resp::BulkString))
+                            .collect(),
+                    ),
+                    None => Resp::Array(fields.iter().map(|_| Resp::Null).collect()),
+                    Some(Err(e)) => e.into(),
+                }
+            }
+            // Like TTL: -2 for a missing key, -1 for a key without expiry.
+            Command::ExpireTime(key) | Command::PExpireTime(key) => {
+                match storage.lookup(key, |entry| entry.expires_at) {
+                    Some(Some(expires_at)) => {
+                        let since_epoch = expires_at.duration_since(UNIX_EPOCH).unwrap_or_default();
+                        if matches!(self, Command::PExpireTime(_)) {
+                            Resp::Integer(since_epoch.as_millis() as i64)
+                        } else {
+                            Resp::Integer(since_epoch.as_secs() as i64)
+                        }
+                    }
+                    Some(None) => Resp::Integer(-1),
+                    None => Resp::Integer(-2),
+                }
+            }
+            Command::Dump(key) => match storage.get_entry(key) {
+                Some(entry) => match dump::encode(&entry) {
+                    Ok(payload) => Resp::BulkString(hex::encode(payload)),
+                    Err(e) => storage_error("dump value", e),
+                },
+                None => Resp::Null,
+            },
+            Command::Restore {
+                key,
+                ttl,
+                serialized,
+                replace,
+            } => {
+                let entry = hex::decode(serialized)
+                    .map_err(|_| DecodeError::Footer)
+                    .and_then(|payload| dump::decode(&payload));
+                let mut entry = match entry {
+                    Ok(entry) => entry,
+                    Err(DecodeError::Footer) => {
+                        return Resp::Error("ERR DUMP payload version or checksum are wrong".into())
+                    }
+                    Err(DecodeError::BadData) => return Resp::Error("ERR Bad data format".into()),
+                };
+                entry.expires_at = (*ttl > 0).then(|| storage.now() + Duration::from_millis(*ttl));
+
+                match storage.restore(key.clone(), entry, *replace).await {
+                    Ok(true) => Resp::SimpleString("OK".into()),
+                    Ok(false) => Resp::Error("BUSYKEY Target key name already exists.".into()),
+                    Err(e) => storage_error("restore value", e),
+                }
+            }
+            Command::Copy {
+                source,
+                destination,
+                db,
+                replace,
+            } => {
+                let db = match db.map(usize::try_from) {
+                    None => client.db,
+                    Some(Ok(db)) if db < storage.database_count() => db,
+                    Some(_) => return Resp::Error("ERR DB index is out of range".into()),
+                };
+                if db == client.db && source == destination {
+                    return Resp::Error("ERR source and destination objects are the same".into());
+                }
+                let target = storage.select(db);
+                match storage
+                    .copy(source, &target, destination.clone(), *replace)
+                    .await
+                {
+                    Ok(copied) => Resp::Integer(copied.into()),
+                    Err(e) => storage_error("copy value", e),
+                }
+            }
+            Command::Migrate {
+                host,
+                port,
+                key,
+                timeout,
+                copy,
+                replace,
+            } => {
+                let Some(entry) = storage.get_entry(key) else {
+                    return Resp::SimpleString("NOKEY".into());
+                };
+                let ttl = entry.expires_at.map_or(0, |expires_at| {
+                    let remaining = expires_at.duration_since(storage.now()).unwrap_or_default();
+                    (remaining.as_millis() as u64).max(1)
+                });
+                let payload = match dump::encode(&entry) {
+                    Ok(payload) => hex::encode(payload),
+                    Err(e) => return storage_error("dump value", e),
+                };
+
+                match migrate::restore_remote(host, *port, key, ttl, payload, *replace, *timeout)
+                    .await
+                {
+                    Ok(reply) if reply.starts_with('+') => {}
+                    Ok(reply) => {
+                        return Resp::Error(format!(
+                            "ERR Target instance replied with error: {}",
+                            reply.trim_start_matches('-')
+                        ))
+                    }
+                    Err(e) => {
+                        return Resp::Error(format!(
+                            "IOERR error or timeout migrating to target instance: {}",
+                            e
+                        ))
+                    }
+                }
+
+                if !copy {
+                    if let Err(e) = storage.delete(key.clone()).await {
+                        return storage_error("delete key", e);
+                    }
+                }
+                Resp::SimpleString("OK".into())
+            }
+            Command::MemoryUsage { key, samples } => match storage.memory_usage(key, *samples) {
+                Some(bytes) => Resp::Integer(bytes as i64),
+                None => Resp::Null,
+            },
+            Command::ObjectEncoding(key) => match storage.get_entry(key) {
+                Some(entry) => Resp::BulkString(
+                    entry
+                        .encoding(storage.config().list_max_listpack_size)
+                        .into(),
+                ),
+                None => Resp::Null,
+            },
+            // Values are never shared between keys, so every live key is
+            // referenced exactly once.
+            Command::ObjectRefCount(key) => match storage.get_entry(key) {
+                Some(_) => Resp::Integer(1),
+                None => Resp::Error("ERR no such key".into()),
+            },
+            Command::ObjectIdleTime(key) => match storage.get_entry(key) {
+                Some(entry) => Resp::Integer(entry.idle_time(storage.now()).as_secs() as i64),
+                None => Resp::Null,
+            },
+            // Invalidations are only ever sent as push frames; RESP2
+            // connections would need REDIRECT to another connection.
+            Command::ClientTracking(true) if client.protocol() != 3 => {
+                Resp::Error("ERR CLIENT TRACKING requires RESP3, switch with HELLO 3 first".into())
+            }
+            Command::ClientTracking(true) => {
+                storage.tracking().enable(client);
+                Resp::SimpleString("OK".into())
+            }
+            Command::ClientTracking(false) => {
+                storage.tracking().disable(client);
+                Resp::SimpleString("OK".into())
+            }
+            Command::ClusterKeySlot(key) => Resp::Integer(cluster::key_slot(key.as_bytes()).into()),
+            Command::ReplicaOf(Some((host, port))) => {
+                storage.replicate_from(host.clone(), *port);
+                Resp::SimpleString("OK".into())
+            }
+            Command::ReplicaOf(None) => {
+                storage.replication().promote();
+                Resp::SimpleString("OK".into())
+            }
+            Command::Wait {
+                numreplicas,
+                timeout,
+            } => {
+                let timeout = (*timeout > 0).then(|| Duration::from_millis(*timeout));
+                let acked = storage.replication().wait(*numreplicas, timeout).await;
+                Resp::Integer(acked as i64)
+            }
+            // Replicas are counted as WAIT counts them.
+            Command::WaitAof {
+                numlocal,
+                numreplicas,
+                timeout,
+            } => {
+                // Without an AOF there is nothing to fsync, so asking for a
+                // local fsync can never be satisfied.
+                let local = if storage.aof_enabled() {
+                    if let Err(e) = storage.sync_aof().await {
+                        return storage_error("sync the AOF", e);
+                    }
+                    1
+                } else if *numlocal > 0 {
+                    return Resp::Error(
+                        "ERR WAITAOF cannot be used when numlocal is set but appendonly is disabled."
+                            .into(),
+                    );
+                } else {
+                    0
+                };
+                let timeout = (*timeout > 0).then(|| Duration::from_millis(*timeout));
+                let acked = match numreplicas {
+                    0 => 0,
+                    _ => storage.replication().wait(*numreplicas, timeout).await,
+                };
+                Resp::Array(vec![Resp::Integer(local), Resp::Integer(acked as i64)])
+            }
+            Command::Auth { user, password } => {
+                let user = user.as_deref().unwrap_or(acl::DEFAULT_USER);
+                if storage.acl().authenticate(user, password) {
+                    client.user = Some(user.to_string());
+                    Resp::SimpleString("OK".into())
+                } else {
+                    Resp::Error(
+                        "WRONGPASS invalid username-password pair or user is disabled.".into(),
+                    )
+                }
+            }
+            Command::Hello {
+                protover,
+                auth,
+                setname,
+            } => {
+                if protover.is_some_and(|protover| !(2..=3).contains(&protover)) {
+                    return Resp::Error("NOPROTO unsupported protocol version".into());
+                }
+                if let Some((user, password)) = auth {
+                    if !storage.acl().authenticate(user, password) {
+                        return Resp::Error(
+                            "WRONGPASS invalid username-password pair or user is disabled.".into(),
+                        );
+                    }
+                    client.user = Some(user.clone());
+                }
+                if client.user.is_none() {
+                    return Resp::Error(
+                        "NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time"
+                            .into(),
+                    );
+                }
+                if let Some(name) = setname {
+                    client.name = Some(name.clone());
+                }
+                if let Some(protover) = protover {
+                    client.set_protocol(*protover);
+                }
+
+                let role = if storage.replication().is_replica() {
+                    "replica"
+                } else {
+                    "master"
+                };
+                Resp::Array(vec![
+                    Resp::BulkString("server".into()),
+                    Resp::BulkString("redis".into()),
+                    Resp::BulkString("version".into()),
+                    Resp::BulkString(env!("CARGO_PKG_VERSION").into()),
+                    Resp::BulkString("proto".into()),
+                    Resp::Integer(client.protocol()),
+                    Resp::BulkString("id".into()),
+                    Resp::Integer(client.id as i64),
+                    Resp::BulkString("mode".into()),
+                    Resp::BulkString("standalone".into()),
+                    Resp::BulkString("role".into()),
+                    Resp::BulkString(role.into()),
+                    Resp::BulkString("modules".into()),
+                    Resp::Array(Vec::new()),
+                ])
+            }
+            Command::Info(section) => Resp::BulkString(info::render(storage, section.as_deref())),
+            Command::Help(subcommands) => subcommands.help(),
+            Command::ConfigResetStat => {
+                storage.stats().reset();
+                storage.override_used_memory(0);
+                storage.latency().reset_histograms();
+                Resp::SimpleString("OK".into())
+            }
+            Command::ConfigSetLogLevel(level) => {
+                if let Some(logger) = logging::logger() {
+                    if let Err(e) = logger.set_level(*level) {
+                        return Resp::Error(format!("ERR Failed to change the log level: {}", e));
+                    }
+                }
+                tracing::info!("Log level set to {}", level.name());
+                Resp::SimpleString("OK".into())
+            }
+            Command::DescribeCommands(names) if names.is_empty() => {
+                Resp::Array(COMMANDS.iter().map(|spec| spec.info()).collect())
+            }
+            Command::DescribeCommands(names) => Resp::Array(
+                names
+                    .iter()
+                    .map(|name| {
+                        command_table::lookup(&name.to_uppercase())
+                            .map_or(Resp::Null, |spec| spec.info())
+                    })
+                    .collect(),
+            ),
+            Command::CountCommands => Resp::Integer(COMMANDS.len() as i64),
+            Command::GetKeys(command) => {
+                let keys = command.keys();
+                if keys.is_empty() {
+                    return Resp::Error("ERR The command has no key arguments".into());
+                }
+                Resp::Array(
+                    keys.into_iter()
+                        .map(|key| Resp::BulkString(key.to_string()))
+                        .collect(),
+                )
+            }
+            Command::Subscribe(channels) => Resp::Sequence(
+                channels
+                    .iter()
+                    .map(|channel| storage.pubsub().subscribe(client, channel))
+                    .collect(),
+            ),
+            Command::Unsubscribe(channels) => {
+                Resp::Sequence(storage.pubsub().unsubscribe(client, channels))
+            }
+            Command::PSubscribe(patterns) => Resp::Sequence(
+                patterns
+                    .iter()
+                    .map(|pattern| storage.pubsub().psubscribe(client, pattern))
+                    .collect(),
+            ),
+            Command::PUnsubscribe(patterns) => {
+                Resp::Sequence(storage.pubsub().punsubscribe(client, patterns))
+            }
+            Command::Publish { channel, message } => {
+                Resp::Integer(storage.pubsub().publish(channel, message) as i64)
+            }
+            Command::SSubscribe(channels) => Resp::Sequence(
+                channels
+                    .iter()
+                    .map(|channel| storage.pubsub().ssubscribe(client, channel))
+                    .collect(),
+            ),
+            Command::SUnsubscribe(channels) => {
+                Resp::Sequence(storage.pubsub().sunsubscribe(client, channels))
+            }
+            Command::SPublish { channel, message } => {
+                Resp::Integer(storage.pubsub().spublish(channel, message) as i64)
+            }
+            Command::PubSubChannels(pattern) => Resp::Array(
+                storage
+                    .pubsub()
+                    .active_channels(pattern.as_deref())
+                    .into_iter()
+                    .map(Resp::BulkString)
+                    .collect(),
+            ),
+            Command::PubSubNumSub(channels) => Resp::Array(
+                channels
+                    .iter()
+                    .flat_map(|channel| {
+                        let count = storage.pubsub().subscriber_count(channel);
+                        [
+                            Resp::BulkString(channel.clone()),
+                            Resp::Integer(count as i64),
+                        ]
+                    })
+                    .collect(),
+            ),
+            Command::PubSubNumPat => Resp::Integer(storage.pubsub().pattern_count() as i64),
+            Command::Reset => {
+                storage.pubsub().unsubscribe_all(client);
+                storage.tracking().disable(client);
+                client.reset(storage.acl());
+                Resp::SimpleString("RESET".into())
+            }
+            // The connection handler closes the connection after replying.
+            Command::Quit => Resp::SimpleString("OK".into()),
+            Command::Select(index) => match usize::try_from(*index) {
+                Ok(db) if db < storage.database_count() => {
+                    client.db = db;
+                    Resp::SimpleString("OK".into())
+                }
+                _ => Resp::Error("ERR DB index is out of range".into()),
+            },
+            Command::AclWhoAmI => match &client.user {
+                Some(user) => Resp::BulkString(user.clone()),
+                None => Resp::Null,
+            },
+            Command::AclList => {
+                let mut users: Vec<_> = storage.acl().users().collect();
+                users.sort_by(|a, b| a.name().cmp(b.name()));
+                Resp::Array(
+                    users
+                        .into_iter()
+                        .map(|user| Resp::BulkString(user.describe()))
+                        .collect(),
+                )
+            }
+            Command::Eval { script, keys, args } => {
+                storage.scripts().load(script);
+                scripting::eval(storage, client, script, keys, args)
+            }
+            Command::EvalSha { sha1, keys, args } => match storage.scripts().get(sha1) {
+                Some(script) => scripting::eval(storage, client, &script, keys, args),
+                None => Resp::Error("NOSCRIPT No matching script. Please use EVAL.".into()),
+            },
+            Command::ScriptLoad(script) => Resp::BulkString(storage.scripts().load(script)),
+            Command::ScriptExists(shas) => Resp::Array(
+                shas.iter()
+                    .map(|sha| Resp::Integer(storage.scripts().contains(sha) as i64))
+                    .collect(),
+            ),
+            Command::ScriptFlush => {
+                storage.scripts().flush();
+                Resp::SimpleString("OK".into())
+            }
+            Command::DebugSetActiveExpire(enabled) => {
+                storage.set_active_expire(*enabled);
+                Resp::SimpleString("OK".into())
+            }
+            Command::DebugSetMemoryUsage(bytes) => {
+                storage.override_used_memory(*bytes);
+                Resp::SimpleString("OK".into())
+            }
+            Command::DebugChangeReplId => {
+                storage.replication().change_replid();
+                Resp::SimpleString("OK".into())
+            }
+            Command::DebugStringMatch { pattern, string } => {
+                Resp::Integer(glob::matches(pattern, string).into())
+            }
+            Command::DebugObject(key) => match storage.get_entry(key) {
+                Some(entry) => Resp::SimpleString(debug_object(&entry, storage)),
+                None => Resp::Error("ERR no such key".into()),
+            },
+            Command::DebugSleep(duration) => {
+                tokio::time::sleep(*duration).await;
+                Resp::SimpleString("OK".into())
+            }
+            Command::LatencyLatest => Resp::Array(
+                storage
+                    .latency()
+                    .latest()
+                    .into_iter()
+                    .map(|(event, time, latency, max)| {
+                        Resp::Array(vec![
+                            Resp::BulkString(event.into()),
+                            Resp::Integer(time as i64),
+                            Resp::Integer(latency as i64),
+                            Resp::Integer(max as i64),
+                        ])
+                    })
+                    .collect(),
+            ),
+            Command::LatencyHistory(event) => Resp::Array(
+                storage
+                    .latency()
+                    .history(event)
+                    .into_iter()
+                    .map(|(time, latency)| {
+                        Resp::Array(vec![
+                            Resp::Integer(time as i64),
+                            Resp::Integer(latency as i64),
+                        ])
+                    })
+                    .collect(),
+            ),
+            Command::LatencyReset(events) => Resp::Integer(storage.latency().reset(events) as i64),
+            Command::DebugReload => match storage.reload().await {
+                Ok(()) => Resp::SimpleString("OK".into()),
+                Err(e) => storage_error("reload dataset", e),
+            },
+            Command::Save => match storage.save().await {
+                Ok(()) => Resp::SimpleString("OK".into()),
+                Err(e) => storage_error("save dataset", e),
+            },
+            Command::BgSave => {
+                let storage = storage.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = storage.save().await {
+                        error!("Background save failed: {}", e);
+                    }
+                });
+                Resp::SimpleString("Background saving started".into())
+            }
+            // The connection handler takes over the socket for SYNC; there is
+            // no reply to produce on the regular request/response path.
+            Command::Sync => Resp::Error("ERR SYNC is not allowed in this context".into()),
+            Command::ChangeFeed { .. } => {
+                Resp::Error("ERR CHANGEFEED is not allowed in this context".into())
+            }
+        }
+    }
+
+    fn get(mut items: Vec<Resp>) -> Result<Command> {
+        if items.len() != 1 {
+            return Err(Error::Command("GET requires exactly one argument".into()));
+        }
+        if let Some(key) = items.remove(0).into_string() {
+            Ok(Command::Get(key))
+        } else {
+            Err(Error::Command("Invalid GET argument".into()))
+        }
+    }
+
+    fn counter(command: &str, items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, command)?.into_iter();
+        match (args.next(), args.next(), command) {
+            (Some(key), None, "INCR") => Ok(Command::Incr(key)),
+            (Some(key), None, _) => Ok(Command::Decr(key)),
+            _ => Err(Error::Command(format!(
+                "{} requires exactly one argument",
+                command
+            ))),
+        }
+    }
+
+    fn msetnx(items: Vec<Resp>) -> Result<Command> {
+        if items.is_empty() || !items.len().is_multiple_of(2) {
+            return Err(Error::Command("Wrong number of MSETNX arguments".into()));
+        }
+
+        let mut items = items.into_iter();
+        let mut pairs = Vec::new();
+        while let (Some(key), Some(value)) = (items.next(), items.next()) {
+            match (key.into_string(), value.into_bulk()) {
+                (Some(key), Some(value)) => pairs.push((key, value)),
+                _ => return Err(Error::Command("Invalid MSETNX arguments".into())),
+            }
+        }
+        Ok(Command::MSetNx(pairs))
+    }
+
+    fn lcs(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "LCS")?.into_iter();
+        let (Some(key1), Some(key2)) = (args.next(), args.next()) else {
+            return Err(Error::Command("Wrong number of LCS arguments".into()));
+        };
+
+        let (mut len, mut idx, mut min_match_len, mut with_match_len) = (false, false, 0, false);
+        while let Some(option) = args.next() {
+            match option.to_uppercase().as_str() {
+                "LEN" => len = true,
+                "IDX" => idx = true,
+                "WITHMATCHLEN" => with_match_len = true,
+                "MINMATCHLEN" => {
+                    min_match_len = args
+                        .next()
+                        .and_then(|n| n.parse().ok())
+                        .ok_or_else(|| Error::Command("Invalid MINMATCHLEN value".into()))?;
+                }
+                _ => return Err(Error::Command(format!("Invalid LCS option: {}", option))),
+            }
+        }
+
+        if len && idx {
+            return Err(Error::Command(
+                "If you want both the length and indexes, please just use IDX.".into(),
+            ));
+        }
+
+        Ok(Command::Lcs {
+            key1,
+            key2,
+            len,
+            idx,
+            min_match_len,
+            with_match_len,
+        })
+    }
+
+    /// Parses `BITOP operation destkey key...`.
+    fn bitop(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "BITOP")?.into_iter();
+        let (Some(op), Some(destination)) = (args.next(), args.next()) else {
+            return Err(Error::Command("Wrong number of BITOP arguments".into()));
+        };
+        let op = op
+            .parse::<bitmap::BitOp>()
+            .map_err(|_| Error::Command("syntax error".into()))?;
+        let keys: Vec<String> = args.collect();
+        if keys.is_empty() {
+            return Err(Error::Command("Wrong number of BITOP arguments".into()));
+        }
+        if op == bitmap::BitOp::Not && keys.len() != 1 {
+            return Err(Error::Command(
+                "BITOP NOT must be called with a single source key.".into(),
+            ));
+        }
+        Ok(Command::BitOp {
+            op,
+            destination,
+            keys,
+        })
+    }
+
+    fn bitpos(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "BITPOS")?.into_iter();
+        let (Some(key), Some(bit)) = (args.next(), args.next()) else {
+            return Err(Error::Command("Wrong number of BITPOS arguments".into()));
+        };
+        let bit = match bit.as_str() {
+            "0" => 0,
+            "1" => 1,
+            _ => return Err(Error::Command("The bit argument must be 1 or 0.".into())),
+        };
+
+        let index = |value: Option<String>| {
+            value
+                .map(|value| {
+                    value.parse::<i64>().map_err(|_| {
+                        Error::Command("value is not an integer or out of range".into())
+                    })
+                })
+                .transpose()
+        };
+        let start = index(args.next())?.unwrap_or(0);
+        let end = index(args.next())?;
+        let unit = match args.next() {
+            None => Unit::Byte,
+            Some(unit) if unit.eq_ignore_ascii_case("BYTE") => Unit::Byte,
+            Some(unit) if unit.eq_ignore_ascii_case("BIT") => Unit::Bit,
+            Some(unit) => return Err(Error::Command(format!("Invalid BITPOS unit: {}", unit))),
+        };
+        if args.next().is_some() {
+            return Err(Error::Command("Wrong number of BITPOS arguments".into()));
+        }
+
+        Ok(Command::BitPos {
+            key,
+            bit,
+            start,
+            end,
+            unit,
+        })
+    }
+
+    /// Parses `key field value [field value ...]` for HSET and HMSET.
+    fn hash_fields(items: Vec<Resp>, name: &str) -> Result<(String, Vec<(String, String)>)> {
+        let args = Self::string_args(items, name)?;
+        if args.len() < 3 || args.len() % 2 == 0 {
+            return Err(Error::Command(format!(
+                "Wrong number of {} arguments",
+                name
+            )));
+        }
+
+        let mut args = args.into_iter();
+        let key = args.next().unwrap_or_default();
+        let mut fields = Vec::new();
+        while let (Some(field), Some(value)) = (args.next(), args.next()) {
+            fields.push((field, value));
+        }
+        Ok((key, fields))
+    }
+
+    fn hget(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "HGET")?.into_iter();
+        match (args.next(), args.next(), args.next()) {
+            (Some(key), Some(field), None) => Ok(Command::HGet { key, field }),
+            _ => Err(Error::Command("HGET requires exactly two arguments".into())),
+        }
+    }
+
+    fn hmget(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "HMGET")?.into_iter();
+        let key = args
+            .next()
+            .ok_or_else(|| Error::Command("Wrong number of HMGET arguments".into()))?;
+        let fields: Vec<String> = args.collect();
+        if fields.is_empty() {
+            return Err(Error::Command("Wrong number of HMGET arguments".into()));
+        }
+        Ok(Command::HMGet { key, fields })
+    }
+
+    /// Parses `PFADD key [element ...]`, `PFCOUNT key [key ...]` and
+    /// `PFMERGE destkey [sourcekey ...]`, which all start with a key.
+    fn hyperloglog(command: &str, items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, command)?.into_iter();
+        let key = args
+            .next()
+            .ok_or_else(|| Error::Command(format!("Wrong number of {} arguments", command)))?;
+        let rest: Vec<String> = args.collect();
+
+        match command {
+            "PFADD" => Ok(Command::PfAdd {
+                key,
+                elements: rest,
+            }),
+            "PFCOUNT" => Ok(Command::PfCount(std::iter::once(key).chain(rest).collect())),
+            _ => Ok(Command::PfMerge {
+                dest: key,
+                sources: rest,
+            }),
+        }
+    }
+
+    fn xadd(items: Vec<Resp>) -> Result<Command> {
+        let args = Self::string_args(items, "XADD")?;
+        if args.len() < 4 || args.len() % 2 == 1 {
+            return Err(Error::Command("Wrong number of XADD arguments".into()));
+        }
+
+        let mut args = args.into_iter();
+        let key = args.next().unwrap_or_default();
+        let id = match args.next().unwrap_or_default().as_str() {
+            "*" => None,
+            id => match id.parse::<StreamId>() {
+                Ok(StreamId::MIN) => {
+                    return Err(Error::Command(
+                        "The ID specified in XADD must be greater than 0-0".into(),
+                    ))
+                }
+                Ok(id) => Some(id),
+                Err(()) => return Err(Error::Command("Invalid stream ID".into())),
+            },
+        };
+        let mut fields = Vec::new();
+        while let (Some(field), Some(value)) = (args.next(), args.next()) {
+            fields.push((field, value));
+        }
+        Ok(Command::XAdd { key, id, fields })
+    }
+
+    /// Parses `XRANGE key start end [COUNT count]`, where the bounds are
+    /// IDs, bare millisecond times, or `-` and `+` for the smallest and
+    /// greatest possible IDs.
+    fn xrange(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "XRANGE")?.into_iter();
+        let (Some(key), Some(start), Some(end)) = (args.next(), args.next(), args.next()) else {
+            return Err(Error::Command("Wrong number of XRANGE arguments".into()));
+        };
+
+        let bound = |bound: &str, default: StreamId| match bound {
+            "-" => Ok(StreamId::MIN),
+            "+" => Ok(StreamId::MAX),
+            id => StreamId::parse_with_seq(id, default.seq)
+                .ok_or_else(|| Error::Command("Invalid stream ID".into())),
+        };
+        let start = bound(&start, StreamId::MIN)?;
+        let end = bound(&end, StreamId::MAX)?;
+
+        let count = match (args.next(), args.next(), args.next()) {
+            (None, _, _) => None,
+            (Some(option), Some(count), None) if option.eq_ignore_ascii_case("COUNT") => Some(
+                count
+                    .parse::<usize>()
+                    .map_err(|_| Error::Command("Invalid XRANGE count".into()))?,
+            ),
+            _ => return Err(Error::Command("Invalid XRANGE arguments".into())),
+        };
+
+        Ok(Command::XRange {
+            key,
+            start,
+            end,
+            count,
+        })
+    }
+
+    /// Parses `LPUSH key element [element ...]` and the same for RPUSH.
+    fn push(command: &str, items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, command)?.into_iter();
+        let key = args.next();
+        let elements: Vec<String> = args.collect();
+        match key {
+            Some(key) if !elements.is_empty() && command == "LPUSH" => {
+                Ok(Command::LPush { key, elements })
+            }
+            Some(key) if !elements.is_empty() => Ok(Command::RPush { key, elements }),
+            _ => Err(Error::Command(format!(
+                "Wrong number of {} arguments",
+                command
+            ))),
+        }
+    }
+
+    /// Parses `LPOP key [count]` and the same for RPOP.
+    fn pop(command: &str, items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, command)?.into_iter();
+        let (Some(key), count, None) = (args.next(), args.next(), args.next()) else {
+            return Err(Error::Command(format!(
+                "Wrong number of {} arguments",
+                command
+            )));
+        };
+        let count = count
+            .map(|count| {
+                count
+                    .parse::<usize>()
+                    .map_err(|_| Error::Command("value is out of range, must be positive".into()))
+            })
+            .transpose()?;
+        if command == "LPOP" {
+            Ok(Command::LPop { key, count })
+        } else {
+            Ok(Command::RPop { key, count })
+        }
+    }
+
+    /// Parses `LRANGE key start stop` and the same for LTRIM.
+    fn list_range(command: &str, items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, command)?.into_iter();
+        let (Some(key), Some(start), Some(stop), None) =
+            (args.next(), args.next(), args.next(), args.next())
+        else {
+            return Err(Error::Command(format!(
+                "{} requires exactly three arguments",
+                command
+            )));
+        };
+        let index = |index: String| {
+            index
+                .parse::<i64>()
+                .map_err(|_| Error::Command("value is not an integer or out of range".into()))
+        };
+        let (start, stop) = (index(start)?, index(stop)?);
+        if command == "LRANGE" {
+            Ok(Command::LRange { key, start, stop })
+        } else {
+            Ok(Command::LTrim { key, start, stop })
+        }
+    }
+
+    fn lrem(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "LREM")?.into_iter();
+        let (Some(key), Some(count), Some(element), None) =
+            (args.next(), args.next(), args.next(), args.next())
+        else {
+            return Err(Error::Command(
+                "LREM requires exactly three arguments".into(),
+            ));
+        };
+        let count = count
+            .parse::<i64>()
+            .map_err(|_| Error::Command("value is not an integer or out of range".into()))?;
+        Ok(Command::LRem {
+            key,
+            count,
+            element,
+        })
+    }
+
+    fn linsert(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "LINSERT")?.into_iter();
+        let (Some(key), Some(position), Some(pivot), Some(element), None) = (
+            args.next(),
+            args.next(),
+            args.next(),
+            args.next(),
+            args.next(),
+        ) else {
+            return Err(Error::Command(
+                "LINSERT requires exactly four arguments".into(),
+            ));
+        };
+        let before = match position.to_uppercase().as_str() {
+            "BEFORE" => true,
+            "AFTER" => false,
+            _ => return Err(Error::Command("syntax error".into())),
+        };
+        Ok(Command::LInsert {
+            key,
+            before,
+            pivot,
+            element,
+        })
+    }
+
+    /// Parses `LMOVE source destination LEFT|RIGHT LEFT|RIGHT`.
+    fn lmove(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "LMOVE")?.into_iter();
+        let (Some(source), Some(destination), Some(from), Some(to), None) = (
+            args.next(),
+            args.next(),
+            args.next(),
+            args.next(),
+            args.next(),
+        ) else {
+            return Err(Error::Command(
+                "LMOVE requires exactly four arguments".into(),
+            ));
+        };
+        let end = |end: String| {
+            end.parse::<End>()
+                .map_err(|_| Error::Command("syntax error".into()))
+        };
+        Ok(Command::LMove {
+            source,
+            destination,
+            from: end(from)?,
+            to: end(to)?,
+        })
+    }
+
+    /// Parses `LMPOP numkeys key... LEFT|RIGHT [COUNT count]` and the same
+    /// for ZMPOP, which takes `MIN|MAX`, into the keys, the end to pop from
+    /// and the count.
+    fn mpop<T: FromStr>(command: &str, items: Vec<Resp>) -> Result<(Vec<String>, T, usize)> {
+        let mut args = Self::string_args(items, command)?.into_iter();
+        let numkeys = args
+            .next()
+            .and_then(|numkeys| numkeys.parse::<usize>().ok())
+            .filter(|&numkeys| numkeys > 0)
+            .ok_or_else(|| Error::Command("numkeys should be greater than 0".into()))?;
+        let keys: Vec<String> = args.by_ref().take(numkeys).collect();
+        let end = args
+            .next()
+            .filter(|_| keys.len() == numkeys)
+            .ok_or_else(|| {
+                Error::Command("Number of keys can't be greater than number of args".into())
+            })?
+            .parse::<T>()
+            .map_err(|_| Error::Command("syntax error".into()))?;
+
+        let count = match (args.next(), args.next(), args.next()) {
+            (None, _, _) => 1,
+            (Some(option), Some(count), None) if option.eq_ignore_ascii_case("COUNT") => count
+                .parse::<usize>()
+                .ok()
+                .filter(|&count| count > 0)
+                .ok_or_else(|| Error::Command("count should be greater than 0".into()))?,
+            _ => return Err(Error::Command("syntax error".into())),
+        };
+        Ok((keys, end, count))
+    }
+
+    /// Parses `SADD key member...` and the same for SREM.
+    fn set_members(command: &str, items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, command)?.into_iter();
+        let key = args.next();
+        let members: Vec<String> = args.collect();
+        match key {
+            Some(key) if !members.is_empty() && command == "SADD" => {
+                Ok(Command::SAdd { key, members })
+            }
+            Some(key) if !members.is_empty() => Ok(Command::SRem { key, members }),
+            _ => Err(Error::Command(format!(
+                "Wrong number of {} arguments",
+                command
+            ))),
+        }
+    }
+
+    /// Parses `SINTER key...` and the same for SUNION and SDIFF, or
+    /// `SINTERSTORE destination key...` and the same for the other STORE
+    /// forms.
+    fn set_combine(command: &str, items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, command)?.into_iter();
+        let destination = if command.ends_with("STORE") {
+            args.next()
+        } else {
+            None
+        };
+        let keys: Vec<String> = args.collect();
+        if keys.is_empty() {
+            return Err(Error::Command(format!(
+                "Wrong number of {} arguments",
+                command
+            )));
+        }
+        Ok(match (command, destination) {
+            ("SINTER", _) => Command::SInter(keys),
+            ("SUNION", _) => Command::SUnion(keys),
+            ("SDIFF", _) => Command::SDiff(keys),
+            ("SINTERSTORE", Some(destination)) => Command::SInterStore { destination, keys },
+            ("SUNIONSTORE", Some(destination)) => Command::SUnionStore { destination, keys },
+            (_, Some(destination)) => Command::SDiffStore { destination, keys },
+            (_, None) => unreachable!("STORE forms take a destination"),
+        })
+    }
+
+    /// Parses `SINTERCARD numkeys key... [LIMIT limit]`.
+    fn sintercard(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "SINTERCARD")?.into_iter();
+        let numkeys = args
+            .next()
+            .and_then(|numkeys| numkeys.parse::<usize>().ok())
+            .filter(|&numkeys| numkeys > 0)
+            .ok_or_else(|| Error::Command("numkeys should be greater than 0".into()))?;
+        let keys: Vec<String> = args.by_ref().take(numkeys).collect();
+        if keys.len() < numkeys {
+            return Err(Error::Command(
+                "Number of keys can't be greater than number of args".into(),
+            ));
+        }
+
+        let limit = match (args.next(), args.next(), args.next()) {
+            (None, _, _) => 0,
+            (Some(option), Some(limit), None) if option.eq_ignore_ascii_case("LIMIT") => limit
+                .parse::<usize>()
+                .map_err(|_| Error::Command("LIMIT can't be negative".into()))?,
+            _ => return Err(Error::Command("syntax error".into())),
+        };
+        Ok(Command::SInterCard { keys, limit })
+    }
+
+    /// Parses `LPOS key element [RANK rank] [COUNT count] [MAXLEN len]`,
+    /// with the options in any order.
+    fn lpos(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "LPOS")?.into_iter();
+        let (Some(key), Some(element)) = (args.next(), args.next()) else {
+            return Err(Error::Command("Wrong number of LPOS arguments".into()));
+        };
+
+        let mut rank = 1;
+        let mut count = None;
+        let mut maxlen = 0;
+        while let Some(option) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| Error::Command("syntax error".into()))?
+                .parse::<i64>()
+                .map_err(|_| Error::Command("value is not an integer or out of range".into()))?;
+            match option.to_uppercase().as_str() {
+                // i64::MIN has no positive counterpart to start from.
+                "RANK" if value == 0 || value == i64::MIN => {
+                    return Err(Error::Command(
+                        "RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list"
+                            .into(),
+                    ))
+                }
+                "RANK" => rank = value,
+                "COUNT" if value < 0 => {
+                    return Err(Error::Command("COUNT can't be negative".into()))
+                }
+                "COUNT" => count = Some(value as usize),
+                "MAXLEN" if value < 0 => {
+                    return Err(Error::Command("MAXLEN can't be negative".into()))
+                }
+                "MAXLEN" => maxlen = value as usize,
+                _ => return Err(Error::Command("syntax error".into())),
+            }
+        }
+
+        Ok(Command::LPos {
+            key,
+            element,
+            rank,
+            count,
+            maxlen,
+        })
+    }
+
+    /// Parses `ZADD key score member [score member ...]`.
+    fn zadd(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "ZADD")?.into_iter();
+        let key = args
+            .next()
+            .ok_or_else(|| Error::Command("Wrong number of ZADD arguments".into()))?;
+        let args: Vec<String> = args.collect();
+        if args.is_empty() || !args.len().is_multiple_of(2) {
+            return Err(Error::Command("syntax error".into()));
+        }
+        let members = args
+            .chunks(2)
+            .map(|pair| {
+                let score = zset::parse_score(&pair[0])
+                    .map_err(|_| Error::Command("value is not a valid float".into()))?;
+                Ok((pair[1].clone(), score))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Command::ZAdd { key, members })
+    }
+
+    /// Parses `ZRANGE key start stop [WITHSCORES]`.
+    fn zrange(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "ZRANGE")?.into_iter();
+        let (Some(key), Some(start), Some(stop)) = (args.next(), args.next(), args.next()) else {
+            return Err(Error::Command("Wrong number of ZRANGE arguments".into()));
+        };
+        let index = |index: String| {
+            index
+                .parse::<i64>()
+                .map_err(|_| Error::Command("value is not an integer or out of range".into()))
+        };
+        let (start, stop) = (index(start)?, index(stop)?);
+        let with_scores = match (args.next(), args.next()) {
+            (None, _) => false,
+            (Some(option), None) if option.eq_ignore_ascii_case("WITHSCORES") => true,
+            _ => return Err(Error::Command("syntax error".into())),
+        };
+        Ok(Command::ZRange {
+            key,
+            start,
+            stop,
+            with_scores,
+        })
+    }
+
+    /// Parses `ZRANGEBYSCORE key min max [WITHSCORES] [LIMIT offset count]`,
+    /// with the options in any order.
+    fn zrangebyscore(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "ZRANGEBYSCORE")?.into_iter();
+        let (Some(key), Some(min), Some(max)) = (args.next(), args.next(), args.next()) else {
+            return Err(Error::Command(
+                "Wrong number of ZRANGEBYSCORE arguments".into(),
+            ));
+        };
+        let (min, max) = Self::score_range(&min, &max)?;
+
+        let mut with_scores = false;
+        let mut offset = 0;
+        let mut count = None;
+        while let Some(option) = args.next() {
+            match option.to_uppercase().as_str() {
+                "WITHSCORES" => with_scores = true,
+                "LIMIT" => {
+                    let mut number = || {
+                        args.next()
+                            .ok_or_else(|| Error::Command("syntax error".into()))?
+                            .parse::<i64>()
+                            .map_err(|_| {
+                                Error::Command("value is not an integer or out of range".into())
+                            })
+                    };
+                    let (start, limit) = (number()?, number()?);
+                    // A negative offset selects nothing and a negative count
+                    // everything past the offset, as in Redis.
+                    offset = start.max(0) as usize;
+                    count = match (start, limit) {
+                        (..0, _) => Some(0),
+                        (_, ..0) => None,
+                        (_, limit) => Some(limit as usize),
+                    };
+                }
+                _ => return Err(Error::Command("syntax error".into())),
+            }
+        }
+
+        Ok(Command::ZRangeByScore {
+            key,
+            min,
+            max,
+            with_scores,
+            offset,
+            count,
+        })
+    }
+
+    /// Parses `ZREMRANGEBYSCORE key min max` or `ZREMRANGEBYRANK key start
+    /// stop`.
+    fn zremrange(command: &str, items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, command)?.into_iter();
+        let (Some(key), Some(min), Some(max), None) =
+            (args.next(), args.next(), args.next(), args.next())
+        else {
+            return Err(Error::Command(format!(
+                "{} requires exactly three arguments",
+                command
+            )));
+        };
+        if command == "ZREMRANGEBYSCORE" {
+            let (min, max) = Self::score_range(&min, &max)?;
+            return Ok(Command::ZRemRangeByScore { key, min, max });
+        }
+        let index = |index: String| {
+            index
+                .parse::<i64>()
+                .map_err(|_| Error::Command("value is not an integer or out of range".into()))
+        };
+        Ok(Command::ZRemRangeByRank {
+            key,
+            start: index(min)?,
+            stop: index(max)?,
+        })
+    }
+
+    /// Parses the `min` and `max` of a score range, shared by ZRANGEBYSCORE,
+    /// ZCOUNT and ZREMRANGEBYSCORE.
+    fn score_range(min: &str, max: &str) -> Result<(ScoreBound, ScoreBound)> {
+        let bound = |bound: &str| {
+            bound
+                .parse::<ScoreBound>()
+                .map_err(|_| Error::Command("min or max is not a float".into()))
+        };
+        Ok((bound(min)?, bound(max)?))
+    }
+
+    fn hrandfield(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "HRANDFIELD")?.into_iter();
+        let key = args
+            .next()
+            .ok_or_else(|| Error::Command("Wrong number of HRANDFIELD arguments".into()))?;
+
+        let count = args
+            .next()
+            .map(|count| {
+                count
+                    .parse::<i64>()
+                    .map_err(|_| Error::Command("Invalid HRANDFIELD count".into()))
+            })
+            .transpose()?;
+
+        let withvalues = match args.next() {
+            Some(option) if count.is_some() && option.eq_ignore_ascii_case("WITHVALUES") => true,
+            Some(option) => {
+                return Err(Error::Command(format!(
+                    "Invalid HRANDFIELD option: {}",
+                    option
+                )))
+            }
+            None => false,
+        };
+        if args.next().is_some() {
+            return Err(Error::Command(
+                "Wrong number of HRANDFIELD arguments".into(),
+            ));
+        }
+
+        Ok(Command::HRandField {
+            key,
+            count,
+            withvalues,
+        })
+    }
+
+    fn getset(items: Vec<Resp>) -> Result<Command> {
+        let mut items = items.into_iter();
+        match (items.next(), items.next(), items.next()) {
+            (Some(key), Some(value), None) => match (key.into_string(), value.into_bulk()) {
+                (Some(key), Some(value)) => Ok(Command::GetSet { key, value }),
+                _ => Err(Error::Command("Invalid GETSET arguments".into())),
+            },
+            _ => Err(Error::Command(
+                "GETSET requires exactly two arguments".into(),
+            )),
+        }
+    }
+
+    fn dump(mut items: Vec<Resp>) -> Result<Command> {
+        if items.len() != 1 {
+            return Err(Error::Command("DUMP requires exactly one argument".into()));
+        }
+        if let Some(key) = items.remove(0).into_string() {
+            Ok(Command::Dump(key))
+        } else {
+            Err(Error::Command("Invalid DUMP argument".into()))
+        }
+    }
+
+    fn restore(mut items: Vec<Resp>) -> Result<Command> {
+        if items.len() != 3 && items.len() != 4 {
+            return Err(Error::Command("Wrong number of RESTORE arguments".into()));
+        }
+
+        let replace = match items.get(3).map(Resp::as_str) {
+            Some(Some(opt)) if opt.to_uppercase() == "REPLACE" => true,
+            Some(_) => return Err(Error::Command("Invalid RESTORE option".into())),
+            None => false,
+        };
+
+        let (key, ttl, serialized) = match (
+            items.remove(0).into_string(),
+            items.remove(0).into_string(),
+            items.remove(0).into_string(),
+        ) {
+            (Some(k), Some(t), Some(s)) => (k, t, s),
+            _ => return Err(Error::Command("Invalid RESTORE arguments".into())),
+        };
+
+        let ttl = ttl
+            .parse::<u64>()
+            .map_err(|_| Error::Command("Invalid TTL value".into()))?;
+
+        Ok(Command::Restore {
+            key,
+            ttl,
+            serialized,
+            replace,
+        })
+    }
+
+    /// Parses `COPY source destination [DB destination-db] [REPLACE]`.
+    fn copy(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "COPY")?.into_iter();
+        let (Some(source), Some(destination)) = (args.next(), args.next()) else {
+            return Err(Error::Command(
+                "COPY requires a source and a destination".into(),
+            ));
+        };
+        let mut db = None;
+        let mut replace = false;
+        while let Some(option) = args.next() {
+            match option.to_uppercase().as_str() {
+                "REPLACE" => replace = true,
+                "DB" => {
+                    let index = args
+                        .next()
+                        .ok_or_else(|| Error::Command("syntax error".into()))?;
+                    db = Some(index.parse().map_err(|_| {
+                        Error::Command("value is not an integer or out of range".into())
+                    })?);
+                }
+                _ => return Err(Error::Command("syntax error".into())),
+            }
+        }
+        Ok(Command::Copy {
+            source,
+            destination,
+            db,
+            replace,
+        })
+    }
+
+    fn object(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "OBJECT")?.into_iter();
+        let subcommand = args
+            .next()
+            .ok_or_else(|| Error::Command("OBJECT requires a subcommand".into()))?
+            .to_uppercase();
+
+        match subcommand.as_str() {
+            "ENCODING" | "IDLETIME" | "REFCOUNT" => {}
+            "HELP" => return Ok(Command::Help(&help::OBJECT)),
+            _ => return Err(help::OBJECT.unknown(&subcommand)),
+        }
+
+        let key = match (args.next(), args.next()) {
+            (Some(key), None) => key,
+            _ => {
+                return Err(Error::Command(format!(
+                    "Wrong number of OBJECT {} arguments",
+                    subcommand
+                )))
+            }
+        };
+
+        match subcommand.as_str() {
+            "ENCODING" => Ok(Command::ObjectEncoding(key)),
+            "IDLETIME" => Ok(Command::ObjectIdleTime(key)),
+            _ => Ok(Command::ObjectRefCount(key)),
+        }
+    }
+
+    fn client(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "CLIENT")?.into_iter();
+        let subcommand = args
+            .next()
+            .ok_or_else(|| Error::Command("CLIENT requires a subcommand".into()))?
+            .to_uppercase();
+
+        match subcommand.as_str() {
+            "TRACKING" => match (args.next().map(|arg| arg.to_uppercase()), args.next()) {
+                (Some(on), None) if on == "ON" => Ok(Command::ClientTracking(true)),
+                (Some(off), None) if off == "OFF" => Ok(Command::ClientTracking(false)),
+                _ => Err(Error::Command("CLIENT TRACKING takes ON or OFF".into())),
+            },
+            "HELP" => Ok(Command::Help(&help::CLIENT)),
+            _ => Err(help::CLIENT.unknown(&subcommand)),
+        }
+    }
+
+    fn cluster(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "CLUSTER")?.into_iter();
+        let subcommand = args
+            .next()
+            .ok_or_else(|| Error::Command("CLUSTER requires a subcommand".into()))?
+            .to_uppercase();
+
+        match subcommand.as_str() {
+            "KEYSLOT" => match (args.next(), args.next()) {
+                (Some(key), None) => Ok(Command::ClusterKeySlot(key)),
+                _ => Err(Error::Command(
+                    "Wrong number of CLUSTER KEYSLOT arguments".into(),
+                )),
+            },
+            "HELP" => Ok(Command::Help(&help::CLUSTER)),
+            _ => Err(help::CLUSTER.unknown(&subcommand)),
+        }
+    }
+
+    fn memory(mut items: Vec<Resp>) -> Result<Command> {
+        let subcommand = match items.first().and_then(Resp::as_str) {
+            Some(sub) => sub.to_uppercase(),
+            None => return Err(Error::Command("MEMORY requires a subcommand".into())),
+        };
+
+        match subcommand.as_str() {
+            "USAGE" => {}
+            "HELP" => return Ok(Command::Help(&help::MEMORY)),
+            _ => return Err(help::MEMORY.unknown(&subcommand)),
+        }
+
+        // SAMPLES bounds how many elements of an aggregate value are
+        // measured, 5 by default as in Redis; strings are always measured
+        // exactly.
+        let samples = match items.len() {
+            2 => 5,
+            4 => match (items[2].as_str(), items[3].as_str()) {
+                (Some(opt), Some(count)) if opt.to_uppercase() == "SAMPLES" => count
+                    .parse::<usize>()
+                    .map_err(|_| Error::Command("Invalid SAMPLES value".into()))?,
+                _ => return Err(Error::Command("Invalid MEMORY USAGE option".into())),
+            },
+            _ => {
+                return Err(Error::Command(
+                    "Wrong number of MEMORY USAGE arguments".into(),
+                ))
+            }
+        };
+
+        if let Some(key) = items.remove(1).into_string() {
+            Ok(Command::MemoryUsage { key, samples })
+        } else {
+            Err(Error::Command("Invalid MEMORY USAGE argument".into()))
+        }
+    }
+
+    fn replicaof(mut items: Vec<Resp>) -> Result<Command> {
+        if items.len() != 2 {
+            return Err(Error::Command(
+                "REPLICAOF requires exactly two arguments".into(),
+            ));
+        }
+
+        let (host, port) = match (items.remove(0).into_string(), items.remove(0).into_string()) {
+            (Some(h), Some(p)) => (h, p),
+            _ => return Err(Error::Command("Invalid REPLICAOF arguments".into())),
+        };
+
+        if host.to_uppercase() == "NO" && port.to_uppercase() == "ONE" {
+            return Ok(Command::ReplicaOf(None));
+        }
+
+        let port = port
+            .parse::<u16>()
+            .map_err(|_| Error::Command("Invalid REPLICAOF port".into()))?;
+
+        Ok(Command::ReplicaOf(Some((host, port))))
+    }
+
+    /// Parses `MIGRATE host port key destination-db timeout [COPY] [REPLACE]`.
+    /// There is a single database, so the destination must be 0.
+    fn migrate(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "MIGRATE")?.into_iter();
+        let (Some(host), Some(port), Some(key), Some(db), Some(timeout)) = (
+            args.next(),
+            args.next(),
+            args.next(),
+            args.next(),
+            args.next(),
+        ) else {
+            return Err(Error::Command("Wrong number of MIGRATE arguments".into()));
+        };
+
+        let port = port
+            .parse::<u16>()
+            .map_err(|_| Error::Command("Invalid MIGRATE port".into()))?;
+        if db != "0" {
+            return Err(Error::Command("DB index is out of range".into()));
+        }
+        let timeout = timeout
+            .parse::<u64>()
+            .map_err(|_| Error::Command("Invalid MIGRATE timeout".into()))?;
+        // Like Redis, a zero timeout means one second.
+        let timeout = Duration::from_millis(if timeout == 0 { 1000 } else { timeout });
+
+        let (mut copy, mut replace) = (false, false);
+        for option in args {
+            match option.to_uppercase().as_str() {
+                "COPY" => copy = true,
+                "REPLACE" => replace = true,
+                _ => {
+                    return Err(Error::Command(format!(
+                        "Invalid MIGRATE option: {}",
+                        option
+                    )))
+                }
+            }
+        }
+
+        Ok(Command::Migrate {
+            host,
+            port,
+            key,
+            timeout,
+            copy,
+            replace,
+        })
+    }
+
+    fn wait(mut items: Vec<Resp>) -> Result<Command> {
+        if items.len() != 2 {
+            return Err(Error::Command("WAIT requires exactly two arguments".into()));
+        }
+
+        let (numreplicas, timeout) =
+            match (items.remove(0).into_string(), items.remove(0).into_string()) {
+                (Some(n), Some(t)) => (n, t),
+                _ => return Err(Error::Command("Invalid WAIT arguments".into())),
+            };
+
+        let numreplicas = numreplicas
+            .parse::<usize>()
+            .map_err(|_| Error::Command("Invalid number of replicas".into()))?;
+        let timeout = timeout
+            .parse::<u64>()
+            .map_err(|_| Error::Command("Invalid timeout value".into()))?;
+
+        Ok(Command::Wait {
+            numreplicas,
+            timeout,
+        })
+    }
+
+    fn waitaof(items: Vec<Resp>) -> Result<Command> {
+        let args = Self::string_args(items, "WAITAOF")?;
+        let [numlocal, numreplicas, timeout] = args.as_slice() else {
+            return Err(Error::Command(
+                "WAITAOF requires exactly three arguments".into(),
+            ));
+        };
+
+        let numlocal = numlocal
+            .parse::<usize>()
+            .map_err(|_| Error::Command("Invalid numlocal value".into()))?;
+        let numreplicas = numreplicas
+            .parse::<usize>()
+            .map_err(|_| Error::Command("Invalid number of replicas".into()))?;
+        let timeout = timeout
+            .parse::<u64>()
+            .map_err(|_| Error::Command("Invalid timeout value".into()))?;
+
+        Ok(Command::WaitAof {
+            numlocal,
+            numreplicas,
+            timeout,
+        })
+    }
+
+    fn auth(mut items: Vec<Resp>) -> Result<Command> {
+        match items.len() {
+            1 => match items.remove(0).into_string() {
+                Some(password) => Ok(Command::Auth {
+                    user: None,
+                    password,
+                }),
+                None => Err(Error::Command("Invalid AUTH arguments".into())),
+            },
+            2 => match (items.remove(0).into_string(), items.remove(0).into_string()) {
+                (Some(user), Some(password)) => Ok(Command::Auth {
+                    user: Some(user),
+                    password,
+                }),
+                _ => Err(Error::Command("Invalid AUTH arguments".into())),
+            },
+            _ => Err(Error::Command("Wrong number of AUTH arguments".into())),
+        }
+    }
+
+    fn hello(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "HELLO")?.into_iter();
+        let protover = args
+            .next()
+            .map(|protover| {
+                protover.parse::<i64>().map_err(|_| {
+                    Error::Command("Protocol version is not an integer or out of range".into())
+                })
+            })
+            .transpose()?;
+
+        let (mut auth, mut setname) = (None, None);
+        while let Some(option) = args.next() {
+            match option.to_uppercase().as_str() {
+                "AUTH" => match (args.next(), args.next()) {
+                    (Some(user), Some(password)) => auth = Some((user, password)),
+                    _ => {
+                        return Err(Error::Command(
+                            "HELLO AUTH requires a user and a password".into(),
+                        ))
+                    }
+                },
+                "SETNAME" => {
+                    match args.next() {
+                        Some(name) if name.chars().all(|c| c.is_ascii_graphic()) => {
+                            setname = Some(name)
+                        }
+                        Some(_) => return Err(Error::Command(
+                            "Client names cannot contain spaces, newlines or special characters."
+                                .into(),
+                        )),
+                        None => return Err(Error::Command("HELLO SETNAME requires a name".into())),
+                    }
+                }
+                _ => {
+                    return Err(Error::Command(format!(
+                        "Syntax error in HELLO option '{}'",
+                        option
+                    )))
+                }
+            }
+        }
+
+        Ok(Command::Hello {
+            protover,
+            auth,
+            setname,
+        })
+    }
+
+    fn acl(items: Vec<Resp>) -> Result<Command> {
+        let subcommand = match items.first().and_then(Resp::as_str) {
+            Some(sub) if items.len() == 1 => sub.to_uppercase(),
+            _ => return Err(Error::Command("ACL requires a single subcommand".into())),
+        };
+
+        match subcommand.as_str() {
+            "WHOAMI" => Ok(Command::AclWhoAmI),
+            "LIST" => Ok(Command::AclList),
+            "HELP" => Ok(Command::Help(&help::ACL)),
+            _ => Err(help::ACL.unknown(&subcommand)),
+        }
+    }
+
+    /// Parses `<script-or-sha> numkeys key... arg...` for EVAL and EVALSHA.
+    fn eval_args(items: Vec<Resp>, name: &str) -> Result<(String, Vec<String>, Vec<String>)> {
+        let args = Self::string_args(items, name)?;
+        if args.len() < 2 {
+            return Err(Error::Command(format!(
+                "Wrong number of {} arguments",
+                name
+            )));
+        }
+
+        let numkeys = args[1]
+            .parse::<usize>()
+            .map_err(|_| Error::Command("Invalid number of keys".into()))?;
+        if numkeys > args.len() - 2 {
+            return Err(Error::Command(
+                "Number of keys can't be greater than number of args".into(),
+            ));
+        }
+
+        let mut args = args.into_iter();
+        let body = args.next().unwrap_or_default();
+        args.next();
+        let keys = args.by_ref().take(numkeys).collect();
+        Ok((body, keys, args.collect()))
+    }
+
+    fn command_args(mut items: Vec<Resp>) -> Result<Command> {
+        if items.is_empty() {
+            return Ok(Command::DescribeCommands(Vec::new()));
+        }
+        let subcommand = match items.remove(0).as_str() {
+            Some(subcommand) => subcommand.to_uppercase(),
+            None => return Err(Error::Command("Invalid COMMAND arguments".into())),
+        };
+
+        match subcommand.as_str() {
+            "INFO" => Ok(Command::DescribeCommands(Self::string_args(
+                items, "COMMAND",
+            )?)),
+            "COUNT" if items.is_empty() => Ok(Command::CountCommands),
+            "COUNT" => Err(Error::Command("COMMAND COUNT takes no arguments".into())),
+            "GETKEYS" => {
+                let command = Command::from_resp(Resp::Array(items)).map_err(|_| {
+                    Error::Command("Invalid arguments specified for command".into())
+                })?;
+                Ok(Command::GetKeys(Box::new(command)))
+            }
+            "HELP" => Ok(Command::Help(&help::COMMAND)),
+            _ => Err(help::COMMAND.unknown(&subcommand)),
+        }
+    }
+
+    fn config(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "CONFIG")?.into_iter();
+        let subcommand = args
+            .next()
+            .ok_or_else(|| Error::Command("CONFIG requires a subcommand".into()))?
+            .to_uppercase();
+
+        match subcommand.as_str() {
+            "RESETSTAT" if args.next().is_none() => Ok(Command::ConfigResetStat),
+            "RESETSTAT" => Err(Error::Command("CONFIG RESETSTAT takes no arguments".into())),
+            "SET" => match (args.next(), args.next(), args.next()) {
+                (Some(parameter), Some(value), None) if parameter.eq_ignore_ascii_case("loglevel") => {
+                    value.parse().map(Command::ConfigSetLogLevel).map_err(|_| {
+                        Error::Command(format!(
+                            "Invalid argument '{}' for CONFIG SET 'loglevel', expected debug, verbose, notice or warning",
+                            value
+                        ))
+                    })
+                }
+                (Some(parameter), Some(_), None) => Err(Error::Command(format!(
+                    "Unsupported CONFIG parameter: {}",
+                    parameter
+                ))),
+                _ => Err(Error::Command(
+                    "CONFIG SET requires a parameter and a value".into(),
+                )),
+            },
+            "HELP" => Ok(Command::Help(&help::CONFIG)),
+            _ => Err(help::CONFIG.unknown(&subcommand)),
+        }
+    }
+
+    fn script(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "SCRIPT")?.into_iter();
+        let subcommand = args
+            .next()
+            .ok_or_else(|| Error::Command("SCRIPT requires a subcommand".into()))?
+            .to_uppercase();
+
+        match subcommand.as_str() {
+            "LOAD" => match (args.next(), args.next()) {
+                (Some(script), None) => Ok(Command::ScriptLoad(script)),
+                _ => Err(Error::Command(
+                    "SCRIPT LOAD requires exactly one argument".into(),
+                )),
+            },
+            "EXISTS" => {
+                let shas: Vec<String> = args.collect();
+                if shas.is_empty() {
+                    return Err(Error::Command(
+                        "SCRIPT EXISTS requires at least one SHA1".into(),
+                    ));
+                }
+                Ok(Command::ScriptExists(shas))
+            }
+            "FLUSH" => Ok(Command::ScriptFlush),
+            "HELP" => Ok(Command::Help(&help::SCRIPT)),
+            _ => Err(help::SCRIPT.unknown(&subcommand)),
+        }
+    }
+
+    /// Whether the command may run while the connection is in subscribe
+    /// mode.
+    fn allowed_when_subscribed(&self) -> bool {
+        matches!(
+            self,
+            Command::Subscribe(_)
+                | Command::Unsubscribe(_)
+                | Command::PSubscribe(_)
+                | Command::PUnsubscribe(_)
+                | Command::SSubscribe(_)
+                | Command::SUnsubscribe(_)
+                | Command::Ping
+                | Command::Reset
+                | Command::Quit
+        )
+    }
+
+    fn subscription(command: &str, items: Vec<Resp>) -> Result<Command> {
+        let names = Self::string_args(items, command)?;
+        match command {
+            "SUBSCRIBE" | "PSUBSCRIBE" | "SSUBSCRIBE" if names.is_empty() => Err(Error::Command(
+                format!("{} requires at least one channel", command),
+            )),
+            "SUBSCRIBE" => Ok(Command::Subscribe(names)),
+            "PSUBSCRIBE" => Ok(Command::PSubscribe(names)),
+            "SSUBSCRIBE" => Ok(Command::SSubscribe(names)),
+            "UNSUBSCRIBE" => Ok(Command::Unsubscribe(names)),
+            "SUNSUBSCRIBE" => Ok(Command::SUnsubscribe(names)),
+            _ => Ok(Command::PUnsubscribe(names)),
+        }
+    }
+
+    fn pubsub(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "PUBSUB")?.into_iter();
+        let subcommand = args
+            .next()
+            .ok_or_else(|| Error::Command("PUBSUB requires a subcommand".into()))?
+            .to_uppercase();
+
+        match subcommand.as_str() {
+            "CHANNELS" => match (args.next(), args.next()) {
+                (pattern, None) => Ok(Command::PubSubChannels(pattern)),
+                _ => Err(Error::Command(
+                    "PUBSUB CHANNELS takes at most one pattern".into(),
+                )),
+            },
+            "NUMSUB" => Ok(Command::PubSubNumSub(args.collect())),
+            "NUMPAT" if args.next().is_none() => Ok(Command::PubSubNumPat),
+            "NUMPAT" => Err(Error::Command("PUBSUB NUMPAT takes no arguments".into())),
+            "HELP" => Ok(Command::Help(&help::PUBSUB)),
+            _ => Err(help::PUBSUB.unknown(&subcommand)),
+        }
+    }
+
+    fn changefeed(items: Vec<Resp>) -> Result<Command> {
+        let args = Self::string_args(items, "CHANGEFEED")?;
+        match args.as_slice() {
+            [] => Ok(Command::ChangeFeed { snapshot: false }),
+            [option] if option.eq_ignore_ascii_case("SNAPSHOT") => {
+                Ok(Command::ChangeFeed { snapshot: true })
+            }
+            _ => Err(Error::Command("CHANGEFEED takes only SNAPSHOT".into())),
+        }
+    }
+
+    fn info(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "INFO")?.into_iter();
+        match (args.next(), args.next()) {
+            (section, None) => Ok(Command::Info(section)),
+            _ => Err(Error::Command("INFO takes at most one section".into())),
+        }
+    }
+
+    fn debug(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "DEBUG")?.into_iter();
+        let subcommand = args
+            .next()
+            .ok_or_else(|| Error::Command("DEBUG requires a subcommand".into()))?
+            .to_uppercase();
+
+        match subcommand.as_str() {
+            "SET-ACTIVE-EXPIRE" => match (args.next().as_deref(), args.next()) {
+                (Some("0"), None) => Ok(Command::DebugSetActiveExpire(false)),
+                (Some("1"), None) => Ok(Command::DebugSetActiveExpire(true)),
+                _ => Err(Error::Command(
+                    "DEBUG SET-ACTIVE-EXPIRE requires 0 or 1".into(),
+                )),
+            },
+            "SET-MEMORY-USAGE" => match (args.next(), args.next()) {
+                (Some(bytes), None) => bytes
+                    .parse::<usize>()
+                    .map(Command::DebugSetMemoryUsage)
+                    .map_err(|_| {
+                        Error::Command("DEBUG SET-MEMORY-USAGE requires a number of bytes".into())
+                    }),
+                _ => Err(Error::Command(
+                    "DEBUG SET-MEMORY-USAGE requires a number of bytes".into(),
+                )),
+            },
+            "RELOAD" if args.next().is_none() => Ok(Command::DebugReload),
+            "RELOAD" => Err(Error::Command("DEBUG RELOAD takes no arguments".into())),
+            "CHANGE-REPL-ID" if args.next().is_none() => Ok(Command::DebugChangeReplId),
+            "CHANGE-REPL-ID" => Err(Error::Command(
+                "DEBUG CHANGE-REPL-ID takes no arguments".into(),
+            )),
+            "OBJECT" => match (args.next(), args.next()) {
+                (Some(key), None) => Ok(Command::DebugObject(key)),
+                _ => Err(Error::Command("DEBUG OBJECT requires a key".into())),
+            },
+            "STRINGMATCH-LEN" => match (args.next(), args.next(), args.next()) {
+                (Some(pattern), Some(string), None) => {
+                    Ok(Command::DebugStringMatch { pattern, string })
+                }
+                _ => Err(Error::Command(
+                    "DEBUG STRINGMATCH-LEN requires a pattern and a string".into(),
+                )),
+            },
+            "SLEEP" => match (args.next(), args.next()) {
+                (Some(seconds), None) => seconds
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                    .map(Command::DebugSleep)
+                    .ok_or_else(|| {
+                        Error::Command("DEBUG SLEEP requires a number of seconds".into())
+                    }),
+                _ => Err(Error::Command(
+                    "DEBUG SLEEP requires a number of seconds".into(),
+                )),
+            },
+            "HELP" => Ok(Command::Help(&help::DEBUG)),
+            _ => Err(help::DEBUG.unknown(&subcommand)),
+        }
+    }
+
+    fn latency(items: Vec<Resp>) -> Result<Command> {
+        let mut args = Self::string_args(items, "LATENCY")?.into_iter();
+        let subcommand = args
+            .next()
+            .ok_or_else(|| Error::Command("LATENCY requires a subcommand".into()))?
+            .to_uppercase();
+
+        match subcommand.as_str() {
+            "LATEST" if args.next().is_none() => Ok(Command::LatencyLatest),
+            "LATEST" => Err(Error::Command("LATENCY LATEST takes no arguments".into())),
+            "HISTORY" => match (args.next(), args.next()) {
+                (Some(event), None) => Ok(Command::LatencyHistory(event)),
+                _ => Err(Error::Command(
+                    "LATENCY HISTORY requires an event name".into(),
+                )),
+            },
+            "RESET" => Ok(Command::LatencyReset(args.collect())),
+            "HELP" => Ok(Command::Help(&help::LATENCY)),
+            _ => Err(help::LATENCY.unknown(&subcommand)),
+        }
+    }
+
+    /// Unwraps every argument as a bulk string.
+    fn string_args(items: Vec<Resp>, name: &str) -> Result<Vec<String>> {
+        items
+            .into_iter()
+            .map(|item| {
+                item.into_string()
+                    .ok_or_else(|| Error::Command(format!("Invalid {} arguments", name)))
+            })
+            .collect()
+    }
+
+    fn echo(mut items: Vec<Resp>) -> Result<Command> {
+        if items.len() != 1 {
+            return Err(Error::Command("ECHO requires exactly one argument".into()));
+        }
+        if let Some(message) = items.remove(0).into_string() {
+            Ok(Command::Echo(message))
+        } else {
+            Err(Error::Command("Invalid ECHO argument".into()))
+        }
+    }
+
+    fn set(mut items: Vec<Resp>) -> Result<Self> {
+        if let (Some(key), Some(value)) =
+            (items.remove(0).into_string(), items.remove(0).into_bulk())
+        {
+            Ok(Self::Set {
+                key,
+                value,
+                expiry: None,
+            })
+        } else {
+            Err(Error::Command("Invalid SET arguments".into()))
+        }
+    }
+
+    fn set_with_expiry(mut items: Vec<Resp>) -> Result<Self> {
+        let (key, value, opt, px) = match (
+            items.remove(0).into_string(),
+            items.remove(0).into_bulk(),
+            items.remove(0).into_string(),
+            items.remove(0).into_string(),
+        ) {
+            (Some(k), Some(v), Some(o), Some(p)) => (k, v, o, p),
+            _ => return Err(Error::Command("Invalid SET arguments".into())),
+        };
+
+        let to_duration: fn(u64) -> Duration = match opt.to_uppercase().as_str() {
+            "EX" => Duration::from_secs,
+            "PX" => Duration::from_millis,
+            _ => return Err(Error::Command("Invalid SET option".into())),
+        };
+
+        let amount = px
+            .parse::<i64>()
+            .map_err(|_| Error::Command(format!("Invalid {} value", opt.to_uppercase())))?;
+        if amount <= 0 {
+            return Err(Error::Command(
+                "invalid expire time in 'set' command".into(),
+            ));
+        }
+
+        Ok(Self::Set {
+            key,
+            value,
+            expiry: Some(to_duration(amount as u64)),
+        })
+    }
+}
+
+impl From<TypeError> for Resp {
+    fn from(e: TypeError) -> Self {
+        Resp::Error(e.to_string())
+    }
+}
+
+/// Reads the string at `key`, or returns the WRONGTYPE reply if the key
+/// holds another type.
+fn read_string(storage: &Storage, key: &str) -> std::result::Result<Option<Vec<u8>>, Resp> {
+    storage
+        .read(key, |value| value.as_string().map(Cow::into_owned))
+        .transpose()
+        .map_err(Resp::from)
+}
+
+/// Adds `delta` to the integer stored at `key`, starting from 0 if the key
+/// doesn't exist, and stores the result in the integer encoding, keeping the
+/// key's TTL.
+async fn increment(storage: &Storage, key: &str, delta: i64) -> Resp {
+    // The integer encoding is read as is, skipping the round trip through a
+    // string.
+    let current = match storage.read(key, |value| match value {
+        Value::Int(i) => Ok(Some(*i)),
+        value => value.as_string().map(|s| persistence::parse_int(&s)),
+    }) {
+        None => 0,
+        Some(Ok(Some(current))) => current,
+        Some(Ok(None)) => return Resp::Error("ERR value is not an integer or out of range".into()),
+        Some(Err(e)) => return e.into(),
+    };
+
+    let Some(value) = current.checked_add(delta) else {
+        return Resp::Error("ERR increment or decrement would overflow".into());
+    };
+    match storage.overwrite(key.to_string(), Value::Int(value)).await {
+        Ok(()) => Resp::Integer(value),
+        Err(e) => storage_error("set value", e),
+    }
+}
+
+/// Moves an element between the lists at `source` and `destination`, which
+/// may be the same key, replying with the element or null when `source` is
+/// empty. Both keys are checked before anything moves, so a WRONGTYPE
+/// destination leaves the source untouched.
+async fn lmove(storage: &Storage, source: &str, destination: &str, from: End, to: End) -> Resp {
+    match storage.lookup(source, |entry| entry.value.as_list().map(drop)) {
+        Some(Ok(())) => {}
+        Some(Err(e)) => return e.into(),
+        None => return Resp::Null,
+    }
+    if let Some(Err(e)) = storage.lookup(destination, |entry| entry.value.as_list().map(drop)) {
+        return e.into();
+    }
+    match storage
+        .lmove(source.to_string(), destination.to_string(), from, to)
+        .await
+    {
+        Ok(Some(element)) => Resp::BulkString(element),
+        Ok(None) => Resp::Null,
+        Err(e) => storage_error("move element", e),
+    }
+}
+
+/// Counts the members common to the sets at `keys`, stopping at `limit`
+/// unless it is 0. Only the smallest set is copied; the others are probed
+/// for its members from the smallest up, so the candidates shrink as early
+/// as possible. A missing key is an empty set, making the count 0, but every
+/// key is still checked for WRONGTYPE first, as in Redis.
+fn sintercard(storage: &Storage, keys: &[String], limit: usize) -> Resp {
+    let mut sizes = Vec::with_capacity(keys.len());
+    for key in keys {
+        match storage.read(key, |value| value.as_set().map(Set::len)) {
+            Some(Ok(len)) => sizes.push((len, key)),
+            Some(Err(e)) => return e.into(),
+            None => sizes.push((0, key)),
+        }
+    }
+    sizes.sort_unstable();
+    let limit = if limit == 0 { usize::MAX } else { limit };
+
+    // The sets are read one at a time, since holding one while reading
+    // another could deadlock on the map's shards.
+    let (_, smallest) = sizes[0];
+    let mut candidates: Vec<String> = storage
+        .lookup(smallest, |entry| match &entry.value {
+            Value::Set(set) => set.iter().map(Cow::into_owned).collect(),
+            _ => Vec::new(),
+        })
+        .unwrap_or_default();
+    let Some(((_, last), others)) = sizes[1..].split_last() else {
+        return Resp::Integer(candidates.len().min(limit) as i64);
+    };
+    for (_, key) in others {
+        let probed = storage.lookup(key, |entry| match &entry.value {
+            Value::Set(set) => candidates.retain(|member| set.contains(member)),
+            _ => candidates.clear(),
+        });
+        if probed.is_none() {
+            candidates.clear();
+        }
+    }
+    let count = storage
+        .lookup(last, |entry| match &entry.value {
+            Value::Set(set) => candidates
+                .iter()
+                .filter(|member| set.contains(member))
+                .take(limit)
+                .count(),
+            _ => 0,
+        })
+        .unwrap_or(0);
+    Resp::Integer(count as i64)
+}
+
+/// Combines the sets at `keys` as SINTER, SUNION or SDIFF do, or returns the
+/// WRONGTYPE reply if any key holds another type.
+fn combine_sets(
+    storage: &Storage,
+    combine: Combine,
+    keys: &[String],
+) -> std::result::Result<Set, Resp> {
+    let mut sets = Vec::with_capacity(keys.len());
+    for key in keys {
+        match storage.read(key, |value| value.as_set().cloned()) {
+            Some(Ok(set)) => sets.push(Some(set)),
+            Some(Err(e)) => return Err(e.into()),
+            None => sets.push(None),
+        }
+    }
+    Ok(set::combine(
+        combine,
+        &sets,
+        storage.config().set_max_intset_entries,
+    ))
+}
+
+/// Removes the members `select` picks from the sorted set at `key`, as ZREM
+/// and the ZREMRANGE commands do, replying with how many were removed. The
+/// members are picked and journaled while the script lock is held
+/// exclusively, so a replayed removal takes out the same ones.
+async fn zremrange(
+    storage: &Storage,
+    key: &str,
+    select: impl FnOnce(&SortedSet) -> Vec<String>,
+) -> Resp {
+    let selected = storage.lookup(key, |entry| {
+        entry
+            .as_sorted_set(storage.now())
+            .map(|set| set.map(select))
+    });
+    let members = match selected {
+        Some(Ok(Some(members))) if !members.is_empty() => members,
+        Some(Err(e)) => return e.into(),
+        _ => return Resp::Integer(0),
+    };
+    match storage.zrem(key.to_string(), members).await {
+        Ok(removed) => Resp::Integer(removed as i64),
+        Err(e) => storage_error("remove members", e),
+    }
+}
+
+/// The reply listing sorted set members, each followed by its score when
+/// `with_scores` is set, as ZRANGE and ZRANGEBYSCORE reply.
+fn scored_members<'a>(
+    members: impl Iterator<Item = (&'a str, f64)>,
+    with_scores: bool,
+) -> Vec<Resp> {
+    let mut reply = Vec::new();
+    for (member, score) in members {
+        reply.push(Resp::BulkString(member.to_string()));
+        if with_scores {
+            reply.push(Resp::BulkString(zset::format_score(score)));
+        }
+    }
+    reply
+}
+
+/// Reads the HyperLogLog stored at `key`, or returns the WRONGTYPE reply if
+/// the key holds anything else.
+fn read_hyperloglog(
+    storage: &Storage,
+    key: &str,
+) -> std::result::Result<Option<HyperLogLog>, Resp> {
+    let wrongtype = || Resp::Error("WRONGTYPE Key is not a valid HyperLogLog string value.".into());
+    match read_string(storage, key) {
+        Ok(Some(value)) => HyperLogLog::from_value(&value)
+            .map(Some)
+            .ok_or_else(wrongtype),
+        Ok(None) => Ok(None),
+        Err(_) => Err(wrongtype()),
+    }
+}
+
+/// Builds a SCAN-family reply: the next cursor and the page of elements.
+fn scan_reply(cursor: u64, elements: Vec<Resp>) -> Resp {
+    Resp::Array(vec![
+        Resp::BulkString(cursor.to_string()),
+        Resp::Array(elements),
+    ])
+}
+
+/// Picks random field/value pairs for HRANDFIELD: one pair without a count,
+/// up to `count` distinct pairs for a positive count (sampled with reservoir
+/// sampling, so the hash is never copied), and exactly `-count` pairs that
+/// may repeat for a negative one.
+fn random_fields(hash: &HashMap<String, String>, count: Option<i64>) -> Vec<(String, String)> {
+    let count = count.unwrap_or(1);
+    if count >= 0 {
+        fastrand::choose_multiple(hash.iter(), count as usize)
+            .into_iter()
+            .map(|(field, value)| (field.clone(), value.clone()))
+            .collect()
+    } else {
+        let pairs: Vec<_> = hash.iter().collect();
+        if pairs.is_empty() {
+            return Vec::new();
+        }
+        (0..count.unsigned_abs())
+            .map(|_| {
+                let (field, value) = pairs[fastrand::usize(..pairs.len())];
+                (field.clone(), value.clone())
+            })
+            .collect()
+    }
+}
+
+/// The DEBUG OBJECT line for an entry, in Redis's format less the object's
+/// address, which has no stable equivalent here. `serializedlength` is the
+/// size of the value as stored in RDB snapshots, and `lru` is the last
+/// access as a 24-bit clock in seconds, as in Redis. Lists are a single
+/// node here, so there is no `ql_nodes`.
+fn debug_object(entry: &ValueEntry, storage: &Storage) -> String {
+    let serialized_len = bincode::serialized_size(&entry.value).unwrap_or_default();
+    let lru = entry
+        .last_access
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        & ((1 << 24) - 1);
+    format!(
+        "Value refcount:1 encoding:{} serializedlength:{} lru:{} lru_seconds_idle:{}",
+        entry.encoding(storage.config().list_max_listpack_size),
+        serialized_len,
+        lru,
+        entry.idle_time(storage.now()).as_secs()
+    )
+}
+
+/// The error for a command that doesn't exist, quoting its arguments the way
+/// Redis does: each cut to what is left of a 128-character budget, until the
+/// budget runs out.
+fn unknown_command(name: &str, args: &[Resp]) -> Error {
+    const LIMIT: usize = 128;
+    let mut quoted = String::new();
+    let mut len = 0;
+    for arg in args {
+        if len >= LIMIT {
+            break;
+        }
+        let arg = match arg {
+            Resp::SimpleString(arg) => arg.as_str(),
+            arg => arg.as_str().unwrap_or_default(),
+        };
+        let arg: String = arg.chars().take(LIMIT - len).collect();
+        len += arg.chars().count() + 3;
+        quoted.push_str(&format!("'{}' ", arg));
+    }
+    Error::UnknownCommand {
+        name: name.chars().take(LIMIT).collect(),
+        args: quoted,
+    }
+}
+
+/// Logs a storage failure and turns it into an error reply that tells the
+/// client what went wrong and whether retrying may help. Only the error
+/// itself is sent; OS errors never carry the path of the file involved.
+fn storage_error(action: &str, e: io::Error) -> Resp {
+    error!("Failed to {}: {}", action, e);
+    let class = match e.kind() {
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+            "temporary I/O error, try again"
+        }
+        io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput => "invalid data",
+        _ => "I/O error",
+    };
+    Resp::Error(format!("ERR failed to {} ({}): {}", action, class, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Command {
+        let items = args
+            .iter()
+            .map(|arg| Resp::BulkString(arg.to_string()))
+            .collect();
+        Command::from_resp(Resp::Array(items)).unwrap()
+    }
+
+    #[test]
+    fn test_is_write() {
+        assert!(parse(&["SET", "key", "value"]).is_write());
+        assert!(parse(&["set", "key", "value", "PX", "100"]).is_write());
+        assert!(parse(&["RESTORE", "key", "0", "00"]).is_write());
+        assert!(parse(&["COPY", "a", "b", "DB", "1", "REPLACE"]).is_write());
+
+        assert!(!parse(&["GET", "key"]).is_write());
+        assert!(parse(&["GETSET", "key", "value"]).is_write());
+        assert!(parse(&["MSETNX", "a", "1", "b", "2"]).is_write());
+        assert!(parse(&["XADD", "s", "*", "f", "v"]).is_write());
+        assert!(!parse(&["XRANGE", "s", "-", "+"]).is_write());
+        assert!(parse(&["LPUSH", "l", "a"]).is_write());
+        assert!(parse(&["RPOP", "l", "2"]).is_write());
+        assert!(!parse(&["LRANGE", "l", "0", "-1"]).is_write());
+        assert!(!parse(&["LPOS", "l", "a", "RANK", "-1"]).is_write());
+        assert!(parse(&["RPOPLPUSH", "a", "b"]).is_write());
+        assert!(parse(&["LMOVE", "a", "b", "LEFT", "RIGHT"]).is_write());
+        assert!(parse(&["SADD", "s", "a"]).is_write());
+        assert!(parse(&["SREM", "s", "a"]).is_write());
+        assert!(!parse(&["SINTERCARD", "2", "a", "b", "LIMIT", "1"]).is_write());
+        assert!(!parse(&["SUNION", "a", "b"]).is_write());
+        assert!(parse(&["SDIFFSTORE", "dest", "a", "b"]).is_write());
+        assert!(parse(&["BITOP", "AND", "dest", "a", "b"]).is_write());
+        assert!(parse(&["ZADD", "z", "1", "a"]).is_write());
+        assert!(!parse(&["ZRANGEBYSCORE", "z", "-inf", "+inf"]).is_write());
+        assert!(!parse(&["ZCOUNT", "z", "(1", "2"]).is_write());
+        assert!(parse(&["ZREM", "z", "a"]).is_write());
+        assert!(parse(&["ZREMRANGEBYSCORE", "z", "(1", "+inf"]).is_write());
+        assert!(parse(&["ZREMRANGEBYRANK", "z", "0", "-2"]).is_write());
+        assert!(parse(&["LMPOP", "2", "a", "b", "LEFT"]).is_write());
+        assert!(parse(&["ZMPOP", "1", "z", "MAX", "COUNT", "2"]).is_write());
+        assert!(!parse(&["PING"]).is_write());
+        assert!(!parse(&["DUMP", "key"]).is_write());
+        assert!(!parse(&["MEMORY", "USAGE", "key"]).is_write());
+        assert!(!parse(&["WAIT", "0", "0"]).is_write());
+        assert!(!parse(&["INFO"]).is_write());
+        assert!(!parse(&["INFO", "memory"]).is_write());
+        assert!(!parse(&["SELECT", "1"]).is_write());
+        assert!(parse(&["DEL", "key"]).is_write());
+        assert!(parse(&["DEL", "a", "b"]).is_write());
+    }
+
+    #[test]
+    fn test_keys() {
+        assert_eq!(parse(&["SET", "key", "value"]).keys(), vec!["key"]);
+        assert_eq!(
+            parse(&["MSETNX", "a", "1", "b", "2"]).keys(),
+            vec!["a", "b"]
+        );
+        assert_eq!(parse(&["DEL", "a", "b"]).keys(), vec!["a", "b"]);
+        assert_eq!(
+            parse(&["EVAL", "return 1", "2", "k1", "k2", "arg"]).keys(),
+            vec!["k1", "k2"]
+        );
+        assert!(parse(&["PING"]).keys().is_empty());
+        // COMMAND GETKEYS only inspects its argument's keys.
+        assert!(parse(&["COMMAND", "GETKEYS", "GET", "key"])
+            .keys()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_command_table_matches_parsers() {
+        let calls: &[&[&str]] = &[
+            &["SET", "key", "value", "EX", "10"],
+            &["GET", "key"],
+            &["GETSET", "key", "value"],
+            &["MSETNX", "a", "1", "b", "2"],
+            &["DEL", "a", "b"],
+            &["INCR", "key"],
+            &["LCS", "a", "b"],
+            &["HSET", "h", "f", "v"],
+            &["HMGET", "h", "f"],
+            &["PFCOUNT", "a", "b"],
+            &["PFMERGE", "dest", "a", "b"],
+            &["XADD", "s", "*", "f", "v"],
+            &["XRANGE", "s", "-", "+"],
+            &["LPUSH", "l", "a", "b"],
+            &["RPUSH", "l", "a"],
+            &["LPOP", "l"],
+            &["RPOP", "l", "2"],
+            &["LLEN", "l"],
+            &["LRANGE", "l", "0", "-1"],
+            &["LREM", "l", "0", "a"],
+            &["LTRIM", "l", "0", "99"],
+            &["LINSERT", "l", "BEFORE", "a", "b"],
+            &["RPOPLPUSH", "a", "b"],
+            &["LMOVE", "a", "b", "RIGHT", "LEFT"],
+            &["LPOS", "l", "a", "COUNT", "0"],
+            &["SADD", "s", "a", "b"],
+            &["SREM", "s", "a"],
+            &["SMEMBERS", "s"],
+            &["SISMEMBER", "s", "a"],
+            &["SCARD", "s"],
+            &["SINTER", "a", "b"],
+            &["SUNION", "a"],
+            &["SDIFF", "a", "b", "c"],
+            &["SINTERSTORE", "dest", "a", "b"],
+            &["SUNIONSTORE", "dest", "a"],
+            &["SDIFFSTORE", "dest", "a", "b"],
+            &["BITOP", "XOR", "dest", "a", "b"],
+            &["ZADD", "z", "1", "a", "2", "b"],
+            &["ZSCORE", "z", "a"],
+            &["ZRANGE", "z", "0", "-1", "WITHSCORES"],
+            &["ZRANGEBYSCORE", "z", "-inf", "+inf", "LIMIT", "0", "1"],
+            &["ZCARD", "z"],
+            &["ZCOUNT", "z", "1", "2"],
+            &["ZREM", "z", "a", "b"],
+            &["ZREMRANGEBYSCORE", "z", "-inf", "(2"],
+            &["ZREMRANGEBYRANK", "z", "0", "-1"],
+            &["HSCAN", "h", "0"],
+            &["RESTORE", "key", "0", "00"],
+            &["COPY", "a", "b", "DB", "1"],
+            &["MIGRATE", "host", "6379", "key", "0", "1000"],
+            &["SCAN", "0"],
+            &["PUBLISH", "channel", "message"],
+            &["SPUBLISH", "channel", "message"],
+            &["WAIT", "0", "0"],
+            &["SELECT", "1"],
+        ];
+        for call in calls {
+            let command = parse(call);
+            let spec = command_table::lookup(command.name()).unwrap();
+            assert!(spec.accepts(call.len()), "{:?}", call);
+            assert_eq!(
+                spec.flags.contains(&"write"),
+                command.is_write(),
+                "{:?}",
+                call
+            );
+
+            // Resolve the key positions the way a client would.
+            let last = if spec.last_key < 0 {
+                call.len() as i32 + spec.last_key
+            } else {
+                spec.last_key
+            };
+            let keys: Vec<&str> = match spec.step {
+                0 => Vec::new(),
+                step => (spec.first_key..=last)
+                    .step_by(step as usize)
+                    .map(|i| call[i as usize])
+                    .collect(),
+            };
+            assert_eq!(keys, command.keys(), "{:?}", call);
+        }
+    }
+
+    #[test]
+    fn test_arity_is_checked_before_parsing() {
+        let items = ["GET", "a", "b"]
+            .iter()
+            .map(|arg| Resp::BulkString(arg.to_string()))
+            .collect();
+        assert_eq!(
+            Command::from_resp(Resp::Array(items))
+                .unwrap_err()
+                .to_string(),
+            "Command error: Wrong number of arguments for 'get' command"
+        );
+    }
+
+    #[test]
+    fn test_unknown_command() {
+        let error = |args: &[&str]| {
+            let items = args
+                .iter()
+                .map(|arg| Resp::BulkString(arg.to_string()))
+                .collect();
+            Command::from_resp(Resp::Array(items))
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error(&["foo", "bar", "baz"]),
+            "ERR unknown command 'foo', with args beginning with: 'bar' 'baz' "
+        );
+        assert_eq!(
+            error(&["FOO"]),
+            "ERR unknown command 'FOO', with args beginning with: "
+        );
+
+        // Arguments are quoted until 128 characters have been used up.
+        let long = "x".repeat(200);
+        let reply = error(&["foo", "bar", &long, "baz"]);
+        let args = reply.split_once("with: ").unwrap().1;
+        assert_eq!(args, format!("'bar' '{}' ", "x".repeat(122)));
+    }
+
+    #[test]
+    fn test_name() {
+        assert_eq!(parse(&["set", "key", "value"]).name(), "SET");
+        assert_eq!(parse(&["get", "key"]).name(), "GET");
+        assert_eq!(parse(&["Ping"]).name(), "PING");
+        assert_eq!(parse(&["slaveof", "no", "one"]).name(), "REPLICAOF");
+        assert_eq!(parse(&["debug", "set-active-expire", "0"]).name(), "DEBUG");
     }
 }
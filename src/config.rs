@@ -0,0 +1,365 @@
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
+    time::Duration,
+};
+
+use crate::{
+    acl::User,
+    list::ListpackLimit,
+    logging::LogLevel,
+    persistence::{aof::AppendFsync, rdb::Compression, MaxMemoryPolicy, Persistence},
+    pubsub::KeyspaceEvents,
+    Error, Result,
+};
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub port: u16,
+    /// The addresses to listen on, each with its own listener.
+    pub bind: Vec<IpAddr>,
+    pub dir: PathBuf,
+    /// How many databases SELECT switches between, numbered from 0.
+    pub databases: usize,
+    pub replicaof: Option<(String, u16)>,
+    pub read_only: bool,
+    pub requirepass: Option<String>,
+    pub users: Vec<User>,
+    /// Whether the dataset is persisted continuously or only on SAVE.
+    pub persistence: Persistence,
+    /// When AOF writes are fsynced.
+    pub append_fsync: AppendFsync,
+    /// The codec RDB snapshots are written with.
+    pub rdb_compression: Compression,
+    /// Number of live connections at which new ones are turned away with a
+    /// BUSY error while existing ones keep being served.
+    pub busy_threshold: Option<usize>,
+    /// Commands each connection may run per second before further ones are
+    /// rejected, or `None` for no limit.
+    pub max_commands_per_second: Option<u32>,
+    /// Commands taking at least this long are recorded as latency events
+    /// for LATENCY, or `None` to record none.
+    pub latency_monitor_threshold: Option<Duration>,
+    /// How long a drain waits for connected clients to disconnect before
+    /// the server exits anyway.
+    pub drain_timeout: Duration,
+    /// How many times per second the active expiration cycle runs.
+    pub hz: u32,
+    /// How many keys with an expiry each expiration round samples.
+    pub expire_samples: usize,
+    /// Bytes of published messages that may wait to be written to a
+    /// subscriber before it is disconnected, or `None` for no limit.
+    pub pubsub_output_buffer_limit: Option<usize>,
+    /// Keyspace notifications published on expiry; none by default.
+    pub notify_keyspace_events: KeyspaceEvents,
+    /// Snapshot the dataset once at least the given number of writes
+    /// happened and the given time passed since the last save, for any of
+    /// the pairs. Empty when only SAVE and BGSAVE write snapshots.
+    pub save_points: Vec<(Duration, u64)>,
+    /// Idle time after which TCP keepalive probes are sent on client
+    /// connections, or `None` to leave keepalive off.
+    pub tcp_keepalive: Option<Duration>,
+    /// Whether TCP_NODELAY is set on client connections, so small replies
+    /// aren't held back by Nagle's algorithm.
+    pub tcp_nodelay: bool,
+    /// Bytes of `used_memory` past which `maxmemory_policy` applies, or
+    /// `None` for no limit.
+    pub maxmemory: Option<usize>,
+    pub maxmemory_policy: MaxMemoryPolicy,
+    /// Keys sampled for each eviction, of which the least frequently used
+    /// goes.
+    pub maxmemory_samples: usize,
+    /// How many accesses it takes to saturate a key's LFU counter; higher
+    /// values tell apart keys read more often.
+    pub lfu_log_factor: u32,
+    /// Minutes of idleness that take one off a key's LFU counter, or 0 for
+    /// counters that never decay.
+    pub lfu_decay_time: u64,
+    /// How large a list may grow before OBJECT ENCODING reports it as a
+    /// quicklist rather than a listpack.
+    pub list_max_listpack_size: ListpackLimit,
+    /// Most members a set of integers may have while it is kept as a sorted
+    /// array rather than a hash table.
+    pub set_max_intset_entries: usize,
+    /// Port serving the HTTP health endpoints on the `bind` addresses, or
+    /// `None` to serve none.
+    pub http_port: Option<u16>,
+    /// Whether AOF compaction writes the dataset as an RDB snapshot at the
+    /// start of the file, which loads faster than one SET per key.
+    pub aof_use_rdb_preamble: bool,
+    /// Verbosity of the log at startup; CONFIG SET loglevel changes it
+    /// afterwards.
+    pub loglevel: LogLevel,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            port: 6379,
+            bind: vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            dir: PathBuf::from("data"),
+            databases: 16,
+            replicaof: None,
+            read_only: false,
+            requirepass: None,
+            users: Vec::new(),
+            persistence: Persistence::Full,
+            append_fsync: AppendFsync::EverySec,
+            rdb_compression: Compression::None,
+            busy_threshold: None,
+            max_commands_per_second: None,
+            latency_monitor_threshold: None,
+            drain_timeout: Duration::from_secs(30),
+            hz: 10,
+            expire_samples: 20,
+            pubsub_output_buffer_limit: Some(32 * 1024 * 1024),
+            notify_keyspace_events: KeyspaceEvents::default(),
+            save_points: vec![
+                (Duration::from_secs(3600), 1),
+                (Duration::from_secs(300), 100),
+                (Duration::from_secs(60), 10000),
+            ],
+            tcp_keepalive: Some(Duration::from_secs(300)),
+            tcp_nodelay: true,
+            maxmemory: None,
+            maxmemory_policy: MaxMemoryPolicy::NoEviction,
+            maxmemory_samples: 5,
+            lfu_log_factor: 10,
+            lfu_decay_time: 1,
+            list_max_listpack_size: ListpackLimit::default(),
+            set_max_intset_entries: 512,
+            http_port: None,
+            aof_use_rdb_preamble: true,
+            loglevel: LogLevel::Notice,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut config = Self::default();
+        let mut args = args.into_iter();
+        let mut bind = Vec::new();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--port" => config.port = parse_port(next_value(&mut args, &arg)?)?,
+                "--bind" => {
+                    let value = next_value(&mut args, &arg)?;
+                    let addr = value
+                        .parse()
+                        .map_err(|_| Error::Config(format!("Invalid bind address: {}", value)))?;
+                    bind.push(addr);
+                }
+                "--dir" => config.dir = PathBuf::from(next_value(&mut args, &arg)?),
+                "--databases" => {
+                    config.databases = parse_positive(next_value(&mut args, &arg)?, &arg)?;
+                }
+                "--replicaof" => {
+                    let host = next_value(&mut args, &arg)?;
+                    let port = parse_port(next_value(&mut args, &arg)?)?;
+                    config.replicaof = Some((host, port));
+                }
+                "--read-only" => config.read_only = true,
+                "--requirepass" => config.requirepass = Some(next_value(&mut args, &arg)?),
+                "--user" => {
+                    let user = User::parse(&next_value(&mut args, &arg)?).map_err(Error::Config)?;
+                    config.users.push(user);
+                }
+                "--persistence" => {
+                    let value = next_value(&mut args, &arg)?;
+                    config.persistence = value.parse().map_err(|_| {
+                        Error::Config(format!("Invalid value for --persistence: {}", value))
+                    })?;
+                }
+                "--appendfsync" => {
+                    let value = next_value(&mut args, &arg)?;
+                    config.append_fsync = value.parse().map_err(|_| {
+                        Error::Config(format!("Invalid value for --appendfsync: {}", value))
+                    })?;
+                }
+                "--rdbcompression" => {
+                    let value = next_value(&mut args, &arg)?;
+                    config.rdb_compression = value.parse().map_err(|_| {
+                        Error::Config(format!("Invalid value for --rdbcompression: {}", value))
+                    })?;
+                }
+                "--busy-threshold" => {
+                    config.busy_threshold =
+                        Some(parse_positive(next_value(&mut args, &arg)?, &arg)?);
+                }
+                "--max-commands-per-second" => {
+                    let value = next_value(&mut args, &arg)?;
+                    let limit = value.parse::<u32>().map_err(|_| {
+                        Error::Config(format!("Invalid value for {}: {}", arg, value))
+                    })?;
+                    config.max_commands_per_second = (limit > 0).then_some(limit);
+                }
+                "--latency-monitor-threshold" => {
+                    let value = next_value(&mut args, &arg)?;
+                    let millis = value.parse::<u64>().map_err(|_| {
+                        Error::Config(format!("Invalid value for {}: {}", arg, value))
+                    })?;
+                    config.latency_monitor_threshold =
+                        (millis > 0).then(|| Duration::from_millis(millis));
+                }
+                "--drain-timeout" => {
+                    let secs = parse_positive(next_value(&mut args, &arg)?, &arg)?;
+                    config.drain_timeout = Duration::from_secs(secs);
+                }
+                "--hz" => {
+                    config.hz = parse_positive(next_value(&mut args, &arg)?, &arg)?;
+                    if config.hz > 500 {
+                        return Err(Error::Config("--hz must be at most 500".into()));
+                    }
+                }
+                "--expire-samples" => {
+                    config.expire_samples = parse_positive(next_value(&mut args, &arg)?, &arg)?;
+                }
+                "--client-output-buffer-limit-pubsub" => {
+                    let value = next_value(&mut args, &arg)?;
+                    let limit = value.parse::<usize>().map_err(|_| {
+                        Error::Config(format!("Invalid value for {}: {}", arg, value))
+                    })?;
+                    config.pubsub_output_buffer_limit = (limit > 0).then_some(limit);
+                }
+                "--notify-keyspace-events" => {
+                    let value = next_value(&mut args, &arg)?;
+                    config.notify_keyspace_events = value.parse().map_err(|_| {
+                        Error::Config(format!("Invalid value for {}: {}", arg, value))
+                    })?;
+                }
+                "--save" => config.save_points = parse_save_points(&next_value(&mut args, &arg)?)?,
+                "--tcp-keepalive" => {
+                    let value = next_value(&mut args, &arg)?;
+                    let secs = value.parse::<u64>().map_err(|_| {
+                        Error::Config(format!("Invalid value for {}: {}", arg, value))
+                    })?;
+                    config.tcp_keepalive = (secs > 0).then(|| Duration::from_secs(secs));
+                }
+                "--tcp-nodelay" => {
+                    config.tcp_nodelay = parse_yes_no(next_value(&mut args, &arg)?, &arg)?
+                }
+                "--aof-use-rdb-preamble" => {
+                    config.aof_use_rdb_preamble = parse_yes_no(next_value(&mut args, &arg)?, &arg)?;
+                }
+                "--http-port" => config.http_port = Some(parse_port(next_value(&mut args, &arg)?)?),
+                "--maxmemory" => {
+                    let value = next_value(&mut args, &arg)?;
+                    let bytes = value.parse::<usize>().map_err(|_| {
+                        Error::Config(format!("Invalid value for {}: {}", arg, value))
+                    })?;
+                    config.maxmemory = (bytes > 0).then_some(bytes);
+                }
+                "--maxmemory-policy" => {
+                    let value = next_value(&mut args, &arg)?;
+                    config.maxmemory_policy = value.parse().map_err(|_| {
+                        Error::Config(format!("Invalid value for {}: {}", arg, value))
+                    })?;
+                }
+                "--maxmemory-samples" => {
+                    let value = next_value(&mut args, &arg)?;
+                    config.maxmemory_samples = value
+                        .parse()
+                        .ok()
+                        .filter(|&samples| samples > 0)
+                        .ok_or_else(|| {
+                            Error::Config(format!("Invalid value for {}: {}", arg, value))
+                        })?;
+                }
+                "--lfu-log-factor" => {
+                    let value = next_value(&mut args, &arg)?;
+                    config.lfu_log_factor = value.parse().map_err(|_| {
+                        Error::Config(format!("Invalid value for {}: {}", arg, value))
+                    })?;
+                }
+                "--lfu-decay-time" => {
+                    let value = next_value(&mut args, &arg)?;
+                    config.lfu_decay_time = value.parse().map_err(|_| {
+                        Error::Config(format!("Invalid value for {}: {}", arg, value))
+                    })?;
+                }
+                "--list-max-listpack-size" => {
+                    let value = next_value(&mut args, &arg)?;
+                    config.list_max_listpack_size = value.parse().map_err(|_| {
+                        Error::Config(format!("Invalid value for {}: {}", arg, value))
+                    })?;
+                }
+                "--set-max-intset-entries" => {
+                    let value = next_value(&mut args, &arg)?;
+                    config.set_max_intset_entries = value.parse().map_err(|_| {
+                        Error::Config(format!("Invalid value for {}: {}", arg, value))
+                    })?;
+                }
+                "--loglevel" => {
+                    let value = next_value(&mut args, &arg)?;
+                    config.loglevel = value.parse().map_err(|_| {
+                        Error::Config(format!("Invalid value for {}: {}", arg, value))
+                    })?;
+                }
+                _ => return Err(Error::Config(format!("Unknown option: {}", arg))),
+            }
+        }
+
+        if !bind.is_empty() {
+            config.bind = bind;
+        }
+        Ok(config)
+    }
+
+    /// The codec of the RDB preamble AOF compaction writes, if it writes one.
+    pub fn aof_preamble(&self) -> Option<Compression> {
+        self.aof_use_rdb_preamble.then_some(self.rdb_compression)
+    }
+}
+
+fn next_value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String> {
+    args.next()
+        .ok_or_else(|| Error::Config(format!("Missing value for {}", flag)))
+}
+
+fn parse_yes_no(value: String, flag: &str) -> Result<bool> {
+    match value.as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(Error::Config(format!(
+            "Invalid value for {}: {}",
+            flag, value
+        ))),
+    }
+}
+
+fn parse_port(value: String) -> Result<u16> {
+    value
+        .parse()
+        .map_err(|_| Error::Config(format!("Invalid port: {}", value)))
+}
+
+/// Parses save points written as Redis does, e.g. `"3600 1 300 100"` for a
+/// save after an hour if anything changed or after five minutes if 100 keys
+/// did. An empty string disables automatic saves.
+fn parse_save_points(value: &str) -> Result<Vec<(Duration, u64)>> {
+    let invalid = || Error::Config(format!("Invalid value for --save: {}", value));
+    let numbers = value
+        .split_whitespace()
+        .map(|n| n.parse::<u64>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>>>()?;
+    if !numbers.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+    Ok(numbers
+        .chunks(2)
+        .map(|pair| (Duration::from_secs(pair[0]), pair[1]))
+        .collect())
+}
+
+fn parse_positive<T: std::str::FromStr + PartialOrd + Default>(
+    value: String,
+    flag: &str,
+) -> Result<T> {
+    value
+        .parse()
+        .ok()
+        .filter(|n| *n > T::default())
+        .ok_or_else(|| Error::Config(format!("Invalid value for {}: {}", flag, value)))
+}
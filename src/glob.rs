@@ -0,0 +1,199 @@
+/// Matches `string` against a Redis-style glob `pattern`.
+///
+/// Supports `*` (any sequence), `?` (any single character), `[abc]`,
+/// `[a-z]` and `[^x]` character classes, and `\` to escape the next
+/// character. Matching is iterative with single-star backtracking, so it
+/// runs in O(pattern * string) time regardless of how many stars are used.
+pub fn matches(pattern: &str, string: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let string: Vec<char> = string.chars().collect();
+
+    let (mut p, mut s) = (0, 0);
+    // Position of the last `*` seen and the string position it was tried at.
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while s < string.len() {
+        if p < pattern.len() {
+            match pattern[p] {
+                '*' => {
+                    backtrack = Some((p, s));
+                    p += 1;
+                    continue;
+                }
+                '?' => {
+                    p += 1;
+                    s += 1;
+                    continue;
+                }
+                '[' => match match_class(&pattern, p, string[s]) {
+                    Some((true, next)) => {
+                        p = next;
+                        s += 1;
+                        continue;
+                    }
+                    Some((false, _)) => {}
+                    None => {
+                        if string[s] == '[' {
+                            p += 1;
+                            s += 1;
+                            continue;
+                        }
+                    }
+                },
+                '\\' if p + 1 < pattern.len() => {
+                    if pattern[p + 1] == string[s] {
+                        p += 2;
+                        s += 1;
+                        continue;
+                    }
+                }
+                c => {
+                    if c == string[s] {
+                        p += 1;
+                        s += 1;
+                        continue;
+                    }
+                }
+            }
+        }
+
+        match backtrack {
+            Some((star, from)) => {
+                p = star + 1;
+                s = from + 1;
+                backtrack = Some((star, from + 1));
+            }
+            None => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Matches `c` against the character class starting at `pattern[start]`
+/// (the opening `[`). Returns whether it matched and the index just past the
+/// class, or `None` if the class is never closed, in which case the caller
+/// treats the `[` as a literal.
+fn match_class(pattern: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negate = pattern.get(i) == Some(&'^');
+    if negate {
+        i += 1;
+    }
+
+    let mut matched = false;
+    while i < pattern.len() && pattern[i] != ']' {
+        if pattern[i] == '\\' && i + 1 < pattern.len() {
+            matched |= pattern[i + 1] == c;
+            i += 2;
+        } else if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' {
+            let (low, high) = if pattern[i] <= pattern[i + 2] {
+                (pattern[i], pattern[i + 2])
+            } else {
+                (pattern[i + 2], pattern[i])
+            };
+            matched |= low <= c && c <= high;
+            i += 3;
+        } else {
+            matched |= pattern[i] == c;
+            i += 1;
+        }
+    }
+
+    if i >= pattern.len() {
+        return None;
+    }
+
+    Some((matched != negate, i + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    #[test]
+    fn test_literals() {
+        assert!(matches("", ""));
+        assert!(!matches("", "a"));
+        assert!(matches("hello", "hello"));
+        assert!(!matches("hello", "hell"));
+        assert!(!matches("hell", "hello"));
+    }
+
+    #[test]
+    fn test_star() {
+        assert!(matches("*", ""));
+        assert!(matches("*", "anything"));
+        assert!(matches("user:*", "user:1000"));
+        assert!(matches("user:*", "user:"));
+        assert!(!matches("user:*", "session:1000"));
+        assert!(matches("*:1000", "user:1000"));
+        assert!(matches("a*b*c", "aXXbYYc"));
+        assert!(!matches("a*b*c", "aXXbYY"));
+        assert!(matches("**", "x"));
+    }
+
+    #[test]
+    fn test_question_mark() {
+        assert!(matches("h?llo", "hello"));
+        assert!(matches("h?llo", "hallo"));
+        assert!(!matches("h?llo", "hllo"));
+        assert!(!matches("?", ""));
+        assert!(matches("??", "é!"));
+    }
+
+    #[test]
+    fn test_classes() {
+        assert!(matches("h[ae]llo", "hallo"));
+        assert!(matches("h[ae]llo", "hello"));
+        assert!(!matches("h[ae]llo", "hillo"));
+        assert!(matches("h[a-c]llo", "hbllo"));
+        assert!(!matches("h[a-c]llo", "hdllo"));
+        // Reversed ranges match like their forward counterpart.
+        assert!(matches("h[c-a]llo", "hbllo"));
+        assert!(matches("[a-c0-9]", "7"));
+    }
+
+    #[test]
+    fn test_negated_classes() {
+        assert!(!matches("h[^e]llo", "hello"));
+        assert!(matches("h[^e]llo", "hallo"));
+        assert!(!matches("[^a-z]", "q"));
+        assert!(matches("[^a-z]", "Q"));
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert!(matches("h\\*llo", "h*llo"));
+        assert!(!matches("h\\*llo", "hello"));
+        assert!(matches("\\?", "?"));
+        assert!(!matches("\\?", "a"));
+        assert!(matches("\\[a]", "[a]"));
+        assert!(matches("[\\]]", "]"));
+        assert!(matches("[\\^a]", "^"));
+        // A trailing backslash matches itself.
+        assert!(matches("a\\", "a\\"));
+    }
+
+    #[test]
+    fn test_unclosed_class_is_literal() {
+        assert!(matches("[abc", "[abc"));
+        assert!(!matches("[abc", "a"));
+    }
+
+    #[test]
+    fn test_pathological_patterns_run_in_polynomial_time() {
+        let string = "a".repeat(10_000);
+        let start = Instant::now();
+        assert!(!matches("*****a*****a*****a*****b", &string));
+        assert!(matches("*****a", &string));
+        assert!(!matches(&"a*".repeat(50).replace("a*a*", "a*b*"), &string));
+        assert!(
+            start.elapsed() < Duration::from_secs(1),
+            "{:?}",
+            start.elapsed()
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use crate::{resp::Resp, Error};
+
+/// The subcommands of a container command such as OBJECT or CONFIG, used to
+/// answer `<command> HELP` and to reject unknown subcommands uniformly.
+#[derive(Debug)]
+pub struct Subcommands {
+    pub command: &'static str,
+    /// Usage line and description of each subcommand, HELP excluded.
+    pub entries: &'static [(&'static str, &'static str)],
+}
+
+impl Subcommands {
+    /// The `HELP` reply: a usage header, then each subcommand's usage line
+    /// followed by its indented description.
+    pub fn help(&self) -> Resp {
+        let mut lines = vec![format!(
+            "{} <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            self.command
+        )];
+        for (usage, description) in self.entries.iter().chain([&HELP]) {
+            lines.push(usage.to_string());
+            lines.push(format!("    {}", description));
+        }
+        Resp::Array(lines.into_iter().map(Resp::SimpleString).collect())
+    }
+
+    /// The error for a subcommand that isn't one of `entries`.
+    pub fn unknown(&self, subcommand: &str) -> Error {
+        let valid: Vec<&str> = self
+            .entries
+            .iter()
+            .chain([&HELP])
+            .map(|(usage, _)| usage.split(' ').next().unwrap_or(usage))
+            .collect();
+        Error::UnknownSubcommand {
+            command: self.command,
+            subcommand: subcommand.to_string(),
+            valid: valid.join(", "),
+        }
+    }
+}
+
+const HELP: (&str, &str) = ("HELP", "Print this help.");
+
+pub const ACL: Subcommands = Subcommands {
+    command: "ACL",
+    entries: &[
+        ("LIST", "List all users in the ACL rules format."),
+        (
+            "WHOAMI",
+            "Return the user the connection is authenticated as.",
+        ),
+    ],
+};
+
+pub const CLIENT: Subcommands = Subcommands {
+    command: "CLIENT",
+    entries: &[(
+        "TRACKING (ON|OFF)",
+        "Send invalidation pushes for the keys this connection reads when they change.",
+    )],
+};
+
+pub const CLUSTER: Subcommands = Subcommands {
+    command: "CLUSTER",
+    entries: &[("KEYSLOT <key>", "Return the hash slot for <key>.")],
+};
+
+pub const COMMAND: Subcommands = Subcommands {
+    command: "COMMAND",
+    entries: &[
+        (
+            "COUNT",
+            "Return the total number of commands in this server.",
+        ),
+        (
+            "GETKEYS <full-command>",
+            "Return the keys from a full command.",
+        ),
+        (
+            "INFO [<command-name> ...]",
+            "Return details about the given commands, or all of them.",
+        ),
+    ],
+};
+
+pub const CONFIG: Subcommands = Subcommands {
+    command: "CONFIG",
+    entries: &[
+        (
+            "RESETSTAT",
+            "Reset the statistics reported by the INFO command.",
+        ),
+        (
+            "SET loglevel <level>",
+            "Set the log verbosity: debug, verbose, notice or warning.",
+        ),
+    ],
+};
+
+pub const DEBUG: Subcommands = Subcommands {
+    command: "DEBUG",
+    entries: &[
+        (
+            "CHANGE-REPL-ID",
+            "Change the replication ID, starting a new replication history.",
+        ),
+        (
+            "RELOAD",
+            "Save the dataset to the RDB file, then reload it from there.",
+        ),
+        (
+            "SET-ACTIVE-EXPIRE <0|1>",
+            "Disable or enable the active expiration of keys.",
+        ),
+        (
+            "SET-MEMORY-USAGE <bytes>",
+            "Report <bytes> as the used memory, or the real figure again with 0.",
+        ),
+        (
+            "OBJECT <key>",
+            "Show low-level information about the <key> and its value.",
+        ),
+        ("SLEEP <seconds>", "Stop the connection for <seconds>."),
+        (
+            "STRINGMATCH-LEN <pattern> <string>",
+            "Return 1 if <string> matches the glob-style <pattern>, 0 otherwise.",
+        ),
+    ],
+};
+
+pub const LATENCY: Subcommands = Subcommands {
+    command: "LATENCY",
+    entries: &[
+        (
+            "HISTORY <event>",
+            "Return the time and latency of the latest samples of <event>.",
+        ),
+        (
+            "LATEST",
+            "Return the latest sample and the maximum latency of each event.",
+        ),
+        (
+            "RESET [<event> ...]",
+            "Reset the samples of the given events, or of all of them.",
+        ),
+    ],
+};
+
+pub const MEMORY: Subcommands = Subcommands {
+    command: "MEMORY",
+    entries: &[(
+        "USAGE <key> [SAMPLES <count>]",
+        "Return the memory usage of the key and its value in bytes.",
+    )],
+};
+
+pub const OBJECT: Subcommands = Subcommands {
+    command: "OBJECT",
+    entries: &[
+        (
+            "ENCODING <key>",
+            "Return the kind of internal representation used to store the value at <key>.",
+        ),
+        (
+            "IDLETIME <key>",
+            "Return the number of seconds since the value at <key> was last accessed.",
+        ),
+        (
+            "REFCOUNT <key>",
+            "Return the number of references of the value at <key>.",
+        ),
+    ],
+};
+
+pub const PUBSUB: Subcommands = Subcommands {
+    command: "PUBSUB",
+    entries: &[
+        (
+            "CHANNELS [<pattern>]",
+            "Return the channels with subscribers, optionally matching <pattern>.",
+        ),
+        ("NUMPAT", "Return the number of subscribed patterns."),
+        (
+            "NUMSUB [<channel> ...]",
+            "Return the number of subscribers of each channel.",
+        ),
+    ],
+};
+
+pub const SCRIPT: Subcommands = Subcommands {
+    command: "SCRIPT",
+    entries: &[
+        (
+            "EXISTS <sha1> [<sha1> ...]",
+            "Return whether each script is in the script cache.",
+        ),
+        ("FLUSH", "Flush the script cache."),
+        ("LOAD <script>", "Load a script into the script cache."),
+    ],
+};
@@ -0,0 +1,125 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{debug, error};
+
+/// Longest request head read before answering; probes send a few lines.
+const MAX_HEAD: usize = 4096;
+/// How long a probe has to send its request head.
+const HEAD_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serves the health endpoints for container orchestration: `GET /health`
+/// answers 200 while `listening` counts at least one RESP listener
+/// accepting connections, and `GET /ready` answers 200 while `ready` is set,
+/// which it is from the end of the initial load until the server starts
+/// draining. Both answer 503 otherwise.
+pub async fn serve(listener: TcpListener, listening: Arc<AtomicUsize>, ready: Arc<AtomicBool>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                let listening = listening.clone();
+                let ready = ready.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = respond(stream, &listening, &ready).await {
+                        debug!("Failed to answer health check from {}: {}", addr, e);
+                    }
+                });
+            }
+            Err(e) => {
+                error!("Failed to accept health check connection: {}", e);
+            }
+        }
+    }
+}
+
+/// Answers one request and closes the connection. Only the method and path
+/// of the request line are looked at. A probe that doesn't send its head
+/// within `HEAD_TIMEOUT` is disconnected unanswered.
+async fn respond(
+    mut stream: TcpStream,
+    listening: &AtomicUsize,
+    ready: &AtomicBool,
+) -> io::Result<()> {
+    let mut head = Vec::new();
+    let read_head = async {
+        let mut buf = [0; 1024];
+        while !head.windows(4).any(|window| window == b"\r\n\r\n") && head.len() < MAX_HEAD {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            head.extend_from_slice(&buf[..n]);
+        }
+        Ok::<_, io::Error>(())
+    };
+    tokio::time::timeout(HEAD_TIMEOUT, read_head)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "request head timed out"))??;
+
+    let head = String::from_utf8_lossy(&head);
+    let mut request_line = head.lines().next().unwrap_or_default().split(' ');
+    let status = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/health")) if listening.load(Ordering::Relaxed) > 0 => "200 OK",
+        (Some("GET"), Some("/ready")) if ready.load(Ordering::Relaxed) => "200 OK",
+        (Some("GET"), Some("/health") | Some("/ready")) => "503 Service Unavailable",
+        (Some("GET"), _) => "404 Not Found",
+        _ => "405 Method Not Allowed",
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{status}\n",
+        status.len() + 1
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn get(addr: std::net::SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response.lines().next().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_health_and_readiness() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let listening = Arc::new(AtomicUsize::new(0));
+        let ready = Arc::new(AtomicBool::new(false));
+        tokio::spawn(serve(listener, listening.clone(), ready.clone()));
+
+        // Loading: nothing is served yet.
+        let unavailable = "HTTP/1.1 503 Service Unavailable";
+        assert_eq!(get(addr, "/health").await, unavailable);
+        assert_eq!(get(addr, "/ready").await, unavailable);
+
+        listening.fetch_add(1, Ordering::Relaxed);
+        ready.store(true, Ordering::Relaxed);
+        assert_eq!(get(addr, "/health").await, "HTTP/1.1 200 OK");
+        assert_eq!(get(addr, "/ready").await, "HTTP/1.1 200 OK");
+        assert_eq!(get(addr, "/metrics").await, "HTTP/1.1 404 Not Found");
+
+        // Draining: the listeners stop accepting.
+        ready.store(false, Ordering::Relaxed);
+        listening.fetch_sub(1, Ordering::Relaxed);
+        assert_eq!(get(addr, "/health").await, unavailable);
+        assert_eq!(get(addr, "/ready").await, unavailable);
+    }
+}
@@ -0,0 +1,181 @@
+const MAGIC: &[u8] = b"HYLL";
+const P: u32 = 14;
+const REGISTERS: usize = 1 << P;
+const Q: u32 = 64 - P;
+const ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+const SEED: u64 = 0xadc8_3b19;
+
+/// HyperLogLog cardinality estimation with Redis' parameters: 2^14
+/// registers (a standard error of 0.81%), MurmurHash64A hashing and Ertl's
+/// improved estimator, so counts match what Redis reports for the same
+/// elements.
+///
+/// The registers are stored in a string value, one byte per register after
+/// a `HYLL` header, at the cost of 16 KiB per key instead of Redis' 12 KiB
+/// packed layout.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        Self {
+            registers: vec![0; REGISTERS],
+        }
+    }
+
+    /// Decodes a stored string value, or returns `None` if it isn't a valid
+    /// HyperLogLog.
+    pub fn from_value(value: &[u8]) -> Option<Self> {
+        let registers = value.strip_prefix(MAGIC)?;
+        if registers.len() != REGISTERS || registers.iter().any(|&r| r > Q as u8 + 1) {
+            return None;
+        }
+        Some(Self {
+            registers: registers.to_vec(),
+        })
+    }
+
+    pub fn to_value(&self) -> Vec<u8> {
+        [MAGIC, &self.registers].concat()
+    }
+
+    /// Adds an element, returning whether any register changed.
+    pub fn add(&mut self, element: &[u8]) -> bool {
+        let hash = murmur_hash64a(element, SEED);
+        let index = (hash & (REGISTERS as u64 - 1)) as usize;
+        // The sentinel bit bounds the run of zeros to Q.
+        let count = ((hash >> P) | (1 << Q)).trailing_zeros() as u8 + 1;
+        if count > self.registers[index] {
+            self.registers[index] = count;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Folds another HyperLogLog into this one, so it estimates the
+    /// cardinality of the union.
+    pub fn merge(&mut self, other: &Self) {
+        for (register, &theirs) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(theirs);
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        let mut histogram = [0u32; Q as usize + 2];
+        for &register in &self.registers {
+            histogram[register as usize] += 1;
+        }
+
+        let m = REGISTERS as f64;
+        let mut z = m * tau((m - histogram[Q as usize + 1] as f64) / m);
+        for &count in histogram[1..=Q as usize].iter().rev() {
+            z += count as f64;
+            z *= 0.5;
+        }
+        z += m * sigma(histogram[0] as f64 / m);
+
+        (ALPHA_INF * m * m / z).round() as u64
+    }
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if previous == z {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if previous == z {
+            return z / 3.0;
+        }
+    }
+}
+
+/// MurmurHash64A, the hash Redis uses for HyperLogLog elements.
+fn murmur_hash64a(key: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+
+    let mut h = seed ^ (key.len() as u64).wrapping_mul(M);
+
+    let mut chunks = key.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().expect("8-byte chunk"));
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, &byte) in tail.iter().enumerate() {
+            h ^= (byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_is_within_error_bound() {
+        let mut hll = HyperLogLog::new();
+        assert_eq!(hll.count(), 0);
+
+        for n in [100u64, 1_000, 10_000, 100_000] {
+            for i in 0..n {
+                hll.add(format!("element:{}", i).as_bytes());
+            }
+            let error = (hll.count() as f64 - n as f64).abs() / n as f64;
+            assert!(error < 0.03, "{} elements estimated as {}", n, hll.count());
+        }
+    }
+
+    #[test]
+    fn test_value_round_trip_and_merge() {
+        let mut a = HyperLogLog::new();
+        let mut b = HyperLogLog::new();
+        assert!(a.add(b"x"));
+        assert!(!a.add(b"x"));
+        b.add(b"y");
+
+        assert_eq!(HyperLogLog::from_value(&a.to_value()), Some(a.clone()));
+        assert_eq!(HyperLogLog::from_value(b"not a hll"), None);
+
+        a.merge(&b);
+        assert_eq!(a.count(), 2);
+    }
+}
@@ -0,0 +1,230 @@
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::UNIX_EPOCH,
+};
+
+use crate::Storage;
+
+/// Server-wide counters reported by INFO.
+#[derive(Debug, Default)]
+pub struct Stats {
+    total_connections_received: AtomicU64,
+    total_commands_processed: AtomicU64,
+    rejected_connections: AtomicU64,
+    expired_keys: AtomicU64,
+    evicted_keys: AtomicU64,
+    keyspace_hits: AtomicU64,
+    keyspace_misses: AtomicU64,
+    /// A gauge rather than a cumulative counter, so RESETSTAT leaves it.
+    connected_clients: AtomicUsize,
+}
+
+impl Stats {
+    /// Records the outcome of a read command looking up a key.
+    pub fn record_lookup(&self, hit: bool) {
+        let counter = if hit {
+            &self.keyspace_hits
+        } else {
+            &self.keyspace_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_command(&self) {
+        self.total_commands_processed
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_expired_key(&self) {
+        self.expired_keys.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_evicted_key(&self) {
+        self.evicted_keys.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a client as connected until the returned guard is dropped.
+    pub fn connect(self: &Arc<Self>) -> ConnectionGuard {
+        self.total_connections_received
+            .fetch_add(1, Ordering::Relaxed);
+        self.connected_clients.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard {
+            stats: Arc::clone(self),
+        }
+    }
+
+    pub fn connected_clients(&self) -> usize {
+        self.connected_clients.load(Ordering::Relaxed)
+    }
+
+    pub fn record_rejected_connection(&self) {
+        self.total_connections_received
+            .fetch_add(1, Ordering::Relaxed);
+        self.rejected_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Zeroes the cumulative counters, as CONFIG RESETSTAT does.
+    pub fn reset(&self) {
+        for counter in [
+            &self.total_connections_received,
+            &self.total_commands_processed,
+            &self.rejected_connections,
+            &self.expired_keys,
+            &self.evicted_keys,
+            &self.keyspace_hits,
+            &self.keyspace_misses,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Keeps a client counted in `connected_clients` while it is alive.
+pub struct ConnectionGuard {
+    stats: Arc<Stats>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.stats.connected_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Renders the INFO reply. `section` selects a single section by name
+/// (case-insensitive); `None`, `all` and `default` render every section, and
+/// an unknown section renders nothing.
+pub fn render(storage: &Storage, section: Option<&str>) -> String {
+    let section = section.map(str::to_lowercase);
+    let wanted = |name: &str| match section.as_deref() {
+        None | Some("all") | Some("default") | Some("everything") => true,
+        Some(section) => section == name,
+    };
+
+    let stats = storage.stats();
+    let mut info = String::new();
+    if wanted("server") {
+        info.push_str("# Server\r\n");
+        let _ = write!(info, "run_id:{}\r\n", storage.run_id());
+    }
+    if wanted("clients") {
+        if !info.is_empty() {
+            info.push_str("\r\n");
+        }
+        info.push_str("# Clients\r\n");
+        let _ = write!(info, "connected_clients:{}\r\n", stats.connected_clients());
+        let _ = write!(
+            info,
+            "tracking_clients:{}\r\n",
+            storage.tracking().clients()
+        );
+    }
+    if wanted("memory") {
+        if !info.is_empty() {
+            info.push_str("\r\n");
+        }
+        info.push_str("# Memory\r\n");
+        let used = storage.used_memory();
+        let peak = storage.peak_memory();
+        let _ = write!(info, "used_memory:{}\r\n", used);
+        let _ = write!(info, "used_memory_human:{}\r\n", human_bytes(used));
+        let _ = write!(info, "used_memory_peak:{}\r\n", peak);
+        let _ = write!(info, "used_memory_peak_human:{}\r\n", human_bytes(peak));
+        let config = storage.config();
+        let _ = write!(info, "maxmemory:{}\r\n", config.maxmemory.unwrap_or(0));
+        let _ = write!(
+            info,
+            "maxmemory_policy:{}\r\n",
+            config.maxmemory_policy.name()
+        );
+        // used_memory estimates the dataset rather than measuring what the
+        // allocator holds, so there is no fragmentation to compare it with.
+        info.push_str("mem_fragmentation_ratio:1.00\r\n");
+    }
+    if wanted("stats") {
+        if !info.is_empty() {
+            info.push_str("\r\n");
+        }
+        info.push_str("# Stats\r\n");
+        for (name, counter) in [
+            (
+                "total_connections_received",
+                &stats.total_connections_received,
+            ),
+            ("total_commands_processed", &stats.total_commands_processed),
+            ("rejected_connections", &stats.rejected_connections),
+            ("expired_keys", &stats.expired_keys),
+            ("evicted_keys", &stats.evicted_keys),
+            ("keyspace_hits", &stats.keyspace_hits),
+            ("keyspace_misses", &stats.keyspace_misses),
+        ] {
+            let _ = write!(info, "{}:{}\r\n", name, counter.load(Ordering::Relaxed));
+        }
+    }
+    if wanted("persistence") {
+        if !info.is_empty() {
+            info.push_str("\r\n");
+        }
+        info.push_str("# Persistence\r\n");
+        let last_save = storage
+            .last_save()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let _ = write!(
+            info,
+            "rdb_changes_since_last_save:{}\r\n",
+            storage.changes_since_save()
+        );
+        let _ = write!(info, "rdb_last_save_time:{}\r\n", last_save.as_secs());
+    }
+    if wanted("replication") {
+        if !info.is_empty() {
+            info.push_str("\r\n");
+        }
+        let replication = storage.replication();
+        info.push_str("# Replication\r\n");
+        let role = if replication.is_replica() {
+            "slave"
+        } else {
+            "master"
+        };
+        let _ = write!(info, "role:{}\r\n", role);
+        let _ = write!(info, "master_replid:{}\r\n", replication.replid());
+        let _ = write!(info, "master_repl_offset:{}\r\n", replication.offset());
+    }
+    if wanted("latencystats") {
+        if !info.is_empty() {
+            info.push_str("\r\n");
+        }
+        info.push_str("# Latencystats\r\n");
+        for (command, [p50, p99, p999]) in storage.latency().percentiles() {
+            let _ = write!(
+                info,
+                "latency_percentiles_usec_{}:p50={:.3},p99={:.3},p99.9={:.3}\r\n",
+                command.to_lowercase(),
+                p50 as f64,
+                p99 as f64,
+                p999 as f64
+            );
+        }
+    }
+    info
+}
+
+/// Formats a byte count the way Redis does in its `_human` fields, e.g.
+/// `1.50M`.
+fn human_bytes(bytes: usize) -> String {
+    const UNITS: [(f64, &str); 3] = [
+        (1024.0 * 1024.0 * 1024.0, "G"),
+        (1024.0 * 1024.0, "M"),
+        (1024.0, "K"),
+    ];
+    let bytes = bytes as f64;
+    UNITS.iter().find(|(size, _)| bytes >= *size).map_or_else(
+        || format!("{}B", bytes),
+        |(size, unit)| format!("{:.2}{}", bytes / size, unit),
+    )
+}
@@ -0,0 +1,236 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use dashmap::DashMap;
+
+/// Samples kept per latency event, as in Redis.
+const HISTORY_LEN: usize = 160;
+/// Sub-buckets per power of two in a histogram, bounding the error of a
+/// reported percentile to about 6%.
+const SUB_BUCKETS: u64 = 16;
+const SUB_BUCKET_BITS: u32 = SUB_BUCKETS.trailing_zeros();
+const BUCKETS: usize = (64 - SUB_BUCKET_BITS as usize + 1) * SUB_BUCKETS as usize;
+
+/// Tracks how long commands take: a histogram of every execution per
+/// command, for the `latencystats` INFO section, and a history of the
+/// executions that exceeded the monitor threshold, for LATENCY.
+#[derive(Debug)]
+pub struct LatencyMonitor {
+    /// Executions at least this slow are recorded as events; `None` disables
+    /// event tracking, as a zero `latency-monitor-threshold` does in Redis.
+    threshold: Option<Duration>,
+    events: Mutex<HashMap<&'static str, Event>>,
+    histograms: DashMap<&'static str, Histogram>,
+}
+
+#[derive(Debug, Default)]
+struct Event {
+    /// Unix time in seconds and latency in milliseconds, oldest first, with
+    /// at most one sample per second.
+    history: VecDeque<(u64, u64)>,
+    max: u64,
+}
+
+impl LatencyMonitor {
+    pub fn new(threshold: Option<Duration>) -> Self {
+        Self {
+            threshold,
+            events: Mutex::new(HashMap::new()),
+            histograms: DashMap::new(),
+        }
+    }
+
+    /// Records one execution of `command`. `event` names the kind of
+    /// execution, `command` or `fast-command`, should it cross the threshold.
+    pub fn record(&self, command: &'static str, event: &'static str, elapsed: Duration) {
+        self.histograms
+            .entry(command)
+            .or_default()
+            .record(elapsed.as_micros() as u64);
+
+        if self.threshold.is_none_or(|threshold| elapsed < threshold) {
+            return;
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let millis = elapsed.as_millis() as u64;
+
+        let mut events = self.events.lock().unwrap();
+        let event = events.entry(event).or_default();
+        event.max = event.max.max(millis);
+        match event.history.back_mut() {
+            // Like Redis, keep the worst sample of each second.
+            Some((time, latency)) if *time == now => *latency = (*latency).max(millis),
+            _ => {
+                if event.history.len() == HISTORY_LEN {
+                    event.history.pop_front();
+                }
+                event.history.push_back((now, millis));
+            }
+        }
+    }
+
+    /// Each event's name, latest sample time and latency, and all-time
+    /// maximum latency, for LATENCY LATEST.
+    pub fn latest(&self) -> Vec<(&'static str, u64, u64, u64)> {
+        let mut latest: Vec<_> = self
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(name, event)| {
+                let (time, latency) = event.history.back()?;
+                Some((*name, *time, *latency, event.max))
+            })
+            .collect();
+        latest.sort();
+        latest
+    }
+
+    /// The samples of one event, oldest first, for LATENCY HISTORY.
+    pub fn history(&self, event: &str) -> Vec<(u64, u64)> {
+        self.events
+            .lock()
+            .unwrap()
+            .get(event)
+            .map(|event| event.history.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Drops the history of the given events, or of all of them. Returns
+    /// the number of events dropped.
+    pub fn reset(&self, events: &[String]) -> usize {
+        let mut tracked = self.events.lock().unwrap();
+        if events.is_empty() {
+            let count = tracked.len();
+            tracked.clear();
+            return count;
+        }
+        events
+            .iter()
+            .filter(|event| tracked.remove(event.as_str()).is_some())
+            .count()
+    }
+
+    /// The p50, p99 and p99.9 latencies in microseconds of each command run
+    /// so far, sorted by command.
+    pub fn percentiles(&self) -> Vec<(&'static str, [u64; 3])> {
+        let mut percentiles: Vec<_> = self
+            .histograms
+            .iter()
+            .map(|entry| {
+                let histogram = entry.value();
+                (
+                    *entry.key(),
+                    [
+                        histogram.percentile(50.0),
+                        histogram.percentile(99.0),
+                        histogram.percentile(99.9),
+                    ],
+                )
+            })
+            .collect();
+        percentiles.sort();
+        percentiles
+    }
+
+    /// Clears the histograms, as CONFIG RESETSTAT does.
+    pub fn reset_histograms(&self) {
+        self.histograms.clear();
+    }
+}
+
+/// A histogram with logarithmic buckets, each split into linear
+/// sub-buckets, so recording is a couple of bit operations and an increment
+/// while percentiles keep a bounded relative error, as in HDR histograms.
+#[derive(Debug)]
+struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; BUCKETS],
+            total: 0,
+        }
+    }
+}
+
+impl Histogram {
+    fn record(&mut self, value: u64) {
+        self.counts[bucket(value)] += 1;
+        self.total += 1;
+    }
+
+    /// The smallest bucket bound at or below which `percentile` percent of
+    /// the recorded values fall.
+    fn percentile(&self, percentile: f64) -> u64 {
+        let rank = ((percentile / 100.0 * self.total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return upper_bound(index);
+            }
+        }
+        0
+    }
+}
+
+/// The bucket holding `value`: values below [`SUB_BUCKETS`] get a bucket
+/// each, larger ones share a power of two split into [`SUB_BUCKETS`] parts.
+fn bucket(value: u64) -> usize {
+    if value < SUB_BUCKETS {
+        return value as usize;
+    }
+    let exponent = 63 - value.leading_zeros();
+    let shift = exponent - SUB_BUCKET_BITS;
+    let group = (shift + 1) as usize;
+    let sub = ((value >> shift) - SUB_BUCKETS) as usize;
+    group * SUB_BUCKETS as usize + sub
+}
+
+/// The largest value that falls in bucket `index`.
+fn upper_bound(index: usize) -> u64 {
+    let sub_buckets = SUB_BUCKETS as usize;
+    if index < sub_buckets {
+        return index as u64;
+    }
+    let shift = (index / sub_buckets - 1) as u32;
+    let sub = (index % sub_buckets) as u64;
+    let bound = (u128::from(SUB_BUCKETS + sub + 1) << shift) - 1;
+    u64::try_from(bound).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buckets_bound_their_values() {
+        for value in [0, 1, 15, 16, 17, 31, 32, 100, 1000, 123_456, u64::MAX] {
+            let bound = upper_bound(bucket(value));
+            assert!(bound >= value, "{} > {}", value, bound);
+            assert!(bound - value <= value / SUB_BUCKETS, "{}: {}", value, bound);
+        }
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let mut histogram = Histogram::default();
+        for value in 1..=1000 {
+            histogram.record(value);
+        }
+        let p50 = histogram.percentile(50.0);
+        assert!((500..=532).contains(&p50), "{}", p50);
+        let p999 = histogram.percentile(99.9);
+        assert!((999..=1023).contains(&p999), "{}", p999);
+    }
+}
@@ -0,0 +1,105 @@
+/// A run of consecutive bytes common to both inputs, as inclusive index
+/// ranges into each of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+    pub a: (usize, usize),
+    pub b: (usize, usize),
+}
+
+impl Match {
+    pub fn len(&self) -> usize {
+        self.a.1 - self.a.0 + 1
+    }
+}
+
+/// Longest common subsequence of two byte strings.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Lcs {
+    pub sequence: Vec<u8>,
+    /// The contiguous runs making up the subsequence, from the end of the
+    /// inputs towards the start, as Redis reports them.
+    pub matches: Vec<Match>,
+}
+
+/// Computes the longest common subsequence of `a` and `b` with the classic
+/// dynamic-programming table, in O(len(a) * len(b)) time and space. Ties are
+/// broken the same way Redis does, so the chosen subsequence and its match
+/// ranges are identical to what Redis returns.
+pub fn lcs(a: &[u8], b: &[u8]) -> Lcs {
+    let width = b.len() + 1;
+    // table[i * width + j] is the LCS length of a[..i] and b[..j].
+    let mut table = vec![0u32; (a.len() + 1) * width];
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            table[i * width + j] = if a[i - 1] == b[j - 1] {
+                table[(i - 1) * width + j - 1] + 1
+            } else {
+                table[(i - 1) * width + j].max(table[i * width + j - 1])
+            };
+        }
+    }
+
+    let mut sequence = Vec::with_capacity(table[a.len() * width + b.len()] as usize);
+    let mut matches = Vec::new();
+    let mut current: Option<Match> = None;
+    let (mut i, mut j) = (a.len(), b.len());
+
+    while i > 0 && j > 0 {
+        if a[i - 1] == b[j - 1] {
+            sequence.push(a[i - 1]);
+            match &mut current {
+                // Extends the run found so far one byte towards the start.
+                Some(run) if run.a.0 == i && run.b.0 == j => {
+                    run.a.0 -= 1;
+                    run.b.0 -= 1;
+                }
+                _ => {
+                    matches.extend(current.take());
+                    current = Some(Match {
+                        a: (i - 1, i - 1),
+                        b: (j - 1, j - 1),
+                    });
+                }
+            }
+            i -= 1;
+            j -= 1;
+        } else if table[(i - 1) * width + j] > table[i * width + j - 1] {
+            i -= 1;
+        } else {
+            j -= 1;
+        }
+    }
+    matches.extend(current);
+    sequence.reverse();
+
+    Lcs { sequence, matches }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lcs() {
+        let result = lcs(b"ohmytext", b"mynewtext");
+        assert_eq!(result.sequence, b"mytext");
+        assert_eq!(
+            result.matches,
+            vec![
+                Match {
+                    a: (4, 7),
+                    b: (5, 8)
+                },
+                Match {
+                    a: (2, 3),
+                    b: (0, 1)
+                },
+            ]
+        );
+        assert_eq!(result.matches[0].len(), 4);
+
+        assert_eq!(lcs(b"", b"text").sequence, b"");
+        assert!(lcs(b"abc", b"xyz").matches.is_empty());
+        assert_eq!(lcs(b"same", b"same").matches.len(), 1);
+    }
+}
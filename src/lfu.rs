@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+/// The counter a new key starts with, so it isn't evicted before it had a
+/// chance to be read.
+pub const INITIAL_COUNTER: u8 = 5;
+
+/// Counts one access in a logarithmic access-frequency counter, a Morris
+/// counter as Redis keeps for LFU eviction: past [`INITIAL_COUNTER`], the
+/// counter only goes up with probability `1 / (excess * log_factor + 1)`,
+/// so its 255 values cover millions of accesses. A higher `log_factor`
+/// takes more accesses to saturate it.
+pub fn increment(counter: u8, log_factor: u32) -> u8 {
+    if counter == u8::MAX {
+        return counter;
+    }
+    let excess = counter.saturating_sub(INITIAL_COUNTER) as f64;
+    let probability = 1.0 / (excess * log_factor as f64 + 1.0);
+    if fastrand::f64() < probability {
+        counter + 1
+    } else {
+        counter
+    }
+}
+
+/// The counter after sitting `idle` without access: one less for every
+/// `decay_time` minutes, down to 0. A `decay_time` of 0 never decays it.
+pub fn decay(counter: u8, idle: Duration, decay_time: u64) -> u8 {
+    if decay_time == 0 {
+        return counter;
+    }
+    let periods = idle.as_secs() / 60 / decay_time;
+    counter.saturating_sub(periods.min(u8::MAX as u64) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_increment_is_logarithmic() {
+        // The first access past the initial value always counts.
+        assert_eq!(increment(INITIAL_COUNTER, 10), INITIAL_COUNTER + 1);
+        assert_eq!(increment(0, 10), 1);
+        assert_eq!(increment(u8::MAX, 0), u8::MAX);
+
+        let mut counter = INITIAL_COUNTER;
+        for _ in 0..1000 {
+            counter = increment(counter, 10);
+        }
+        // About sqrt(2 * 1000 / 10) increments past the first.
+        assert!((10..40).contains(&counter), "{}", counter);
+    }
+
+    #[test]
+    fn test_decay() {
+        let minutes = |minutes: u64| Duration::from_secs(minutes * 60);
+        assert_eq!(decay(10, minutes(0), 1), 10);
+        assert_eq!(decay(10, Duration::from_secs(59), 1), 10);
+        assert_eq!(decay(10, minutes(3), 1), 7);
+        assert_eq!(decay(10, minutes(3), 2), 9);
+        assert_eq!(decay(10, minutes(1000), 1), 0);
+        assert_eq!(decay(10, minutes(1000), 0), 10);
+    }
+}
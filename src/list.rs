@@ -0,0 +1,244 @@
+use std::{collections::VecDeque, ops::RangeInclusive, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// An end of a list, which commands spell `LEFT` for the head and `RIGHT`
+/// for the tail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum End {
+    Left,
+    Right,
+}
+
+impl FromStr for End {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "LEFT" => Ok(End::Left),
+            "RIGHT" => Ok(End::Right),
+            _ => Err(()),
+        }
+    }
+}
+
+/// How large a list may grow while it is reported in the compact listpack
+/// encoding rather than as a quicklist, as `list-max-listpack-size` sets it:
+/// a positive value caps the number of elements, while -1 to -5 cap the
+/// encoded size at 4, 8, 16, 32 or 64 KB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListpackLimit {
+    Entries(usize),
+    Bytes(usize),
+}
+
+impl ListpackLimit {
+    /// Redis's bound on the size of a listpack capped by entries, so a few
+    /// huge elements still make a quicklist.
+    const SAFETY_LIMIT: usize = 8192;
+
+    /// Whether `list` fits in a single listpack. Each element is counted
+    /// with the couple of bytes of header and backlength listpacks store
+    /// next to it.
+    pub fn fits(&self, list: &VecDeque<String>) -> bool {
+        let size = || -> usize { list.iter().map(|element| element.len() + 2).sum() };
+        match *self {
+            ListpackLimit::Entries(entries) => {
+                list.len() <= entries && size() <= Self::SAFETY_LIMIT
+            }
+            ListpackLimit::Bytes(bytes) => size() <= bytes,
+        }
+    }
+}
+
+impl Default for ListpackLimit {
+    /// Redis's default of -2, 8 KB.
+    fn default() -> Self {
+        ListpackLimit::Bytes(8192)
+    }
+}
+
+impl FromStr for ListpackLimit {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<i64>().map_err(drop)? {
+            entries @ 1.. => Ok(ListpackLimit::Entries(entries as usize)),
+            level @ -5..=-1 => Ok(ListpackLimit::Bytes(4096 << (-level - 1))),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Adds `elements` one at a time at `end`, so pushing `a b c` to the left
+/// leaves `c` at the head, as LPUSH does.
+pub fn push(list: &mut VecDeque<String>, end: End, elements: impl IntoIterator<Item = String>) {
+    for element in elements {
+        match end {
+            End::Left => list.push_front(element),
+            End::Right => list.push_back(element),
+        }
+    }
+}
+
+/// Removes up to `count` elements from `end`, in the order they are popped.
+pub fn pop(list: &mut VecDeque<String>, end: End, count: usize) -> Vec<String> {
+    let count = count.min(list.len());
+    match end {
+        End::Left => list.drain(..count).collect(),
+        End::Right => list.drain(list.len() - count..).rev().collect(),
+    }
+}
+
+/// Removes up to `count` elements equal to `element`, the first ones when
+/// `count` is positive, the last ones when it is negative and all of them
+/// when it is 0, as LREM does. Returns how many were removed.
+pub fn remove(list: &mut VecDeque<String>, element: &str, count: i64) -> usize {
+    let limit = match count {
+        0 => usize::MAX,
+        count => count.unsigned_abs() as usize,
+    };
+    let matches = list
+        .iter()
+        .filter(|candidate| *candidate == element)
+        .count();
+    let removed = limit.min(matches);
+    // Matches before `first` and after the removed ones are kept.
+    let first = if count < 0 { matches - removed } else { 0 };
+    let mut seen = 0;
+    list.retain(|candidate| {
+        if candidate != element {
+            return true;
+        }
+        seen += 1;
+        seen <= first || seen > first + removed
+    });
+    removed
+}
+
+/// The indexes between `start` and `stop` inclusive in a list of `len`
+/// elements, negative values counting from the tail, as LRANGE takes them.
+/// Out-of-range bounds are clamped; `None` when no index is left.
+pub fn range(len: usize, start: i64, stop: i64) -> Option<RangeInclusive<usize>> {
+    let len = len as i64;
+    let start = if start < 0 { len + start } else { start }.max(0);
+    let stop = if stop < 0 { len + stop } else { stop }.min(len - 1);
+    (start <= stop).then_some(start as usize..=stop as usize)
+}
+
+/// The indexes of elements equal to `element`, as LPOS finds them. The
+/// search starts at the `rank`th match from the head, or from the tail for
+/// a negative rank, in which case indexes are returned tail first. At most
+/// `count` indexes are returned, 0 meaning all of them, and at most
+/// `maxlen` elements are compared, 0 meaning the whole list.
+pub fn positions(
+    list: &VecDeque<String>,
+    element: &str,
+    rank: i64,
+    count: usize,
+    maxlen: usize,
+) -> Vec<usize> {
+    let count = if count == 0 { usize::MAX } else { count };
+    let maxlen = if maxlen == 0 { usize::MAX } else { maxlen };
+    let skip = (rank.unsigned_abs() - 1) as usize;
+
+    let indexes: Box<dyn Iterator<Item = usize>> = if rank > 0 {
+        Box::new(0..list.len())
+    } else {
+        Box::new((0..list.len()).rev())
+    };
+    indexes
+        .take(maxlen)
+        .filter(|&index| list[index] == element)
+        .skip(skip)
+        .take(count)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(elements: &[&str]) -> VecDeque<String> {
+        elements.iter().map(|element| element.to_string()).collect()
+    }
+
+    #[test]
+    fn test_push_and_pop() {
+        let mut numbers = VecDeque::new();
+        push(&mut numbers, End::Left, ["a".into(), "b".into()]);
+        push(&mut numbers, End::Right, ["c".into()]);
+        assert_eq!(numbers, list(&["b", "a", "c"]));
+
+        assert_eq!(pop(&mut numbers, End::Right, 2), vec!["c", "a"]);
+        assert_eq!(pop(&mut numbers, End::Left, 5), vec!["b"]);
+        assert!(numbers.is_empty());
+    }
+
+    #[test]
+    fn test_listpack_limit() {
+        assert_eq!("3".parse(), Ok(ListpackLimit::Entries(3)));
+        assert_eq!("-1".parse(), Ok(ListpackLimit::Bytes(4096)));
+        assert_eq!("-5".parse(), Ok(ListpackLimit::Bytes(65536)));
+        assert!("0".parse::<ListpackLimit>().is_err());
+        assert!("-6".parse::<ListpackLimit>().is_err());
+
+        let letters = list(&["a", "b", "c"]);
+        assert!(ListpackLimit::Entries(3).fits(&letters));
+        assert!(!ListpackLimit::Entries(2).fits(&letters));
+        assert!(ListpackLimit::default().fits(&letters));
+        assert!(!ListpackLimit::Entries(3).fits(&list(&[&"x".repeat(8192)])));
+        assert!(!ListpackLimit::Bytes(4096).fits(&list(&[&"x".repeat(4096)])));
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut letters = list(&["a", "x", "b", "x", "c", "x"]);
+        assert_eq!(remove(&mut letters, "x", 1), 1);
+        assert_eq!(letters, list(&["a", "b", "x", "c", "x"]));
+        assert_eq!(remove(&mut letters, "x", -1), 1);
+        assert_eq!(letters, list(&["a", "b", "x", "c"]));
+        assert_eq!(remove(&mut letters, "missing", 0), 0);
+
+        let mut letters = list(&["x", "a", "x", "x"]);
+        assert_eq!(remove(&mut letters, "x", -2), 2);
+        assert_eq!(letters, list(&["x", "a"]));
+        assert_eq!(remove(&mut letters, "x", 0), 1);
+        assert_eq!(letters, list(&["a"]));
+    }
+
+    #[test]
+    fn test_range() {
+        assert_eq!(range(5, 0, -1), Some(0..=4));
+        assert_eq!(range(5, -3, 2), Some(2..=2));
+        assert_eq!(range(5, -100, 100), Some(0..=4));
+        assert_eq!(range(5, 3, 1), None);
+        assert_eq!(range(5, 5, 10), None);
+        assert_eq!(range(0, 0, -1), None);
+    }
+
+    #[test]
+    fn test_positions() {
+        // The examples of the LPOS documentation.
+        let letters = list(&["a", "b", "c", "d", "1", "2", "3", "4", "3", "3", "3"]);
+        assert_eq!(positions(&letters, "3", 1, 1, 0), vec![6]);
+        assert_eq!(positions(&letters, "3", 2, 0, 0), vec![8, 9, 10]);
+
+        let letters = list(&["a", "b", "c", "1", "2", "3", "c", "c"]);
+        assert_eq!(positions(&letters, "c", 1, 1, 0), vec![2]);
+        assert_eq!(positions(&letters, "c", 2, 1, 0), vec![6]);
+        assert_eq!(positions(&letters, "c", -1, 1, 0), vec![7]);
+        assert_eq!(positions(&letters, "c", 1, 2, 0), vec![2, 6]);
+        assert_eq!(positions(&letters, "c", 1, 0, 0), vec![2, 6, 7]);
+        assert_eq!(positions(&letters, "c", -1, 2, 0), vec![7, 6]);
+        assert_eq!(positions(&letters, "c", -2, 0, 0), vec![6, 2]);
+
+        // MAXLEN bounds the comparisons, not the matches.
+        assert_eq!(positions(&letters, "c", 1, 0, 1), Vec::<usize>::new());
+        assert_eq!(positions(&letters, "c", 1, 0, 3), vec![2]);
+        assert_eq!(positions(&letters, "c", -1, 0, 2), vec![7, 6]);
+        assert_eq!(positions(&letters, "c", 2, 0, 3), Vec::<usize>::new());
+
+        assert!(positions(&letters, "missing", 1, 0, 0).is_empty());
+    }
+}
@@ -0,0 +1,151 @@
+use std::{str::FromStr, sync::OnceLock};
+
+use tracing_subscriber::{
+    fmt, layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Registry,
+};
+
+/// Verbosity of the server log, named after the Redis `loglevel` values.
+/// `notice`, the default, logs tracing's `info` events, so the two more
+/// verbose levels map to `debug` and `trace`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogLevel {
+    Debug,
+    Verbose,
+    #[default]
+    Notice,
+    Warning,
+}
+
+impl LogLevel {
+    /// The name the level is configured with.
+    pub fn name(self) -> &'static str {
+        match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Verbose => "verbose",
+            LogLevel::Notice => "notice",
+            LogLevel::Warning => "warning",
+        }
+    }
+
+    fn filter(self) -> EnvFilter {
+        EnvFilter::new(match self {
+            LogLevel::Debug => "trace",
+            LogLevel::Verbose => "debug",
+            LogLevel::Notice => "info",
+            LogLevel::Warning => "warn",
+        })
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "debug" => Ok(LogLevel::Debug),
+            "verbose" => Ok(LogLevel::Verbose),
+            "notice" => Ok(LogLevel::Notice),
+            "warning" => Ok(LogLevel::Warning),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Changes the level of an installed subscriber's filter.
+#[derive(Debug)]
+pub struct Logger {
+    handle: reload::Handle<EnvFilter, Registry>,
+}
+
+impl Logger {
+    /// A logger and the filter layer it controls, to be the first layer of
+    /// the subscriber.
+    fn new(level: LogLevel) -> (Self, reload::Layer<EnvFilter, Registry>) {
+        let (filter, handle) = reload::Layer::new(level.filter());
+        (Logger { handle }, filter)
+    }
+
+    /// Applies `level` to every event logged from now on.
+    pub fn set_level(&self, level: LogLevel) -> Result<(), reload::Error> {
+        self.handle.reload(level.filter())
+    }
+}
+
+/// The logger of the global subscriber, once `init` has installed it.
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Installs the global subscriber, logging to stdout at `level`.
+pub fn init(level: LogLevel) {
+    let (logger, filter) = Logger::new(level);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer())
+        .init();
+    LOGGER
+        .set(logger)
+        .expect("logging is initialized only once");
+}
+
+/// The logger of the global subscriber. `None` when nothing was installed,
+/// as in tests that run the server in process.
+pub fn logger() -> Option<&'static Logger> {
+    LOGGER.get()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+
+    /// Collects formatted events in memory.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_set_level_at_runtime() {
+        let captured = Captured::default();
+        let (logger, filter) = Logger::new(LogLevel::Notice);
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::registry().with(filter).with(
+            fmt::layer()
+                .with_ansi(false)
+                .with_writer(move || writer.clone()),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!("before");
+            tracing::info!("notice");
+            logger.set_level(LogLevel::Debug).unwrap();
+            tracing::debug!("after");
+            logger.set_level(LogLevel::Warning).unwrap();
+            tracing::info!("silenced");
+        });
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        assert!(!output.contains("before"));
+        assert!(output.contains("notice"));
+        assert!(output.contains("DEBUG") && output.contains("after"));
+        assert!(!output.contains("silenced"));
+    }
+
+    #[test]
+    fn test_parse_level() {
+        assert_eq!("VERBOSE".parse(), Ok(LogLevel::Verbose));
+        assert_eq!("info".parse::<LogLevel>(), Err(()));
+    }
+}
@@ -1,51 +1,214 @@
-use std::sync::Arc;
 use persistence::storage::Storage;
+use socket2::{SockRef, TcpKeepalive};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 use thiserror::Error;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
     net::{TcpListener, TcpStream},
+    signal,
+    sync::watch,
+    time,
 };
-use tracing::{error, info, Level};
+use tracing::{debug, error, info, warn};
 
+mod acl;
+mod bitmap;
+mod client;
+mod clock;
+mod cluster;
+mod command_table;
 mod commands;
-mod resp;
+mod config;
+mod glob;
+mod help;
+mod http;
+mod hyperloglog;
+mod info;
+mod latency;
+mod lcs;
+mod lfu;
+mod list;
+mod logging;
+mod migrate;
 mod persistence;
+mod pubsub;
+mod ratelimit;
+mod read_buffer;
+mod replication;
+mod resp;
+mod scan;
+mod scripting;
+mod set;
+mod stream;
+mod tracking;
+mod zset;
 
+use client::Client;
 use commands::Command;
-use resp::Resp;
+use config::Config;
+use ratelimit::TokenBucket;
+use read_buffer::ReadBuffer;
+use resp::{RequestParser, Resp};
 
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
-    #[error("Protocol error: {0}")]
+    #[error("ERR Protocol error: {0}")]
     Protocol(String),
 
     #[error("Command error: {0}")]
     Command(String),
+
+    #[error("ERR unknown command '{name}', with args beginning with: {args}")]
+    UnknownCommand { name: String, args: String },
+
+    #[error("ERR Unknown subcommand '{subcommand}'. Valid {command} subcommands are: {valid}")]
+    UnknownSubcommand {
+        command: &'static str,
+        subcommand: String,
+        valid: String,
+    },
+
+    #[error("Config error: {0}")]
+    Config(String),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-async fn handle_client(mut stream: TcpStream, storage: Arc<Storage>) {
-    let mut buffer = vec![0; 1024];
+/// Owns a connection's client state and releases its subscriptions and
+/// tracked keys however the connection ends, including when it is handed
+/// over to a replica or change feed.
+struct Session {
+    client: Client,
+    storage: Arc<Storage>,
+}
 
-    loop {
-        match stream.read(&mut buffer).await {
-            Ok(n) if n == 0 => break,
-            Ok(n) => {
-                let response = match Resp::parse(&buffer[..n]) {
-                    Ok(Some(resp)) => match Command::from_resp(resp) {
-                        Ok(cmd) => cmd.execute(&storage).await,
-                        Err(e) => Resp::Error(e.to_string()),
-                    },
-                    Ok(None) => Resp::Error("Empty request".into()),
-                    Err(e) => Resp::Error(e.to_string()),
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.storage.pubsub().unsubscribe_all(&mut self.client);
+        self.storage.tracking().disable(&mut self.client);
+        debug!(id = self.client.id, name = ?self.client.name, "Client disconnected");
+    }
+}
+
+/// Serves one client connection over any byte stream: TCP sockets in the
+/// server, in-memory pipes in tests.
+async fn handle_client<S>(mut stream: S, storage: Arc<Storage>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut buffer = ReadBuffer::new();
+    let mut parser = RequestParser::default();
+    let mut session = Session {
+        client: Client::new(storage.acl()),
+        storage: Arc::clone(&storage),
+    };
+    let client = &mut session.client;
+    let mut limiter = storage
+        .config()
+        .max_commands_per_second
+        .map(TokenBucket::new);
+
+    'connection: loop {
+        let read = tokio::select! {
+            read = buffer.read_from(&mut stream) => read,
+            () = client.mailbox.overflowed() => {
+                warn!(id = client.id, "Disconnecting subscriber over the pubsub output buffer limit");
+                break;
+            }
+            // The client holds a mailbox itself, so the queue never closes.
+            Some(message) = client.messages.recv() => {
+                // A subscriber that stops reading blocks the write, so the
+                // limit has to be watched while writing too.
+                let written = tokio::select! {
+                    written = stream.write_all(&message) => written,
+                    () = client.mailbox.overflowed() => {
+                        warn!(id = client.id, "Disconnecting subscriber over the pubsub output buffer limit");
+                        break;
+                    }
                 };
+                client.mailbox.written(&message);
+                if let Err(e) = written {
+                    error!("Failed to write message: {}", e);
+                    break;
+                }
+                continue;
+            }
+        };
 
-                if let Err(e) = stream.write_all(&response.into_bytes()).await {
-                    error!("Failed to write response: {}", e);
+        match read {
+            Ok(0) => break,
+            Ok(_) => {
+                // Every complete request received so far is answered in
+                // order; an incomplete one waits for the rest of its bytes.
+                let mut quit = false;
+                while !quit {
+                    let request = match parser.parse(&mut buffer) {
+                        Ok(Some(request)) => Ok(request),
+                        Ok(None) => break,
+                        Err(e) => Err(e),
+                    };
+                    let throttled = limiter
+                        .as_mut()
+                        .is_some_and(|limiter| !limiter.try_acquire());
+                    let response = match request {
+                        // The rest of the stream can't be framed any more,
+                        // so the connection is closed after the error, as
+                        // Redis does. Errors in a well-framed request, by
+                        // contrast, leave the connection open.
+                        Err(e) => {
+                            quit = true;
+                            Resp::Error(e.to_string())
+                        }
+                        _ if throttled => Resp::Error("ERR rate limit exceeded".into()),
+                        Ok(resp) => match Command::from_resp(resp) {
+                            Ok(cmd @ Command::Sync)
+                                if !client.is_subscribed()
+                                    && storage.acl().check(client, &cmd).is_ok() =>
+                            {
+                                info!("Replica connected, starting full resync");
+                                if let Err(e) = replication::serve_replica(stream, &storage).await {
+                                    error!("Replica stream closed: {}", e);
+                                }
+                                return;
+                            }
+                            Ok(cmd @ Command::ChangeFeed { snapshot })
+                                if !client.is_subscribed()
+                                    && storage.acl().check(client, &cmd).is_ok() =>
+                            {
+                                info!("Change feed consumer connected");
+                                if let Err(e) =
+                                    replication::serve_changefeed(stream, &storage, snapshot).await
+                                {
+                                    error!("Change feed closed: {}", e);
+                                }
+                                return;
+                            }
+                            Ok(cmd) => {
+                                quit = matches!(cmd, Command::Quit);
+                                cmd.execute(&storage, client).await
+                            }
+                            Err(e) => Resp::Error(e.to_string()),
+                        },
+                    };
+
+                    if let Err(e) = stream.write_all(&response.into_bytes()).await {
+                        error!("Failed to write response: {}", e);
+                        break 'connection;
+                    }
+                }
+                if quit {
+                    // Flushes the reply before the connection is dropped.
+                    let _ = stream.shutdown().await;
                     break;
                 }
             }
@@ -57,24 +220,55 @@ async fn handle_client(mut stream: TcpStream, storage: Arc<Storage>) {
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    tracing_subscriber::fmt().with_max_level(Level::INFO).init();
-
-    let addr = "127.0.0.1:6379";
-    let listener = TcpListener::bind(addr).await?;
-    let storage = Arc::new(Storage::new()?);
+/// Applies the configured TCP options to an accepted connection.
+fn configure_socket(stream: &TcpStream, config: &Config) -> std::io::Result<()> {
+    stream.set_nodelay(config.tcp_nodelay)?;
+    if let Some(idle) = config.tcp_keepalive {
+        SockRef::from(stream).set_tcp_keepalive(&TcpKeepalive::new().with_time(idle))?;
+    }
+    Ok(())
+}
 
-    info!("Server listening on {}", addr);
+const BUSY_REPLY: &[u8] = b"-BUSY Too many connections, try again later\r\n";
 
+/// Accepts connections until `drain` turns true, then closes the listener so
+/// new connections are refused while accepted ones keep being served.
+async fn serve(listener: TcpListener, storage: Arc<Storage>, mut drain: watch::Receiver<bool>) {
     loop {
-        match listener.accept().await {
-            Ok((stream, addr)) => {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            Ok(_) = drain.wait_for(|draining| *draining) => {
+                info!("Draining, no longer accepting connections");
+                return;
+            }
+        };
+
+        match accepted {
+            Ok((mut stream, addr)) => {
+                let stats = storage.stats();
+                if storage
+                    .config()
+                    .busy_threshold
+                    .is_some_and(|threshold| stats.connected_clients() >= threshold)
+                {
+                    warn!("Rejecting connection from {}: server is busy", addr);
+                    stats.record_rejected_connection();
+                    tokio::spawn(async move {
+                        let _ = stream.write_all(BUSY_REPLY).await;
+                    });
+                    continue;
+                }
+
                 info!("New connection from {}", addr);
+                if let Err(e) = configure_socket(&stream, storage.config()) {
+                    warn!("Failed to set socket options for {}: {}", addr, e);
+                }
+                let connection = stats.connect();
                 let storage = storage.clone();
 
                 tokio::spawn(async move {
                     handle_client(stream, storage).await;
+                    drop(connection);
                 });
             }
             Err(e) => {
@@ -84,22 +278,216 @@ async fn main() -> Result<()> {
     }
 }
 
+/// Binds a listener on every configured address, failing on the first one
+/// that can't be bound.
+async fn bind(ips: &[IpAddr], port: u16) -> Result<Vec<TcpListener>> {
+    let mut listeners = Vec::with_capacity(ips.len());
+    for ip in ips {
+        let addr = SocketAddr::new(*ip, port);
+        let listener = TcpListener::bind(addr).await.map_err(|e| {
+            Error::Io(std::io::Error::new(
+                e.kind(),
+                format!("failed to bind {}: {}", addr, e),
+            ))
+        })?;
+        listeners.push(listener);
+    }
+    Ok(listeners)
+}
+
+/// Resolves when the process is asked to drain with SIGUSR1.
+async fn drain_signal() {
+    #[cfg(unix)]
+    if let Ok(mut drain) = signal::unix::signal(signal::unix::SignalKind::user_defined1()) {
+        drain.recv().await;
+        return;
+    }
+    std::future::pending::<()>().await;
+}
+
+/// Resolves once every client has disconnected, or after `timeout`.
+async fn drain(storage: &Storage, timeout: Duration) {
+    let disconnected = async {
+        while storage.stats().connected_clients() > 0 {
+            time::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    if time::timeout(timeout, disconnected).await.is_err() {
+        warn!(
+            "{} clients still connected after the drain timeout",
+            storage.stats().connected_clients()
+        );
+    }
+}
+
+/// Resolves when the process is asked to stop with Ctrl-C or SIGTERM.
+async fn shutdown_signal() {
+    let terminate = async {
+        #[cfg(unix)]
+        if let Ok(mut terminate) = signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            terminate.recv().await;
+            return;
+        }
+        std::future::pending::<()>().await;
+    };
+
+    tokio::select! {
+        _ = signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let config = Config::from_args(std::env::args().skip(1))?;
+    logging::init(config.loglevel);
+
+    let listeners = bind(&config.bind, config.port).await?;
+    // Health checks are answered while the dataset loads, reporting 503
+    // until it is done and the RESP listeners are serving.
+    let listening = Arc::new(AtomicUsize::new(0));
+    let ready = Arc::new(AtomicBool::new(false));
+    if let Some(port) = config.http_port {
+        for listener in bind(&config.bind, port).await? {
+            info!("Health checks served on {}", listener.local_addr()?);
+            tokio::spawn(http::serve(listener, listening.clone(), ready.clone()));
+        }
+    }
+    let replicaof = config.replicaof.clone();
+    let storage = tokio::task::spawn_blocking(move || Storage::new(config))
+        .await
+        .map_err(std::io::Error::other)??;
+    let storage = Arc::new(storage);
+    ready.store(true, Ordering::Relaxed);
+
+    if let Some((host, port)) = replicaof {
+        storage.replicate_from(host, port);
+    }
+
+    let (drain_tx, drain_rx) = watch::channel(false);
+    for listener in listeners {
+        info!("Server listening on {}", listener.local_addr()?);
+        listening.fetch_add(1, Ordering::Relaxed);
+        let serving = serve(listener, storage.clone(), drain_rx.clone());
+        let listening = listening.clone();
+        tokio::spawn(async move {
+            serving.await;
+            listening.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+
+    tokio::select! {
+        _ = shutdown_signal() => {}
+        _ = drain_signal() => {
+            info!("Draining: waiting for connected clients to disconnect");
+            drain_tx.send_replace(true);
+            ready.store(false, Ordering::Relaxed);
+            // A second signal still stops the server right away.
+            tokio::select! {
+                _ = drain(&storage, storage.config().drain_timeout) => {}
+                _ = shutdown_signal() => {}
+            }
+        }
+    }
+    info!("Shutting down");
+    storage.shutdown().await?;
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use std::{
-        io::{Read, Write},
-        net::TcpStream,
+        io::{BufRead, BufReader, Read, Write},
+        net::{SocketAddr, TcpStream},
+        path::Path,
+        sync::{mpsc, Arc},
         thread,
-        time::Duration,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    use socket2::SockRef;
+    use tokio::{net::TcpListener, sync::watch};
+
+    use crate::{
+        acl::User,
+        bind,
+        config::Config,
+        configure_socket, handle_client,
+        list::ListpackLimit,
+        persistence::{
+            aof::{AofManager, AppendFsync},
+            MaxMemoryPolicy, Operation, Value,
+        },
+        serve, Storage,
     };
 
+    fn start_server() -> SocketAddr {
+        start_server_with(Config {
+            dir: tempfile::tempdir().unwrap().keep(),
+            ..Config::default()
+        })
+    }
+
+    fn start_server_with(config: Config) -> SocketAddr {
+        let (tx, rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+            runtime.block_on(async move {
+                let storage = Arc::new(Storage::new(config).unwrap());
+                let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                tx.send(listener.local_addr().unwrap()).unwrap();
+                let (_drain, draining) = watch::channel(false);
+                serve(listener, storage, draining).await;
+            });
+        });
+
+        rx.recv().unwrap()
+    }
+
+    fn preload(dir: &Path, key: &str, value: &str) {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let aof = AofManager::new(dir.join("appendonly.aof"), AppendFsync::EverySec).unwrap();
+            let op = Operation::Set {
+                key: key.into(),
+                value: Value::String(value.to_string().into()),
+                expires_at: None,
+            };
+            aof.append_operation(0, &op).await.unwrap();
+            aof.sync().await.unwrap();
+        });
+    }
+
+    fn send(stream: &mut TcpStream, args: &[&str]) -> String {
+        let args: Vec<&[u8]> = args.iter().map(|arg| arg.as_bytes()).collect();
+        String::from_utf8_lossy(&send_bytes(stream, &args)).into_owned()
+    }
+
+    /// Like [`send`], for arguments and replies that may not be UTF-8.
+    fn send_bytes(stream: &mut TcpStream, args: &[&[u8]]) -> Vec<u8> {
+        let mut command = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            command.extend(format!("${}\r\n", arg.len()).into_bytes());
+            command.extend(*arg);
+            command.extend(b"\r\n");
+        }
+        stream.write_all(&command).unwrap();
+        stream.flush().unwrap();
+
+        let mut read_buffer = [0; 1024];
+        let n = stream.read(&mut read_buffer).unwrap();
+        read_buffer[..n].to_vec()
+    }
+
     #[test]
     fn test_set_and_get() {
-        let mut stream = TcpStream::connect("127.0.0.1:6379").unwrap();
+        let mut stream = TcpStream::connect(start_server()).unwrap();
         let mut read_buffer = [0; 1024];
 
         let set_command = "*3\r\n$3\r\nSET\r\n$5\r\nhello\r\n$5\r\nworld\r\n";
-        stream.write(set_command.as_bytes()).unwrap();
+        stream.write_all(set_command.as_bytes()).unwrap();
         stream.flush().unwrap();
 
         let n = stream.read(&mut read_buffer).unwrap();
@@ -107,7 +495,7 @@ mod tests {
         assert_eq!(set_response, "+OK\r\n");
 
         let get_command = "*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n";
-        stream.write(get_command.as_bytes()).unwrap();
+        stream.write_all(get_command.as_bytes()).unwrap();
         stream.flush().unwrap();
 
         let n = stream.read(&mut read_buffer).unwrap();
@@ -116,7 +504,7 @@ mod tests {
         assert_eq!(get_response, "$5\r\nworld\r\n");
 
         let get_missing = "*2\r\n$3\r\nGET\r\n$7\r\nmissing\r\n";
-        stream.write(get_missing.as_bytes()).unwrap();
+        stream.write_all(get_missing.as_bytes()).unwrap();
         stream.flush().unwrap();
 
         let n = stream.read(&mut read_buffer).unwrap();
@@ -126,11 +514,11 @@ mod tests {
 
     #[test]
     fn test_ping() {
-        let mut stream = TcpStream::connect("127.0.0.1:6379").unwrap();
+        let mut stream = TcpStream::connect(start_server()).unwrap();
         let mut read_buffer = [0; 1024];
 
         let message = "*1\r\n$4\r\nPING\r\n";
-        stream.write(message.as_bytes()).unwrap();
+        stream.write_all(message.as_bytes()).unwrap();
         stream.flush().unwrap();
 
         let n = stream.read(&mut read_buffer).unwrap();
@@ -140,11 +528,11 @@ mod tests {
 
     #[test]
     fn test_echo() {
-        let mut stream = TcpStream::connect("127.0.0.1:6379").unwrap();
+        let mut stream = TcpStream::connect(start_server()).unwrap();
         let mut read_buffer = [0; 1024];
 
         let message = "*2\r\n$4\r\nECHO\r\n$3\r\nhey\r\n";
-        stream.write(message.as_bytes()).unwrap();
+        stream.write_all(message.as_bytes()).unwrap();
         stream.flush().unwrap();
 
         let n = stream.read(&mut read_buffer).unwrap();
@@ -152,14 +540,140 @@ mod tests {
         assert_eq!(response, "+hey\r\n");
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_handle_client_over_in_memory_stream() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let storage = Arc::new(
+            Storage::new(Config {
+                dir: tempfile::tempdir().unwrap().keep(),
+                ..Config::default()
+            })
+            .unwrap(),
+        );
+        let (mut client, server) = tokio::io::duplex(1024);
+        tokio::spawn(handle_client(server, storage));
+
+        let mut buffer = [0; 64];
+        client
+            .write_all(b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n")
+            .await
+            .unwrap();
+        let n = client.read(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..n], b"+OK\r\n");
+
+        client
+            .write_all(b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n")
+            .await
+            .unwrap();
+        let n = client.read(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..n], b"$5\r\nvalue\r\n");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_change_feed_connection_stops_tracking_on_disconnect() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let storage = Arc::new(
+            Storage::new(Config {
+                dir: tempfile::tempdir().unwrap().keep(),
+                ..Config::default()
+            })
+            .unwrap(),
+        );
+        let (mut client, server) = tokio::io::duplex(4096);
+        let connection = tokio::spawn(handle_client(server, Arc::clone(&storage)));
+
+        let mut buffer = [0; 4096];
+        client
+            .write_all(b"*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n")
+            .await
+            .unwrap();
+        let n = client.read(&mut buffer).await.unwrap();
+        assert!(buffer[..n].starts_with(b"*14\r\n"));
+        client
+            .write_all(b"*3\r\n$6\r\nCLIENT\r\n$8\r\nTRACKING\r\n$2\r\nON\r\n")
+            .await
+            .unwrap();
+        let n = client.read(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..n], b"+OK\r\n");
+        assert_eq!(storage.tracking().clients(), 1);
+
+        // The connection turns into a change feed and returns from the
+        // handler once the consumer hangs up.
+        client
+            .write_all(b"*1\r\n$10\r\nCHANGEFEED\r\n")
+            .await
+            .unwrap();
+        drop(client);
+        connection.await.unwrap();
+        assert_eq!(storage.tracking().clients(), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_drain_refuses_new_connections() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let storage = Arc::new(
+            Storage::new(Config {
+                dir: tempfile::tempdir().unwrap().keep(),
+                ..Config::default()
+            })
+            .unwrap(),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (drain, draining) = watch::channel(false);
+        let server = tokio::spawn(serve(listener, storage.clone(), draining));
+
+        let mut buffer = [0; 64];
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+        let n = stream.read(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..n], b"+PONG\r\n");
+
+        drain.send_replace(true);
+        server.await.unwrap();
+        assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+
+        // The connection accepted before the drain is still served.
+        stream
+            .write_all(b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n")
+            .await
+            .unwrap();
+        let n = stream.read(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..n], b"+OK\r\n");
+        assert_eq!(storage.stats().connected_clients(), 1);
+    }
+
+    #[test]
+    fn test_max_commands_per_second() {
+        let addr = start_server_with(Config {
+            dir: tempfile::tempdir().unwrap().keep(),
+            max_commands_per_second: Some(10),
+            ..Config::default()
+        });
+        let mut flooding = TcpStream::connect(addr).unwrap();
+        let mut polite = TcpStream::connect(addr).unwrap();
+
+        let replies: Vec<String> = (0..30).map(|_| send(&mut flooding, &["PING"])).collect();
+        assert_eq!(replies[0], "+PONG\r\n");
+        assert!(replies.contains(&"-ERR rate limit exceeded\r\n".to_string()));
+
+        // The limit is per connection.
+        for _ in 0..5 {
+            assert_eq!(send(&mut polite, &["PING"]), "+PONG\r\n");
+        }
+    }
+
     #[test]
     fn test_set_with_px() {
-        let mut stream = TcpStream::connect("127.0.0.1:6379").unwrap();
+        let mut stream = TcpStream::connect(start_server()).unwrap();
         let mut read_buffer = [0; 1024];
 
         let set_command =
             "*5\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n$2\r\nPX\r\n$4\r\n1000\r\n";
-        stream.write(set_command.as_bytes()).unwrap();
+        stream.write_all(set_command.as_bytes()).unwrap();
         stream.flush().unwrap();
 
         let n = stream.read(&mut read_buffer).unwrap();
@@ -167,7 +681,7 @@ mod tests {
         assert_eq!(set_response, "+OK\r\n");
 
         let get_command = "*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n";
-        stream.write(get_command.as_bytes()).unwrap();
+        stream.write_all(get_command.as_bytes()).unwrap();
         stream.flush().unwrap();
 
         let n = stream.read(&mut read_buffer).unwrap();
@@ -176,7 +690,7 @@ mod tests {
 
         thread::sleep(Duration::from_millis(1100));
 
-        stream.write(get_command.as_bytes()).unwrap();
+        stream.write_all(get_command.as_bytes()).unwrap();
         stream.flush().unwrap();
 
         let n = stream.read(&mut read_buffer).unwrap();
@@ -187,4 +701,2843 @@ mod tests {
 
         thread::sleep(Duration::from_millis(100));
     }
+
+    #[test]
+    fn test_getset() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        assert_eq!(send(&mut stream, &["GETSET", "key", "first"]), "$-1\r\n");
+        assert_eq!(
+            send(&mut stream, &["SET", "key", "second", "PX", "200"]),
+            "+OK\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["GETSET", "key", "third"]),
+            "$6\r\nsecond\r\n"
+        );
+
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(send(&mut stream, &["GET", "key"]), "$5\r\nthird\r\n");
+    }
+
+    #[test]
+    fn test_msetnx() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        send(&mut stream, &["SET", "b", "existing"]);
+        assert_eq!(send(&mut stream, &["MSETNX", "a", "1", "b", "2"]), ":0\r\n");
+        assert_eq!(send(&mut stream, &["GET", "a"]), "$-1\r\n");
+        assert_eq!(send(&mut stream, &["GET", "b"]), "$8\r\nexisting\r\n");
+
+        assert_eq!(send(&mut stream, &["MSETNX", "a", "1", "c", "3"]), ":1\r\n");
+        assert_eq!(send(&mut stream, &["GET", "a"]), "$1\r\n1\r\n");
+        assert_eq!(send(&mut stream, &["GET", "c"]), "$1\r\n3\r\n");
+
+        assert!(send(&mut stream, &["MSETNX", "a", "1", "b"]).starts_with('-'));
+    }
+
+    #[test]
+    fn test_del() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        send(&mut stream, &["SET", "string", "value"]);
+        send(&mut stream, &["HSET", "hash", "field", "value"]);
+        send(&mut stream, &["SET", "expiring", "value", "PX", "20"]);
+        thread::sleep(Duration::from_millis(50));
+
+        // Expired and missing keys aren't counted.
+        assert_eq!(
+            send(
+                &mut stream,
+                &["DEL", "string", "hash", "expiring", "missing"]
+            ),
+            ":2\r\n"
+        );
+        assert_eq!(send(&mut stream, &["GET", "string"]), "$-1\r\n");
+        assert_eq!(send(&mut stream, &["HGET", "hash", "field"]), "$-1\r\n");
+        assert_eq!(send(&mut stream, &["DEL", "string"]), ":0\r\n");
+
+        assert!(send(&mut stream, &["DEL"]).starts_with('-'));
+    }
+
+    #[test]
+    fn test_lcs() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        send(&mut stream, &["SET", "key1", "ohmytext"]);
+        send(&mut stream, &["SET", "key2", "mynewtext"]);
+
+        assert_eq!(
+            send(&mut stream, &["LCS", "key1", "key2"]),
+            "$6\r\nmytext\r\n"
+        );
+        assert_eq!(send(&mut stream, &["LCS", "key1", "key2", "LEN"]), ":6\r\n");
+        assert_eq!(
+            send(
+                &mut stream,
+                &[
+                    "LCS",
+                    "key1",
+                    "key2",
+                    "IDX",
+                    "MINMATCHLEN",
+                    "4",
+                    "WITHMATCHLEN"
+                ]
+            ),
+            "*4\r\n$7\r\nmatches\r\n*1\r\n*3\r\n*2\r\n:4\r\n:7\r\n*2\r\n:5\r\n:8\r\n:4\r\n\
+             $3\r\nlen\r\n:6\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["LCS", "key1", "missing", "LEN"]),
+            ":0\r\n"
+        );
+    }
+
+    #[test]
+    fn test_hmset_and_hmget() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        assert_eq!(
+            send(&mut stream, &["HMSET", "user", "name", "ada", "lang", "en"]),
+            "+OK\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["HMGET", "user", "name", "missing", "lang"]),
+            "*3\r\n$3\r\nada\r\n$-1\r\n$2\r\nen\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["HSET", "user", "lang", "fr", "age", "36"]),
+            ":1\r\n"
+        );
+        assert_eq!(send(&mut stream, &["HGET", "user", "lang"]), "$2\r\nfr\r\n");
+        assert_eq!(
+            send(&mut stream, &["HMGET", "nokey", "a", "b"]),
+            "*2\r\n$-1\r\n$-1\r\n"
+        );
+
+        let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        send(&mut stream, &["SET", "string", "value"]);
+        assert_eq!(send(&mut stream, &["HMGET", "string", "a"]), wrongtype);
+        assert_eq!(send(&mut stream, &["HMSET", "string", "a", "1"]), wrongtype);
+        assert_eq!(send(&mut stream, &["GET", "user"]), wrongtype);
+    }
+
+    #[test]
+    fn test_hrandfield() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        assert_eq!(send(&mut stream, &["HRANDFIELD", "hash"]), "$-1\r\n");
+        assert_eq!(send(&mut stream, &["HRANDFIELD", "hash", "3"]), "*0\r\n");
+
+        send(&mut stream, &["HSET", "hash", "a", "1", "b", "2", "c", "3"]);
+
+        let response = send(&mut stream, &["HRANDFIELD", "hash", "10"]);
+        assert!(response.starts_with("*3\r\n"), "{}", response);
+        let mut fields: Vec<&str> = response.split("\r\n").skip(2).step_by(2).collect();
+        fields.sort();
+        assert_eq!(fields, ["a", "b", "c"]);
+
+        let response = send(&mut stream, &["HRANDFIELD", "hash", "-5", "WITHVALUES"]);
+        assert!(response.starts_with("*10\r\n"), "{}", response);
+    }
+
+    #[test]
+    fn test_hscan_and_scan() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        let fields: Vec<String> = (0..500).map(|i| format!("field:{}", i)).collect();
+        for chunk in fields.chunks(20) {
+            let mut hset = vec!["HSET", "big"];
+            for field in chunk {
+                hset.extend([field.as_str(), "v"]);
+            }
+            assert_eq!(send(&mut stream, &hset), ":20\r\n");
+        }
+
+        let mut seen = Vec::new();
+        let mut cursor = "0".to_string();
+        loop {
+            let response = send(&mut stream, &["HSCAN", "big", &cursor, "COUNT", "20"]);
+            let lines: Vec<&str> = response.split("\r\n").collect();
+            cursor = lines[2].to_string();
+            // Each field/value pair spans four lines: "$len", field, "$len", value.
+            seen.extend(lines[5..].iter().step_by(4).map(|field| field.to_string()));
+            if cursor == "0" {
+                break;
+            }
+        }
+        seen.sort();
+        let mut expected = fields.clone();
+        expected.sort();
+        assert_eq!(seen, expected);
+
+        send(&mut stream, &["SET", "user:1", "a"]);
+        send(&mut stream, &["SET", "session:1", "b"]);
+        assert_eq!(
+            send(
+                &mut stream,
+                &["SCAN", "0", "MATCH", "user:*", "COUNT", "100"]
+            ),
+            "*2\r\n$1\r\n0\r\n*1\r\n$6\r\nuser:1\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["SCAN", "0", "TYPE", "hash", "COUNT", "100"]),
+            "*2\r\n$1\r\n0\r\n*1\r\n$3\r\nbig\r\n"
+        );
+    }
+
+    #[test]
+    fn test_hyperloglog() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        let elements: Vec<String> = (0..5000).map(|i| format!("e{}", i)).collect();
+        for (i, chunk) in elements.chunks(40).enumerate() {
+            let key = if i % 2 == 0 { "even" } else { "odd" };
+            let mut pfadd = vec!["PFADD", key];
+            pfadd.extend(chunk.iter().map(String::as_str));
+            assert_eq!(send(&mut stream, &pfadd), ":1\r\n");
+        }
+        assert_eq!(send(&mut stream, &["PFADD", "even", "e0"]), ":0\r\n");
+
+        let count = |stream: &mut TcpStream, keys: &[&str]| -> u64 {
+            let mut pfcount = vec!["PFCOUNT"];
+            pfcount.extend(keys);
+            send(stream, &pfcount)[1..].trim_end().parse().unwrap()
+        };
+        let within_bound =
+            |estimate: u64, actual: f64| (estimate as f64 - actual).abs() / actual < 0.03;
+
+        assert!(within_bound(count(&mut stream, &["even"]), 2500.0));
+        assert!(within_bound(count(&mut stream, &["even", "odd"]), 5000.0));
+
+        assert_eq!(
+            send(&mut stream, &["PFMERGE", "all", "even", "odd"]),
+            "+OK\r\n"
+        );
+        assert_eq!(
+            count(&mut stream, &["all"]),
+            count(&mut stream, &["even", "odd"])
+        );
+        assert_eq!(count(&mut stream, &["missing"]), 0);
+
+        send(&mut stream, &["SET", "plain", "value"]);
+        assert_eq!(
+            send(&mut stream, &["PFADD", "plain", "x"]),
+            "-WRONGTYPE Key is not a valid HyperLogLog string value.\r\n"
+        );
+    }
+
+    #[test]
+    fn test_streams() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        let ids: Vec<String> = (0..5)
+            .map(|i| {
+                let response = send(&mut stream, &["XADD", "log", "*", "n", &i.to_string()]);
+                response.lines().nth(1).unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(send(&mut stream, &["XLEN", "log"]), ":5\r\n");
+        assert_eq!(send(&mut stream, &["XLEN", "missing"]), ":0\r\n");
+
+        let parsed: Vec<(u64, u64)> = ids
+            .iter()
+            .map(|id| {
+                let (ms, seq) = id.split_once('-').unwrap();
+                (ms.parse().unwrap(), seq.parse().unwrap())
+            })
+            .collect();
+        assert!(parsed.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let mut expected = String::from("*5\r\n");
+        for (i, id) in ids.iter().enumerate() {
+            expected += &format!(
+                "*2\r\n${}\r\n{}\r\n*2\r\n$1\r\nn\r\n$1\r\n{}\r\n",
+                id.len(),
+                id,
+                i
+            );
+        }
+        assert_eq!(send(&mut stream, &["XRANGE", "log", "-", "+"]), expected);
+
+        let response = send(&mut stream, &["XRANGE", "log", &ids[3], "+", "COUNT", "1"]);
+        assert!(response.starts_with(&format!("*1\r\n*2\r\n${}\r\n{}\r\n", ids[3].len(), ids[3])));
+
+        assert_eq!(
+            send(&mut stream, &["XADD", "log", "1-1", "n", "old"]),
+            "-ERR The ID specified in XADD is equal or smaller than the target stream top item\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["XADD", "other", "5-1", "a", "b"]),
+            "$3\r\n5-1\r\n"
+        );
+        send(&mut stream, &["SET", "plain", "value"]);
+        assert!(send(&mut stream, &["XLEN", "plain"]).starts_with("-WRONGTYPE"));
+    }
+
+    #[test]
+    fn test_lists() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        assert_eq!(send(&mut stream, &["RPUSH", "list", "b", "c"]), ":2\r\n");
+        assert_eq!(send(&mut stream, &["LPUSH", "list", "a", "z"]), ":4\r\n");
+        assert_eq!(
+            send(&mut stream, &["LRANGE", "list", "0", "-1"]),
+            "*4\r\n$1\r\nz\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\nc\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["LRANGE", "list", "-2", "100"]),
+            "*2\r\n$1\r\nb\r\n$1\r\nc\r\n"
+        );
+        assert_eq!(send(&mut stream, &["LRANGE", "list", "3", "1"]), "*0\r\n");
+        assert_eq!(send(&mut stream, &["LLEN", "list"]), ":4\r\n");
+        assert_eq!(
+            send(&mut stream, &["OBJECT", "ENCODING", "list"]),
+            "$8\r\nlistpack\r\n"
+        );
+
+        assert_eq!(send(&mut stream, &["LPOP", "list"]), "$1\r\nz\r\n");
+        assert_eq!(
+            send(&mut stream, &["RPOP", "list", "2"]),
+            "*2\r\n$1\r\nc\r\n$1\r\nb\r\n"
+        );
+        // Popping the last element deletes the key.
+        assert_eq!(
+            send(&mut stream, &["LPOP", "list", "5"]),
+            "*1\r\n$1\r\na\r\n"
+        );
+        assert_eq!(send(&mut stream, &["GET", "list"]), "$-1\r\n");
+        assert_eq!(send(&mut stream, &["LPOP", "list"]), "$-1\r\n");
+        assert_eq!(send(&mut stream, &["LLEN", "list"]), ":0\r\n");
+        assert_eq!(send(&mut stream, &["LRANGE", "list", "0", "-1"]), "*0\r\n");
+
+        send(
+            &mut stream,
+            &["RPUSH", "letters", "a", "b", "c", "1", "2", "3", "c", "c"],
+        );
+        assert_eq!(send(&mut stream, &["LPOS", "letters", "c"]), ":2\r\n");
+        assert_eq!(
+            send(&mut stream, &["LPOS", "letters", "c", "RANK", "-1"]),
+            ":7\r\n"
+        );
+        assert_eq!(
+            send(
+                &mut stream,
+                &["LPOS", "letters", "c", "COUNT", "0", "RANK", "2"]
+            ),
+            "*2\r\n:6\r\n:7\r\n"
+        );
+        assert_eq!(
+            send(
+                &mut stream,
+                &["LPOS", "letters", "c", "COUNT", "0", "MAXLEN", "2"]
+            ),
+            "*0\r\n"
+        );
+        assert_eq!(send(&mut stream, &["LPOS", "letters", "x"]), "$-1\r\n");
+        assert_eq!(send(&mut stream, &["LPOS", "missing", "x"]), "$-1\r\n");
+        assert_eq!(
+            send(&mut stream, &["LPOS", "missing", "x", "COUNT", "1"]),
+            "*0\r\n"
+        );
+        assert!(send(&mut stream, &["LPOS", "letters", "c", "RANK", "0"])
+            .contains("RANK can't be zero"));
+        assert!(send(&mut stream, &["LPOS", "letters", "c", "COUNT", "-1"])
+            .contains("COUNT can't be negative"));
+
+        send(&mut stream, &["SET", "plain", "value"]);
+        for command in [
+            &["LPUSH", "plain", "a"][..],
+            &["RPOP", "plain"],
+            &["LLEN", "plain"],
+            &["LRANGE", "plain", "0", "-1"],
+            &["LPOS", "plain", "a"],
+        ] {
+            assert!(
+                send(&mut stream, command).starts_with("-WRONGTYPE"),
+                "{:?}",
+                command
+            );
+        }
+        assert!(send(&mut stream, &["GET", "letters"]).starts_with("-WRONGTYPE"));
+    }
+
+    #[test]
+    fn test_list_encoding_follows_the_listpack_limit() {
+        let addr = start_server_with(Config {
+            dir: tempfile::tempdir().unwrap().keep(),
+            list_max_listpack_size: ListpackLimit::Entries(3),
+            ..Config::default()
+        });
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        send(&mut stream, &["RPUSH", "list", "a", "b", "c"]);
+        assert_eq!(
+            send(&mut stream, &["OBJECT", "ENCODING", "list"]),
+            "$8\r\nlistpack\r\n"
+        );
+        send(&mut stream, &["RPUSH", "list", "d"]);
+        assert_eq!(
+            send(&mut stream, &["OBJECT", "ENCODING", "list"]),
+            "$9\r\nquicklist\r\n"
+        );
+        assert!(send(&mut stream, &["DEBUG", "OBJECT", "list"]).contains("encoding:quicklist"));
+    }
+
+    #[test]
+    fn test_lrem() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+        let contents = |stream: &mut TcpStream| send(stream, &["LRANGE", "list", "0", "-1"]);
+
+        send(
+            &mut stream,
+            &["RPUSH", "list", "x", "a", "x", "b", "x", "c", "x"],
+        );
+        assert_eq!(send(&mut stream, &["LREM", "list", "2", "x"]), ":2\r\n");
+        assert_eq!(
+            contents(&mut stream),
+            "*5\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\nx\r\n$1\r\nc\r\n$1\r\nx\r\n"
+        );
+        assert_eq!(send(&mut stream, &["LREM", "list", "-1", "x"]), ":1\r\n");
+        assert_eq!(
+            contents(&mut stream),
+            "*4\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\nx\r\n$1\r\nc\r\n"
+        );
+        send(&mut stream, &["RPUSH", "list", "x", "x"]);
+        assert_eq!(send(&mut stream, &["LREM", "list", "0", "x"]), ":3\r\n");
+        assert_eq!(
+            contents(&mut stream),
+            "*3\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\nc\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["LREM", "list", "0", "missing"]),
+            ":0\r\n"
+        );
+        assert_eq!(send(&mut stream, &["LREM", "missing", "0", "x"]), ":0\r\n");
+
+        // Removing every element deletes the key.
+        for element in ["a", "b", "c"] {
+            send(&mut stream, &["LREM", "list", "0", element]);
+        }
+        assert_eq!(send(&mut stream, &["GET", "list"]), "$-1\r\n");
+
+        send(&mut stream, &["SET", "plain", "value"]);
+        assert!(send(&mut stream, &["LREM", "plain", "0", "x"]).starts_with("-WRONGTYPE"));
+    }
+
+    #[test]
+    fn test_ltrim() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        send(&mut stream, &["RPUSH", "list", "a", "b", "c", "d", "e"]);
+        assert_eq!(send(&mut stream, &["LTRIM", "list", "1", "-2"]), "+OK\r\n");
+        assert_eq!(
+            send(&mut stream, &["LRANGE", "list", "0", "-1"]),
+            "*3\r\n$1\r\nb\r\n$1\r\nc\r\n$1\r\nd\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["LTRIM", "list", "-100", "100"]),
+            "+OK\r\n"
+        );
+        assert_eq!(send(&mut stream, &["LLEN", "list"]), ":3\r\n");
+
+        // An empty range deletes the key.
+        assert_eq!(send(&mut stream, &["LTRIM", "list", "2", "1"]), "+OK\r\n");
+        assert_eq!(send(&mut stream, &["GET", "list"]), "$-1\r\n");
+        assert_eq!(
+            send(&mut stream, &["LTRIM", "missing", "0", "1"]),
+            "+OK\r\n"
+        );
+
+        send(&mut stream, &["SET", "plain", "value"]);
+        assert!(send(&mut stream, &["LTRIM", "plain", "0", "1"]).starts_with("-WRONGTYPE"));
+    }
+
+    #[test]
+    fn test_linsert() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        send(&mut stream, &["RPUSH", "list", "a", "c", "c"]);
+        assert_eq!(
+            send(&mut stream, &["LINSERT", "list", "BEFORE", "c", "b"]),
+            ":4\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["LINSERT", "list", "after", "c", "d"]),
+            ":5\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["LRANGE", "list", "0", "-1"]),
+            "*5\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\nc\r\n$1\r\nd\r\n$1\r\nc\r\n"
+        );
+
+        assert_eq!(
+            send(&mut stream, &["LINSERT", "list", "BEFORE", "missing", "x"]),
+            ":-1\r\n"
+        );
+        assert_eq!(send(&mut stream, &["LLEN", "list"]), ":5\r\n");
+        assert_eq!(
+            send(&mut stream, &["LINSERT", "missing", "BEFORE", "a", "x"]),
+            ":0\r\n"
+        );
+        assert!(send(&mut stream, &["LINSERT", "list", "BESIDE", "a", "x"]).starts_with('-'));
+
+        send(&mut stream, &["SET", "plain", "value"]);
+        assert!(
+            send(&mut stream, &["LINSERT", "plain", "BEFORE", "a", "x"]).starts_with("-WRONGTYPE")
+        );
+    }
+
+    #[test]
+    fn test_lmove() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        send(&mut stream, &["RPUSH", "source", "a", "b", "c"]);
+        send(&mut stream, &["RPUSH", "destination", "x"]);
+        assert_eq!(
+            send(&mut stream, &["RPOPLPUSH", "source", "destination"]),
+            "$1\r\nc\r\n"
+        );
+        assert_eq!(
+            send(
+                &mut stream,
+                &["LMOVE", "source", "destination", "LEFT", "RIGHT"]
+            ),
+            "$1\r\na\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["LRANGE", "source", "0", "-1"]),
+            "*1\r\n$1\r\nb\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["LRANGE", "destination", "0", "-1"]),
+            "*3\r\n$1\r\nc\r\n$1\r\nx\r\n$1\r\na\r\n"
+        );
+
+        // Moving the last element deletes the source and creates the
+        // destination.
+        assert_eq!(
+            send(
+                &mut stream,
+                &["LMOVE", "source", "created", "right", "left"]
+            ),
+            "$1\r\nb\r\n"
+        );
+        assert_eq!(send(&mut stream, &["EXPIRETIME", "source"]), ":-2\r\n");
+        assert_eq!(send(&mut stream, &["LLEN", "created"]), ":1\r\n");
+        assert_eq!(
+            send(&mut stream, &["RPOPLPUSH", "source", "destination"]),
+            "$-1\r\n"
+        );
+
+        send(&mut stream, &["SET", "plain", "value"]);
+        assert!(send(&mut stream, &["RPOPLPUSH", "plain", "destination"]).starts_with("-WRONGTYPE"));
+        assert!(send(&mut stream, &["RPOPLPUSH", "destination", "plain"]).starts_with("-WRONGTYPE"));
+        assert_eq!(send(&mut stream, &["LLEN", "destination"]), ":3\r\n");
+        assert!(send(
+            &mut stream,
+            &["LMOVE", "destination", "created", "UP", "LEFT"]
+        )
+        .starts_with('-'));
+    }
+
+    #[test]
+    fn test_lmove_rotation() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        send(&mut stream, &["RPUSH", "list", "a", "b", "c"]);
+        assert_eq!(
+            send(&mut stream, &["RPOPLPUSH", "list", "list"]),
+            "$1\r\nc\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["LMOVE", "list", "list", "LEFT", "RIGHT"]),
+            "$1\r\nc\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["LRANGE", "list", "0", "-1"]),
+            "*3\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\nc\r\n"
+        );
+
+        // Rotating a single element keeps the key.
+        send(&mut stream, &["RPUSH", "single", "only"]);
+        assert_eq!(
+            send(&mut stream, &["RPOPLPUSH", "single", "single"]),
+            "$4\r\nonly\r\n"
+        );
+        assert_eq!(send(&mut stream, &["LLEN", "single"]), ":1\r\n");
+    }
+
+    #[test]
+    fn test_lmpop() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        send(&mut stream, &["RPUSH", "second", "a", "b", "c"]);
+        assert_eq!(
+            send(
+                &mut stream,
+                &["LMPOP", "3", "first", "second", "third", "LEFT"]
+            ),
+            "*2\r\n$6\r\nsecond\r\n*1\r\n$1\r\na\r\n"
+        );
+        assert_eq!(
+            send(
+                &mut stream,
+                &["LMPOP", "2", "first", "second", "RIGHT", "COUNT", "5"]
+            ),
+            "*2\r\n$6\r\nsecond\r\n*2\r\n$1\r\nc\r\n$1\r\nb\r\n"
+        );
+        assert_eq!(send(&mut stream, &["EXPIRETIME", "second"]), ":-2\r\n");
+        assert_eq!(
+            send(&mut stream, &["LMPOP", "2", "first", "second", "LEFT"]),
+            "$-1\r\n"
+        );
+
+        assert_eq!(
+            send(&mut stream, &["LMPOP", "0", "first", "LEFT"]),
+            "-Command error: numkeys should be greater than 0\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["LMPOP", "1", "first", "LEFT", "COUNT", "0"]),
+            "-Command error: count should be greater than 0\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["LMPOP", "1", "first", "UP"]),
+            "-Command error: syntax error\r\n"
+        );
+
+        // Any key examined before a list is found is type checked.
+        send(&mut stream, &["SET", "plain", "value"]);
+        send(&mut stream, &["RPUSH", "list", "x"]);
+        assert!(
+            send(&mut stream, &["LMPOP", "2", "plain", "list", "LEFT"]).starts_with("-WRONGTYPE")
+        );
+        assert_eq!(
+            send(&mut stream, &["LMPOP", "2", "list", "plain", "LEFT"]),
+            "*2\r\n$4\r\nlist\r\n*1\r\n$1\r\nx\r\n"
+        );
+    }
+
+    #[test]
+    fn test_sets() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        assert_eq!(send(&mut stream, &["SADD", "set", "a", "b", "a"]), ":2\r\n");
+        assert_eq!(send(&mut stream, &["SADD", "set", "b", "c"]), ":1\r\n");
+        assert_eq!(send(&mut stream, &["SCARD", "set"]), ":3\r\n");
+        assert_eq!(send(&mut stream, &["SISMEMBER", "set", "c"]), ":1\r\n");
+        assert_eq!(send(&mut stream, &["SISMEMBER", "set", "d"]), ":0\r\n");
+        assert_eq!(
+            send(&mut stream, &["SCAN", "0", "TYPE", "set"]),
+            "*2\r\n$1\r\n0\r\n*1\r\n$3\r\nset\r\n"
+        );
+
+        assert_eq!(send(&mut stream, &["SREM", "set", "a", "d"]), ":1\r\n");
+        let members = send(&mut stream, &["SMEMBERS", "set"]);
+        assert!(
+            members == "*2\r\n$1\r\nb\r\n$1\r\nc\r\n" || members == "*2\r\n$1\r\nc\r\n$1\r\nb\r\n",
+            "{}",
+            members
+        );
+        // Removing the last member deletes the key.
+        assert_eq!(send(&mut stream, &["SREM", "set", "b", "c"]), ":2\r\n");
+        assert_eq!(send(&mut stream, &["EXPIRETIME", "set"]), ":-2\r\n");
+        assert_eq!(send(&mut stream, &["SMEMBERS", "set"]), "*0\r\n");
+        assert_eq!(send(&mut stream, &["SCARD", "set"]), ":0\r\n");
+
+        send(&mut stream, &["SET", "plain", "value"]);
+        assert!(send(&mut stream, &["SADD", "plain", "a"]).starts_with("-WRONGTYPE"));
+        assert!(send(&mut stream, &["SISMEMBER", "plain", "a"]).starts_with("-WRONGTYPE"));
+    }
+
+    #[test]
+    fn test_set_encoding_upgrades_once() {
+        let addr = start_server_with(Config {
+            dir: tempfile::tempdir().unwrap().keep(),
+            set_max_intset_entries: 3,
+            ..Config::default()
+        });
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        send(&mut stream, &["SADD", "numbers", "3", "1", "2"]);
+        assert_eq!(
+            send(&mut stream, &["OBJECT", "ENCODING", "numbers"]),
+            "$6\r\nintset\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["SMEMBERS", "numbers"]),
+            "*3\r\n$1\r\n1\r\n$1\r\n2\r\n$1\r\n3\r\n"
+        );
+        send(&mut stream, &["SADD", "numbers", "4"]);
+        assert_eq!(
+            send(&mut stream, &["OBJECT", "ENCODING", "numbers"]),
+            "$9\r\nhashtable\r\n"
+        );
+
+        send(&mut stream, &["SADD", "mixed", "1", "2"]);
+        assert_eq!(send(&mut stream, &["SADD", "mixed", "a"]), ":1\r\n");
+        assert_eq!(
+            send(&mut stream, &["OBJECT", "ENCODING", "mixed"]),
+            "$9\r\nhashtable\r\n"
+        );
+        assert_eq!(send(&mut stream, &["SISMEMBER", "mixed", "1"]), ":1\r\n");
+        // Removing the string member doesn't convert the set back.
+        assert_eq!(send(&mut stream, &["SREM", "mixed", "a"]), ":1\r\n");
+        assert_eq!(
+            send(&mut stream, &["OBJECT", "ENCODING", "mixed"]),
+            "$9\r\nhashtable\r\n"
+        );
+        assert_eq!(send(&mut stream, &["SCARD", "mixed"]), ":2\r\n");
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        send(&mut stream, &["SADD", "a", "1", "2", "3"]);
+        send(&mut stream, &["SADD", "b", "2", "3", "4"]);
+        assert_eq!(
+            send(&mut stream, &["SINTER", "a", "b"]),
+            "*2\r\n$1\r\n2\r\n$1\r\n3\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["SDIFF", "a", "b", "missing"]),
+            "*1\r\n$1\r\n1\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["SUNION", "a", "b"]),
+            "*4\r\n$1\r\n1\r\n$1\r\n2\r\n$1\r\n3\r\n$1\r\n4\r\n"
+        );
+        assert_eq!(send(&mut stream, &["SINTER", "a", "missing"]), "*0\r\n");
+
+        assert_eq!(
+            send(&mut stream, &["SINTERSTORE", "both", "a", "b"]),
+            ":2\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["SMEMBERS", "both"]),
+            "*2\r\n$1\r\n2\r\n$1\r\n3\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["SUNIONSTORE", "both", "a", "b"]),
+            ":4\r\n"
+        );
+        assert_eq!(send(&mut stream, &["SCARD", "both"]), ":4\r\n");
+        // An empty result deletes the destination.
+        assert_eq!(
+            send(&mut stream, &["SDIFFSTORE", "both", "a", "a"]),
+            ":0\r\n"
+        );
+        assert_eq!(send(&mut stream, &["EXPIRETIME", "both"]), ":-2\r\n");
+
+        send(&mut stream, &["SET", "plain", "value"]);
+        assert!(send(&mut stream, &["SUNION", "a", "plain"]).starts_with("-WRONGTYPE"));
+        assert!(send(&mut stream, &["SINTERSTORE", "both", "a", "plain"]).starts_with("-WRONGTYPE"));
+        assert!(send(&mut stream, &["SINTERSTORE", "plain", "a", "b"]).starts_with("-WRONGTYPE"));
+        assert_eq!(send(&mut stream, &["GET", "plain"]), "$5\r\nvalue\r\n");
+    }
+
+    #[test]
+    fn test_sintercard() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        send(&mut stream, &["SADD", "a", "1", "2", "3", "4", "5"]);
+        send(&mut stream, &["SADD", "b", "2", "3", "4", "5", "6"]);
+        send(&mut stream, &["SADD", "c", "3", "4", "5", "7"]);
+        assert_eq!(send(&mut stream, &["SINTERCARD", "2", "a", "b"]), ":4\r\n");
+        assert_eq!(
+            send(&mut stream, &["SINTERCARD", "3", "a", "b", "c"]),
+            ":3\r\n"
+        );
+        assert_eq!(
+            send(
+                &mut stream,
+                &["SINTERCARD", "3", "a", "b", "c", "LIMIT", "2"]
+            ),
+            ":2\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["SINTERCARD", "1", "a", "LIMIT", "0"]),
+            ":5\r\n"
+        );
+
+        // A missing key is an empty set, but every key is type checked.
+        assert_eq!(
+            send(&mut stream, &["SINTERCARD", "2", "a", "missing"]),
+            ":0\r\n"
+        );
+        send(&mut stream, &["SET", "plain", "value"]);
+        assert!(
+            send(&mut stream, &["SINTERCARD", "3", "missing", "a", "plain"])
+                .starts_with("-WRONGTYPE")
+        );
+
+        assert!(send(&mut stream, &["SINTERCARD", "0", "a"]).starts_with('-'));
+        assert!(send(&mut stream, &["SINTERCARD", "3", "a", "b"]).starts_with('-'));
+        assert!(send(&mut stream, &["SINTERCARD", "1", "a", "LIMIT", "-1"]).starts_with('-'));
+    }
+
+    #[test]
+    fn test_sorted_sets() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        assert_eq!(
+            send(&mut stream, &["ZADD", "z", "2", "b", "1", "a", "2", "c"]),
+            ":3\r\n"
+        );
+        // Updating a score adds nothing.
+        assert_eq!(send(&mut stream, &["ZADD", "z", "3", "a"]), ":0\r\n");
+        assert_eq!(send(&mut stream, &["ZSCORE", "z", "a"]), "$1\r\n3\r\n");
+        assert_eq!(send(&mut stream, &["ZSCORE", "z", "missing"]), "$-1\r\n");
+        assert_eq!(
+            send(&mut stream, &["ZRANGE", "z", "0", "-1"]),
+            "*3\r\n$1\r\nb\r\n$1\r\nc\r\n$1\r\na\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["ZRANGE", "z", "-1", "-1", "WITHSCORES"]),
+            "*2\r\n$1\r\na\r\n$1\r\n3\r\n"
+        );
+        assert_eq!(
+            send(
+                &mut stream,
+                &["ZRANGEBYSCORE", "z", "(2", "+inf", "WITHSCORES"]
+            ),
+            "*2\r\n$1\r\na\r\n$1\r\n3\r\n"
+        );
+        assert_eq!(
+            send(
+                &mut stream,
+                &["ZRANGEBYSCORE", "z", "-inf", "+inf", "LIMIT", "1", "1"]
+            ),
+            "*1\r\n$1\r\nc\r\n"
+        );
+        assert_eq!(
+            send(
+                &mut stream,
+                &["ZRANGEBYSCORE", "z", "-inf", "+inf", "LIMIT", "1", "-1"]
+            ),
+            "*2\r\n$1\r\nc\r\n$1\r\na\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["ZRANGEBYSCORE", "z", "x", "1"]),
+            "-Command error: min or max is not a float\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["ZADD", "z", "nan", "a"]),
+            "-Command error: value is not a valid float\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["OBJECT", "ENCODING", "z"]),
+            "$8\r\nlistpack\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["SCAN", "0", "TYPE", "zset"]),
+            "*2\r\n$1\r\n0\r\n*1\r\n$1\r\nz\r\n"
+        );
+
+        send(&mut stream, &["SET", "plain", "value"]);
+        assert!(send(&mut stream, &["ZADD", "plain", "1", "a"]).starts_with("-WRONGTYPE"));
+        assert!(send(&mut stream, &["ZRANGE", "plain", "0", "-1"]).starts_with("-WRONGTYPE"));
+    }
+
+    #[test]
+    fn test_zcard_and_zcount() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        send(
+            &mut stream,
+            &["ZADD", "z", "1", "a", "2", "b", "2", "c", "3", "d"],
+        );
+        assert_eq!(send(&mut stream, &["ZCARD", "z"]), ":4\r\n");
+        assert_eq!(send(&mut stream, &["ZCARD", "missing"]), ":0\r\n");
+
+        assert_eq!(
+            send(&mut stream, &["ZCOUNT", "z", "-inf", "+inf"]),
+            ":4\r\n"
+        );
+        assert_eq!(send(&mut stream, &["ZCOUNT", "z", "2", "3"]), ":3\r\n");
+        assert_eq!(send(&mut stream, &["ZCOUNT", "z", "(1", "(3"]), ":2\r\n");
+        assert_eq!(send(&mut stream, &["ZCOUNT", "z", "(2", "+inf"]), ":1\r\n");
+        assert_eq!(send(&mut stream, &["ZCOUNT", "z", "3", "1"]), ":0\r\n");
+        assert_eq!(
+            send(&mut stream, &["ZCOUNT", "missing", "-inf", "+inf"]),
+            ":0\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["ZCOUNT", "z", "(", "1"]),
+            "-Command error: min or max is not a float\r\n"
+        );
+
+        send(&mut stream, &["SET", "plain", "value"]);
+        assert!(send(&mut stream, &["ZCARD", "plain"]).starts_with("-WRONGTYPE"));
+        assert!(send(&mut stream, &["ZCOUNT", "plain", "0", "1"]).starts_with("-WRONGTYPE"));
+    }
+
+    #[test]
+    fn test_zrem() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        send(&mut stream, &["ZADD", "z", "1", "a", "2", "b", "3", "c"]);
+        assert_eq!(
+            send(&mut stream, &["ZREM", "z", "a", "a", "missing"]),
+            ":1\r\n"
+        );
+        assert_eq!(send(&mut stream, &["ZREM", "z", "a"]), ":0\r\n");
+        assert_eq!(send(&mut stream, &["ZREM", "missing", "a"]), ":0\r\n");
+        // Removing the last members deletes the key.
+        assert_eq!(send(&mut stream, &["ZREM", "z", "b", "c"]), ":2\r\n");
+        assert_eq!(send(&mut stream, &["EXPIRETIME", "z"]), ":-2\r\n");
+
+        send(&mut stream, &["SET", "plain", "value"]);
+        assert!(send(&mut stream, &["ZREM", "plain", "a"]).starts_with("-WRONGTYPE"));
+    }
+
+    #[test]
+    fn test_zremrangebyscore() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        send(
+            &mut stream,
+            &[
+                "ZADD", "z", "1", "a", "2", "b", "3", "c", "4", "d", "5", "e",
+            ],
+        );
+        assert_eq!(
+            send(&mut stream, &["ZREMRANGEBYSCORE", "z", "(1", "(3"]),
+            ":1\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["ZREMRANGEBYSCORE", "z", "4", "4"]),
+            ":1\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["ZREMRANGEBYSCORE", "z", "(5", "+inf"]),
+            ":0\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["ZREMRANGEBYSCORE", "z", "5", "1"]),
+            ":0\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["ZRANGE", "z", "0", "-1"]),
+            "*3\r\n$1\r\na\r\n$1\r\nc\r\n$1\r\ne\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["ZREMRANGEBYSCORE", "z", "x", "1"]),
+            "-Command error: min or max is not a float\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["ZREMRANGEBYSCORE", "z", "-inf", "+inf"]),
+            ":3\r\n"
+        );
+        assert_eq!(send(&mut stream, &["EXPIRETIME", "z"]), ":-2\r\n");
+        assert_eq!(
+            send(&mut stream, &["ZREMRANGEBYSCORE", "z", "-inf", "+inf"]),
+            ":0\r\n"
+        );
+
+        send(&mut stream, &["SET", "plain", "value"]);
+        assert!(
+            send(&mut stream, &["ZREMRANGEBYSCORE", "plain", "0", "1"]).starts_with("-WRONGTYPE")
+        );
+    }
+
+    #[test]
+    fn test_zremrangebyrank() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        send(
+            &mut stream,
+            &[
+                "ZADD", "z", "1", "a", "2", "b", "3", "c", "4", "d", "5", "e",
+            ],
+        );
+        // Trims a leaderboard to its top three.
+        assert_eq!(
+            send(&mut stream, &["ZREMRANGEBYRANK", "z", "0", "-4"]),
+            ":2\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["ZRANGE", "z", "0", "-1"]),
+            "*3\r\n$1\r\nc\r\n$1\r\nd\r\n$1\r\ne\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["ZREMRANGEBYRANK", "z", "-1", "-1"]),
+            ":1\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["ZREMRANGEBYRANK", "z", "5", "10"]),
+            ":0\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["ZREMRANGEBYRANK", "z", "1", "0"]),
+            ":0\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["ZREMRANGEBYRANK", "z", "-100", "100"]),
+            ":2\r\n"
+        );
+        assert_eq!(send(&mut stream, &["EXPIRETIME", "z"]), ":-2\r\n");
+
+        send(&mut stream, &["SET", "plain", "value"]);
+        assert!(
+            send(&mut stream, &["ZREMRANGEBYRANK", "plain", "0", "-1"]).starts_with("-WRONGTYPE")
+        );
+    }
+
+    #[test]
+    fn test_zmpop() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        send(
+            &mut stream,
+            &["ZADD", "second", "1", "a", "2", "b", "3", "c"],
+        );
+        assert_eq!(
+            send(&mut stream, &["ZMPOP", "2", "first", "second", "MIN"]),
+            "*2\r\n$6\r\nsecond\r\n*1\r\n*2\r\n$1\r\na\r\n$1\r\n1\r\n"
+        );
+        assert_eq!(
+            send(
+                &mut stream,
+                &["ZMPOP", "2", "first", "second", "MAX", "COUNT", "5"]
+            ),
+            "*2\r\n$6\r\nsecond\r\n*2\r\n*2\r\n$1\r\nc\r\n$1\r\n3\r\n*2\r\n$1\r\nb\r\n$1\r\n2\r\n"
+        );
+        assert_eq!(send(&mut stream, &["EXPIRETIME", "second"]), ":-2\r\n");
+        assert_eq!(
+            send(&mut stream, &["ZMPOP", "1", "second", "MIN"]),
+            "$-1\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["ZMPOP", "1", "second", "LEFT"]),
+            "-Command error: syntax error\r\n"
+        );
+
+        send(&mut stream, &["SET", "plain", "value"]);
+        assert!(
+            send(&mut stream, &["ZMPOP", "2", "first", "plain", "MIN"]).starts_with("-WRONGTYPE")
+        );
+    }
+
+    #[test]
+    fn test_value_containing_crlf() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        let value = "line one\r\nline two\r\n$3\r\nend";
+        assert_eq!(send(&mut stream, &["SET", "multiline", value]), "+OK\r\n");
+        assert_eq!(
+            send(&mut stream, &["GET", "multiline"]),
+            format!("${}\r\n{}\r\n", value.len(), value)
+        );
+    }
+
+    #[test]
+    fn test_bind() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let config = Config {
+            port: 0,
+            bind: vec!["127.0.0.1".parse().unwrap()],
+            ..Config::default()
+        };
+        let listeners = runtime.block_on(bind(&config.bind, config.port)).unwrap();
+        let addr = listeners[0].local_addr().unwrap();
+        assert!(addr.ip().is_loopback());
+        TcpStream::connect(addr).unwrap();
+
+        let taken = Config {
+            port: addr.port(),
+            ..config
+        };
+        let error = runtime.block_on(bind(&taken.bind, taken.port)).unwrap_err();
+        assert!(error.to_string().contains(&addr.to_string()));
+    }
+
+    #[test]
+    fn test_debug_reload() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        send(&mut stream, &["SET", "string", "value"]);
+        send(&mut stream, &["SET", "volatile", "soon", "EX", "100"]);
+        send(&mut stream, &["HSET", "hash", "a", "1", "b", "2"]);
+        send(&mut stream, &["XADD", "log", "1-1", "n", "1"]);
+        send(&mut stream, &["PFADD", "hll", "x", "y", "z"]);
+        send(&mut stream, &["RPUSH", "list", "a", "b", "c"]);
+        send(&mut stream, &["SADD", "set", "a", "b"]);
+        send(&mut stream, &["ZADD", "zset", "2", "a", "1", "b"]);
+        let hash_before = send(&mut stream, &["HMGET", "hash", "a", "b"]);
+        let log_before = send(&mut stream, &["XRANGE", "log", "-", "+"]);
+
+        assert_eq!(send(&mut stream, &["DEBUG", "RELOAD"]), "+OK\r\n");
+
+        assert_eq!(send(&mut stream, &["GET", "string"]), "$5\r\nvalue\r\n");
+        assert_eq!(send(&mut stream, &["GET", "volatile"]), "$4\r\nsoon\r\n");
+        assert_eq!(send(&mut stream, &["HMGET", "hash", "a", "b"]), hash_before);
+        assert_eq!(send(&mut stream, &["XRANGE", "log", "-", "+"]), log_before);
+        assert_eq!(send(&mut stream, &["PFCOUNT", "hll"]), ":3\r\n");
+        assert_eq!(
+            send(&mut stream, &["LRANGE", "list", "0", "-1"]),
+            "*3\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\nc\r\n"
+        );
+        assert_eq!(send(&mut stream, &["SCARD", "set"]), ":2\r\n");
+        assert_eq!(
+            send(&mut stream, &["ZRANGE", "zset", "0", "-1", "WITHSCORES"]),
+            "*4\r\n$1\r\nb\r\n$1\r\n1\r\n$1\r\na\r\n$1\r\n2\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["XADD", "log", "1-1", "n", "2"]),
+            "-ERR The ID specified in XADD is equal or smaller than the target stream top item\r\n"
+        );
+    }
+
+    #[test]
+    fn test_select() {
+        let addr = start_server();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut other = TcpStream::connect(addr).unwrap();
+
+        send(&mut stream, &["SET", "key", "zero"]);
+        assert_eq!(send(&mut stream, &["SELECT", "1"]), "+OK\r\n");
+        assert_eq!(send(&mut stream, &["GET", "key"]), "$-1\r\n");
+        send(&mut stream, &["SET", "key", "one"]);
+        assert_eq!(send(&mut stream, &["GET", "key"]), "$3\r\none\r\n");
+        // Each connection selects its own database.
+        assert_eq!(send(&mut other, &["GET", "key"]), "$4\r\nzero\r\n");
+        assert_eq!(
+            send(
+                &mut stream,
+                &[
+                    "EVAL",
+                    "redis.call('SELECT', 0) return redis.call('GET', 'key')",
+                    "0"
+                ]
+            ),
+            "$4\r\nzero\r\n"
+        );
+        assert_eq!(send(&mut stream, &["GET", "key"]), "$3\r\none\r\n");
+
+        assert_eq!(
+            send(&mut stream, &["SELECT", "16"]),
+            "-ERR DB index is out of range\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["SELECT", "-1"]),
+            "-ERR DB index is out of range\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["SELECT", "one"]),
+            "-Command error: value is not an integer or out of range\r\n"
+        );
+        assert_eq!(send(&mut stream, &["RESET"]), "+RESET\r\n");
+        assert_eq!(send(&mut stream, &["GET", "key"]), "$4\r\nzero\r\n");
+    }
+
+    #[test]
+    fn test_copy() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        send(&mut stream, &["SET", "key", "value", "EX", "100"]);
+        let expiretime = send(&mut stream, &["PEXPIRETIME", "key"]);
+        assert_eq!(send(&mut stream, &["COPY", "key", "copy"]), ":1\r\n");
+        assert_eq!(send(&mut stream, &["PEXPIRETIME", "copy"]), expiretime);
+        assert_eq!(send(&mut stream, &["COPY", "key", "copy"]), ":0\r\n");
+        assert_eq!(send(&mut stream, &["COPY", "missing", "copy"]), ":0\r\n");
+        send(&mut stream, &["SET", "other", "new"]);
+        assert_eq!(
+            send(&mut stream, &["COPY", "other", "copy", "REPLACE"]),
+            ":1\r\n"
+        );
+        assert_eq!(send(&mut stream, &["GET", "copy"]), "$3\r\nnew\r\n");
+        assert_eq!(send(&mut stream, &["PEXPIRETIME", "copy"]), ":-1\r\n");
+
+        // Into another database, the key exists in both with the same TTL.
+        assert_eq!(
+            send(&mut stream, &["COPY", "key", "key", "DB", "1"]),
+            ":1\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["COPY", "key", "key", "DB", "1"]),
+            ":0\r\n"
+        );
+        assert_eq!(send(&mut stream, &["GET", "key"]), "$5\r\nvalue\r\n");
+        send(&mut stream, &["SELECT", "1"]);
+        assert_eq!(send(&mut stream, &["GET", "key"]), "$5\r\nvalue\r\n");
+        assert_eq!(send(&mut stream, &["PEXPIRETIME", "key"]), expiretime);
+
+        assert_eq!(
+            send(&mut stream, &["COPY", "key", "key"]),
+            "-ERR source and destination objects are the same\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["COPY", "key", "key", "DB", "16"]),
+            "-ERR DB index is out of range\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["COPY", "key", "copy", "DB", "one"]),
+            "-Command error: value is not an integer or out of range\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["COPY", "key", "copy", "DB"]),
+            "-Command error: syntax error\r\n"
+        );
+    }
+
+    #[test]
+    fn test_bitpos() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        assert_eq!(send(&mut stream, &["BITPOS", "missing", "0"]), ":0\r\n");
+        assert_eq!(send(&mut stream, &["BITPOS", "missing", "1"]), ":-1\r\n");
+
+        // "\x7f\x7f" is 01111111 01111111.
+        send(&mut stream, &["SET", "key", "\x7f\x7f"]);
+        assert_eq!(send(&mut stream, &["BITPOS", "key", "1"]), ":1\r\n");
+        assert_eq!(send(&mut stream, &["BITPOS", "key", "0", "1"]), ":8\r\n");
+        assert_eq!(
+            send(&mut stream, &["BITPOS", "key", "0", "2", "15", "BIT"]),
+            ":8\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["BITPOS", "key", "0", "9", "-1", "BIT"]),
+            ":-1\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["BITPOS", "key", "2"]),
+            "-Command error: The bit argument must be 1 or 0.\r\n"
+        );
+    }
+
+    #[test]
+    fn test_values_are_binary_safe() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        let value = b"\xff\x00\r\n\xfe";
+        assert_eq!(
+            send_bytes(&mut stream, &[b"SET", b"key", value]),
+            b"+OK\r\n"
+        );
+        assert_eq!(
+            send_bytes(&mut stream, &[b"GET", b"key"]),
+            b"$5\r\n\xff\x00\r\n\xfe\r\n"
+        );
+        assert_eq!(
+            send_bytes(&mut stream, &[b"GETSET", b"key", b"\x80"]),
+            b"$5\r\n\xff\x00\r\n\xfe\r\n"
+        );
+        assert_eq!(
+            send_bytes(&mut stream, &[b"MSETNX", b"other", b"\xc3\x28"]),
+            b":1\r\n"
+        );
+        assert_eq!(
+            send_bytes(&mut stream, &[b"LCS", b"key", b"other"]),
+            b"$0\r\n\r\n"
+        );
+        assert_eq!(
+            send_bytes(
+                &mut stream,
+                &[b"EVAL", b"return redis.call('GET', KEYS[1])", b"1", b"key"]
+            ),
+            b"$1\r\n\x80\r\n"
+        );
+    }
+
+    #[test]
+    fn test_bitop() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        send_bytes(&mut stream, &[b"SET", b"a", b"\xff\x0f"]);
+        send_bytes(&mut stream, &[b"SET", b"b", b"\xf0"]);
+        assert_eq!(
+            send(&mut stream, &["BITOP", "AND", "dest", "a", "b"]),
+            ":2\r\n"
+        );
+        assert_eq!(
+            send_bytes(&mut stream, &[b"GET", b"dest"]),
+            b"$2\r\n\xf0\x00\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["BITOP", "OR", "dest", "a", "b"]),
+            ":2\r\n"
+        );
+        assert_eq!(
+            send_bytes(&mut stream, &[b"GET", b"dest"]),
+            b"$2\r\n\xff\x0f\r\n"
+        );
+        // A missing key is an empty string.
+        assert_eq!(
+            send(&mut stream, &["BITOP", "XOR", "dest", "a", "b", "missing"]),
+            ":2\r\n"
+        );
+        assert_eq!(
+            send_bytes(&mut stream, &[b"GET", b"dest"]),
+            b"$2\r\n\x0f\x0f\r\n"
+        );
+        assert_eq!(send(&mut stream, &["BITOP", "NOT", "dest", "b"]), ":1\r\n");
+        assert_eq!(send(&mut stream, &["GET", "dest"]), "$1\r\n\x0f\r\n");
+
+        // An empty result deletes the destination.
+        assert_eq!(
+            send(&mut stream, &["BITOP", "OR", "dest", "missing"]),
+            ":0\r\n"
+        );
+        assert_eq!(send(&mut stream, &["GET", "dest"]), "$-1\r\n");
+
+        assert_eq!(
+            send(&mut stream, &["BITOP", "NOT", "dest", "a", "b"]),
+            "-Command error: BITOP NOT must be called with a single source key.\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["BITOP", "NAND", "dest", "a"]),
+            "-Command error: syntax error\r\n"
+        );
+        send(&mut stream, &["LPUSH", "list", "x"]);
+        assert_eq!(
+            send(&mut stream, &["BITOP", "AND", "dest", "a", "list"]),
+            "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+    }
+
+    #[test]
+    fn test_set_rejects_non_positive_expiry() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        for (option, amount) in [("PX", "0"), ("PX", "-100"), ("EX", "0")] {
+            let response = send(&mut stream, &["SET", "key", "value", option, amount]);
+            assert!(response.starts_with('-'), "{}", response);
+            assert!(response.contains("invalid expire time in 'set' command"));
+        }
+        assert_eq!(send(&mut stream, &["GET", "key"]), "$-1\r\n");
+
+        assert_eq!(
+            send(&mut stream, &["SET", "key", "value", "EX", "10"]),
+            "+OK\r\n"
+        );
+        assert_eq!(send(&mut stream, &["GET", "key"]), "$5\r\nvalue\r\n");
+    }
+
+    #[test]
+    fn test_dump_and_restore() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        assert_eq!(send(&mut stream, &["SET", "source", "payload"]), "+OK\r\n");
+
+        let response = send(&mut stream, &["DUMP", "source"]);
+        let serialized = response.lines().nth(1).unwrap().to_string();
+
+        assert_eq!(
+            send(&mut stream, &["RESTORE", "copy", "0", &serialized]),
+            "+OK\r\n"
+        );
+        assert_eq!(send(&mut stream, &["GET", "copy"]), "$7\r\npayload\r\n");
+
+        assert_eq!(
+            send(&mut stream, &["RESTORE", "copy", "0", &serialized]),
+            "-BUSYKEY Target key name already exists.\r\n"
+        );
+        assert_eq!(
+            send(
+                &mut stream,
+                &["RESTORE", "copy", "0", &serialized, "REPLACE"]
+            ),
+            "+OK\r\n"
+        );
+
+        let mut corrupted = serialized.clone();
+        corrupted.replace_range(..2, "ff");
+        assert_eq!(
+            send(&mut stream, &["RESTORE", "other", "0", &corrupted]),
+            "-ERR DUMP payload version or checksum are wrong\r\n"
+        );
+
+        // A payload from Redis itself, dumped after `SET mykey 10`.
+        assert_eq!(
+            send(
+                &mut stream,
+                &["RESTORE", "redis", "0", "00c00a0a006e9f57450eae63bb"]
+            ),
+            "+OK\r\n"
+        );
+        assert_eq!(send(&mut stream, &["GET", "redis"]), "$2\r\n10\r\n");
+
+        send(&mut stream, &["RPUSH", "list", "a", "b"]);
+        let response = send(&mut stream, &["DUMP", "list"]);
+        let serialized = response.lines().nth(1).unwrap().to_string();
+        assert_eq!(
+            send(&mut stream, &["RESTORE", "list-copy", "0", &serialized]),
+            "+OK\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["LRANGE", "list-copy", "0", "-1"]),
+            "*2\r\n$1\r\na\r\n$1\r\nb\r\n"
+        );
+    }
+
+    #[test]
+    fn test_migrate() {
+        let mut source = TcpStream::connect(start_server()).unwrap();
+        let target_addr = start_server();
+        let mut target = TcpStream::connect(target_addr).unwrap();
+        let (host, port) = (target_addr.ip().to_string(), target_addr.port().to_string());
+
+        send(&mut source, &["SET", "moved", "value", "EX", "100"]);
+        send(&mut source, &["HSET", "copied", "field", "value"]);
+
+        assert_eq!(
+            send(
+                &mut source,
+                &["MIGRATE", &host, &port, "moved", "0", "1000"]
+            ),
+            "+OK\r\n"
+        );
+        assert_eq!(send(&mut source, &["GET", "moved"]), "$-1\r\n");
+        assert_eq!(send(&mut target, &["GET", "moved"]), "$5\r\nvalue\r\n");
+
+        assert_eq!(
+            send(
+                &mut source,
+                &["MIGRATE", &host, &port, "copied", "0", "1000", "COPY"]
+            ),
+            "+OK\r\n"
+        );
+        assert_eq!(
+            send(&mut source, &["HGET", "copied", "field"]),
+            "$5\r\nvalue\r\n"
+        );
+        assert_eq!(
+            send(&mut target, &["HGET", "copied", "field"]),
+            "$5\r\nvalue\r\n"
+        );
+
+        assert_eq!(
+            send(
+                &mut source,
+                &["MIGRATE", &host, &port, "copied", "0", "1000", "COPY"]
+            ),
+            "-ERR Target instance replied with error: BUSYKEY Target key name already exists.\r\n"
+        );
+        assert_eq!(
+            send(
+                &mut source,
+                &["MIGRATE", &host, &port, "copied", "0", "1000", "REPLACE"]
+            ),
+            "+OK\r\n"
+        );
+        assert_eq!(
+            send(
+                &mut source,
+                &["MIGRATE", &host, &port, "missing", "0", "1000"]
+            ),
+            "+NOKEY\r\n"
+        );
+
+        // Nothing listens on the port of a dropped listener.
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed_port = closed.local_addr().unwrap().port().to_string();
+        drop(closed);
+        send(&mut source, &["SET", "stays", "value"]);
+        let response = send(
+            &mut source,
+            &["MIGRATE", "127.0.0.1", &closed_port, "stays", "0", "100"],
+        );
+        assert!(response.starts_with("-IOERR"), "{}", response);
+        assert_eq!(send(&mut source, &["GET", "stays"]), "$5\r\nvalue\r\n");
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+        let value = "x".repeat(100);
+
+        assert_eq!(send(&mut stream, &["SET", "sized", &value]), "+OK\r\n");
+
+        let response = send(&mut stream, &["MEMORY", "USAGE", "sized"]);
+        let bytes: usize = response
+            .strip_prefix(':')
+            .and_then(|n| n.trim_end().parse().ok())
+            .unwrap();
+        assert!(bytes >= "sized".len() + value.len());
+        assert!(bytes < 1024);
+
+        assert_eq!(
+            send(&mut stream, &["MEMORY", "USAGE", "sized", "SAMPLES", "5"]),
+            response
+        );
+        assert_eq!(
+            send(&mut stream, &["MEMORY", "USAGE", "missing"]),
+            "$-1\r\n"
+        );
+    }
+
+    #[test]
+    fn test_info_keyspace_hits_and_misses() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        send(&mut stream, &["SET", "present", "value"]);
+        send(&mut stream, &["GET", "present"]);
+        send(&mut stream, &["GET", "present"]);
+        send(&mut stream, &["GET", "absent"]);
+
+        let info = send(&mut stream, &["INFO", "stats"]);
+        assert!(info.contains("keyspace_hits:2\r\n"), "{}", info);
+        assert!(info.contains("keyspace_misses:1\r\n"), "{}", info);
+
+        assert_eq!(send(&mut stream, &["INFO", "nosuchsection"]), "$0\r\n\r\n");
+    }
+
+    #[test]
+    fn test_run_id_and_change_repl_id() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+        let field = |info: &str, name: &str| -> String {
+            info.lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+                .unwrap_or_else(|| panic!("no {} in {}", name, info))
+                .to_string()
+        };
+
+        let info = send(&mut stream, &["INFO"]);
+        let run_id = field(&info, "run_id");
+        assert_eq!(run_id.len(), 40);
+        assert!(run_id.chars().all(|c| c.is_ascii_hexdigit()));
+        let replid = field(&info, "master_replid");
+        assert_eq!(replid.len(), 40);
+
+        assert_eq!(send(&mut stream, &["DEBUG", "CHANGE-REPL-ID"]), "+OK\r\n");
+        let info = send(&mut stream, &["INFO"]);
+        assert_eq!(field(&info, "run_id"), run_id);
+        assert_ne!(field(&info, "master_replid"), replid);
+    }
+
+    #[test]
+    fn test_busy_threshold_sheds_new_connections() {
+        let addr = start_server_with(Config {
+            dir: tempfile::tempdir().unwrap().keep(),
+            busy_threshold: Some(2),
+            ..Config::default()
+        });
+
+        let mut first = TcpStream::connect(addr).unwrap();
+        let mut second = TcpStream::connect(addr).unwrap();
+        assert_eq!(send(&mut first, &["PING"]), "+PONG\r\n");
+        assert_eq!(send(&mut second, &["PING"]), "+PONG\r\n");
+
+        let mut rejected = TcpStream::connect(addr).unwrap();
+        let mut reply = String::new();
+        rejected.read_to_string(&mut reply).unwrap();
+        assert_eq!(reply, "-BUSY Too many connections, try again later\r\n");
+
+        assert_eq!(send(&mut first, &["PING"]), "+PONG\r\n");
+        let info = send(&mut first, &["INFO"]);
+        assert!(info.contains("connected_clients:2\r\n"), "{}", info);
+        assert!(info.contains("rejected_connections:1\r\n"), "{}", info);
+        assert!(
+            info.contains("total_connections_received:3\r\n"),
+            "{}",
+            info
+        );
+
+        drop(second);
+        let accepted = (0..50).any(|_| {
+            thread::sleep(Duration::from_millis(20));
+            let mut stream = TcpStream::connect(addr).unwrap();
+            send(&mut stream, &["PING"]) == "+PONG\r\n"
+        });
+        assert!(accepted);
+    }
+
+    #[test]
+    fn test_command_getkeys() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        assert_eq!(
+            send(&mut stream, &["COMMAND", "GETKEYS", "SET", "key", "value"]),
+            "*1\r\n$3\r\nkey\r\n"
+        );
+        assert_eq!(
+            send(
+                &mut stream,
+                &["COMMAND", "GETKEYS", "MSETNX", "a", "1", "b", "2"]
+            ),
+            "*2\r\n$1\r\na\r\n$1\r\nb\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["COMMAND", "GETKEYS", "PING"]),
+            "-ERR The command has no key arguments\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["COMMAND", "GETKEYS", "GET"]),
+            "-Command error: Invalid arguments specified for command\r\n"
+        );
+    }
+
+    #[test]
+    fn test_config_resetstat() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        send(&mut stream, &["DEBUG", "SET-ACTIVE-EXPIRE", "0"]);
+        send(&mut stream, &["SET", "key", "value"]);
+        send(&mut stream, &["SET", "volatile", "value", "PX", "1"]);
+        thread::sleep(Duration::from_millis(10));
+        send(&mut stream, &["GET", "key"]);
+        send(&mut stream, &["GET", "volatile"]);
+
+        let info = send(&mut stream, &["INFO", "stats"]);
+        for counter in [
+            "total_connections_received:1",
+            "total_commands_processed:5",
+            "expired_keys:1",
+            "keyspace_hits:1",
+            "keyspace_misses:1",
+        ] {
+            assert!(info.contains(counter), "{}", info);
+        }
+
+        assert_eq!(send(&mut stream, &["CONFIG", "RESETSTAT"]), "+OK\r\n");
+
+        let info = send(&mut stream, &["INFO"]);
+        for counter in [
+            "total_connections_received:0",
+            // CONFIG RESETSTAT itself is counted once it completes.
+            "total_commands_processed:1",
+            "expired_keys:0",
+            "keyspace_hits:0",
+            "keyspace_misses:0",
+            "connected_clients:1",
+        ] {
+            assert!(info.contains(counter), "{}", info);
+        }
+    }
+
+    /// Reads everything the stream receives until it stays quiet for
+    /// 300ms.
+    fn read_until_quiet(stream: &mut TcpStream) -> String {
+        stream
+            .set_read_timeout(Some(Duration::from_millis(300)))
+            .unwrap();
+        let mut received = Vec::new();
+        let mut read_buffer = [0; 1024];
+        while let Ok(n) = stream.read(&mut read_buffer) {
+            if n == 0 {
+                break;
+            }
+            received.extend_from_slice(&read_buffer[..n]);
+        }
+        String::from_utf8(received).unwrap()
+    }
+
+    #[test]
+    fn test_expired_keyspace_events() {
+        let addr = start_server_with(Config {
+            dir: tempfile::tempdir().unwrap().keep(),
+            notify_keyspace_events: "KEx".parse().unwrap(),
+            ..Config::default()
+        });
+        let mut subscriber = TcpStream::connect(addr).unwrap();
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        send(&mut subscriber, &["SUBSCRIBE", "__keyevent@0__:expired"]);
+        send(&mut subscriber, &["PSUBSCRIBE", "__keyspace@0__:*"]);
+
+        // Found by the active expiration cycle.
+        send(&mut stream, &["SET", "active", "value", "PX", "50"]);
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(
+            read_until_quiet(&mut subscriber),
+            "*4\r\n$8\r\npmessage\r\n$16\r\n__keyspace@0__:*\r\n\
+             $21\r\n__keyspace@0__:active\r\n$7\r\nexpired\r\n\
+             *3\r\n$7\r\nmessage\r\n$22\r\n__keyevent@0__:expired\r\n$6\r\nactive\r\n"
+        );
+
+        // Found by lookups, of which only the first reports it.
+        send(&mut stream, &["DEBUG", "SET-ACTIVE-EXPIRE", "0"]);
+        send(&mut stream, &["SET", "lazy", "value", "PX", "50"]);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(send(&mut stream, &["GET", "lazy"]), "$-1\r\n");
+        assert_eq!(send(&mut stream, &["GET", "lazy"]), "$-1\r\n");
+        assert_eq!(
+            read_until_quiet(&mut subscriber),
+            "*4\r\n$8\r\npmessage\r\n$16\r\n__keyspace@0__:*\r\n\
+             $19\r\n__keyspace@0__:lazy\r\n$7\r\nexpired\r\n\
+             *3\r\n$7\r\nmessage\r\n$22\r\n__keyevent@0__:expired\r\n$4\r\nlazy\r\n"
+        );
+    }
+
+    #[test]
+    fn test_pubsub() {
+        let addr = start_server();
+        let mut subscriber = TcpStream::connect(addr).unwrap();
+        let mut publisher = TcpStream::connect(addr).unwrap();
+
+        assert_eq!(
+            send(&mut subscriber, &["SUBSCRIBE", "news", "sports"]),
+            "*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n\
+             *3\r\n$9\r\nsubscribe\r\n$6\r\nsports\r\n:2\r\n"
+        );
+        assert_eq!(
+            send(&mut subscriber, &["GET", "key"]),
+            "-ERR Can't execute 'get': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context\r\n"
+        );
+        assert_eq!(
+            send(&mut subscriber, &["PING"]),
+            "*2\r\n$4\r\npong\r\n$0\r\n\r\n"
+        );
+
+        assert_eq!(
+            send(&mut publisher, &["PUBSUB", "CHANNELS"]),
+            "*2\r\n$4\r\nnews\r\n$6\r\nsports\r\n"
+        );
+        assert_eq!(
+            send(&mut publisher, &["PUBSUB", "CHANNELS", "n*"]),
+            "*1\r\n$4\r\nnews\r\n"
+        );
+        assert_eq!(
+            send(&mut publisher, &["PUBSUB", "NUMSUB", "news", "missing"]),
+            "*4\r\n$4\r\nnews\r\n:1\r\n$7\r\nmissing\r\n:0\r\n"
+        );
+
+        assert_eq!(
+            send(&mut subscriber, &["PSUBSCRIBE", "s*"]),
+            "*3\r\n$10\r\npsubscribe\r\n$2\r\ns*\r\n:3\r\n"
+        );
+        assert_eq!(send(&mut publisher, &["PUBSUB", "NUMPAT"]), ":1\r\n");
+
+        assert_eq!(
+            send(&mut publisher, &["PUBLISH", "news", "hello"]),
+            ":1\r\n"
+        );
+        let mut read_buffer = [0; 1024];
+        let n = subscriber.read(&mut read_buffer).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&read_buffer[..n]),
+            "*3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$5\r\nhello\r\n"
+        );
+
+        assert_eq!(
+            send(&mut subscriber, &["UNSUBSCRIBE"]),
+            "*3\r\n$11\r\nunsubscribe\r\n$4\r\nnews\r\n:2\r\n\
+             *3\r\n$11\r\nunsubscribe\r\n$6\r\nsports\r\n:1\r\n"
+        );
+        assert_eq!(
+            send(&mut subscriber, &["PUNSUBSCRIBE"]),
+            "*3\r\n$12\r\npunsubscribe\r\n$2\r\ns*\r\n:0\r\n"
+        );
+        assert_eq!(send(&mut subscriber, &["GET", "key"]), "$-1\r\n");
+        assert_eq!(send(&mut publisher, &["PUBSUB", "CHANNELS"]), "*0\r\n");
+    }
+
+    #[test]
+    fn test_pubsub_resp3() {
+        let addr = start_server();
+        let mut resp3 = TcpStream::connect(addr).unwrap();
+        let mut resp2 = TcpStream::connect(addr).unwrap();
+        let mut publisher = TcpStream::connect(addr).unwrap();
+
+        assert!(send(&mut resp3, &["HELLO", "3"]).contains("$5\r\nproto\r\n:3\r\n"));
+        assert_eq!(
+            send(&mut resp3, &["SUBSCRIBE", "news"]),
+            ">3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n"
+        );
+        send(&mut resp2, &["SUBSCRIBE", "news"]);
+
+        assert_eq!(
+            send(&mut publisher, &["PUBLISH", "news", "hello"]),
+            ":2\r\n"
+        );
+        let mut read_buffer = [0; 1024];
+        let n = resp3.read(&mut read_buffer).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&read_buffer[..n]),
+            ">3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$5\r\nhello\r\n"
+        );
+        let n = resp2.read(&mut read_buffer).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&read_buffer[..n]),
+            "*3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$5\r\nhello\r\n"
+        );
+
+        // Push frames can't be mistaken for replies, so RESP3 subscribers
+        // may run any command.
+        assert_eq!(send(&mut resp3, &["SET", "key", "v"]), "+OK\r\n");
+        assert_eq!(send(&mut resp3, &["GET", "key"]), "$1\r\nv\r\n");
+        assert_eq!(send(&mut resp3, &["PING"]), "+PONG\r\n");
+        assert!(send(&mut resp2, &["GET", "key"]).starts_with("-ERR Can't execute 'get'"));
+    }
+
+    #[test]
+    fn test_sharded_pubsub() {
+        let addr = start_server();
+        let mut shard_subscriber = TcpStream::connect(addr).unwrap();
+        let mut subscriber = TcpStream::connect(addr).unwrap();
+        let mut publisher = TcpStream::connect(addr).unwrap();
+
+        assert_eq!(
+            send(&mut shard_subscriber, &["SSUBSCRIBE", "news"]),
+            "*3\r\n$10\r\nssubscribe\r\n$4\r\nnews\r\n:1\r\n"
+        );
+        assert!(
+            send(&mut shard_subscriber, &["GET", "key"]).starts_with("-ERR Can't execute 'get'")
+        );
+        send(&mut subscriber, &["SUBSCRIBE", "news"]);
+
+        assert_eq!(
+            send(&mut publisher, &["SPUBLISH", "news", "hello"]),
+            ":1\r\n"
+        );
+        let mut read_buffer = [0; 1024];
+        let n = shard_subscriber.read(&mut read_buffer).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&read_buffer[..n]),
+            "*3\r\n$8\r\nsmessage\r\n$4\r\nnews\r\n$5\r\nhello\r\n"
+        );
+
+        // Each namespace only reaches its own subscribers.
+        assert_eq!(
+            send(&mut publisher, &["PUBLISH", "news", "regular"]),
+            ":1\r\n"
+        );
+        let n = subscriber.read(&mut read_buffer).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&read_buffer[..n]),
+            "*3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$7\r\nregular\r\n"
+        );
+        assert_eq!(
+            send(&mut shard_subscriber, &["PING"]),
+            "*2\r\n$4\r\npong\r\n$0\r\n\r\n"
+        );
+
+        assert_eq!(
+            send(&mut shard_subscriber, &["SUNSUBSCRIBE"]),
+            "*3\r\n$12\r\nsunsubscribe\r\n$4\r\nnews\r\n:0\r\n"
+        );
+        assert_eq!(send(&mut shard_subscriber, &["GET", "key"]), "$-1\r\n");
+        assert_eq!(
+            send(&mut publisher, &["SPUBLISH", "news", "hello"]),
+            ":0\r\n"
+        );
+    }
+
+    #[test]
+    fn test_client_tracking() {
+        let addr = start_server();
+        let mut reader = TcpStream::connect(addr).unwrap();
+        let mut writer = TcpStream::connect(addr).unwrap();
+
+        assert!(send(&mut reader, &["CLIENT", "TRACKING", "ON"])
+            .starts_with("-ERR CLIENT TRACKING requires RESP3"));
+        send(&mut reader, &["HELLO", "3"]);
+        assert_eq!(send(&mut reader, &["CLIENT", "TRACKING", "ON"]), "+OK\r\n");
+
+        send(&mut writer, &["SET", "key", "old"]);
+        assert_eq!(send(&mut reader, &["GET", "key"]), "$3\r\nold\r\n");
+        assert_eq!(send(&mut writer, &["SET", "key", "new"]), "+OK\r\n");
+
+        let mut read_buffer = [0; 1024];
+        let n = reader.read(&mut read_buffer).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&read_buffer[..n]),
+            ">2\r\n$10\r\ninvalidate\r\n*1\r\n$3\r\nkey\r\n"
+        );
+
+        // The key is no longer tracked until it is read again.
+        send(&mut writer, &["SET", "key", "newer"]);
+        assert_eq!(send(&mut reader, &["GET", "key"]), "$5\r\nnewer\r\n");
+
+        // Expiring counts as a modification too.
+        send(&mut writer, &["SET", "key", "value", "PX", "50"]);
+        let n = reader.read(&mut read_buffer).unwrap();
+        assert!(
+            String::from_utf8_lossy(&read_buffer[..n]).starts_with(">2\r\n$10\r\ninvalidate\r\n")
+        );
+        send(&mut reader, &["GET", "key"]);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(send(&mut writer, &["GET", "key"]), "$-1\r\n");
+        let n = reader.read(&mut read_buffer).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&read_buffer[..n]),
+            ">2\r\n$10\r\ninvalidate\r\n*1\r\n$3\r\nkey\r\n"
+        );
+
+        assert_eq!(send(&mut reader, &["CLIENT", "TRACKING", "OFF"]), "+OK\r\n");
+        send(&mut reader, &["GET", "key"]);
+        send(&mut writer, &["SET", "key", "untracked"]);
+        assert_eq!(send(&mut reader, &["PING"]), "+PONG\r\n");
+    }
+
+    #[test]
+    fn test_slow_subscriber_is_disconnected() {
+        let addr = start_server_with(Config {
+            dir: tempfile::tempdir().unwrap().keep(),
+            pubsub_output_buffer_limit: Some(64 * 1024),
+            ..Config::default()
+        });
+        let mut slow = TcpStream::connect(addr).unwrap();
+        let mut fast = TcpStream::connect(addr).unwrap();
+        let mut publisher = TcpStream::connect(addr).unwrap();
+        send(&mut slow, &["SUBSCRIBE", "news"]);
+        send(&mut fast, &["SUBSCRIBE", "news"]);
+
+        let fast_reader = fast.try_clone().unwrap();
+        let reader = thread::spawn(move || {
+            let mut lines = BufReader::new(fast_reader).lines();
+            let mut messages = Vec::new();
+            loop {
+                // Each message is seven lines, its payload last.
+                let message = lines.nth(6).unwrap().unwrap();
+                if message == "done" {
+                    return messages;
+                }
+                messages.push(message);
+            }
+        });
+
+        // The slow subscriber never reads, so once the socket buffers fill
+        // up its queue crosses the limit and it stops counting as a
+        // delivery.
+        // Requests are read 1KB at a time, so each stays under that.
+        let padding = "x".repeat(900);
+        let mut published = 0;
+        loop {
+            let message = format!("{:06}{}", published, padding);
+            published += 1;
+            if send(&mut publisher, &["PUBLISH", "news", &message]) == ":1\r\n" {
+                break;
+            }
+            assert!(published < 100_000, "the slow subscriber was never cut off");
+        }
+        send(&mut publisher, &["PUBLISH", "news", "done"]);
+
+        let messages = reader.join().unwrap();
+        assert_eq!(messages.len(), published);
+        for (i, message) in messages.iter().enumerate() {
+            assert!(message.starts_with(&format!("{:06}", i)));
+        }
+
+        // The slow subscriber's connection closes once the data already in
+        // its socket is read, and it no longer counts as subscribed.
+        let mut read_buffer = [0; 64 * 1024];
+        while slow.read(&mut read_buffer).unwrap() > 0 {}
+        assert_eq!(
+            send(&mut publisher, &["PUBSUB", "NUMSUB", "news"]),
+            "*2\r\n$4\r\nnews\r\n:1\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_socket_options() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+
+        configure_socket(&stream, &Config::default()).unwrap();
+        assert!(stream.nodelay().unwrap());
+        assert!(SockRef::from(&stream).keepalive().unwrap());
+
+        let config = Config {
+            tcp_keepalive: None,
+            tcp_nodelay: false,
+            ..Config::default()
+        };
+        let _client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        configure_socket(&stream, &config).unwrap();
+        assert!(!stream.nodelay().unwrap());
+        assert!(!SockRef::from(&stream).keepalive().unwrap());
+    }
+
+    #[test]
+    fn test_quit_closes_connection() {
+        let addr = start_server();
+        let mut read_buffer = [0; 1024];
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        assert_eq!(send(&mut stream, &["QUIT"]), "+OK\r\n");
+        assert_eq!(stream.read(&mut read_buffer).unwrap(), 0);
+
+        // QUIT is also accepted in subscribe mode.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        send(&mut stream, &["SUBSCRIBE", "news"]);
+        assert_eq!(send(&mut stream, &["QUIT"]), "+OK\r\n");
+        assert_eq!(stream.read(&mut read_buffer).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_subcommand_help() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        let help = send(&mut stream, &["OBJECT", "HELP"]);
+        assert!(help.starts_with("*9\r\n+OBJECT <subcommand>"));
+        assert!(help.contains("+ENCODING <key>\r\n"));
+        assert_eq!(
+            send(&mut stream, &["object", "bogus"]),
+            "-ERR Unknown subcommand 'BOGUS'. Valid OBJECT subcommands are: \
+             ENCODING, IDLETIME, REFCOUNT, HELP\r\n"
+        );
+        assert!(send(&mut stream, &["CONFIG", "HELP"]).starts_with("*7\r\n"));
+    }
+
+    #[test]
+    fn test_config_set_loglevel() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        assert_eq!(
+            send(&mut stream, &["CONFIG", "SET", "loglevel", "debug"]),
+            "+OK\r\n"
+        );
+        assert!(send(&mut stream, &["CONFIG", "SET", "loglevel", "info"])
+            .starts_with("-Command error: Invalid argument 'info'"));
+        assert_eq!(
+            send(&mut stream, &["CONFIG", "SET", "maxclients", "10"]),
+            "-Command error: Unsupported CONFIG parameter: maxclients\r\n"
+        );
+    }
+
+    #[test]
+    fn test_type_filters() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        assert_eq!(send(&mut stream, &["RANDOMKEY"]), "$-1\r\n");
+        send(&mut stream, &["SET", "a", "1"]);
+        send(&mut stream, &["SET", "b", "2"]);
+        send(&mut stream, &["HSET", "h", "field", "value"]);
+
+        let reply = send(
+            &mut stream,
+            &["SCAN", "0", "TYPE", "string", "COUNT", "100"],
+        );
+        assert!(reply.contains("$1\r\na\r\n") && reply.contains("$1\r\nb\r\n"));
+        assert!(!reply.contains("$1\r\nh\r\n"));
+
+        for _ in 0..10 {
+            assert_eq!(
+                send(&mut stream, &["RANDOMKEY", "TYPE", "hash"]),
+                "$1\r\nh\r\n"
+            );
+        }
+        assert_eq!(
+            send(&mut stream, &["RANDOMKEY", "TYPE", "stream"]),
+            "$-1\r\n"
+        );
+    }
+
+    #[test]
+    fn test_command_info() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        assert_eq!(
+            send(&mut stream, &["COMMAND", "INFO", "get", "nosuch"]),
+            "*2\r\n*6\r\n$3\r\nget\r\n:2\r\n*2\r\n+readonly\r\n+fast\r\n\
+             :1\r\n:1\r\n:1\r\n$-1\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["GET"]),
+            "-Command error: Wrong number of arguments for 'get' command\r\n"
+        );
+
+        // The full listing outgrows the read buffer, so only its header is
+        // compared.
+        let count = send(&mut stream, &["COMMAND", "COUNT"]);
+        assert!(send(&mut stream, &["COMMAND"]).starts_with(&count.replacen(':', "*", 1)));
+    }
+
+    #[test]
+    fn test_latency_monitor() {
+        let addr = start_server_with(Config {
+            dir: tempfile::tempdir().unwrap().keep(),
+            latency_monitor_threshold: Some(Duration::from_millis(50)),
+            ..Config::default()
+        });
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        assert_eq!(send(&mut stream, &["LATENCY", "LATEST"]), "*0\r\n");
+        assert_eq!(send(&mut stream, &["DEBUG", "SLEEP", "0.1"]), "+OK\r\n");
+        send(&mut stream, &["GET", "key"]);
+
+        // One event: name, timestamp, latest and maximum latency in ms.
+        let latest = send(&mut stream, &["LATENCY", "LATEST"]);
+        let fields: Vec<&str> = latest.split("\r\n").collect();
+        assert_eq!(fields[..3], ["*1", "*4", "$7"]);
+        assert_eq!(fields[3], "command");
+        let latency: u64 = fields[5].trim_start_matches(':').parse().unwrap();
+        assert!(latency >= 100, "{}", latest);
+
+        assert!(send(&mut stream, &["LATENCY", "HISTORY", "command"]).starts_with("*1\r\n"));
+        assert!(send(&mut stream, &["INFO", "latencystats"])
+            .contains("latency_percentiles_usec_debug:p50="));
+        assert_eq!(send(&mut stream, &["LATENCY", "RESET"]), ":1\r\n");
+        assert_eq!(send(&mut stream, &["LATENCY", "LATEST"]), "*0\r\n");
+    }
+
+    #[test]
+    fn test_debug_stringmatch_len() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        assert_eq!(
+            send(
+                &mut stream,
+                &["DEBUG", "STRINGMATCH-LEN", "user:[0-9]*", "user:42"]
+            ),
+            ":1\r\n"
+        );
+        assert_eq!(
+            send(
+                &mut stream,
+                &["DEBUG", "STRINGMATCH-LEN", "user:[^0-9]*", "user:42"]
+            ),
+            ":0\r\n"
+        );
+    }
+
+    #[test]
+    fn test_debug_object() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        assert_eq!(
+            send(&mut stream, &["DEBUG", "OBJECT", "key"]),
+            "-ERR no such key\r\n"
+        );
+        send(&mut stream, &["SET", "key", "value"]);
+        let line = send(&mut stream, &["DEBUG", "OBJECT", "key"]);
+        assert!(
+            line.starts_with("+Value refcount:1 encoding:embstr "),
+            "{}",
+            line
+        );
+        let serialized_len = line
+            .split_whitespace()
+            .find_map(|field| field.strip_prefix("serializedlength:"))
+            .unwrap();
+        assert!(serialized_len.parse::<u64>().unwrap() > 0, "{}", line);
+        assert!(line.contains(" lru_seconds_idle:0\r\n"), "{}", line);
+    }
+
+    #[test]
+    fn test_maxmemory_noeviction() {
+        let mut stream = TcpStream::connect(start_server_with(Config {
+            dir: tempfile::tempdir().unwrap().keep(),
+            maxmemory: Some(4096),
+            maxmemory_policy: MaxMemoryPolicy::NoEviction,
+            ..Config::default()
+        }))
+        .unwrap();
+
+        let value = "x".repeat(500);
+        let oom = (0..20)
+            .map(|i| send(&mut stream, &["SET", &format!("key:{}", i), &value]))
+            .find(|reply| reply != "+OK\r\n")
+            .unwrap();
+        assert_eq!(
+            oom,
+            "-OOM command not allowed when used memory > 'maxmemory'.\r\n"
+        );
+        assert!(send(&mut stream, &["SET", "key:0", "small"]).starts_with("-OOM"));
+
+        // Reads and commands that can't grow the dataset still run.
+        assert!(send(&mut stream, &["GET", "key:0"]).starts_with("$500\r\n"));
+        assert_eq!(send(&mut stream, &["EXPIRETIME", "key:0"]), ":-1\r\n");
+        let info = send(&mut stream, &["INFO", "memory"]);
+        assert!(info.contains("maxmemory:4096\r\n"), "{}", info);
+
+        // Deleting frees memory even while over the limit, after which
+        // writes are accepted again.
+        let keys: Vec<String> = (0..20).map(|i| format!("key:{}", i)).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        assert!(send(&mut stream, &[&["DEL"][..], &keys].concat()).starts_with(':'));
+        assert_eq!(send(&mut stream, &["GET", "key:0"]), "$-1\r\n");
+        assert_eq!(send(&mut stream, &["SET", "key:0", "small"]), "+OK\r\n");
+        assert!(info.contains("maxmemory_policy:noeviction\r\n"), "{}", info);
+    }
+
+    #[test]
+    fn test_maxmemory_allkeys_lfu_keeps_the_hot_key() {
+        let mut stream = TcpStream::connect(start_server_with(Config {
+            dir: tempfile::tempdir().unwrap().keep(),
+            maxmemory: Some(4096),
+            maxmemory_policy: MaxMemoryPolicy::AllKeysLfu,
+            ..Config::default()
+        }))
+        .unwrap();
+
+        let value = "x".repeat(500);
+        send(&mut stream, &["SET", "hot", &value]);
+        for _ in 0..200 {
+            send(&mut stream, &["GET", "hot"]);
+        }
+
+        // Every write makes room by evicting, and the keys written once go
+        // before the one read over and over.
+        for i in 0..20 {
+            let key = format!("cold:{}", i);
+            assert_eq!(send(&mut stream, &["SET", &key, &value]), "+OK\r\n");
+        }
+        assert!(send(&mut stream, &["GET", "hot"]).starts_with("$500\r\n"));
+        assert!(send(&mut stream, &["GET", "cold:19"]).starts_with("$500\r\n"));
+
+        let info = send(&mut stream, &["INFO", "stats"]);
+        let evicted = info
+            .split("\r\n")
+            .find_map(|line| line.strip_prefix("evicted_keys:"))
+            .unwrap();
+        assert!(evicted.parse::<u64>().unwrap() >= 10, "{}", info);
+        let info = send(&mut stream, &["INFO", "memory"]);
+        assert!(
+            info.contains("maxmemory_policy:allkeys-lfu\r\n"),
+            "{}",
+            info
+        );
+    }
+
+    #[test]
+    fn test_maxmemory_volatile_lfu_only_evicts_keys_with_an_expiry() {
+        let mut stream = TcpStream::connect(start_server_with(Config {
+            dir: tempfile::tempdir().unwrap().keep(),
+            maxmemory: Some(4096),
+            maxmemory_policy: MaxMemoryPolicy::VolatileLfu,
+            ..Config::default()
+        }))
+        .unwrap();
+
+        let value = "x".repeat(500);
+        for i in 0..3 {
+            send(&mut stream, &["SET", &format!("keep:{}", i), &value]);
+        }
+        for i in 0..20 {
+            let key = format!("volatile:{}", i);
+            let reply = send(&mut stream, &["SET", &key, &value, "EX", "1000"]);
+            assert_eq!(reply, "+OK\r\n");
+        }
+
+        // Once only keys without an expiry are left, writes are refused.
+        let oom = (0..20)
+            .map(|i| send(&mut stream, &["SET", &format!("more:{}", i), &value]))
+            .find(|reply| reply != "+OK\r\n")
+            .unwrap();
+        assert!(oom.starts_with("-OOM"), "{}", oom);
+        for i in 0..3 {
+            let reply = send(&mut stream, &["GET", &format!("keep:{}", i)]);
+            assert!(reply.starts_with("$500\r\n"), "{}", reply);
+        }
+    }
+
+    #[test]
+    fn test_debug_set_memory_usage() {
+        let mut stream = TcpStream::connect(start_server_with(Config {
+            dir: tempfile::tempdir().unwrap().keep(),
+            maxmemory: Some(1024 * 1024),
+            ..Config::default()
+        }))
+        .unwrap();
+
+        assert_eq!(send(&mut stream, &["SET", "key", "value"]), "+OK\r\n");
+        assert_eq!(
+            send(&mut stream, &["DEBUG", "SET-MEMORY-USAGE", "2000000"]),
+            "+OK\r\n"
+        );
+        assert!(send(&mut stream, &["INFO", "memory"]).contains("used_memory:2000000\r\n"));
+        assert!(send(&mut stream, &["SET", "key", "value"]).starts_with("-OOM"));
+        assert_eq!(send(&mut stream, &["GET", "key"]), "$5\r\nvalue\r\n");
+
+        send(&mut stream, &["DEBUG", "SET-MEMORY-USAGE", "0"]);
+        assert_eq!(send(&mut stream, &["SET", "key", "value"]), "+OK\r\n");
+
+        send(&mut stream, &["DEBUG", "SET-MEMORY-USAGE", "2000000"]);
+        send(&mut stream, &["CONFIG", "RESETSTAT"]);
+        assert_eq!(send(&mut stream, &["SET", "key", "value"]), "+OK\r\n");
+    }
+
+    #[test]
+    fn test_protocol_error_closes_connection() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        stream.write_all(b"*1\r\n:4\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert_eq!(response, "-ERR Protocol error: expected '$', got ':'\r\n");
+    }
+
+    #[test]
+    fn test_large_value_round_trip() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+        let value: String = (0..5 * 1024 * 1024)
+            .map(|i| (b'a' + (i % 26) as u8) as char)
+            .collect();
+
+        assert_eq!(send(&mut stream, &["SET", "big", &value]), "+OK\r\n");
+        stream
+            .write_all(b"*2\r\n$3\r\nGET\r\n$3\r\nbig\r\n")
+            .unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut header = String::new();
+        reader.read_line(&mut header).unwrap();
+        assert_eq!(header, format!("${}\r\n", value.len()));
+        let mut body = vec![0; value.len() + 2];
+        reader.read_exact(&mut body).unwrap();
+        assert!(body[..value.len()] == *value.as_bytes());
+
+        // The connection is still usable once its buffer has shrunk back.
+        assert_eq!(send(&mut stream, &["PING"]), "+PONG\r\n");
+    }
+
+    #[test]
+    fn test_split_and_pipelined_requests() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        // A request arriving in pieces is answered once it is complete.
+        stream.write_all(b"*3\r\n$3\r\nSET\r\n$3\r\nk").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        stream.write_all(b"ey\r\n$5\r\nvalue\r\n").unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "+OK\r\n");
+
+        // Pipelined requests are all answered, in order, and an invalid
+        // command doesn't close the connection.
+        stream
+            .write_all(b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n*1\r\n$5\r\nBOGUS\r\n*1\r\n$4\r\nPING\r\n")
+            .unwrap();
+        let lines: Vec<String> = (0..4)
+            .map(|_| {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                line
+            })
+            .collect();
+        assert_eq!(lines[0], "$5\r\n");
+        assert_eq!(lines[1], "value\r\n");
+        assert!(
+            lines[2].starts_with("-ERR unknown command 'BOGUS'"),
+            "{}",
+            lines[2]
+        );
+        assert_eq!(lines[3], "+PONG\r\n");
+    }
+
+    #[test]
+    fn test_cluster_keyslot() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        assert_eq!(
+            send(&mut stream, &["CLUSTER", "KEYSLOT", "foo"]),
+            ":12182\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["CLUSTER", "KEYSLOT", "{user1000}.following"]),
+            ":3443\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["CLUSTER", "KEYSLOT", "{user1000}.followers"]),
+            ":3443\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["CLUSTER", "NODES"]),
+            "-ERR Unknown subcommand 'NODES'. Valid CLUSTER subcommands are: KEYSLOT, HELP\r\n"
+        );
+    }
+
+    #[test]
+    fn test_object_idletime() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        assert_eq!(send(&mut stream, &["OBJECT", "IDLETIME", "key"]), "$-1\r\n");
+
+        send(&mut stream, &["SET", "key", "value"]);
+        thread::sleep(Duration::from_millis(1100));
+        assert_eq!(send(&mut stream, &["OBJECT", "IDLETIME", "key"]), ":1\r\n");
+
+        send(&mut stream, &["GET", "key"]);
+        assert_eq!(send(&mut stream, &["OBJECT", "IDLETIME", "key"]), ":0\r\n");
+    }
+
+    #[test]
+    fn test_object_refcount_and_encoding() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        assert_eq!(
+            send(&mut stream, &["OBJECT", "REFCOUNT", "key"]),
+            "-ERR no such key\r\n"
+        );
+
+        send(&mut stream, &["SET", "key", "12345"]);
+        assert_eq!(send(&mut stream, &["OBJECT", "REFCOUNT", "key"]), ":1\r\n");
+        assert_eq!(
+            send(&mut stream, &["OBJECT", "ENCODING", "key"]),
+            "$3\r\nint\r\n"
+        );
+
+        send(&mut stream, &["SET", "key", "value"]);
+        assert_eq!(
+            send(&mut stream, &["OBJECT", "ENCODING", "key"]),
+            "$6\r\nembstr\r\n"
+        );
+    }
+
+    #[test]
+    fn test_incr_decr() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        send(&mut stream, &["SET", "counter", "100"]);
+        assert_eq!(
+            send(&mut stream, &["OBJECT", "ENCODING", "counter"]),
+            "$3\r\nint\r\n"
+        );
+        assert_eq!(send(&mut stream, &["GET", "counter"]), "$3\r\n100\r\n");
+        assert_eq!(send(&mut stream, &["INCR", "counter"]), ":101\r\n");
+        assert_eq!(send(&mut stream, &["GET", "counter"]), "$3\r\n101\r\n");
+        assert_eq!(send(&mut stream, &["DECR", "missing"]), ":-1\r\n");
+
+        send(&mut stream, &["SET", "text", "abc"]);
+        assert_eq!(
+            send(&mut stream, &["INCR", "text"]),
+            "-ERR value is not an integer or out of range\r\n"
+        );
+        send(&mut stream, &["SET", "max", &i64::MAX.to_string()]);
+        assert_eq!(
+            send(&mut stream, &["INCR", "max"]),
+            "-ERR increment or decrement would overflow\r\n"
+        );
+        send(&mut stream, &["HSET", "hash", "field", "1"]);
+        assert!(send(&mut stream, &["INCR", "hash"]).starts_with("-WRONGTYPE"));
+    }
+
+    #[test]
+    fn test_replication() {
+        let primary = start_server();
+        let mut primary_stream = TcpStream::connect(primary).unwrap();
+        let mut replica_stream = TcpStream::connect(start_server()).unwrap();
+
+        assert_eq!(
+            send(&mut primary_stream, &["SET", "before", "sync"]),
+            "+OK\r\n"
+        );
+        assert_eq!(
+            send(
+                &mut replica_stream,
+                &["REPLICAOF", "127.0.0.1", &primary.port().to_string()]
+            ),
+            "+OK\r\n"
+        );
+        assert_eq!(
+            send(&mut primary_stream, &["SET", "after", "sync"]),
+            "+OK\r\n"
+        );
+
+        let mut replicated = false;
+        for _ in 0..50 {
+            if send(&mut replica_stream, &["GET", "after"]) == "$4\r\nsync\r\n" {
+                replicated = true;
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(replicated);
+        assert_eq!(send(&mut primary_stream, &["WAIT", "1", "1000"]), ":1\r\n");
+        assert_eq!(
+            send(&mut replica_stream, &["GET", "before"]),
+            "$4\r\nsync\r\n"
+        );
+
+        // Writes reach the replica's database of the same index.
+        send(&mut primary_stream, &["SELECT", "1"]);
+        send(&mut primary_stream, &["SET", "before", "db1"]);
+        assert_eq!(send(&mut primary_stream, &["WAIT", "1", "1000"]), ":1\r\n");
+        assert_eq!(
+            send(&mut replica_stream, &["GET", "before"]),
+            "$4\r\nsync\r\n"
+        );
+        send(&mut replica_stream, &["SELECT", "1"]);
+        assert_eq!(
+            send(&mut replica_stream, &["GET", "before"]),
+            "$3\r\ndb1\r\n"
+        );
+        send(&mut replica_stream, &["SELECT", "0"]);
+
+        assert_eq!(
+            send(&mut replica_stream, &["SET", "after", "write"]),
+            "-READONLY You can't write against a read only replica.\r\n"
+        );
+
+        assert_eq!(
+            send(&mut replica_stream, &["REPLICAOF", "NO", "ONE"]),
+            "+OK\r\n"
+        );
+        assert_eq!(
+            send(&mut replica_stream, &["SET", "after", "write"]),
+            "+OK\r\n"
+        );
+    }
+
+    #[test]
+    fn test_refused_sync_keeps_the_dataset() {
+        let primary = start_server_with(Config {
+            dir: tempfile::tempdir().unwrap().keep(),
+            requirepass: Some("secret".into()),
+            ..Config::default()
+        });
+        let mut replica_stream = TcpStream::connect(start_server()).unwrap();
+        assert_eq!(send(&mut replica_stream, &["SET", "key", "v"]), "+OK\r\n");
+
+        // The primary answers SYNC with NOAUTH, which must not be mistaken
+        // for the start of a snapshot.
+        assert_eq!(
+            send(
+                &mut replica_stream,
+                &["REPLICAOF", "127.0.0.1", &primary.port().to_string()]
+            ),
+            "+OK\r\n"
+        );
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(send(&mut replica_stream, &["GET", "key"]), "$1\r\nv\r\n");
+    }
+
+    #[test]
+    fn test_changefeed() {
+        let addr = start_server();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        assert_eq!(send(&mut stream, &["SET", "before", "1"]), "+OK\r\n");
+        assert!(send(&mut stream, &["CHANGEFEED", "BOGUS"]).starts_with("-Command error"));
+
+        let mut consumer = TcpStream::connect(addr).unwrap();
+        consumer
+            .write_all(b"*2\r\n$10\r\nCHANGEFEED\r\n$8\r\nSNAPSHOT\r\n")
+            .unwrap();
+        let mut next = || {
+            let mut len = [0; 4];
+            consumer.read_exact(&mut len).unwrap();
+            let mut frame = vec![0; u32::from_le_bytes(len) as usize];
+            consumer.read_exact(&mut frame).unwrap();
+            bincode::deserialize::<Operation>(&frame).unwrap()
+        };
+
+        assert!(
+            matches!(next(), Operation::Set { key, value: Value::Int(1), .. } if key == "before")
+        );
+
+        assert_eq!(send(&mut stream, &["SET", "key", "a"]), "+OK\r\n");
+        assert_eq!(send(&mut stream, &["HSET", "hash", "f", "v"]), ":1\r\n");
+        assert_eq!(send(&mut stream, &["SET", "key", "b"]), "+OK\r\n");
+        assert!(matches!(
+            next(),
+            Operation::Set { key, value: Value::String(value), .. } if key == "key" && value[..] == b"a"[..]
+        ));
+        assert!(matches!(next(), Operation::HSet { key, .. } if key == "hash"));
+        assert!(matches!(
+            next(),
+            Operation::Set { key, value: Value::String(value), .. } if key == "key" && value[..] == b"b"[..]
+        ));
+
+        // Writes to another database come after a SELECT frame.
+        assert_eq!(send(&mut stream, &["SELECT", "2"]), "+OK\r\n");
+        assert_eq!(send(&mut stream, &["SET", "key", "c"]), "+OK\r\n");
+        assert!(matches!(next(), Operation::Select { db: 2 }));
+        assert!(matches!(next(), Operation::Set { key, .. } if key == "key"));
+    }
+
+    #[test]
+    fn test_save_point() {
+        let dir = tempfile::tempdir().unwrap().keep();
+        let mut stream = TcpStream::connect(start_server_with(Config {
+            dir: dir.clone(),
+            save_points: vec![(Duration::from_secs(1), 1)],
+            ..Config::default()
+        }))
+        .unwrap();
+        assert!(send(&mut stream, &["INFO", "persistence"])
+            .contains("rdb_changes_since_last_save:0\r\n"));
+
+        assert_eq!(send(&mut stream, &["SET", "key", "value"]), "+OK\r\n");
+        assert!(send(&mut stream, &["INFO", "persistence"])
+            .contains("rdb_changes_since_last_save:1\r\n"));
+
+        let mut saved = false;
+        for _ in 0..30 {
+            thread::sleep(Duration::from_millis(100));
+            if send(&mut stream, &["INFO", "persistence"])
+                .contains("rdb_changes_since_last_save:0\r\n")
+            {
+                saved = true;
+                break;
+            }
+        }
+        assert!(saved);
+        assert!(dir.join("dump.rdb").exists());
+    }
+
+    #[test]
+    fn test_info_memory() {
+        fn used_memory(stream: &mut TcpStream) -> usize {
+            let info = send(stream, &["INFO", "memory"]);
+            info.lines()
+                .find_map(|line| line.strip_prefix("used_memory:"))
+                .unwrap()
+                .parse()
+                .unwrap()
+        }
+
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+        assert_eq!(used_memory(&mut stream), 0);
+        assert!(send(&mut stream, &["INFO", "memory"]).contains("maxmemory_policy:noeviction\r\n"));
+
+        let value = "x".repeat(900);
+        for i in 0..100 {
+            send(&mut stream, &["SET", &format!("a:{}", i), &value]);
+        }
+        let first = used_memory(&mut stream);
+        assert!((90_000..135_000).contains(&first), "{}", first);
+
+        for i in 0..100 {
+            send(&mut stream, &["SET", &format!("b:{}", i), &value]);
+        }
+        let second = used_memory(&mut stream);
+        assert!(
+            second.abs_diff(2 * first) < first / 20,
+            "{} {}",
+            first,
+            second
+        );
+
+        // Overwriting with small values gives the memory back.
+        for i in 0..100 {
+            send(&mut stream, &["SET", &format!("b:{}", i), "x"]);
+        }
+        let third = used_memory(&mut stream);
+        assert!(third < first + first / 4, "{} {}", first, third);
+        let info = send(&mut stream, &["INFO", "memory"]);
+        assert!(
+            info.contains(&format!("used_memory_peak:{}\r\n", second)),
+            "{}",
+            info
+        );
+    }
+
+    #[test]
+    fn test_expiretime() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        assert_eq!(send(&mut stream, &["EXPIRETIME", "missing"]), ":-2\r\n");
+        send(&mut stream, &["SET", "persistent", "value"]);
+        assert_eq!(send(&mut stream, &["PEXPIRETIME", "persistent"]), ":-1\r\n");
+
+        send(&mut stream, &["SET", "key", "value", "PX", "100000"]);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let reply = send(&mut stream, &["PEXPIRETIME", "key"]);
+        let millis: u128 = reply[1..reply.len() - 2].parse().unwrap();
+        let expected = now.as_millis() + 100_000;
+        assert!(millis.abs_diff(expected) < 1000, "{} {}", millis, expected);
+
+        let reply = send(&mut stream, &["EXPIRETIME", "key"]);
+        let secs: u128 = reply[1..reply.len() - 2].parse().unwrap();
+        assert_eq!(secs, millis / 1000);
+    }
+
+    #[test]
+    fn test_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        preload(dir.path(), "preloaded", "value");
+
+        let mut stream = TcpStream::connect(start_server_with(Config {
+            dir: dir.path().to_path_buf(),
+            read_only: true,
+            ..Config::default()
+        }))
+        .unwrap();
+
+        assert_eq!(send(&mut stream, &["GET", "preloaded"]), "$5\r\nvalue\r\n");
+        assert_eq!(
+            send(&mut stream, &["SET", "preloaded", "changed"]),
+            "-READONLY You can't write against a read only replica.\r\n"
+        );
+        assert_eq!(send(&mut stream, &["PING"]), "+PONG\r\n");
+    }
+
+    #[test]
+    fn test_wait_without_replicas() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        assert_eq!(send(&mut stream, &["SET", "key", "value"]), "+OK\r\n");
+
+        let started = std::time::Instant::now();
+        assert_eq!(send(&mut stream, &["WAIT", "1", "5000"]), ":0\r\n");
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_blocked_wait_does_not_stall_other_clients() {
+        let addr = start_server();
+        // A replica that never acknowledges anything.
+        let mut replica = TcpStream::connect(addr).unwrap();
+        replica.write_all(b"*1\r\n$4\r\nSYNC\r\n").unwrap();
+        let mut handshake = [0; 64];
+        let _ = replica.read(&mut handshake).unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        assert_eq!(send(&mut stream, &["SET", "key", "value"]), "+OK\r\n");
+        let mut waiting = TcpStream::connect(addr).unwrap();
+        waiting
+            .write_all(b"*3\r\n$4\r\nWAIT\r\n$1\r\n1\r\n$1\r\n0\r\n")
+            .unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        // INCR takes the lock exclusively, so it would queue behind the
+        // WAIT if that held the lock while blocked.
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        assert_eq!(send(&mut stream, &["INCR", "counter"]), ":1\r\n");
+        assert_eq!(send(&mut stream, &["GET", "key"]), "$5\r\nvalue\r\n");
+    }
+
+    #[test]
+    fn test_acl_permissions() {
+        let mut stream = TcpStream::connect(start_server_with(Config {
+            dir: tempfile::tempdir().unwrap().keep(),
+            requirepass: Some("admin".into()),
+            users: vec![User::parse("reader on >secret -@all +get ~public:*").unwrap()],
+            ..Config::default()
+        }))
+        .unwrap();
+
+        assert_eq!(
+            send(&mut stream, &["GET", "public:key"]),
+            "-NOAUTH Authentication required.\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["AUTH", "reader", "wrong"]),
+            "-WRONGPASS invalid username-password pair or user is disabled.\r\n"
+        );
+
+        assert_eq!(send(&mut stream, &["AUTH", "admin"]), "+OK\r\n");
+        assert_eq!(send(&mut stream, &["ACL", "WHOAMI"]), "$7\r\ndefault\r\n");
+        assert_eq!(
+            send(&mut stream, &["SET", "public:key", "value"]),
+            "+OK\r\n"
+        );
+
+        assert_eq!(send(&mut stream, &["AUTH", "reader", "secret"]), "+OK\r\n");
+        assert_eq!(send(&mut stream, &["GET", "public:key"]), "$5\r\nvalue\r\n");
+        assert_eq!(
+            send(&mut stream, &["SET", "public:key", "other"]),
+            "-NOPERM User reader has no permissions to run the 'set' command\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["GET", "private:key"]),
+            "-NOPERM No permissions to access a key\r\n"
+        );
+    }
+
+    #[test]
+    fn test_hello_auth() {
+        let mut stream = TcpStream::connect(start_server_with(Config {
+            dir: tempfile::tempdir().unwrap().keep(),
+            requirepass: Some("admin".into()),
+            ..Config::default()
+        }))
+        .unwrap();
+
+        assert!(send(&mut stream, &["HELLO", "2"]).starts_with("-NOAUTH HELLO must be called"));
+        assert_eq!(
+            send(&mut stream, &["HELLO", "2", "AUTH", "default", "wrong"]),
+            "-WRONGPASS invalid username-password pair or user is disabled.\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["HELLO", "4"]),
+            "-NOPROTO unsupported protocol version\r\n"
+        );
+
+        let reply = send(
+            &mut stream,
+            &[
+                "HELLO", "2", "AUTH", "default", "admin", "SETNAME", "worker",
+            ],
+        );
+        assert!(
+            reply.starts_with("*14\r\n$6\r\nserver\r\n$5\r\nredis\r\n"),
+            "{}",
+            reply
+        );
+        assert!(reply.contains("$5\r\nproto\r\n:2\r\n"));
+        assert_eq!(send(&mut stream, &["GET", "key"]), "$-1\r\n");
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut stream = TcpStream::connect(start_server_with(Config {
+            dir: tempfile::tempdir().unwrap().keep(),
+            requirepass: Some("admin".into()),
+            ..Config::default()
+        }))
+        .unwrap();
+
+        assert_eq!(send(&mut stream, &["AUTH", "admin"]), "+OK\r\n");
+        assert_eq!(send(&mut stream, &["GET", "key"]), "$-1\r\n");
+
+        assert_eq!(send(&mut stream, &["RESET"]), "+RESET\r\n");
+        assert_eq!(
+            send(&mut stream, &["GET", "key"]),
+            "-NOAUTH Authentication required.\r\n"
+        );
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        assert_eq!(
+            send(
+                &mut stream,
+                &[
+                    "EVAL",
+                    "redis.call('SET', KEYS[1], ARGV[1]) return redis.call('GET', KEYS[1])",
+                    "1",
+                    "scripted",
+                    "value",
+                ]
+            ),
+            "$5\r\nvalue\r\n"
+        );
+        assert_eq!(send(&mut stream, &["GET", "scripted"]), "$5\r\nvalue\r\n");
+
+        assert_eq!(
+            send(
+                &mut stream,
+                &["EVAL", "return {1, 'two', {ok = 'OK'}}", "0"]
+            ),
+            "*3\r\n:1\r\n$3\r\ntwo\r\n+OK\r\n"
+        );
+        assert_eq!(
+            send(
+                &mut stream,
+                &["EVAL", "return redis.pcall('GET')['err'] ~= nil", "0"]
+            ),
+            ":1\r\n"
+        );
+    }
+
+    #[test]
+    fn test_script_cache() {
+        let mut stream = TcpStream::connect(start_server()).unwrap();
+
+        let response = send(&mut stream, &["SCRIPT", "LOAD", "return ARGV[1]"]);
+        let sha = response.lines().nth(1).unwrap().to_string();
+        assert_eq!(sha.len(), 40);
+
+        assert_eq!(
+            send(&mut stream, &["EVALSHA", &sha, "0", "cached"]),
+            "$6\r\ncached\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["SCRIPT", "EXISTS", &sha, &"0".repeat(40)]),
+            "*2\r\n:1\r\n:0\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["EVALSHA", &"0".repeat(40), "0"]),
+            "-NOSCRIPT No matching script. Please use EVAL.\r\n"
+        );
+
+        assert_eq!(send(&mut stream, &["SCRIPT", "FLUSH"]), "+OK\r\n");
+        assert_eq!(
+            send(&mut stream, &["EVALSHA", &sha, "0", "cached"]),
+            "-NOSCRIPT No matching script. Please use EVAL.\r\n"
+        );
+    }
 }
@@ -0,0 +1,54 @@
+use std::{io, time::Duration};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time,
+};
+
+use crate::resp::Resp;
+
+/// Restores a DUMP payload under `key` on the instance at `host:port`, as
+/// MIGRATE does, giving up once `timeout` elapses. Returns the target's
+/// reply line: `+OK` on success or the error it replied with.
+pub async fn restore_remote(
+    host: &str,
+    port: u16,
+    key: &str,
+    ttl: u64,
+    payload: String,
+    replace: bool,
+    timeout: Duration,
+) -> io::Result<String> {
+    let mut args = vec![
+        Resp::BulkString("RESTORE".into()),
+        Resp::BulkString(key.into()),
+        Resp::BulkString(ttl.to_string()),
+        Resp::BulkString(payload),
+    ];
+    if replace {
+        args.push(Resp::BulkString("REPLACE".into()));
+    }
+
+    let exchange = async {
+        let mut stream = TcpStream::connect((host, port)).await?;
+        stream.write_all(&Resp::Array(args).into_bytes()).await?;
+
+        // RESTORE replies with a single status or error line.
+        let mut reply = Vec::new();
+        let mut buffer = [0; 256];
+        while !reply.ends_with(b"\r\n") {
+            let n = stream.read(&mut buffer).await?;
+            if n == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+            }
+            reply.extend_from_slice(&buffer[..n]);
+        }
+        reply.truncate(reply.len() - 2);
+        Ok(String::from_utf8_lossy(&reply).into_owned())
+    };
+
+    time::timeout(timeout, exchange)
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?
+}
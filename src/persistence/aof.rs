@@ -1,129 +1,429 @@
+use super::rdb::{self, Compression};
 use super::Operation;
-use super::ValueEntry;
+use super::Snapshot;
 use bincode::{deserialize, serialize};
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom};
 use std::{
     fs::{File, OpenOptions},
     io::{self, BufReader, BufWriter, Write},
-    path::PathBuf,
-    sync::Arc,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
 };
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::oneshot;
 
+/// Identifies an AOF file starting with a header. Files without it hold bare
+/// frames in either the current or the string-only operation format.
+const MAGIC: &[u8] = b"RLAOF";
+const AOF_VERSION: u8 = 1;
+/// Version 2 follows the header with an RDB snapshot of the dataset as of
+/// the last compaction, prefixed with its length as a little-endian `u64`,
+/// then the operations logged since, so loading skips replaying the bulk
+/// of the dataset frame by frame.
+const AOF_VERSION_PREAMBLE: u8 = 2;
+
+/// Most operations the writer takes off the queue before flushing them.
+const MAX_BATCH: usize = 1024;
+/// Operations buffered before an `everysec` flush and fsync is forced.
+const SYNC_EVERY: usize = 1000;
+
+/// Encodes an operation as a length-prefixed bincode frame, the format used
+/// both for the AOF file and for streaming operations to replicas.
+pub fn encode_operation(op: &Operation) -> io::Result<Vec<u8>> {
+    let serialized = serialize(op).map_err(io::Error::other)?;
+    let mut frame = Vec::with_capacity(4 + serialized.len());
+    frame.extend_from_slice(&(serialized.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&serialized);
+    Ok(frame)
+}
+
+/// The database a log of operations is in as it is written, so a SELECT
+/// frame goes in ahead of each operation on another database than the one
+/// before. The default is not knowing, as when appending to an existing
+/// file.
+#[derive(Debug, Default)]
+pub struct SelectedDb(Option<usize>);
+
+impl SelectedDb {
+    /// The database at the start of a log, 0, which readers start out in.
+    pub fn start() -> Self {
+        Self(Some(0))
+    }
+
+    /// The SELECT frame to write ahead of an operation on `db`, if the log
+    /// isn't in it already.
+    pub fn switch_to(&mut self, db: usize) -> io::Result<Option<Vec<u8>>> {
+        if self.0 == Some(db) {
+            return Ok(None);
+        }
+        self.0 = Some(db);
+        encode_operation(&Operation::Select { db }).map(Some)
+    }
+}
+
+/// When appended operations are flushed and fsynced to disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AppendFsync {
+    /// Before each write is acknowledged, so no acknowledged write is lost.
+    Always,
+    /// Every 1000 operations and once a second from a background task, so a
+    /// crash loses at most about a second of writes.
+    #[default]
+    EverySec,
+}
+
+impl FromStr for AppendFsync {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "always" => Ok(AppendFsync::Always),
+            "everysec" => Ok(AppendFsync::EverySec),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Appends operations to the AOF.
+///
+/// The file is owned by a dedicated writer thread fed through a channel, so
+/// callers enqueue without contending on it. The writer drains whatever has
+/// queued up on each wakeup and writes it as one batch, which under the
+/// `always` policy shares a single fsync between all the writes it holds.
+/// Each caller still waits until its operation has been written (and synced,
+/// when the policy asks for it), so failures reach the client that issued it.
 pub struct AofManager {
-    writer: RwLock<BufWriter<File>>,
+    requests: Sender<Request>,
     path: PathBuf,
-    sync_counter: Arc<Mutex<usize>>,
+}
+
+enum Request {
+    /// Appends an encoded operation on a database.
+    Append(usize, Vec<u8>, oneshot::Sender<io::Result<()>>),
+    /// Flushes and fsyncs everything appended so far.
+    Sync(oneshot::Sender<io::Result<()>>),
+    /// Switches to the file now at the AOF path, once compaction replaced it.
+    Reopen(oneshot::Sender<io::Result<()>>),
 }
 
 impl AofManager {
-    pub fn new(path: PathBuf) -> io::Result<Self> {
-        let file = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .append(true)
-            .open(&path)?;
+    pub fn new(path: PathBuf, fsync: AppendFsync) -> io::Result<Self> {
+        let mut writer = Writer::open(&path, fsync)?;
+        let (requests, queue) = mpsc::channel();
+        thread::Builder::new()
+            .name("aof-writer".into())
+            .spawn(move || writer.run(&queue))?;
 
-        let writer = RwLock::new(BufWriter::with_capacity(32 * 1024 * 1024, file));
+        Ok(Self { requests, path })
+    }
 
-        Ok(Self {
-            writer,
-            path,
-            sync_counter: Arc::new(Mutex::new(0)),
-        })
+    pub async fn append_operation(&self, db: usize, op: &Operation) -> io::Result<()> {
+        let frame = encode_operation(op)?;
+        self.request(|done| Request::Append(db, frame, done)).await
+    }
+
+    pub async fn sync(&self) -> io::Result<()> {
+        self.request(Request::Sync).await
     }
 
-    pub async fn append_operation(&self, op: &Operation) -> io::Result<()> {
-        let serialized = serialize(op).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        let len = (serialized.len() as u32).to_le_bytes();
+    async fn request(
+        &self,
+        request: impl FnOnce(oneshot::Sender<io::Result<()>>) -> Request,
+    ) -> io::Result<()> {
+        let (done, result) = oneshot::channel();
+        self.requests
+            .send(request(done))
+            .map_err(|_| io::Error::other("AOF writer stopped"))?;
+        result
+            .await
+            .map_err(|_| io::Error::other("AOF writer stopped"))?
+    }
+
+    /// Replaces the AOF with `snapshot`, written as an RDB preamble
+    /// compressed with `preamble` if given and as one SET frame per entry
+    /// otherwise.
+    pub async fn compact(
+        &self,
+        snapshot: &Snapshot,
+        preamble: Option<Compression>,
+    ) -> io::Result<()> {
+        write_snapshot(&self.path, snapshot, preamble)?;
+        self.request(Request::Reopen).await
+    }
 
-        {
-            let mut writer = self.writer.write().await;
-            writer.write_all(&len)?;
-            writer.write_all(&serialized)?;
+    pub fn load(&self) -> io::Result<AofContents> {
+        let mut contents = AofContents::default();
+
+        if self.path.exists() {
+            let file = File::open(&self.path)?;
+            let mut reader = BufReader::with_capacity(32 * 1024 * 1024, file);
+            let mut header = [0u8; MAGIC.len() + 1];
+            let legacy = match reader.read_exact(&mut header) {
+                Ok(()) if header.starts_with(MAGIC) => match header[MAGIC.len()] {
+                    AOF_VERSION => false,
+                    AOF_VERSION_PREAMBLE => {
+                        let mut len_bytes = [0u8; 8];
+                        reader.read_exact(&mut len_bytes)?;
+                        let mut snapshot = vec![0u8; u64::from_le_bytes(len_bytes) as usize];
+                        reader.read_exact(&mut snapshot)?;
+                        contents.preamble = rdb::decode(&snapshot)?;
+                        false
+                    }
+                    version => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("unsupported AOF version {}", version),
+                        ))
+                    }
+                },
+                _ => {
+                    reader.rewind()?;
+                    true
+                }
+            };
+            let mut len_bytes = [0u8; 4];
+            let mut db = 0;
 
-            let mut counter = self.sync_counter.lock().await;
-            *counter += 1;
+            while reader.read_exact(&mut len_bytes).is_ok() {
+                let len = u32::from_le_bytes(len_bytes) as usize;
+                let mut buf = vec![0u8; len];
+                reader.read_exact(&mut buf)?;
 
-            if *counter >= 1000 {
-                writer.flush()?;
-                writer.get_ref().sync_all()?;
-                *counter = 0;
+                let op = if legacy {
+                    super::legacy::decode_operation(&buf)?
+                } else {
+                    deserialize(&buf).map_err(io::Error::other)?
+                };
+                match op {
+                    Operation::Select { db: selected } => db = selected,
+                    op => contents.operations.push((db, op)),
+                }
             }
         }
 
-        Ok(())
-    }
-
-    pub async fn sync(&self) -> io::Result<()> {
-        let mut writer = self.writer.write().await;
-        writer.flush()?;
-        writer.get_ref().sync_all()?;
-        Ok(())
+        Ok(contents)
     }
+}
 
-    pub async fn compact(&self, entries: &[(String, ValueEntry)]) -> io::Result<()> {
-        let temp_path = self.path.with_extension("temp");
+/// What an AOF holds: the snapshot in its RDB preamble, if it has one, and
+/// the operations to replay on top of it, with the database each applies
+/// to.
+#[derive(Default)]
+pub struct AofContents {
+    pub preamble: Snapshot,
+    pub operations: Vec<(usize, Operation)>,
+}
 
-        let file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(&temp_path)?;
+/// Replaces the AOF at `path` with one holding `snapshot`, so that loading
+/// it alone rebuilds the dataset: as an RDB preamble compressed with
+/// `preamble` if given, and as one SET frame per entry otherwise.
+pub fn write_snapshot(
+    path: &Path,
+    snapshot: &Snapshot,
+    preamble: Option<Compression>,
+) -> io::Result<()> {
+    let temp_path = path.with_extension("temp");
 
-        let mut writer = BufWriter::with_capacity(32 * 1024 * 1024, file);
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(&temp_path)?;
 
-        for (key, entry) in entries {
-            let op = Operation::Set {
-                key: key.clone(),
-                value: entry.value.clone(),
-                expires_at: entry.expires_at,
-            };
-            let serialized = serialize(&op).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-            let len = (serialized.len() as u32).to_le_bytes();
-            writer.write_all(&len)?;
-            writer.write_all(&serialized)?;
+    let mut writer = BufWriter::with_capacity(32 * 1024 * 1024, file);
+    match preamble {
+        Some(compression) => {
+            writer.write_all(MAGIC)?;
+            writer.write_all(&[AOF_VERSION_PREAMBLE])?;
+            // The length is filled in once the snapshot is written.
+            let len_at = writer.stream_position()?;
+            writer.write_all(&0u64.to_le_bytes())?;
+            rdb::write(
+                &mut writer,
+                snapshot.iter().map(|(db, entries)| (*db, entries)),
+                compression,
+            )?;
+            let end = writer.stream_position()?;
+            writer.seek(SeekFrom::Start(len_at))?;
+            writer.write_all(&(end - len_at - 8).to_le_bytes())?;
+            writer.seek(SeekFrom::Start(end))?;
         }
+        None => {
+            write_header(&mut writer)?;
+            let mut selected = SelectedDb::start();
+            for (db, entries) in snapshot {
+                if let Some(select) = selected.switch_to(*db)? {
+                    writer.write_all(&select)?;
+                }
+                for (key, entry) in entries {
+                    let op = Operation::Set {
+                        key: key.clone(),
+                        value: entry.value.clone(),
+                        expires_at: entry.expires_at,
+                    };
+                    writer.write_all(&encode_operation(&op)?)?;
+                }
+            }
+        }
+    }
+
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
 
-        writer.flush()?;
-        writer.get_ref().sync_all()?;
+    super::replace_file(&temp_path, path)
+}
+
+fn write_header(writer: &mut impl Write) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[AOF_VERSION])
+}
+
+struct Writer {
+    file: BufWriter<File>,
+    path: PathBuf,
+    fsync: AppendFsync,
+    /// The database the operations at the end of the file apply to.
+    selected: SelectedDb,
+    /// Operations written since the last fsync.
+    unsynced: usize,
+    fsyncs: usize,
+}
 
-        std::fs::rename(temp_path, &self.path)?;
+impl Writer {
+    fn open(path: &Path, fsync: AppendFsync) -> io::Result<Self> {
+        Ok(Self {
+            file: Self::open_file(path)?,
+            path: path.to_path_buf(),
+            fsync,
+            selected: SelectedDb::default(),
+            unsynced: 0,
+            fsyncs: 0,
+        })
+    }
 
+    fn open_file(path: &Path) -> io::Result<BufWriter<File>> {
         let file = OpenOptions::new()
             .create(true)
             .read(true)
-            .write(true)
             .append(true)
-            .open(&self.path)?;
+            .open(path)?;
+        let empty = file.metadata()?.len() == 0;
+        let mut file = BufWriter::with_capacity(32 * 1024 * 1024, file);
+        if empty {
+            write_header(&mut file)?;
+        }
+        Ok(file)
+    }
+
+    /// Serves requests until every [`AofManager`] handle is dropped.
+    fn run(&mut self, queue: &Receiver<Request>) {
+        while let Ok(first) = queue.recv() {
+            let mut appended = Vec::new();
+            for request in std::iter::once(first).chain(queue.try_iter().take(MAX_BATCH - 1)) {
+                match request {
+                    Request::Append(db, frame, done) => match self.append(db, &frame) {
+                        Ok(()) => {
+                            self.unsynced += 1;
+                            appended.push(done);
+                        }
+                        Err(e) => {
+                            let _ = done.send(Err(e));
+                        }
+                    },
+                    Request::Sync(done) => {
+                        self.complete(&mut appended);
+                        let _ = done.send(self.sync());
+                    }
+                    Request::Reopen(done) => {
+                        self.complete(&mut appended);
+                        let _ = done.send(self.reopen());
+                    }
+                }
+            }
+            self.complete(&mut appended);
+        }
+    }
+
+    /// Writes an operation frame, preceded by a SELECT frame if the file
+    /// is in another database. The frames are written in the order the
+    /// requests were queued, so the file always switches databases where
+    /// its operations need it to.
+    fn append(&mut self, db: usize, frame: &[u8]) -> io::Result<()> {
+        if let Some(select) = self.selected.switch_to(db)? {
+            self.file.write_all(&select)?;
+        }
+        self.file.write_all(frame)
+    }
+
+    /// Acknowledges the appends written so far, syncing first if the policy
+    /// calls for it.
+    fn complete(&mut self, appended: &mut Vec<oneshot::Sender<io::Result<()>>>) {
+        if appended.is_empty() {
+            return;
+        }
+        let result = if self.fsync == AppendFsync::Always || self.unsynced >= SYNC_EVERY {
+            self.sync()
+        } else {
+            Ok(())
+        };
+        for done in appended.drain(..) {
+            let result = match &result {
+                Ok(()) => Ok(()),
+                Err(e) => Err(io::Error::new(e.kind(), e.to_string())),
+            };
+            let _ = done.send(result);
+        }
+    }
 
-        let mut writer = self.writer.write().await;
-        *writer = BufWriter::with_capacity(32 * 1024 * 1024, file);
+    fn sync(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.get_ref().sync_all()?;
+        self.unsynced = 0;
+        self.fsyncs += 1;
+        Ok(())
+    }
 
+    fn reopen(&mut self) -> io::Result<()> {
+        self.file = Self::open_file(&self.path)?;
+        self.selected = SelectedDb::default();
+        self.unsynced = 0;
         Ok(())
     }
+}
 
-    pub fn load_operations(&self) -> io::Result<Vec<Operation>> {
-        let mut operations = Vec::new();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        if self.path.exists() {
-            let file = File::open(&self.path)?;
-            let mut reader = BufReader::with_capacity(32 * 1024 * 1024, file);
-            let mut len_bytes = [0u8; 4];
+    #[test]
+    fn test_concurrent_appends_share_an_fsync() {
+        let path = tempfile::tempdir().unwrap().keep().join("appendonly.aof");
+        let mut writer = Writer::open(&path, AppendFsync::Always).unwrap();
 
-            while let Ok(_) = reader.read_exact(&mut len_bytes) {
-                let len = u32::from_le_bytes(len_bytes) as usize;
-                let mut buf = vec![0u8; len];
-                reader.read_exact(&mut buf)?;
+        // Everything queued before the writer wakes up is one batch.
+        let (requests, queue) = mpsc::channel();
+        let results: Vec<_> = (0..500)
+            .map(|i| {
+                let op = Operation::Delete {
+                    key: format!("key:{}", i),
+                };
+                let (done, result) = oneshot::channel();
+                requests
+                    .send(Request::Append(0, encode_operation(&op).unwrap(), done))
+                    .unwrap();
+                result
+            })
+            .collect();
+        drop(requests);
+        writer.run(&queue);
 
-                match deserialize(&buf) {
-                    Ok(op) => operations.push(op),
-                    Err(e) => return Err(io::Error::new(io::ErrorKind::Other, e)),
-                }
-            }
+        for mut result in results {
+            assert!(result.try_recv().unwrap().is_ok());
         }
+        assert_eq!(writer.fsyncs, 1);
 
-        Ok(operations)
+        let aof = AofManager::new(path, AppendFsync::EverySec).unwrap();
+        assert_eq!(aof.load().unwrap().operations.len(), 500);
     }
 }
@@ -0,0 +1,72 @@
+use bincode::{deserialize, serialize};
+use crc::{Crc, CRC_64_REDIS};
+use std::io;
+
+use super::{redis_object, ValueEntry};
+
+const DUMP_VERSION: u16 = 4;
+const CRC64: Crc<u64> = Crc::<u64>::new(&CRC_64_REDIS);
+
+/// Serializes a single entry into a DUMP payload: the bincode-encoded entry
+/// followed by a 2-byte version and an 8-byte CRC64 of everything before it.
+pub fn encode(entry: &ValueEntry) -> io::Result<Vec<u8>> {
+    let mut payload = serialize(entry).map_err(io::Error::other)?;
+    payload.extend_from_slice(&DUMP_VERSION.to_le_bytes());
+    let checksum = CRC64.checksum(&payload);
+    payload.extend_from_slice(&checksum.to_le_bytes());
+    Ok(payload)
+}
+
+/// Why a DUMP payload couldn't be restored.
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The footer's version is unknown or its checksum doesn't match.
+    Footer,
+    /// The footer is valid but the value can't be read or has no equivalent
+    /// in this server.
+    BadData,
+}
+
+/// Validates the footer of a DUMP payload and decodes the entry. Payloads
+/// from this server carry a bincode-encoded entry; payloads with the footer
+/// version of a real Redis carry a value in its RDB object format, which is
+/// converted to this server's representation.
+pub fn decode(payload: &[u8]) -> Result<ValueEntry, DecodeError> {
+    if payload.len() < 10 {
+        return Err(DecodeError::Footer);
+    }
+
+    let (body, checksum) = payload.split_at(payload.len() - 8);
+    if CRC64.checksum(body).to_le_bytes() != checksum {
+        return Err(DecodeError::Footer);
+    }
+
+    let (entry, version) = body.split_at(body.len() - 2);
+    let version = u16::from_le_bytes([version[0], version[1]]);
+    if version == DUMP_VERSION {
+        deserialize(entry).map_err(|_| DecodeError::BadData)
+    } else if redis_object::VERSIONS.contains(&version) {
+        redis_object::decode(entry)
+            .map(|value| ValueEntry::new(value, None))
+            .ok_or(DecodeError::BadData)
+    } else {
+        Err(DecodeError::Footer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::Value;
+
+    #[test]
+    fn test_decode_redis_payload() {
+        // `DUMP mykey` from Redis 7.0 after `SET mykey 10`.
+        let payload = b"\x00\xc0\n\n\x00n\x9fWE\x0e\xaec\xbb";
+        assert_eq!(decode(payload).unwrap().value, Value::Int(10));
+
+        let mut corrupted = payload.to_vec();
+        corrupted[2] = 11;
+        assert_eq!(decode(&corrupted).unwrap_err(), DecodeError::Footer);
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+
+/// The keys that were written with an expiry, kept in a vector so the active
+/// expiration cycle can sample them at random in O(1).
+///
+/// The index may hold keys that have since been deleted or overwritten
+/// without an expiry; the cycle drops those when it samples them.
+#[derive(Debug, Default)]
+pub struct VolatileKeys {
+    keys: Vec<String>,
+    positions: HashMap<String, usize>,
+}
+
+impl VolatileKeys {
+    pub fn insert(&mut self, key: &str) {
+        if !self.positions.contains_key(key) {
+            self.positions.insert(key.to_string(), self.keys.len());
+            self.keys.push(key.to_string());
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        if let Some(position) = self.positions.remove(key) {
+            self.keys.swap_remove(position);
+            if let Some(moved) = self.keys.get(position) {
+                self.positions.insert(moved.clone(), position);
+            }
+        }
+    }
+
+    pub fn random(&self) -> Option<&str> {
+        if self.keys.is_empty() {
+            return None;
+        }
+        Some(&self.keys[fastrand::usize(..self.keys.len())])
+    }
+
+    /// Up to `count` keys picked at random, possibly more than once, or
+    /// every key when there are no more than `count`.
+    pub fn sample(&self, count: usize) -> Vec<String> {
+        if self.keys.len() <= count {
+            return self.keys.clone();
+        }
+        (0..count)
+            .filter_map(|_| self.random().map(str::to_string))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.keys.clear();
+        self.positions.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_and_remove() {
+        let mut keys = VolatileKeys::default();
+        keys.insert("a");
+        keys.insert("b");
+        keys.insert("c");
+        keys.insert("a");
+        assert_eq!(keys.len(), 3);
+
+        keys.remove("a");
+        keys.remove("missing");
+        assert_eq!(keys.len(), 2);
+        assert!(matches!(keys.random(), Some("b" | "c")));
+
+        let mut sample = keys.sample(5);
+        sample.sort();
+        assert_eq!(sample, vec!["b", "c"]);
+        assert_eq!(keys.sample(1).len(), 1);
+
+        keys.remove("c");
+        keys.remove("b");
+        assert_eq!(keys.random(), None);
+    }
+}
@@ -0,0 +1,166 @@
+//! Readers for the on-disk formats of earlier versions: the string-only one
+//! written before values became typed, and entries written before access
+//! frequencies were recorded. `dump.rdb` and `appendonly.aof` files from
+//! those versions still load after an upgrade; their records are converted
+//! to the current types as they are read.
+
+use std::{io, time::SystemTime};
+
+use bincode::Options;
+use serde::{de::DeserializeOwned, Deserialize};
+
+use super::{Operation, Value, ValueEntry};
+
+/// A snapshot entry as written when every value was a string and no access
+/// time was recorded.
+#[derive(Deserialize)]
+struct LegacyEntry {
+    value: String,
+    expires_at: Option<SystemTime>,
+}
+
+impl From<LegacyEntry> for ValueEntry {
+    fn from(entry: LegacyEntry) -> Self {
+        ValueEntry::new(Value::string(entry.value.into()), entry.expires_at)
+    }
+}
+
+/// A snapshot entry as written before access frequencies were recorded, by
+/// RDB versions 1 and 2 and in headerless files of typed values. It loads
+/// with the counter a new key starts with.
+#[derive(Deserialize)]
+pub struct UncountedEntry {
+    value: Value,
+    expires_at: Option<SystemTime>,
+    last_access: SystemTime,
+}
+
+impl From<UncountedEntry> for ValueEntry {
+    fn from(entry: UncountedEntry) -> Self {
+        ValueEntry {
+            last_access: entry.last_access,
+            ..ValueEntry::new(entry.value, entry.expires_at)
+        }
+    }
+}
+
+/// A journaled operation from the string-only format.
+#[derive(Deserialize)]
+enum LegacyOperation {
+    Set {
+        key: String,
+        value: String,
+        expires_at: Option<SystemTime>,
+    },
+    Delete {
+        key: String,
+    },
+}
+
+impl From<LegacyOperation> for Operation {
+    fn from(op: LegacyOperation) -> Self {
+        match op {
+            LegacyOperation::Set {
+                key,
+                value,
+                expires_at,
+            } => Operation::Set {
+                key,
+                value: Value::string(value.into()),
+                expires_at,
+            },
+            LegacyOperation::Delete { key } => Operation::Delete { key },
+        }
+    }
+}
+
+/// Decodes a headerless RDB body, which holds either typed entries without
+/// access frequencies or string-only ones.
+pub fn decode_entries(bytes: &[u8]) -> io::Result<Vec<(String, ValueEntry)>> {
+    decode::<Vec<(String, UncountedEntry)>>(bytes)
+        .map(convert)
+        .or_else(|e| {
+            decode::<Vec<(String, LegacyEntry)>>(bytes)
+                .map(convert)
+                .map_err(|_| e)
+        })
+}
+
+/// Converts entries in an earlier format to the current one.
+pub fn convert<T: Into<ValueEntry>>(entries: Vec<(String, T)>) -> Vec<(String, ValueEntry)> {
+    entries
+        .into_iter()
+        .map(|(key, entry)| (key, entry.into()))
+        .collect()
+}
+
+/// Decodes a frame from a headerless AOF, which holds either the current
+/// operation format or the string-only one.
+pub fn decode_operation(bytes: &[u8]) -> io::Result<Operation> {
+    decode::<Operation>(bytes).or_else(|e| {
+        decode::<LegacyOperation>(bytes)
+            .map(Operation::from)
+            .map_err(|_| e)
+    })
+}
+
+/// Like `bincode::deserialize`, but rejects input that isn't consumed
+/// entirely, so a record in one format is never mistaken for the other.
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> io::Result<T> {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+        .deserialize(bytes)
+        .map_err(io::Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use bincode::serialize;
+    use serde::Serialize;
+
+    use super::*;
+
+    #[derive(Serialize)]
+    enum StringOperation {
+        Set {
+            key: String,
+            value: String,
+            expires_at: Option<SystemTime>,
+        },
+        Delete {
+            key: String,
+        },
+    }
+
+    #[test]
+    fn test_decode_string_only_operations() {
+        let set = serialize(&StringOperation::Set {
+            key: "k".into(),
+            value: "v".into(),
+            expires_at: None,
+        })
+        .unwrap();
+        let delete = serialize(&StringOperation::Delete { key: "k".into() }).unwrap();
+
+        assert!(matches!(
+            decode_operation(&set).unwrap(),
+            Operation::Set { key, value: Value::String(value), expires_at: None }
+                if key == "k" && value[..] == b"v"[..]
+        ));
+        assert!(matches!(
+            decode_operation(&delete).unwrap(),
+            Operation::Delete { key } if key == "k"
+        ));
+    }
+
+    #[test]
+    fn test_current_format_is_preferred() {
+        let op = Operation::HSet {
+            key: "h".into(),
+            fields: vec![("f".into(), "v".into())],
+        };
+        let decoded = decode_operation(&serialize(&op).unwrap()).unwrap();
+        assert!(matches!(decoded, Operation::HSet { key, .. } if key == "h"));
+    }
+}
@@ -1,25 +1,631 @@
-use std::time::SystemTime;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, VecDeque},
+    fs, io, mem,
+    path::Path,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
 
+use bytes::Bytes;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    lfu,
+    list::{End, ListpackLimit},
+    set::Set,
+    stream::{Stream, StreamId},
+    zset::SortedSet,
+};
 
 pub mod aof;
+pub mod dump;
+pub mod expiry;
+mod legacy;
 pub mod rdb;
+pub mod redis_object;
 pub mod storage;
 
+/// Longest string Redis stores inline with its object header.
+const EMBSTR_MAX_LEN: usize = 44;
+/// Largest hash or sorted set, and longest field, value or member in it,
+/// that Redis keeps in the compact listpack encoding.
+const LISTPACK_MAX_ENTRIES: usize = 128;
+const LISTPACK_MAX_VALUE: usize = 64;
+
+/// Replaces `path` with the fully written `temp_path`. The rename is atomic,
+/// but only durable once the directory entry is on disk too, so the
+/// directory is fsynced after it; the caller fsyncs the file itself first.
+fn replace_file(temp_path: &Path, path: &Path) -> io::Result<()> {
+    fs::rename(temp_path, path)?;
+    // Directories can only be opened for fsync on Unix.
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// When the dataset is written to disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Persistence {
+    /// Every write is journaled to the AOF and the dataset is snapshotted to
+    /// the RDB file at the save points. Only the AOF is loaded at startup,
+    /// unless there is none yet.
+    #[default]
+    Full,
+    /// Nothing is written until SAVE or BGSAVE asks for a snapshot; there is
+    /// no AOF. The RDB file is still loaded at startup, for warm restarts.
+    OnDemand,
+}
+
+impl FromStr for Persistence {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "full" => Ok(Persistence::Full),
+            "on-demand" => Ok(Persistence::OnDemand),
+            _ => Err(()),
+        }
+    }
+}
+
+/// What happens to writes once the dataset outgrows `maxmemory`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MaxMemoryPolicy {
+    /// Nothing is evicted: commands that could grow the dataset are refused
+    /// with an OOM error, while reads and other commands keep working.
+    #[default]
+    NoEviction,
+    /// The least frequently used of a sample of all keys is evicted until
+    /// the dataset fits.
+    AllKeysLfu,
+    /// Like `AllKeysLfu`, but only keys with an expiry are candidates; once
+    /// none is left, writes are refused as under `NoEviction`.
+    VolatileLfu,
+}
+
+impl MaxMemoryPolicy {
+    /// The name the policy is configured with and reported as in INFO.
+    pub fn name(self) -> &'static str {
+        match self {
+            MaxMemoryPolicy::NoEviction => "noeviction",
+            MaxMemoryPolicy::AllKeysLfu => "allkeys-lfu",
+            MaxMemoryPolicy::VolatileLfu => "volatile-lfu",
+        }
+    }
+}
+
+impl FromStr for MaxMemoryPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "noeviction" => Ok(MaxMemoryPolicy::NoEviction),
+            "allkeys-lfu" => Ok(MaxMemoryPolicy::AllKeysLfu),
+            "volatile-lfu" => Ok(MaxMemoryPolicy::VolatileLfu),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A command was run against a key holding another type than the one it
+/// operates on. Displays as the WRONGTYPE reply.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
+pub struct TypeError;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Value {
+    /// A string, which may hold any bytes. Serialized like a `String`, so
+    /// files written when strings had to be UTF-8 still load.
+    String(Bytes),
+    Hash(HashMap<String, String>),
+    Stream(Stream),
+    /// A string holding the canonical decimal form of a 64-bit integer,
+    /// stored as the integer so counters skip parsing and formatting.
+    Int(i64),
+    List(VecDeque<String>),
+    Set(Set),
+    SortedSet(SortedSet),
+}
+
+impl Value {
+    /// A string value, stored as [`Value::Int`] when it is exactly the
+    /// decimal form of an `i64`, as Redis' `int` encoding does. Strings like
+    /// `007` or `+1` would not read back the same, so they stay strings.
+    pub fn string(s: Bytes) -> Self {
+        match parse_int(&s) {
+            Some(i) if i.to_string().as_bytes() == s => Value::Int(i),
+            _ => Value::String(s),
+        }
+    }
+
+    /// The value as a string, for either encoding of the string type.
+    ///
+    /// This and the other typed accessors are the one place type rules are
+    /// enforced: typed commands read values through them rather than
+    /// matching on variants, so none of them can forget the WRONGTYPE check.
+    pub fn as_string(&self) -> Result<Cow<'_, [u8]>, TypeError> {
+        match self {
+            Value::String(s) => Ok(Cow::Borrowed(s)),
+            Value::Int(i) => Ok(Cow::Owned(i.to_string().into_bytes())),
+            Value::Hash(_)
+            | Value::Stream(_)
+            | Value::List(_)
+            | Value::Set(_)
+            | Value::SortedSet(_) => Err(TypeError),
+        }
+    }
+
+    pub fn as_hash(&self) -> Result<&HashMap<String, String>, TypeError> {
+        match self {
+            Value::Hash(hash) => Ok(hash),
+            Value::String(_)
+            | Value::Int(_)
+            | Value::Stream(_)
+            | Value::List(_)
+            | Value::Set(_)
+            | Value::SortedSet(_) => Err(TypeError),
+        }
+    }
+
+    pub fn as_stream(&self) -> Result<&Stream, TypeError> {
+        match self {
+            Value::Stream(stream) => Ok(stream),
+            Value::String(_)
+            | Value::Int(_)
+            | Value::Hash(_)
+            | Value::List(_)
+            | Value::Set(_)
+            | Value::SortedSet(_) => Err(TypeError),
+        }
+    }
+
+    pub fn as_list(&self) -> Result<&VecDeque<String>, TypeError> {
+        match self {
+            Value::List(list) => Ok(list),
+            Value::String(_)
+            | Value::Int(_)
+            | Value::Hash(_)
+            | Value::Stream(_)
+            | Value::Set(_)
+            | Value::SortedSet(_) => Err(TypeError),
+        }
+    }
+
+    pub fn as_set(&self) -> Result<&Set, TypeError> {
+        match self {
+            Value::Set(set) => Ok(set),
+            Value::String(_)
+            | Value::Int(_)
+            | Value::Hash(_)
+            | Value::Stream(_)
+            | Value::List(_)
+            | Value::SortedSet(_) => Err(TypeError),
+        }
+    }
+
+    pub fn as_sorted_set(&self) -> Result<&SortedSet, TypeError> {
+        match self {
+            Value::SortedSet(set) => Ok(set),
+            Value::String(_)
+            | Value::Int(_)
+            | Value::Hash(_)
+            | Value::Stream(_)
+            | Value::List(_)
+            | Value::Set(_) => Err(TypeError),
+        }
+    }
+
+    /// The type name reported by TYPE and used in WRONGTYPE checks.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) | Value::Int(_) => "string",
+            Value::Hash(_) => "hash",
+            Value::Stream(_) => "stream",
+            Value::List(_) => "list",
+            Value::Set(_) => "set",
+            Value::SortedSet(_) => "zset",
+        }
+    }
+
+    fn heap_size(&self) -> usize {
+        match self {
+            Value::String(s) => s.len(),
+            Value::Hash(hash) => {
+                hash.capacity() * mem::size_of::<(String, String)>()
+                    + hash
+                        .iter()
+                        .map(|(field, value)| field.capacity() + value.capacity())
+                        .sum::<usize>()
+            }
+            Value::Stream(stream) => stream.heap_size(),
+            Value::Int(_) => 0,
+            Value::List(list) => {
+                list.capacity() * mem::size_of::<String>()
+                    + list.iter().map(String::capacity).sum::<usize>()
+            }
+            Value::Set(set) => set.heap_size(),
+            Value::SortedSet(set) => set.heap_size(),
+        }
+    }
+
+    /// Like [`Value::heap_size`], but measuring at most `samples` elements
+    /// of an aggregate and scaling their average to the whole, as MEMORY
+    /// USAGE SAMPLES does. 0 measures every element.
+    fn sampled_heap_size(&self, samples: usize) -> usize {
+        match self {
+            Value::Hash(hash) if samples > 0 && hash.len() > samples => {
+                let sampled: usize = hash
+                    .iter()
+                    .take(samples)
+                    .map(|(field, value)| field.capacity() + value.capacity())
+                    .sum();
+                hash.capacity() * mem::size_of::<(String, String)>()
+                    + sampled * hash.len() / samples
+            }
+            Value::Stream(stream) => stream.sampled_heap_size(samples),
+            Value::List(list) if samples > 0 && list.len() > samples => {
+                let sampled: usize = list.iter().take(samples).map(String::capacity).sum();
+                list.capacity() * mem::size_of::<String>() + sampled * list.len() / samples
+            }
+            Value::Set(set) => set.sampled_heap_size(samples),
+            Value::SortedSet(set) => set.sampled_heap_size(samples),
+            _ => self.heap_size(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ValueEntry {
-    pub value: String,
+    pub value: Value,
+    /// Absolute expiry time. Serde encodes `SystemTime` as the seconds and
+    /// nanoseconds since the Unix epoch, so RDB snapshots and DUMP payloads
+    /// carry a wall-clock deadline that stays valid across restarts and
+    /// machines.
     pub expires_at: Option<SystemTime>,
+    /// When the key was last read or written, for OBJECT IDLETIME.
+    pub last_access: SystemTime,
+    /// Logarithmic count of recent accesses, for the LFU eviction policies.
+    /// It decays while the key sits idle; see [`ValueEntry::frequency`].
+    pub lfu_counter: u8,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl ValueEntry {
+    pub fn new(value: Value, expires_at: Option<SystemTime>) -> Self {
+        Self {
+            value,
+            expires_at,
+            last_access: SystemTime::now(),
+            lfu_counter: lfu::INITIAL_COUNTER,
+        }
+    }
+
+    /// The LFU counter as of `now`, decayed for the time since the last
+    /// access by one every `decay_time` minutes.
+    pub fn frequency(&self, now: SystemTime, decay_time: u64) -> u8 {
+        lfu::decay(self.lfu_counter, self.idle_time(now), decay_time)
+    }
+
+    /// The encoding Redis would report for this value in OBJECT ENCODING:
+    /// `int` for 64-bit integers, `embstr` for short strings, `raw` otherwise,
+    /// `listpack` or `hashtable` for hashes depending on their size,
+    /// `listpack` or `quicklist` for lists depending on whether they fit in
+    /// `list_limit`, `intset` or `hashtable` for sets, `listpack` or
+    /// `skiplist` for sorted sets depending on their size, and `stream` for
+    /// streams.
+    pub fn encoding(&self, list_limit: ListpackLimit) -> &'static str {
+        match &self.value {
+            Value::Int(_) => "int",
+            // Integer strings loaded from files written before `Int` existed.
+            Value::String(s) if s.len() <= 20 && parse_int(s).is_some() => "int",
+            Value::String(s) if s.len() <= EMBSTR_MAX_LEN => "embstr",
+            Value::String(_) => "raw",
+            Value::Hash(hash)
+                if hash.len() <= LISTPACK_MAX_ENTRIES
+                    && hash.iter().all(|(field, value)| {
+                        field.len() <= LISTPACK_MAX_VALUE && value.len() <= LISTPACK_MAX_VALUE
+                    }) =>
+            {
+                "listpack"
+            }
+            Value::Hash(_) => "hashtable",
+            Value::Stream(_) => "stream",
+            Value::List(list) if list_limit.fits(list) => "listpack",
+            Value::List(_) => "quicklist",
+            Value::Set(set) => set.encoding(),
+            Value::SortedSet(set)
+                if set.len() <= LISTPACK_MAX_ENTRIES
+                    && set
+                        .iter()
+                        .all(|(member, _)| member.len() <= LISTPACK_MAX_VALUE) =>
+            {
+                "listpack"
+            }
+            Value::SortedSet(_) => "skiplist",
+        }
+    }
+
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at.is_some_and(|expires_at| now > expires_at)
+    }
+
+    /// The string held by the entry, or `None` if it expired by `now`: an
+    /// expired entry is absent whatever its type, so it never reports
+    /// WRONGTYPE.
+    pub fn as_string(&self, now: SystemTime) -> Result<Option<Cow<'_, [u8]>>, TypeError> {
+        self.live(now).map(Value::as_string).transpose()
+    }
+
+    /// The hash held by the entry, or `None` if it expired by `now`.
+    pub fn as_hash(&self, now: SystemTime) -> Result<Option<&HashMap<String, String>>, TypeError> {
+        self.live(now).map(Value::as_hash).transpose()
+    }
+
+    /// The stream held by the entry, or `None` if it expired by `now`.
+    pub fn as_stream(&self, now: SystemTime) -> Result<Option<&Stream>, TypeError> {
+        self.live(now).map(Value::as_stream).transpose()
+    }
+
+    /// The list held by the entry, or `None` if it expired by `now`.
+    pub fn as_list(&self, now: SystemTime) -> Result<Option<&VecDeque<String>>, TypeError> {
+        self.live(now).map(Value::as_list).transpose()
+    }
+
+    /// The set held by the entry, or `None` if it expired by `now`.
+    pub fn as_set(&self, now: SystemTime) -> Result<Option<&Set>, TypeError> {
+        self.live(now).map(Value::as_set).transpose()
+    }
+
+    /// The sorted set held by the entry, or `None` if it expired by `now`.
+    pub fn as_sorted_set(&self, now: SystemTime) -> Result<Option<&SortedSet>, TypeError> {
+        self.live(now).map(Value::as_sorted_set).transpose()
+    }
+
+    fn live(&self, now: SystemTime) -> Option<&Value> {
+        (!self.is_expired(now)).then_some(&self.value)
+    }
+
+    /// Time elapsed between the key's last access and `now`.
+    pub fn idle_time(&self, now: SystemTime) -> Duration {
+        now.duration_since(self.last_access).unwrap_or_default()
+    }
+
+    /// Approximate number of bytes this entry occupies in memory: the struct
+    /// itself plus the heap allocations backing the value.
+    pub fn size_of(&self) -> usize {
+        mem::size_of::<Self>() + self.value.heap_size()
+    }
+
+    /// Like [`ValueEntry::size_of`], but estimating an aggregate value from
+    /// at most `samples` of its elements; 0 measures all of them.
+    pub fn sampled_size_of(&self, samples: usize) -> usize {
+        mem::size_of::<Self>() + self.value.sampled_heap_size(samples)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Operation {
     Set {
         key: String,
-        value: String,
+        value: Value,
         expires_at: Option<SystemTime>,
     },
     Delete {
         key: String,
     },
+    /// Sets fields of a hash, creating it if the key doesn't hold one.
+    HSet {
+        key: String,
+        fields: Vec<(String, String)>,
+    },
+    /// Appends an entry to a stream, creating it if the key doesn't hold
+    /// one. The ID is resolved before journaling so replays and replicas
+    /// assign the same one.
+    XAdd {
+        key: String,
+        id: StreamId,
+        fields: Vec<(String, String)>,
+    },
+    /// Pushes elements one at a time to an end of a list, creating it if
+    /// the key doesn't hold one.
+    Push {
+        key: String,
+        end: End,
+        elements: Vec<String>,
+    },
+    /// Pops up to `count` elements from an end of a list, deleting the key
+    /// once the list is empty.
+    Pop {
+        key: String,
+        end: End,
+        count: usize,
+    },
+    /// Pops an element from an end of a list and pushes it to an end of
+    /// another, or of the same one, in one step.
+    Move {
+        source: String,
+        destination: String,
+        from: End,
+        to: End,
+    },
+    /// Adds members to a set, creating it if the key doesn't hold one.
+    SAdd {
+        key: String,
+        members: Vec<String>,
+    },
+    /// Removes members from a set, deleting the key once it is empty.
+    SRem {
+        key: String,
+        members: Vec<String>,
+    },
+    /// Sets the scores of members of a sorted set, creating it if the key
+    /// doesn't hold one.
+    ZAdd {
+        key: String,
+        members: Vec<(String, f64)>,
+    },
+    /// Removes members from a sorted set, deleting the key once it is
+    /// empty. The range removals journal the members they found in range.
+    ZRem {
+        key: String,
+        members: Vec<String>,
+    },
+    /// Switches the database the operations after it in a log apply to.
+    /// Logs start out in database 0, so those written before there were
+    /// several databases replay into it. Readers of a log act on it
+    /// themselves; it never reaches a dataset.
+    Select {
+        db: usize,
+    },
+}
+
+impl Operation {
+    /// The keys the operation writes.
+    pub fn keys(&self) -> Vec<&str> {
+        match self {
+            Operation::Set { key, .. }
+            | Operation::Delete { key }
+            | Operation::HSet { key, .. }
+            | Operation::XAdd { key, .. }
+            | Operation::Push { key, .. }
+            | Operation::Pop { key, .. }
+            | Operation::SAdd { key, .. }
+            | Operation::SRem { key, .. }
+            | Operation::ZAdd { key, .. }
+            | Operation::ZRem { key, .. } => vec![key],
+            Operation::Move {
+                source,
+                destination,
+                ..
+            } => vec![source, destination],
+            Operation::Select { .. } => Vec::new(),
+        }
+    }
+}
+
+/// A copy of the dataset: the entries of each database holding any, with
+/// its index.
+pub type Snapshot = Vec<(usize, Vec<(String, ValueEntry)>)>;
+
+/// A string value read as an integer, as INCR reads it. Bytes that aren't
+/// UTF-8 are no number either.
+pub fn parse_int(bytes: &[u8]) -> Option<i64> {
+    std::str::from_utf8(bytes).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> [ValueEntry; 7] {
+        [
+            ValueEntry::new(Value::String("v".into()), None),
+            ValueEntry::new(Value::Int(7), None),
+            ValueEntry::new(Value::Hash(HashMap::new()), None),
+            ValueEntry::new(Value::Stream(Stream::default()), None),
+            ValueEntry::new(Value::List(VecDeque::new()), None),
+            ValueEntry::new(Value::Set(Set::default()), None),
+            ValueEntry::new(Value::SortedSet(SortedSet::default()), None),
+        ]
+    }
+
+    #[test]
+    fn test_accessors_reject_other_types() {
+        let now = SystemTime::now();
+        let [string, int, hash, stream, list, set, zset] = entries();
+
+        assert_eq!(string.as_string(now), Ok(Some(b"v"[..].into())));
+        assert_eq!(int.as_string(now), Ok(Some(b"7"[..].into())));
+        assert_eq!(hash.as_string(now), Err(TypeError));
+        assert_eq!(stream.as_string(now), Err(TypeError));
+        assert_eq!(list.as_string(now), Err(TypeError));
+        assert_eq!(set.as_string(now), Err(TypeError));
+        assert_eq!(zset.as_string(now), Err(TypeError));
+
+        assert!(matches!(hash.as_hash(now), Ok(Some(_))));
+        for entry in [&string, &int, &stream, &list, &set, &zset] {
+            assert_eq!(entry.as_hash(now), Err(TypeError));
+        }
+
+        assert!(matches!(stream.as_stream(now), Ok(Some(_))));
+        for entry in [&string, &int, &hash, &list, &set, &zset] {
+            assert_eq!(
+                entry.as_stream(now).map(|s| s.map(Stream::len)),
+                Err(TypeError)
+            );
+        }
+
+        assert!(matches!(list.as_list(now), Ok(Some(_))));
+        for entry in [&string, &int, &hash, &stream, &set, &zset] {
+            assert_eq!(entry.as_list(now), Err(TypeError));
+        }
+
+        assert!(matches!(set.as_set(now), Ok(Some(_))));
+        for entry in [&string, &int, &hash, &stream, &list, &zset] {
+            assert_eq!(entry.as_set(now), Err(TypeError));
+        }
+
+        assert!(matches!(zset.as_sorted_set(now), Ok(Some(_))));
+        for entry in [&string, &int, &hash, &stream, &list, &set] {
+            assert_eq!(
+                entry.as_sorted_set(now).map(|s| s.map(SortedSet::len)),
+                Err(TypeError)
+            );
+        }
+
+        assert_eq!(
+            TypeError.to_string(),
+            "WRONGTYPE Operation against a key holding the wrong kind of value"
+        );
+    }
+
+    #[test]
+    fn test_sampled_size_scales_the_measured_elements() {
+        let hash: HashMap<_, _> = (0..100)
+            .map(|i| (format!("field:{:03}", i), "x".repeat(10)))
+            .collect();
+        let hash = Value::Hash(hash);
+        // Every field has the same size, so any sample is exact.
+        assert_eq!(hash.sampled_heap_size(5), hash.heap_size());
+        assert_eq!(hash.sampled_heap_size(0), hash.heap_size());
+
+        // Streams are sampled from their oldest entries, which miss the
+        // large one added last.
+        let mut stream = Stream::default();
+        for seq in 1..=10 {
+            let value = if seq == 10 {
+                "x".repeat(10_000)
+            } else {
+                "x".into()
+            };
+            stream.insert(StreamId { ms: 0, seq }, vec![("f".into(), value)]);
+        }
+        let stream = Value::Stream(stream);
+        assert!(stream.sampled_heap_size(5) < stream.heap_size());
+        assert_eq!(stream.sampled_heap_size(0), stream.heap_size());
+        assert_eq!(stream.sampled_heap_size(10), stream.heap_size());
+    }
+
+    #[test]
+    fn test_accessors_treat_expired_entries_as_absent() {
+        let now = SystemTime::now();
+        for mut entry in entries() {
+            entry.expires_at = Some(now - Duration::from_secs(1));
+            assert_eq!(entry.as_string(now), Ok(None));
+            assert_eq!(entry.as_hash(now), Ok(None));
+            assert!(matches!(entry.as_stream(now), Ok(None)));
+            assert_eq!(entry.as_list(now), Ok(None));
+            assert_eq!(entry.as_set(now), Ok(None));
+            assert!(matches!(entry.as_sorted_set(now), Ok(None)));
+        }
+    }
 }
@@ -1,39 +1,489 @@
-use bincode::{deserialize_from, serialize_into};
+use bincode::{deserialize, deserialize_from, serialize_into};
+use serde::{de::DeserializeOwned, Serialize};
 
-use super::ValueEntry;
+use super::{
+    legacy::{self, UncountedEntry},
+    Snapshot, ValueEntry,
+};
 use std::{
-    fs::File,
-    io::{self, BufReader, BufWriter},
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
     path::PathBuf,
+    str::FromStr,
 };
 
+/// Identifies an RDB file written with a header. Files without it are read as
+/// the headerless, uncompressed format written before compression existed,
+/// including the string-only entries from before values were typed.
+const MAGIC: &[u8] = b"RLRDB";
+const RDB_VERSION: u8 = 4;
+/// Version 3 held the entries of a single database, written like a section
+/// of version 4. It is still loaded, into database 0, as are the versions
+/// before it.
+const RDB_VERSION_SINGLE_DB: u8 = 3;
+/// Version 2 was written like version 3, but without the LFU counter of
+/// each entry. It is still loaded.
+const RDB_VERSION_UNCOUNTED: u8 = 2;
+/// Version 1 held every entry in one bincode-encoded `Vec`, compressed as a
+/// single block. It is still loaded.
+const RDB_VERSION_BLOCK: u8 = 1;
+const ZSTD_LEVEL: i32 = 3;
+
+/// The codec RDB snapshots are compressed with, recorded in the file header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl Compression {
+    fn id(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Lz4 => 1,
+            Compression::Zstd => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Lz4),
+            2 => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    fn encoder<W: Write>(self, writer: W) -> io::Result<Encoder<W>> {
+        Ok(match self {
+            Compression::None => Encoder::None(writer),
+            Compression::Lz4 => Encoder::Lz4(lz4_flex::frame::FrameEncoder::new(writer)),
+            Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(writer, ZSTD_LEVEL)?),
+        })
+    }
+
+    fn decoder<'a>(self, payload: &'a [u8]) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Compression::None => Box::new(payload),
+            Compression::Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(payload)),
+            Compression::Zstd => Box::new(zstd::Decoder::new(payload)?),
+        })
+    }
+
+    /// Decompresses a version 1 body, compressed as a single block.
+    fn decompress(self, payload: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(payload.to_vec()),
+            Compression::Lz4 => lz4_flex::decompress_size_prepended(payload)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Compression::Zstd => zstd::decode_all(payload),
+        }
+    }
+}
+
+/// A streaming compressor for one of the codecs.
+enum Encoder<W: Write> {
+    None(W),
+    Lz4(lz4_flex::frame::FrameEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Writes out the end of the compressed stream and returns the writer.
+    fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::None(writer) => Ok(writer),
+            Encoder::Lz4(encoder) => encoder.finish().map_err(io::Error::other),
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::None(writer) => writer.write(buf),
+            Encoder::Lz4(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::None(writer) => writer.flush(),
+            Encoder::Lz4(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" | "no" => Ok(Compression::None),
+            "lz4" => Ok(Compression::Lz4),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(()),
+        }
+    }
+}
+
 pub struct RdbManager {
     path: PathBuf,
+    compression: Compression,
 }
 
 impl RdbManager {
-    pub fn new(path: PathBuf) -> Self {
-        Self { path }
+    pub fn new(path: PathBuf, compression: Compression) -> Self {
+        Self { path, compression }
     }
 
-    pub async fn save(&self, entries: &[(String, ValueEntry)]) -> io::Result<()> {
+    pub async fn save<T: Serialize, E: IntoIterator<Item = T>>(
+        &self,
+        databases: impl IntoIterator<Item = (usize, E)>,
+    ) -> io::Result<()> {
         let temp_path = self.path.with_extension("temp");
-        let file = File::create(&temp_path)?;
-        let writer = BufWriter::new(file);
+        let file = BufWriter::new(File::create(&temp_path)?);
+        let mut file = write(file, databases, self.compression)?;
+        file.flush()?;
+        file.get_ref().sync_all()?;
+
+        super::replace_file(&temp_path, &self.path)
+    }
+
+    /// Reads a snapshot written with any codec, regardless of the one
+    /// currently configured.
+    pub fn load(&self) -> io::Result<Snapshot> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        decode(&fs::read(&self.path)?)
+    }
+}
+
+/// Writes a snapshot to `writer`: the magic, a version byte and the codec
+/// id, then a section per database, compressed as one stream with
+/// `compression`. A section is the database's index as a bincode-encoded
+/// `Some(db)`, then each entry as a `Some((key, entry))` followed by a
+/// `None`; a final `None` closes the snapshot. Entries are encoded as
+/// `databases` yields them, so the snapshot is never held in memory as a
+/// whole.
+pub fn write<T: Serialize, W: Write, E: IntoIterator<Item = T>>(
+    mut writer: W,
+    databases: impl IntoIterator<Item = (usize, E)>,
+    compression: Compression,
+) -> io::Result<W> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[RDB_VERSION, compression.id()])?;
+
+    let mut encoder = compression.encoder(writer)?;
+    for (db, entries) in databases {
+        serialize_into(&mut encoder, &Some(db)).map_err(io::Error::other)?;
+        for entry in entries {
+            serialize_into(&mut encoder, &Some(entry)).map_err(io::Error::other)?;
+        }
+        serialize_into(&mut encoder, &None::<T>).map_err(io::Error::other)?;
+    }
+    serialize_into(&mut encoder, &None::<usize>).map_err(io::Error::other)?;
+    encoder.finish()
+}
+
+/// Decodes a whole snapshot in any of the formats RDB files were written in.
+pub fn decode(file: &[u8]) -> io::Result<Snapshot> {
+    let body = match file.strip_prefix(MAGIC) {
+        Some([version, codec, payload @ ..]) => {
+            let compression = Compression::from_id(*codec)
+                .ok_or_else(|| invalid_data(format!("unknown RDB codec {}", codec)))?;
+            let mut reader = compression.decoder(payload)?;
+            match *version {
+                RDB_VERSION => return read_databases(reader),
+                RDB_VERSION_SINGLE_DB => {
+                    return read_entries::<ValueEntry>(&mut reader).map(database_0)
+                }
+                RDB_VERSION_UNCOUNTED => {
+                    return read_entries::<UncountedEntry>(&mut reader).map(database_0)
+                }
+                RDB_VERSION_BLOCK => compression.decompress(payload)?,
+                _ => return Err(invalid_data(format!("unsupported RDB version {}", version))),
+            }
+        }
+        Some(_) => return Err(invalid_data("truncated RDB header".into())),
+        None => return legacy::decode_entries(file).map(database_0),
+    };
+    deserialize::<Vec<(String, UncountedEntry)>>(&body)
+        .map(legacy::convert)
+        .map(database_0)
+        .map_err(io::Error::other)
+}
 
-        serialize_into(writer, &entries).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+/// The snapshot of a format without databases, whose entries all belong to
+/// database 0.
+fn database_0(entries: Vec<(String, ValueEntry)>) -> Snapshot {
+    if entries.is_empty() {
+        Vec::new()
+    } else {
+        vec![(0, entries)]
+    }
+}
 
-        std::fs::rename(temp_path, &self.path)?;
-        Ok(())
+/// Reads the sections of a version 4 snapshot body up to the closing `None`.
+fn read_databases(mut reader: impl Read) -> io::Result<Snapshot> {
+    let mut snapshot = Vec::new();
+    while let Some(db) =
+        deserialize_from::<_, Option<usize>>(&mut reader).map_err(io::Error::other)?
+    {
+        snapshot.push((db, read_entries::<ValueEntry>(&mut reader)?));
     }
+    Ok(snapshot)
+}
+
+/// Reads the entries of a section, or of a version 2 or 3 snapshot body, up
+/// to the closing `None`, each encoded as `T`.
+fn read_entries<T: DeserializeOwned + Into<ValueEntry>>(
+    mut reader: impl Read,
+) -> io::Result<Vec<(String, ValueEntry)>> {
+    let mut entries = Vec::new();
+    while let Some((key, entry)) =
+        deserialize_from::<_, Option<(String, T)>>(&mut reader).map_err(io::Error::other)?
+    {
+        entries.push((key, entry.into()));
+    }
+    Ok(entries)
+}
 
-    pub fn load(&self) -> io::Result<Vec<(String, ValueEntry)>> {
-        if self.path.exists() {
-            let file = File::open(&self.path)?;
-            let reader = BufReader::new(file);
-            deserialize_from(reader).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
-        } else {
-            Ok(Vec::new())
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use bincode::serialize;
+
+    use super::*;
+    use crate::{lfu, persistence::Value};
+
+    fn entries() -> Vec<(String, ValueEntry)> {
+        (0..1000)
+            .map(|i| {
+                let value = Value::String("the same value over and over ".repeat(4).into());
+                let entry = ValueEntry {
+                    lfu_counter: (i % 256) as u8,
+                    ..ValueEntry::new(value, None)
+                };
+                (format!("key:{}", i), entry)
+            })
+            .collect()
+    }
+
+    /// An entry as written before LFU counters were recorded.
+    #[derive(Serialize)]
+    struct UncountedEntry {
+        value: Value,
+        expires_at: Option<SystemTime>,
+        last_access: SystemTime,
+    }
+
+    /// The entries of a snapshot holding nothing but database 0, as every
+    /// format before version 4 loads.
+    fn only_database_0(mut snapshot: Snapshot) -> Vec<(String, ValueEntry)> {
+        assert_eq!(snapshot.len(), 1);
+        let (db, entries) = snapshot.remove(0);
+        assert_eq!(db, 0);
+        entries
+    }
+
+    fn uncounted_entries() -> Vec<(String, UncountedEntry)> {
+        entries()
+            .into_iter()
+            .map(|(key, entry)| {
+                let entry = UncountedEntry {
+                    value: entry.value,
+                    expires_at: entry.expires_at,
+                    last_access: entry.last_access,
+                };
+                (key, entry)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_round_trip_with_each_codec() {
+        let dir = tempfile::tempdir().unwrap();
+        let entries = entries();
+
+        let mut sizes = Vec::new();
+        for compression in [Compression::None, Compression::Lz4, Compression::Zstd] {
+            let path = dir.path().join(format!("{:?}.rdb", compression));
+            let rdb = RdbManager::new(path.clone(), compression);
+            rdb.save([(0, &entries)]).await.unwrap();
+
+            // Loading doesn't depend on the configured codec.
+            let loaded = RdbManager::new(path.clone(), Compression::None)
+                .load()
+                .unwrap();
+            let loaded = only_database_0(loaded);
+            assert_eq!(loaded.len(), entries.len());
+            for ((key, entry), (loaded_key, loaded_entry)) in entries.iter().zip(&loaded) {
+                assert_eq!(key, loaded_key);
+                assert_eq!(entry.value, loaded_entry.value);
+                assert_eq!(entry.lfu_counter, loaded_entry.lfu_counter);
+            }
+            sizes.push(fs::metadata(&path).unwrap().len());
         }
+
+        assert!(sizes[1] < sizes[0]);
+        assert!(sizes[2] < sizes[0]);
+    }
+
+    #[tokio::test]
+    async fn test_expiry_is_stored_as_wall_clock_time() {
+        // Serde stores the offset from the Unix epoch, not from the local
+        // clock or process start.
+        let expires_at = UNIX_EPOCH + Duration::from_millis(1_500);
+        let mut expected = vec![1];
+        expected.extend(1u64.to_le_bytes());
+        expected.extend(500_000_000u32.to_le_bytes());
+        assert_eq!(serialize(&Some(expires_at)).unwrap(), expected);
+
+        let dir = tempfile::tempdir().unwrap();
+        let rdb = RdbManager::new(dir.path().join("dump.rdb"), Compression::None);
+        let ttl = Duration::from_secs(100);
+        let entry = ValueEntry::new(Value::String("value".into()), Some(SystemTime::now() + ttl));
+        rdb.save([(0, [("key", entry)])]).await.unwrap();
+
+        let (_, loaded) = only_database_0(rdb.load().unwrap()).remove(0);
+        let remaining = loaded
+            .expires_at
+            .unwrap()
+            .duration_since(SystemTime::now())
+            .unwrap();
+        assert!(remaining <= ttl && remaining > ttl - Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_databases_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let rdb = RdbManager::new(dir.path().join("dump.rdb"), Compression::Lz4);
+        let entry = |value: &str| ValueEntry::new(Value::String(value.to_string().into()), None);
+        rdb.save([
+            (0, vec![("a", entry("zero"))]),
+            (3, vec![("a", entry("three")), ("b", entry("three"))]),
+        ])
+        .await
+        .unwrap();
+
+        let loaded = rdb.load().unwrap();
+        let keys: Vec<(usize, Vec<&str>)> = loaded
+            .iter()
+            .map(|(db, entries)| (*db, entries.iter().map(|(key, _)| key.as_str()).collect()))
+            .collect();
+        assert_eq!(keys, vec![(0, vec!["a"]), (3, vec!["a", "b"])]);
+        assert_eq!(loaded[1].1[0].1.value, Value::String("three".into()));
+    }
+
+    #[test]
+    fn test_load_version_3_file_into_database_0() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dump.rdb");
+        let mut file = MAGIC.to_vec();
+        file.extend([RDB_VERSION_SINGLE_DB, Compression::None.id()]);
+        for entry in entries() {
+            file.extend(serialize(&Some(entry)).unwrap());
+        }
+        file.extend(serialize(&None::<(String, ValueEntry)>).unwrap());
+        fs::write(&path, file).unwrap();
+
+        let loaded = RdbManager::new(path, Compression::None).load().unwrap();
+        assert_eq!(only_database_0(loaded).len(), 1000);
+    }
+
+    #[test]
+    fn test_load_version_2_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dump.rdb");
+        let mut file = MAGIC.to_vec();
+        file.extend([RDB_VERSION_UNCOUNTED, Compression::None.id()]);
+        for entry in uncounted_entries() {
+            file.extend(serialize(&Some(entry)).unwrap());
+        }
+        file.extend(serialize(&None::<(String, UncountedEntry)>).unwrap());
+        fs::write(&path, file).unwrap();
+
+        let loaded = RdbManager::new(path, Compression::None).load().unwrap();
+        let loaded = only_database_0(loaded);
+        assert_eq!(loaded.len(), 1000);
+        // Keys load with the counter of a new key.
+        assert!(loaded
+            .iter()
+            .all(|(_, entry)| entry.lfu_counter == lfu::INITIAL_COUNTER));
+    }
+
+    #[test]
+    fn test_load_version_1_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dump.rdb");
+        let mut file = MAGIC.to_vec();
+        file.extend([RDB_VERSION_BLOCK, Compression::Zstd.id()]);
+        let body = serialize(&uncounted_entries()).unwrap();
+        file.extend(zstd::encode_all(body.as_slice(), ZSTD_LEVEL).unwrap());
+        fs::write(&path, file).unwrap();
+
+        let loaded = RdbManager::new(path, Compression::None).load().unwrap();
+        assert_eq!(only_database_0(loaded).len(), 1000);
+    }
+
+    #[test]
+    fn test_load_headerless_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dump.rdb");
+        fs::write(&path, serialize(&uncounted_entries()).unwrap()).unwrap();
+
+        let loaded = RdbManager::new(path, Compression::Zstd).load().unwrap();
+        assert_eq!(only_database_0(loaded).len(), 1000);
+    }
+
+    #[test]
+    fn test_load_string_only_file() {
+        #[derive(Serialize)]
+        struct StringEntry {
+            value: String,
+            expires_at: Option<SystemTime>,
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dump.rdb");
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+        let legacy = vec![
+            (
+                "name",
+                StringEntry {
+                    value: "redis".into(),
+                    expires_at: None,
+                },
+            ),
+            (
+                "count",
+                StringEntry {
+                    value: "42".into(),
+                    expires_at: Some(expires_at),
+                },
+            ),
+        ];
+        fs::write(&path, serialize(&legacy).unwrap()).unwrap();
+
+        let loaded = RdbManager::new(path, Compression::None).load().unwrap();
+        let loaded = only_database_0(loaded);
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].0, "name");
+        assert!(matches!(&loaded[0].1.value, Value::String(s) if s[..] == b"redis"[..]));
+        assert_eq!(loaded[1].0, "count");
+        assert!(matches!(loaded[1].1.value, Value::Int(42)));
+        assert_eq!(loaded[1].1.expires_at, Some(expires_at));
     }
 }
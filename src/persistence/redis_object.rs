@@ -0,0 +1,309 @@
+use std::{collections::HashMap, ops::RangeInclusive};
+
+use super::Value;
+
+/// RDB versions in the footer of DUMP payloads written by Redis itself, from
+/// 2.6, which introduced DUMP, to 7.4.
+pub const VERSIONS: RangeInclusive<u16> = 6..=12;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_HASH: u8 = 4;
+const TYPE_HASH_ZIPLIST: u8 = 13;
+const TYPE_HASH_LISTPACK: u8 = 16;
+
+const ENCODING_INT8: u64 = 0;
+const ENCODING_INT16: u64 = 1;
+const ENCODING_INT32: u64 = 2;
+const ENCODING_LZF: u64 = 3;
+
+/// Decodes a value in the RDB object format Redis uses for DUMP payloads:
+/// a type byte followed by the type's serialization. Strings and hashes are
+/// supported in every encoding Redis writes them in. Other types aren't
+/// converted and yield `None`, as does malformed input. Hash fields and
+/// values must be valid UTF-8.
+pub fn decode(object: &[u8]) -> Option<Value> {
+    let mut reader = Reader { data: object };
+    let value = match reader.byte()? {
+        TYPE_STRING => Value::string(reader.raw_string()?.into()),
+        TYPE_HASH => {
+            let len = reader.length()?;
+            let mut hash = HashMap::new();
+            for _ in 0..len {
+                hash.insert(reader.string()?, reader.string()?);
+            }
+            Value::Hash(hash)
+        }
+        TYPE_HASH_ZIPLIST => Value::Hash(pairs(ziplist(&reader.raw_string()?)?)?),
+        TYPE_HASH_LISTPACK => Value::Hash(pairs(listpack(&reader.raw_string()?)?)?),
+        _ => return None,
+    };
+    reader.data.is_empty().then_some(value)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.data.len() < n {
+            return None;
+        }
+        let (head, rest) = self.data.split_at(n);
+        self.data = rest;
+        Some(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        self.take(N)?.try_into().ok()
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        self.array::<1>().map(|[byte]| byte)
+    }
+
+    /// Reads an RDB length, or the special encoding of the string that
+    /// follows when the second value is `true`.
+    fn length_or_encoding(&mut self) -> Option<(u64, bool)> {
+        let first = self.byte()?;
+        match first >> 6 {
+            0 => Some((u64::from(first & 0x3f), false)),
+            1 => Some((
+                (u64::from(first & 0x3f) << 8) | u64::from(self.byte()?),
+                false,
+            )),
+            2 if first == 0x80 => Some((u32::from_be_bytes(self.array()?).into(), false)),
+            2 if first == 0x81 => Some((u64::from_be_bytes(self.array()?), false)),
+            3 => Some((u64::from(first & 0x3f), true)),
+            _ => None,
+        }
+    }
+
+    fn length(&mut self) -> Option<usize> {
+        match self.length_or_encoding()? {
+            (len, false) => usize::try_from(len).ok(),
+            (_, true) => None,
+        }
+    }
+
+    /// Reads a string stored as raw bytes, as an integer, or LZF-compressed.
+    fn raw_string(&mut self) -> Option<Vec<u8>> {
+        let integer = match self.length_or_encoding()? {
+            (len, false) => return Some(self.take(usize::try_from(len).ok()?)?.to_vec()),
+            (ENCODING_INT8, true) => i64::from(i8::from_le_bytes(self.array()?)),
+            (ENCODING_INT16, true) => i64::from(i16::from_le_bytes(self.array()?)),
+            (ENCODING_INT32, true) => i64::from(i32::from_le_bytes(self.array()?)),
+            (ENCODING_LZF, true) => {
+                let compressed_len = self.length()?;
+                let len = self.length()?;
+                return lzf_decompress(self.take(compressed_len)?, len);
+            }
+            (_, true) => return None,
+        };
+        Some(integer.to_string().into_bytes())
+    }
+
+    fn string(&mut self) -> Option<String> {
+        String::from_utf8(self.raw_string()?).ok()
+    }
+}
+
+/// Expands LZF-compressed data that must decompress to exactly `len` bytes.
+fn lzf_decompress(input: &[u8], len: usize) -> Option<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut i = 0;
+    while i < input.len() {
+        let control = usize::from(input[i]);
+        i += 1;
+        if control < 32 {
+            // A run of `control + 1` literal bytes.
+            output.extend_from_slice(input.get(i..i + control + 1)?);
+            i += control + 1;
+        } else {
+            // A back reference copying `run + 2` bytes, possibly overlapping
+            // the bytes it produces.
+            let mut run = control >> 5;
+            if run == 7 {
+                run += usize::from(*input.get(i)?);
+                i += 1;
+            }
+            let distance = ((control & 0x1f) << 8) + usize::from(*input.get(i)?) + 1;
+            i += 1;
+            let start = output.len().checked_sub(distance)?;
+            for j in start..start + run + 2 {
+                output.push(output[j]);
+            }
+        }
+        if output.len() > len {
+            return None;
+        }
+    }
+    (output.len() == len).then_some(output)
+}
+
+/// The entries of a ziplist, the compact encoding of small hashes before
+/// Redis 7.
+fn ziplist(blob: &[u8]) -> Option<Vec<String>> {
+    // Skip the total size, tail offset and entry count.
+    let mut reader = Reader {
+        data: blob.get(10..)?,
+    };
+    let mut entries = Vec::new();
+    loop {
+        match reader.byte()? {
+            0xff => break,
+            0xfe => {
+                reader.take(4)?;
+            }
+            _ => {}
+        }
+        let encoding = reader.byte()?;
+        let entry = match encoding >> 6 {
+            0 => reader.take(usize::from(encoding & 0x3f))?.to_vec(),
+            1 => {
+                let len = (usize::from(encoding & 0x3f) << 8) | usize::from(reader.byte()?);
+                reader.take(len)?.to_vec()
+            }
+            2 if encoding == 0x80 => {
+                let len = u32::from_be_bytes(reader.array()?);
+                reader.take(usize::try_from(len).ok()?)?.to_vec()
+            }
+            _ => {
+                let integer = match encoding {
+                    0xc0 => i64::from(i16::from_le_bytes(reader.array()?)),
+                    0xd0 => i64::from(i32::from_le_bytes(reader.array()?)),
+                    0xe0 => i64::from_le_bytes(reader.array()?),
+                    0xf0 => i64::from(int24(reader.array()?)),
+                    0xfe => i64::from(i8::from_le_bytes(reader.array()?)),
+                    0xf1..=0xfd => i64::from(encoding & 0x0f) - 1,
+                    _ => return None,
+                };
+                integer.to_string().into_bytes()
+            }
+        };
+        entries.push(String::from_utf8(entry).ok()?);
+    }
+    Some(entries)
+}
+
+/// The entries of a listpack, the compact encoding of small hashes since
+/// Redis 7.
+fn listpack(blob: &[u8]) -> Option<Vec<String>> {
+    // Skip the total size and entry count.
+    let mut reader = Reader {
+        data: blob.get(6..)?,
+    };
+    let mut entries = Vec::new();
+    loop {
+        let first = reader.byte()?;
+        let (entry, encoded_len) = match first {
+            0xff => break,
+            _ if first & 0x80 == 0 => (i64::from(first).to_string().into_bytes(), 1),
+            _ if first & 0xc0 == 0x80 => {
+                let len = usize::from(first & 0x3f);
+                (reader.take(len)?.to_vec(), 1 + len)
+            }
+            _ if first & 0xe0 == 0xc0 => {
+                let unsigned = (i64::from(first & 0x1f) << 8) | i64::from(reader.byte()?);
+                let integer = if unsigned >= 1 << 12 {
+                    unsigned - (1 << 13)
+                } else {
+                    unsigned
+                };
+                (integer.to_string().into_bytes(), 2)
+            }
+            _ if first & 0xf0 == 0xe0 => {
+                let len = (usize::from(first & 0x0f) << 8) | usize::from(reader.byte()?);
+                (reader.take(len)?.to_vec(), 2 + len)
+            }
+            0xf0 => {
+                let len = usize::try_from(u32::from_le_bytes(reader.array()?)).ok()?;
+                (reader.take(len)?.to_vec(), 5 + len)
+            }
+            0xf1 => (
+                i16::from_le_bytes(reader.array()?).to_string().into_bytes(),
+                3,
+            ),
+            0xf2 => (int24(reader.array()?).to_string().into_bytes(), 4),
+            0xf3 => (
+                i32::from_le_bytes(reader.array()?).to_string().into_bytes(),
+                5,
+            ),
+            0xf4 => (
+                i64::from_le_bytes(reader.array()?).to_string().into_bytes(),
+                9,
+            ),
+            _ => return None,
+        };
+        // Each entry ends with its own length, for walking backwards.
+        let backlen = match encoded_len {
+            0..=127 => 1,
+            128..16383 => 2,
+            16383..2097151 => 3,
+            2097151..268435455 => 4,
+            _ => 5,
+        };
+        reader.take(backlen)?;
+        entries.push(String::from_utf8(entry).ok()?);
+    }
+    Some(entries)
+}
+
+fn int24(bytes: [u8; 3]) -> i32 {
+    i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8
+}
+
+/// Pairs up the flattened fields and values of a compact hash encoding.
+fn pairs(entries: Vec<String>) -> Option<HashMap<String, String>> {
+    if !entries.len().is_multiple_of(2) {
+        return None;
+    }
+    let mut entries = entries.into_iter();
+    let mut hash = HashMap::new();
+    while let (Some(field), Some(value)) = (entries.next(), entries.next()) {
+        hash.insert(field, value);
+    }
+    Some(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_strings() {
+        assert_eq!(decode(b"\x00\x03bar"), Some(Value::String("bar".into())));
+        assert_eq!(decode(b"\x00\xc1\x39\x30"), Some(Value::Int(12345)));
+        assert_eq!(
+            decode(b"\x00\x02\xff\x00"),
+            Some(Value::String(vec![0xff, 0].into()))
+        );
+        // "aaaaaaaaaa" compressed by LZF: one literal, then a back reference.
+        assert_eq!(
+            decode(b"\x00\xc3\x05\x0a\x00a\xe0\x00\x00"),
+            Some(Value::String("aaaaaaaaaa".into()))
+        );
+        // Lists and sets aren't converted.
+        assert_eq!(decode(b"\x01\x01\x01a"), None);
+        assert_eq!(decode(b"\x00\x03ba"), None);
+    }
+
+    #[test]
+    fn test_decode_listpack_hash() {
+        // A listpack of `name` => `ada`, `age` => 36, as Redis 7 dumps a
+        // small hash.
+        let mut listpack = vec![0, 0, 0, 0, 4, 0];
+        listpack.extend_from_slice(b"\x84name\x05\x83ada\x04\x83age\x04\x24\x01\xff");
+        let total = listpack.len() as u32;
+        listpack[..4].copy_from_slice(&total.to_le_bytes());
+        let mut object = vec![TYPE_HASH_LISTPACK, listpack.len() as u8];
+        object.extend_from_slice(&listpack);
+
+        let Some(Value::Hash(hash)) = decode(&object) else {
+            panic!("expected a hash");
+        };
+        assert_eq!(hash.len(), 2);
+        assert_eq!(hash["name"], "ada");
+        assert_eq!(hash["age"], "36");
+    }
+}
@@ -1,45 +1,151 @@
 use std::{
-    fs, io,
-    path::PathBuf,
-    sync::Arc,
-    time::{Duration, SystemTime},
+    borrow::Cow,
+    collections::{HashMap, HashSet, VecDeque},
+    fs, io, mem,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
 };
 
+use bytes::Bytes;
 use dashmap::DashMap;
+use tokio::sync::RwLock;
 use tracing::{error, info};
 
-use crate::persistence::{aof::AofManager, rdb::RdbManager, Operation, ValueEntry};
+use crate::{
+    acl::Acl,
+    clock::{Clock, RealClock},
+    config::Config,
+    info::Stats,
+    latency::LatencyMonitor,
+    lfu,
+    list::{self, End},
+    persistence::{
+        aof::{self, AofManager},
+        expiry::VolatileKeys,
+        rdb::RdbManager,
+        MaxMemoryPolicy, Operation, Persistence, Snapshot, Value, ValueEntry,
+    },
+    pubsub::PubSub,
+    replication::{self, Replication},
+    resp::BIG_ARG,
+    scan::{self, ScanOptions},
+    scripting::ScriptCache,
+    set::Set,
+    stream::{Stream, StreamId},
+    tracking::Tracking,
+    zset::{Extreme, SortedSet},
+};
+
+/// One of the numbered databases SELECT switches between: its keys, and
+/// the index of those written with an expiry.
+#[derive(Default)]
+struct Database {
+    data: DashMap<String, ValueEntry>,
+    volatile: Mutex<VolatileKeys>,
+}
 
 pub struct Storage {
-    data: Arc<DashMap<String, ValueEntry>>,
-    aof_manager: Arc<AofManager>,
+    databases: Arc<[Database]>,
+    /// The database this handle reads and writes; see [`Storage::select`].
+    db: usize,
+    /// The summed [`footprint`] of every key in every database, kept up to
+    /// date as keys are written and removed, and its highest value so far.
+    used_memory: Arc<AtomicUsize>,
+    peak_memory: Arc<AtomicUsize>,
+    active_expire: Arc<AtomicBool>,
+    /// Figure reported as `used_memory` instead of the real one when
+    /// nonzero, set with DEBUG SET-MEMORY-USAGE.
+    memory_override: Arc<AtomicUsize>,
+    /// `None` when persistence is on demand, which keeps no AOF.
+    aof_manager: Option<Arc<AofManager>>,
+    /// Set when an AOF write or fsync fails and cleared once an fsync
+    /// succeeds, so writes are refused rather than acknowledged without
+    /// being persisted.
+    aof_failing: Arc<AtomicBool>,
     rdb_manager: Arc<RdbManager>,
+    /// Writes applied since the last successful RDB save, and when that
+    /// save finished, for the save points.
+    dirty: Arc<AtomicU64>,
+    last_save: Arc<Mutex<SystemTime>>,
+    replication: Arc<Replication>,
+    acl: Arc<Acl>,
+    script_lock: Arc<RwLock<()>>,
+    scripts: Arc<ScriptCache>,
+    stats: Arc<Stats>,
+    latency: Arc<LatencyMonitor>,
+    pubsub: Arc<PubSub>,
+    tracking: Arc<Tracking>,
+    /// Identifies this run of the server; constant for the process lifetime.
+    run_id: Arc<str>,
+    clock: Arc<dyn Clock>,
+    config: Arc<Config>,
 }
 
 impl Storage {
-    pub fn new() -> io::Result<Self> {
-        Self::new_with_paths(
-            PathBuf::from("data/dump.rdb"),
-            PathBuf::from("data/appendonly.aof"),
-        )
+    pub fn new(config: Config) -> io::Result<Self> {
+        Self::with_clock(config, Arc::new(RealClock))
     }
 
-    pub fn new_with_paths(rdb_path: PathBuf, aof_path: PathBuf) -> io::Result<Self> {
-        fs::create_dir_all("data")?;
+    /// Like [`Storage::new`], but reading the time for expiry and access
+    /// tracking from `clock`.
+    pub fn with_clock(config: Config, clock: Arc<dyn Clock>) -> io::Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+
+        let rdb_path = config.dir.join("dump.rdb");
+        let aof_path = config.dir.join("appendonly.aof");
 
         info!(
             "Initializing storage with RDB: {:?}, AOF: {:?}",
             rdb_path, aof_path
         );
 
-        let aof_manager = Arc::new(AofManager::new(aof_path)?);
-        let rdb_manager = Arc::new(RdbManager::new(rdb_path));
-        let data = Arc::new(DashMap::new());
-
+        let rdb_manager = Arc::new(RdbManager::new(rdb_path, config.rdb_compression));
+        let aof_manager = match config.persistence {
+            Persistence::Full => {
+                // The AOF is a full log, compacted to the dataset rather than
+                // truncated at each snapshot, so it is loaded alone. When
+                // there is none yet, as after running with on-demand
+                // persistence, it starts as a copy of the RDB file.
+                if fs::metadata(&aof_path).map_or(true, |metadata| metadata.len() == 0) {
+                    let snapshot = rdb_manager.load()?;
+                    if !snapshot.is_empty() {
+                        info!("No AOF found, creating it from the RDB file");
+                        aof::write_snapshot(&aof_path, &snapshot, config.aof_preamble())?;
+                    }
+                }
+                Some(Arc::new(AofManager::new(aof_path, config.append_fsync)?))
+            }
+            Persistence::OnDemand => None,
+        };
         let storage = Self {
-            data,
+            databases: (0..config.databases).map(|_| Database::default()).collect(),
+            db: 0,
+            used_memory: Arc::new(AtomicUsize::new(0)),
+            peak_memory: Arc::new(AtomicUsize::new(0)),
+            active_expire: Arc::new(AtomicBool::new(true)),
+            memory_override: Arc::new(AtomicUsize::new(0)),
             aof_manager,
+            aof_failing: Arc::new(AtomicBool::new(false)),
             rdb_manager,
+            dirty: Arc::new(AtomicU64::new(0)),
+            last_save: Arc::new(Mutex::new(clock.now())),
+            replication: Arc::new(Replication::new()),
+            acl: Arc::new(Acl::new(&config.users, config.requirepass.as_deref())),
+            script_lock: Arc::new(RwLock::new(())),
+            scripts: Arc::new(ScriptCache::new()),
+            stats: Arc::new(Stats::default()),
+            latency: Arc::new(LatencyMonitor::new(config.latency_monitor_threshold)),
+            pubsub: Arc::new(PubSub::new(
+                config.pubsub_output_buffer_limit,
+                config.notify_keyspace_events,
+            )),
+            tracking: Arc::new(Tracking::default()),
+            run_id: replication::random_id().into(),
+            clock,
+            config: Arc::new(config),
         };
 
         storage.load_persistent_data()?;
@@ -48,59 +154,165 @@ impl Storage {
         Ok(storage)
     }
 
+    /// Loads the dataset from the AOF when there is one, since it is
+    /// authoritative, and from the RDB file otherwise. Loading both would let
+    /// an older RDB file bring back keys the AOF no longer holds.
     fn load_persistent_data(&self) -> io::Result<()> {
-        for (key, value) in self.rdb_manager.load()? {
-            if value
-                .expires_at
-                .map_or(true, |expires| SystemTime::now() <= expires)
-            {
-                self.data.insert(key, value);
+        match &self.aof_manager {
+            Some(aof_manager) => {
+                let contents = aof_manager.load()?;
+                self.load_entries(contents.preamble)?;
+                for (db, op) in contents.operations {
+                    self.loaded_database(db)?.apply_locally(&op);
+                }
             }
+            None => self.load_entries(self.rdb_manager.load()?)?,
         }
+        Ok(())
+    }
 
-        for op in self.aof_manager.load_operations()? {
-            match op {
-                Operation::Set {
-                    key,
-                    value,
-                    expires_at,
-                } => {
-                    if expires_at.map_or(true, |expires| SystemTime::now() <= expires) {
-                        self.data.insert(key, ValueEntry { value, expires_at });
-                    }
-                }
-                Operation::Delete { key } => {
-                    self.data.remove(&key);
+    /// Inserts the live entries of `snapshot`, each into the database it was
+    /// saved from.
+    fn load_entries(&self, snapshot: Snapshot) -> io::Result<()> {
+        let now = self.now();
+        for (db, entries) in snapshot {
+            let storage = self.loaded_database(db)?;
+            for (key, value) in entries {
+                if value.expires_at.is_none_or(|expires| now <= expires) {
+                    storage.insert(key, value);
                 }
             }
         }
+        Ok(())
+    }
+
+    /// The database `db` of data being loaded, which fails to load if the
+    /// server was started with fewer databases than it was saved with.
+    fn loaded_database(&self, db: usize) -> io::Result<Cow<'_, Storage>> {
+        if db >= self.databases.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "data for database {} but only {} databases are configured",
+                    db,
+                    self.databases.len()
+                ),
+            ));
+        }
+        Ok(self.select(db))
+    }
+
+    /// A handle on database `db`, sharing everything else with this one.
+    /// The caller checks that the database exists.
+    pub fn select(&self, db: usize) -> Cow<'_, Storage> {
+        if db == self.db {
+            Cow::Borrowed(self)
+        } else {
+            Cow::Owned(Self { db, ..self.clone() })
+        }
+    }
 
+    /// How many databases there are, as configured with `--databases`.
+    pub fn database_count(&self) -> usize {
+        self.databases.len()
+    }
+
+    fn database(&self) -> &Database {
+        &self.databases[self.db]
+    }
+
+    /// Saves the dataset to the RDB file and replaces the in-memory dataset
+    /// with what loads back from it, for DEBUG RELOAD. The dataset is left
+    /// untouched if either step fails.
+    pub async fn reload(&self) -> io::Result<()> {
+        self.save().await?;
+        let snapshot = self.rdb_manager.load()?;
+        self.clear();
+        self.load_entries(snapshot)
+    }
+
+    /// Writes a snapshot of the dataset to the RDB file, for SAVE, BGSAVE
+    /// and the save points.
+    pub async fn save(&self) -> io::Result<()> {
+        // Writes that land while the snapshot is written may or may not be
+        // in it, so they stay counted.
+        let dirty = self.dirty.load(Ordering::Relaxed);
+        self.rdb_manager.save(self.sections()).await?;
+        self.dirty.fetch_sub(dirty, Ordering::Relaxed);
+        *self.last_save.lock().unwrap() = self.now();
         Ok(())
     }
 
+    /// The number of writes applied since the last successful RDB save.
+    pub fn changes_since_save(&self) -> u64 {
+        self.dirty.load(Ordering::Relaxed)
+    }
+
+    /// When the last successful RDB save finished, or the server started if
+    /// there was none.
+    pub fn last_save(&self) -> SystemTime {
+        *self.last_save.lock().unwrap()
+    }
+
+    /// Whether any save point is due: at least its number of writes since
+    /// the last save, which finished at least its time ago.
+    fn save_point_reached(&self) -> bool {
+        let changes = self.changes_since_save();
+        let elapsed = self
+            .now()
+            .duration_since(self.last_save())
+            .unwrap_or_default();
+        self.config
+            .save_points
+            .iter()
+            .any(|&(time, min_changes)| changes >= min_changes && elapsed >= time)
+    }
+
     fn start_background_tasks(&self) {
+        if self.aof_manager.is_some() {
+            self.start_persistence_tasks();
+        }
+
         let storage_clone = self.clone();
         tokio::spawn(async move {
-            let mut interval = tokio::time::interval(Duration::from_secs(300));
+            let period = Duration::from_secs(1) / storage_clone.config.hz;
+            let mut interval = tokio::time::interval(period);
             loop {
                 interval.tick().await;
-                let snapshot: Vec<_> = storage_clone
-                    .data
-                    .iter()
-                    .map(|entry| (entry.key().clone(), entry.value().clone()))
-                    .collect();
-                if let Err(e) = storage_clone.rdb_manager.save(&snapshot).await {
-                    error!("Failed to save RDB: {}", e);
+                if !storage_clone.active_expire.load(Ordering::Relaxed) {
+                    continue;
                 }
+                // Like Redis, spend at most a quarter of each period expiring.
+                storage_clone.active_expire_cycle(storage_clone.config.expire_samples, period / 4);
             }
         });
+    }
+
+    /// Spawns the RDB save point check, AOF fsync and AOF compaction of
+    /// full persistence.
+    fn start_persistence_tasks(&self) {
+        if !self.config.save_points.is_empty() {
+            let storage_clone = self.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(1));
+                loop {
+                    interval.tick().await;
+                    if !storage_clone.save_point_reached() {
+                        continue;
+                    }
+                    if let Err(e) = storage_clone.save().await {
+                        error!("Failed to save RDB: {}", e);
+                    }
+                }
+            });
+        }
 
         let storage_clone = self.clone();
         tokio::spawn(async move {
             let mut interval = tokio::time::interval(Duration::from_secs(1));
             loop {
                 interval.tick().await;
-                if let Err(e) = storage_clone.aof_manager.sync().await {
+                if let Err(e) = storage_clone.sync_aof().await {
                     error!("Failed to sync AOF: {}", e);
                 }
             }
@@ -108,62 +320,1816 @@ impl Storage {
 
         let storage_clone = self.clone();
         tokio::spawn(async move {
-            let mut interval = tokio::time::interval(Duration::from_secs(3600));
+            let period = Duration::from_secs(3600);
+            let mut interval =
+                tokio::time::interval_at(tokio::time::Instant::now() + period, period);
             loop {
                 interval.tick().await;
-                let snapshot: Vec<_> = storage_clone
-                    .data
-                    .iter()
-                    .map(|entry| (entry.key().clone(), entry.value().clone()))
-                    .collect();
-                if let Err(e) = storage_clone.aof_manager.compact(&snapshot).await {
+                let _guard = storage_clone.script_lock.write().await;
+                if let Err(e) = storage_clone.compact_aof().await {
                     error!("Failed to compact AOF: {}", e);
                 }
             }
         });
     }
 
+    fn insert(&self, key: String, entry: ValueEntry) -> Option<ValueEntry> {
+        let key_len = key.len();
+        let size = footprint(key_len, &entry);
+        let previous = if entry.expires_at.is_some() {
+            // Hold the index lock across the insert so the expire cycle never
+            // drops the key from the index while the new entry goes in.
+            let mut volatile = self.database().volatile.lock().unwrap();
+            volatile.insert(&key);
+            self.database().data.insert(key, entry)
+        } else {
+            self.database().data.insert(key, entry)
+        };
+        let previous_size = previous
+            .as_ref()
+            .map_or(0, |previous| footprint(key_len, previous));
+        self.account(previous_size, size);
+        previous
+    }
+
+    /// Records a key's footprint changing from `before` to `after` bytes in
+    /// the memory accounting.
+    fn account(&self, before: usize, after: usize) {
+        if after >= before {
+            let used = self
+                .used_memory
+                .fetch_add(after - before, Ordering::Relaxed)
+                + (after - before);
+            self.peak_memory.fetch_max(used, Ordering::Relaxed);
+        } else {
+            self.used_memory
+                .fetch_sub(before - after, Ordering::Relaxed);
+        }
+    }
+
+    /// Approximate bytes taken by the dataset, kept up to date on every
+    /// write rather than recomputed, for INFO memory. An override set with
+    /// [`Storage::override_used_memory`] takes its place.
+    pub fn used_memory(&self) -> usize {
+        match self.memory_override.load(Ordering::Relaxed) {
+            0 => self.used_memory.load(Ordering::Relaxed),
+            bytes => bytes,
+        }
+    }
+
+    /// Reports `bytes` as the used memory, so the maxmemory limit can be
+    /// reached in tests without filling the dataset. 0 clears the override.
+    pub fn override_used_memory(&self, bytes: usize) {
+        self.memory_override.store(bytes, Ordering::Relaxed);
+    }
+
+    /// Whether `used_memory` is over the configured `maxmemory`.
+    pub fn over_maxmemory(&self) -> bool {
+        self.config
+            .maxmemory
+            .is_some_and(|maxmemory| self.used_memory() > maxmemory)
+    }
+
+    /// Evicts keys as `maxmemory_policy` says until `used_memory` is back
+    /// under `maxmemory`. Each eviction samples `maxmemory_samples` keys of
+    /// every database, among all of them under allkeys-lfu and among those
+    /// with an expiry under volatile-lfu, and deletes the one with the
+    /// lowest access frequency, journaled like a DEL. Returns whether the
+    /// dataset fits, which it may not under noeviction or once no candidate
+    /// is left.
+    pub async fn evict(&self) -> io::Result<bool> {
+        let samples = self.config.maxmemory_samples;
+        let policy = self.config.maxmemory_policy;
+        while self.over_maxmemory() {
+            if policy == MaxMemoryPolicy::NoEviction {
+                return Ok(false);
+            }
+            let now = self.now();
+            let mut sampled = false;
+            let mut victim = None;
+            for db in 0..self.databases.len() {
+                let storage = self.select(db);
+                let candidates = match policy {
+                    MaxMemoryPolicy::VolatileLfu => {
+                        storage.database().volatile.lock().unwrap().sample(samples)
+                    }
+                    _ => storage.sample_keys(samples),
+                };
+                sampled |= !candidates.is_empty();
+
+                for key in candidates {
+                    let frequency = storage.lookup(&key, |entry| {
+                        let volatile = entry.expires_at.is_some();
+                        (volatile || policy == MaxMemoryPolicy::AllKeysLfu)
+                            .then(|| entry.frequency(now, self.config.lfu_decay_time))
+                    });
+                    match frequency {
+                        Some(Some(frequency)) => {
+                            if victim
+                                .as_ref()
+                                .is_none_or(|(lowest, _, _)| frequency < *lowest)
+                            {
+                                victim = Some((frequency, db, key));
+                            }
+                        }
+                        // Keys deleted or persisted since they were indexed
+                        // leave the volatile index, so sampling moves on.
+                        _ => storage.database().volatile.lock().unwrap().remove(&key),
+                    }
+                }
+            }
+            if !sampled {
+                return Ok(false);
+            }
+            if let Some((_, db, key)) = victim {
+                self.select(db).apply(Operation::Delete { key }).await?;
+                self.stats.record_evicted_key();
+            }
+        }
+        Ok(true)
+    }
+
+    /// Up to `count` distinct keys of the database picked uniformly at
+    /// random, in one pass over its keyspace.
+    fn sample_keys(&self, count: usize) -> Vec<String> {
+        let mut sample = Vec::with_capacity(count);
+        for (seen, entry) in self.database().data.iter().enumerate() {
+            if sample.len() < count {
+                sample.push(entry.key().clone());
+            } else {
+                let slot = fastrand::usize(..=seen);
+                if slot < count {
+                    sample[slot] = entry.key().clone();
+                }
+            }
+        }
+        sample
+    }
+
+    /// The highest [`Storage::used_memory`] has been since startup.
+    pub fn peak_memory(&self) -> usize {
+        self.peak_memory.load(Ordering::Relaxed)
+    }
+
+    /// Deletes expired keys by sampling, the way Redis does it: each round
+    /// checks `samples` random keys that were written with an expiry and
+    /// deletes the expired ones, repeating while more than a quarter of the
+    /// sample had expired and `budget` hasn't run out. The cost of a cycle
+    /// depends on how many keys are expiring, not on the size of the dataset.
+    /// Every database takes its turn within the same budget. Returns the
+    /// number of keys deleted.
+    pub fn active_expire_cycle(&self, samples: usize, budget: Duration) -> usize {
+        let deadline = Instant::now() + budget;
+        (0..self.databases.len())
+            .map(|db| self.select(db).expire_database(samples, deadline))
+            .sum()
+    }
+
+    /// The rounds of [`Storage::active_expire_cycle`] on this handle's
+    /// database, until `deadline` at the latest.
+    fn expire_database(&self, samples: usize, deadline: Instant) -> usize {
+        let mut deleted = 0;
+
+        loop {
+            let mut volatile = self.database().volatile.lock().unwrap();
+            let now = self.now();
+            let rounds = samples.min(volatile.len());
+            let mut expired = 0;
+
+            for _ in 0..rounds {
+                let Some(key) = volatile.random().map(str::to_string) else {
+                    break;
+                };
+                if self.remove_expired(&key, now) {
+                    expired += 1;
+                }
+                if self
+                    .database()
+                    .data
+                    .get(&key)
+                    .is_none_or(|entry| entry.expires_at.is_none())
+                {
+                    volatile.remove(&key);
+                }
+            }
+            drop(volatile);
+
+            deleted += expired;
+            if rounds == 0 || expired * 4 <= rounds || Instant::now() >= deadline {
+                return deleted;
+            }
+        }
+    }
+
+    /// Deletes `key` if it has expired by `now`, counting it in the stats
+    /// and publishing its `expired` keyspace events. Lookups and the
+    /// expiration cycle both come through here, and only one removal of an
+    /// entry can succeed, so each expiry is reported once.
+    fn remove_expired(&self, key: &str, now: SystemTime) -> bool {
+        let removed = self
+            .database()
+            .data
+            .remove_if(key, |_, entry| entry.is_expired(now));
+        if let Some((_, entry)) = &removed {
+            self.account(footprint(key.len(), entry), 0);
+            self.stats.record_expired_key();
+            self.tracking.invalidate(key);
+            self.pubsub.notify_expired(self.db, key);
+        }
+        removed.is_some()
+    }
+
+    /// Pauses or resumes the background expiration cycle. While paused,
+    /// expired keys are only removed when a command looks them up.
+    pub fn set_active_expire(&self, enabled: bool) {
+        self.active_expire.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Stores a string under `key`, replacing any existing value and expiry.
+    /// Returns the value it replaced, if the key held a live one.
+    ///
+    /// A value shorter than [`BIG_ARG`] is copied out of the request it was
+    /// sliced from, which it would otherwise keep alive; a longer one is
+    /// kept as it arrived.
     pub async fn set(
         &self,
         key: String,
-        value: String,
+        value: Bytes,
         expiry: Option<Duration>,
-    ) -> io::Result<()> {
-        let expires_at = expiry.map(|duration| SystemTime::now() + duration);
-        let op = Operation::Set {
-            key: key.clone(),
-            value: value.clone(),
-            expires_at,
+    ) -> io::Result<Option<Value>> {
+        let now = self.now();
+        let expires_at = expiry.map(|duration| now + duration);
+        let value = match Value::string(value) {
+            Value::String(s) if s.len() < BIG_ARG => Value::String(Bytes::copy_from_slice(&s)),
+            value => value,
         };
+        self.apply(Operation::Set {
+            key,
+            value,
+            expires_at,
+        })
+        .await
+        .map(|previous| previous.map(|entry| entry.value))
+    }
 
-        self.aof_manager.append_operation(&op).await?;
-        self.data.insert(key, ValueEntry { value, expires_at });
+    /// Replaces the value at `key`, keeping its expiry, as commands that
+    /// modify a value in place do.
+    pub async fn overwrite(&self, key: String, value: Value) -> io::Result<()> {
+        let expires_at = self.lookup(&key, |entry| entry.expires_at).flatten();
+        self.apply(Operation::Set {
+            key,
+            value,
+            expires_at,
+        })
+        .await?;
+        Ok(())
+    }
 
+    /// Sets fields of the hash at `key`, creating it if needed. The caller
+    /// checks that the key doesn't hold another type, holding the script
+    /// lock exclusively so neither the check nor the count goes stale before
+    /// the write. Returns the number of fields that didn't exist before.
+    pub async fn hset(&self, key: String, fields: Vec<(String, String)>) -> io::Result<usize> {
+        let added = self
+            .lookup(&key, |entry| match &entry.value {
+                Value::Hash(hash) => fields
+                    .iter()
+                    .map(|(field, _)| field)
+                    .filter(|field| !hash.contains_key(*field))
+                    .collect::<HashSet<_>>()
+                    .len(),
+                _ => 0,
+            })
+            .unwrap_or_else(|| {
+                fields
+                    .iter()
+                    .map(|(field, _)| field)
+                    .collect::<HashSet<_>>()
+                    .len()
+            });
+
+        self.apply(Operation::HSet { key, fields }).await?;
+        Ok(added)
+    }
+
+    /// Appends an entry with the given ID to the stream at `key`, creating it
+    /// if needed. The caller checks the type and that `id` is greater than
+    /// the stream's last ID.
+    pub async fn xadd(
+        &self,
+        key: String,
+        id: StreamId,
+        fields: Vec<(String, String)>,
+    ) -> io::Result<()> {
+        self.apply(Operation::XAdd { key, id, fields }).await?;
         Ok(())
     }
 
-    pub async fn get(&self, key: &str) -> io::Result<Option<String>> {
-        if let Some(ref_multi) = self.data.get(key) {
-            if let Some(expires_at) = ref_multi.expires_at {
-                if SystemTime::now() > expires_at {
-                    drop(ref_multi);
-                    self.data.remove(key);
-                    return Ok(None);
+    /// Pushes `elements` one at a time to `end` of the list at `key`,
+    /// creating it if needed. The caller checks the type, holding the script
+    /// lock exclusively so the length doesn't go stale before the write.
+    /// Returns the new length of the list.
+    pub async fn push(&self, key: String, end: End, elements: Vec<String>) -> io::Result<usize> {
+        let len = self
+            .lookup(&key, |entry| entry.value.as_list().map_or(0, VecDeque::len))
+            .unwrap_or(0)
+            + elements.len();
+        self.apply(Operation::Push { key, end, elements }).await?;
+        Ok(len)
+    }
+
+    /// Pops up to `count` elements from `end` of the list at `key`, deleting
+    /// the key once the list is empty. The caller checks the type, holding
+    /// the script lock exclusively. Returns the popped elements in order, or
+    /// `None` when there is no list.
+    pub async fn pop(
+        &self,
+        key: String,
+        end: End,
+        count: usize,
+    ) -> io::Result<Option<Vec<String>>> {
+        let popped = self.lookup(&key, |entry| match &entry.value {
+            Value::List(list) => match end {
+                End::Left => list.iter().take(count).cloned().collect::<Vec<_>>(),
+                End::Right => list.iter().rev().take(count).cloned().collect(),
+            },
+            _ => Vec::new(),
+        });
+        if popped.as_ref().is_some_and(|popped| !popped.is_empty()) {
+            self.apply(Operation::Pop { key, end, count }).await?;
+        }
+        Ok(popped)
+    }
+
+    /// Moves an element from `from` of the list at `source` to `to` of the
+    /// list at `destination`, creating it if needed, as a single journaled
+    /// operation. The keys may be the same, rotating the list. The caller
+    /// checks both types, holding the script lock exclusively. Returns the
+    /// element moved, or `None` when there is no source list.
+    pub async fn lmove(
+        &self,
+        source: String,
+        destination: String,
+        from: End,
+        to: End,
+    ) -> io::Result<Option<String>> {
+        let element = self
+            .lookup(&source, |entry| match (&entry.value, from) {
+                (Value::List(list), End::Left) => list.front().cloned(),
+                (Value::List(list), End::Right) => list.back().cloned(),
+                _ => None,
+            })
+            .flatten();
+        if element.is_some() {
+            self.apply(Operation::Move {
+                source,
+                destination,
+                from,
+                to,
+            })
+            .await?;
+        }
+        Ok(element)
+    }
+
+    /// Adds `members` to the set at `key`, creating it if needed. The caller
+    /// checks the type, holding the script lock exclusively so the count
+    /// doesn't go stale before the write. Returns the number of members that
+    /// weren't in the set before.
+    pub async fn sadd(&self, key: String, members: Vec<String>) -> io::Result<usize> {
+        let added = self
+            .lookup(&key, |entry| match &entry.value {
+                Value::Set(set) => members
+                    .iter()
+                    .filter(|member| !set.contains(member))
+                    .collect::<HashSet<_>>()
+                    .len(),
+                _ => 0,
+            })
+            .unwrap_or_else(|| members.iter().collect::<HashSet<_>>().len());
+        if added > 0 {
+            self.apply(Operation::SAdd { key, members }).await?;
+        }
+        Ok(added)
+    }
+
+    /// Removes `members` from the set at `key`, deleting the key once the
+    /// set is empty. The caller checks the type, holding the script lock
+    /// exclusively. Returns the number of members that were in the set.
+    pub async fn srem(&self, key: String, members: Vec<String>) -> io::Result<usize> {
+        let removed = self
+            .lookup(&key, |entry| match &entry.value {
+                Value::Set(set) => members
+                    .iter()
+                    .filter(|member| set.contains(member))
+                    .collect::<HashSet<_>>()
+                    .len(),
+                _ => 0,
+            })
+            .unwrap_or(0);
+        if removed > 0 {
+            self.apply(Operation::SRem { key, members }).await?;
+        }
+        Ok(removed)
+    }
+
+    /// Sets the scores of `members` in the sorted set at `key`, creating it
+    /// if needed. The caller checks the type, holding the script lock
+    /// exclusively so the count doesn't go stale before the write. Returns
+    /// the number of members that weren't in the set before.
+    pub async fn zadd(&self, key: String, members: Vec<(String, f64)>) -> io::Result<usize> {
+        let added = self
+            .lookup(&key, |entry| match &entry.value {
+                Value::SortedSet(set) => members
+                    .iter()
+                    .map(|(member, _)| member)
+                    .filter(|member| set.score(member).is_none())
+                    .collect::<HashSet<_>>()
+                    .len(),
+                _ => 0,
+            })
+            .unwrap_or_else(|| {
+                members
+                    .iter()
+                    .map(|(member, _)| member)
+                    .collect::<HashSet<_>>()
+                    .len()
+            });
+        self.apply(Operation::ZAdd { key, members }).await?;
+        Ok(added)
+    }
+
+    /// Removes up to `count` members from the `extreme` end of the sorted
+    /// set at `key`, deleting the key once it is empty, and journals them as
+    /// a ZREM. The caller checks the type, holding the script lock
+    /// exclusively. Returns the members popped with their scores, in the
+    /// order they were popped.
+    pub async fn zpop(
+        &self,
+        key: String,
+        extreme: Extreme,
+        count: usize,
+    ) -> io::Result<Vec<(String, f64)>> {
+        let popped: Vec<(String, f64)> = self
+            .lookup(&key, |entry| match &entry.value {
+                Value::SortedSet(set) => {
+                    let members: Box<dyn Iterator<Item = (&str, f64)>> = match extreme {
+                        Extreme::Min => Box::new(set.iter()),
+                        Extreme::Max => Box::new(set.iter().rev()),
+                    };
+                    members
+                        .take(count)
+                        .map(|(member, score)| (member.to_string(), score))
+                        .collect()
                 }
+                _ => Vec::new(),
+            })
+            .unwrap_or_default();
+        if !popped.is_empty() {
+            let members = popped.iter().map(|(member, _)| member.clone()).collect();
+            self.apply(Operation::ZRem { key, members }).await?;
+        }
+        Ok(popped)
+    }
+
+    /// Removes `members` from the sorted set at `key`, deleting the key once
+    /// the set is empty. The caller checks the type, holding the script lock
+    /// exclusively. Returns the number of members that were in the set.
+    pub async fn zrem(&self, key: String, members: Vec<String>) -> io::Result<usize> {
+        let removed = self
+            .lookup(&key, |entry| match &entry.value {
+                Value::SortedSet(set) => members
+                    .iter()
+                    .filter(|member| set.score(member).is_some())
+                    .collect::<HashSet<_>>()
+                    .len(),
+                _ => 0,
+            })
+            .unwrap_or(0);
+        if removed > 0 {
+            self.apply(Operation::ZRem { key, members }).await?;
+        }
+        Ok(removed)
+    }
+
+    /// Stores `set` under `key` as SINTERSTORE and the other STORE commands
+    /// do, replacing any existing value and expiry, or deletes the key when
+    /// the set is empty.
+    pub async fn store_set(&self, key: String, set: Set) -> io::Result<()> {
+        let op = if set.is_empty() {
+            Operation::Delete { key }
+        } else {
+            Operation::Set {
+                key,
+                value: Value::Set(set),
+                expires_at: None,
             }
-            Ok(Some(ref_multi.value.clone()))
+        };
+        self.apply(op).await?;
+        Ok(())
+    }
+
+    /// Replaces the list at `key`, keeping its expiry, or deletes the key
+    /// when the list is empty, as commands that rewrite a list do.
+    pub async fn store_list(&self, key: String, list: VecDeque<String>) -> io::Result<()> {
+        if list.is_empty() {
+            self.apply(Operation::Delete { key }).await?;
+            Ok(())
         } else {
-            Ok(None)
+            self.overwrite(key, Value::List(list)).await
+        }
+    }
+
+    /// Journals a write operation to the AOF, applies it to the dataset and
+    /// forwards it to connected replicas. Returns the entry the operation
+    /// replaced or removed, unless it had already expired.
+    pub async fn apply(&self, op: Operation) -> io::Result<Option<ValueEntry>> {
+        if let Some(aof_manager) = &self.aof_manager {
+            if let Err(e) = aof_manager.append_operation(self.db, &op).await {
+                self.aof_failing.store(true, Ordering::Relaxed);
+                return Err(e);
+            }
+        }
+        let previous = self.apply_locally(&op);
+        self.dirty.fetch_add(1, Ordering::Relaxed);
+        self.replication.propagate(self.db, &op);
+        Ok(previous)
+    }
+
+    /// Applies an operation to the in-memory dataset only, as when replaying
+    /// the AOF.
+    fn apply_locally(&self, op: &Operation) -> Option<ValueEntry> {
+        let now = self.now();
+        let previous = match op {
+            Operation::Set {
+                key,
+                value,
+                expires_at,
+            } => {
+                // An overwritten key keeps counting accesses where it left
+                // off, so rewriting a hot key doesn't make it look cold.
+                let mut entry = ValueEntry::new(value.clone(), *expires_at);
+                if let Some(previous) = self.database().data.get(key) {
+                    entry.lfu_counter = previous.lfu_counter;
+                    entry.last_access = previous.last_access;
+                }
+                self.touch(&mut entry, now);
+                self.insert(key.clone(), entry)
+            }
+            Operation::Delete { key } => self.database().data.remove(key).map(|(_, entry)| {
+                self.account(footprint(key.len(), &entry), 0);
+                entry
+            }),
+            Operation::HSet { key, fields } => {
+                let mut created = false;
+                let mut entry = self.database().data.entry(key.clone()).or_insert_with(|| {
+                    created = true;
+                    ValueEntry::new(Value::Hash(HashMap::new()), None)
+                });
+                let before = if created {
+                    0
+                } else {
+                    footprint(key.len(), &entry)
+                };
+                // Commands check the type before journaling, so this only
+                // replaces a value that expired or was overwritten since.
+                if !matches!(entry.as_hash(now), Ok(Some(_))) {
+                    *entry = ValueEntry::new(Value::Hash(HashMap::new()), None);
+                }
+                self.touch(&mut entry, now);
+                if let Value::Hash(hash) = &mut entry.value {
+                    hash.extend(fields.iter().cloned());
+                }
+                self.account(before, footprint(key.len(), &entry));
+                None
+            }
+            Operation::XAdd { key, id, fields } => {
+                let mut created = false;
+                let mut entry = self.database().data.entry(key.clone()).or_insert_with(|| {
+                    created = true;
+                    ValueEntry::new(Value::Stream(Stream::default()), None)
+                });
+                let before = if created {
+                    0
+                } else {
+                    footprint(key.len(), &entry)
+                };
+                if !matches!(entry.as_stream(now), Ok(Some(_))) {
+                    *entry = ValueEntry::new(Value::Stream(Stream::default()), None);
+                }
+                self.touch(&mut entry, now);
+                if let Value::Stream(stream) = &mut entry.value {
+                    stream.insert(*id, fields.clone());
+                }
+                self.account(before, footprint(key.len(), &entry));
+                None
+            }
+            Operation::Push { key, end, elements } => {
+                self.push_locally(key, *end, elements.iter().cloned(), now);
+                None
+            }
+            Operation::Pop { key, end, count } => {
+                self.pop_locally(key, *end, *count, now);
+                self.remove_empty(key);
+                None
+            }
+            Operation::Move {
+                source,
+                destination,
+                from,
+                to,
+            } => {
+                let popped = self.pop_locally(source, *from, 1, now);
+                self.push_locally(destination, *to, popped, now);
+                // Only now, so rotating a one-element list keeps the key.
+                self.remove_empty(source);
+                None
+            }
+            Operation::SAdd { key, members } => {
+                let mut created = false;
+                let mut entry = self.database().data.entry(key.clone()).or_insert_with(|| {
+                    created = true;
+                    ValueEntry::new(Value::Set(Set::default()), None)
+                });
+                let before = if created {
+                    0
+                } else {
+                    footprint(key.len(), &entry)
+                };
+                if !matches!(entry.as_set(now), Ok(Some(_))) {
+                    *entry = ValueEntry::new(Value::Set(Set::default()), None);
+                }
+                self.touch(&mut entry, now);
+                if let Value::Set(set) = &mut entry.value {
+                    for member in members {
+                        set.insert(member.clone(), self.config.set_max_intset_entries);
+                    }
+                }
+                self.account(before, footprint(key.len(), &entry));
+                None
+            }
+            Operation::SRem { key, members } => {
+                if let Some(mut entry) = self.database().data.get_mut(key) {
+                    let before = footprint(key.len(), &entry);
+                    self.touch(&mut entry, now);
+                    if let Value::Set(set) = &mut entry.value {
+                        for member in members {
+                            set.remove(member);
+                        }
+                    }
+                    self.account(before, footprint(key.len(), &entry));
+                }
+                self.remove_empty(key);
+                None
+            }
+            Operation::ZAdd { key, members } => {
+                let mut created = false;
+                let mut entry = self.database().data.entry(key.clone()).or_insert_with(|| {
+                    created = true;
+                    ValueEntry::new(Value::SortedSet(SortedSet::default()), None)
+                });
+                let before = if created {
+                    0
+                } else {
+                    footprint(key.len(), &entry)
+                };
+                if !matches!(entry.as_sorted_set(now), Ok(Some(_))) {
+                    *entry = ValueEntry::new(Value::SortedSet(SortedSet::default()), None);
+                }
+                self.touch(&mut entry, now);
+                if let Value::SortedSet(set) = &mut entry.value {
+                    for (member, score) in members {
+                        set.insert(member.clone(), *score);
+                    }
+                }
+                self.account(before, footprint(key.len(), &entry));
+                None
+            }
+            Operation::ZRem { key, members } => {
+                if let Some(mut entry) = self.database().data.get_mut(key) {
+                    let before = footprint(key.len(), &entry);
+                    self.touch(&mut entry, now);
+                    if let Value::SortedSet(set) = &mut entry.value {
+                        for member in members {
+                            set.remove(member);
+                        }
+                    }
+                    self.account(before, footprint(key.len(), &entry));
+                }
+                self.remove_empty(key);
+                None
+            }
+            Operation::Select { .. } => None,
+        };
+        for key in op.keys() {
+            self.tracking.invalidate(key);
+        }
+
+        previous.filter(|entry| !entry.is_expired(now))
+    }
+
+    /// Pushes `elements` to the list at `key`, replacing any other value.
+    fn push_locally(
+        &self,
+        key: &str,
+        end: End,
+        elements: impl IntoIterator<Item = String>,
+        now: SystemTime,
+    ) {
+        let mut created = false;
+        let mut entry = self
+            .database()
+            .data
+            .entry(key.to_string())
+            .or_insert_with(|| {
+                created = true;
+                ValueEntry::new(Value::List(VecDeque::new()), None)
+            });
+        let before = if created {
+            0
+        } else {
+            footprint(key.len(), &entry)
+        };
+        // Commands check the type before journaling, so this only replaces
+        // a value that expired or was overwritten since.
+        if !matches!(entry.as_list(now), Ok(Some(_))) {
+            *entry = ValueEntry::new(Value::List(VecDeque::new()), None);
+        }
+        self.touch(&mut entry, now);
+        if let Value::List(list) = &mut entry.value {
+            list::push(list, end, elements);
+        }
+        self.account(before, footprint(key.len(), &entry));
+    }
+
+    /// Pops up to `count` elements from the list at `key`, leaving it in
+    /// place even once empty.
+    fn pop_locally(&self, key: &str, end: End, count: usize, now: SystemTime) -> Vec<String> {
+        let Some(mut entry) = self.database().data.get_mut(key) else {
+            return Vec::new();
+        };
+        let before = footprint(key.len(), &entry);
+        self.touch(&mut entry, now);
+        let popped = match &mut entry.value {
+            Value::List(list) => list::pop(list, end, count),
+            _ => Vec::new(),
+        };
+        self.account(before, footprint(key.len(), &entry));
+        popped
+    }
+
+    /// Records an access to `entry` at `now`: its LFU counter decays for the
+    /// time it sat idle, then counts the access, and its access time is
+    /// refreshed.
+    fn touch(&self, entry: &mut ValueEntry, now: SystemTime) {
+        let counter = entry.frequency(now, self.config.lfu_decay_time);
+        entry.lfu_counter = lfu::increment(counter, self.config.lfu_log_factor);
+        entry.last_access = now;
+    }
+
+    /// Deletes `key` if it holds an empty list, set or sorted set, as Redis
+    /// never keeps one.
+    fn remove_empty(&self, key: &str) {
+        let removed = self
+            .database()
+            .data
+            .remove_if(key, |_, entry| match &entry.value {
+                Value::List(list) => list.is_empty(),
+                Value::Set(set) => set.is_empty(),
+                Value::SortedSet(set) => set.is_empty(),
+                _ => false,
+            });
+        if let Some((_, entry)) = removed {
+            self.account(footprint(key.len(), &entry), 0);
+        }
+    }
+
+    /// Reads the live value at `key`, counting the lookup in the keyspace
+    /// stats and as an access to the key.
+    pub fn read<T>(&self, key: &str, f: impl FnOnce(&Value) -> T) -> Option<T> {
+        let now = self.now();
+        let result = match self.database().data.get_mut(key) {
+            Some(entry) if entry.is_expired(now) => {
+                drop(entry);
+                self.remove_expired(key, now);
+                None
+            }
+            Some(mut entry) => {
+                self.touch(&mut entry, now);
+                Some(f(&entry.value))
+            }
+            None => None,
+        };
+        self.stats.record_lookup(result.is_some());
+        result
+    }
+
+    /// Inspects the live entry at `key` without counting it as an access.
+    pub fn lookup<T>(&self, key: &str, f: impl FnOnce(&ValueEntry) -> T) -> Option<T> {
+        let now = self.now();
+        match self.database().data.get(key) {
+            Some(entry) if entry.is_expired(now) => {
+                drop(entry);
+                self.remove_expired(key, now);
+                None
+            }
+            Some(entry) => Some(f(&entry)),
+            None => None,
+        }
+    }
+
+    /// Looks up a live entry without counting it as an access.
+    pub fn get_entry(&self, key: &str) -> Option<ValueEntry> {
+        self.lookup(key, ValueEntry::clone)
+    }
+
+    /// Returns a page of live keys for SCAN; see [`scan::page`].
+    pub fn scan(&self, options: &ScanOptions) -> (u64, Vec<String>) {
+        let now = self.now();
+        let keys = self
+            .database()
+            .data
+            .iter()
+            .filter(|entry| !entry.is_expired(now))
+            .map(|entry| (entry.key().clone(), entry.value.type_name()));
+        let (next, keys) = scan::page(
+            keys,
+            options.cursor,
+            options.count,
+            options.pattern.as_deref(),
+            |(key, _)| key.as_str(),
+        );
+        // Like MATCH, TYPE filters the page rather than the candidates.
+        let keys = keys
+            .into_iter()
+            .filter(|(_, type_name)| {
+                options
+                    .type_name
+                    .as_deref()
+                    .is_none_or(|wanted| wanted.eq_ignore_ascii_case(type_name))
+            })
+            .map(|(key, _)| key)
+            .collect();
+        (next, keys)
+    }
+
+    /// A random live key, optionally only among keys holding `type_name`.
+    /// The map has no random access, so this walks the keyspace once,
+    /// keeping each candidate with equal probability.
+    pub fn random_key(&self, type_name: Option<&str>) -> Option<String> {
+        let now = self.now();
+        let mut chosen = None;
+        let mut candidates = 0;
+        for entry in self.database().data.iter() {
+            if entry.is_expired(now)
+                || type_name
+                    .is_some_and(|wanted| !wanted.eq_ignore_ascii_case(entry.value.type_name()))
+            {
+                continue;
+            }
+            candidates += 1;
+            if fastrand::usize(..candidates) == 0 {
+                chosen = Some(entry.key().clone());
+            }
+        }
+        chosen
+    }
+
+    /// Approximate memory footprint of a key: the key string, its entry and
+    /// the per-slot overhead of the map holding it. An aggregate value is
+    /// estimated from at most `samples` of its elements; 0 measures all of
+    /// them.
+    pub fn memory_usage(&self, key: &str, samples: usize) -> Option<usize> {
+        self.lookup(key, |entry| {
+            mem::size_of::<(String, ValueEntry)>() + key.len() + entry.sampled_size_of(samples)
+        })
+    }
+
+    /// Stores a previously dumped entry under `key`. Returns `false` without
+    /// writing anything if the key already exists and `replace` is not set.
+    /// The check and the write are only atomic under the script lock, which
+    /// RESTORE takes exclusively.
+    pub async fn restore(&self, key: String, entry: ValueEntry, replace: bool) -> io::Result<bool> {
+        if !replace && self.get_entry(&key).is_some() {
+            return Ok(false);
+        }
+
+        self.apply(Operation::Set {
+            key,
+            value: entry.value,
+            expires_at: entry.expires_at,
+        })
+        .await?;
+
+        Ok(true)
+    }
+
+    /// Copies the entry at `source` to `destination` in `target`, which may
+    /// be this database or another, keeping its expiry. Returns `false`
+    /// without writing anything if `source` doesn't exist, or `destination`
+    /// does and `replace` is not set. The check and the write are only
+    /// atomic under the script lock, which COPY takes exclusively.
+    pub async fn copy(
+        &self,
+        source: &str,
+        target: &Storage,
+        destination: String,
+        replace: bool,
+    ) -> io::Result<bool> {
+        match self.get_entry(source) {
+            Some(entry) => target.restore(destination, entry, replace).await,
+            None => Ok(false),
+        }
+    }
+
+    /// Removes `key`, returning whether it held a live value.
+    pub async fn delete(&self, key: String) -> io::Result<bool> {
+        Ok(self.apply(Operation::Delete { key }).await?.is_some())
+    }
+
+    /// Copies of the entries of every database that has any.
+    pub fn snapshot(&self) -> Snapshot {
+        self.sections()
+            .map(|(db, entries)| (db, entries.collect()))
+            .collect()
+    }
+
+    /// The databases that have any entries, each with an iterator over
+    /// copies of them one at a time, so a dataset can be written out
+    /// without cloning all of it first. Writes to a shard wait while an
+    /// iterator is reading it.
+    fn sections(
+        &self,
+    ) -> impl Iterator<Item = (usize, impl Iterator<Item = (String, ValueEntry)> + '_)> + '_ {
+        self.databases
+            .iter()
+            .enumerate()
+            .filter(|(_, database)| !database.data.is_empty())
+            .map(|(db, database)| {
+                let entries = database
+                    .data
+                    .iter()
+                    .map(|entry| (entry.key().clone(), entry.value().clone()));
+                (db, entries)
+            })
+    }
+
+    /// Flushes buffered AOF operations to disk before the server exits, so
+    /// every acknowledged write can be replayed on the next start.
+    pub async fn shutdown(&self) -> io::Result<()> {
+        self.sync_aof().await
+    }
+
+    /// Flushes and fsyncs the AOF, making every write issued so far durable,
+    /// as WAITAOF requires. Does nothing without an AOF.
+    pub async fn sync_aof(&self) -> io::Result<()> {
+        let Some(aof_manager) = &self.aof_manager else {
+            return Ok(());
+        };
+        let result = aof_manager.sync().await;
+        self.aof_failing.store(result.is_err(), Ordering::Relaxed);
+        result
+    }
+
+    /// Whether writes are journaled to an AOF, as with full persistence.
+    pub fn aof_enabled(&self) -> bool {
+        self.aof_manager.is_some()
+    }
+
+    /// Whether the AOF could not be written to since its last successful
+    /// fsync, in which case write commands are refused.
+    pub fn aof_failing(&self) -> bool {
+        self.aof_failing.load(Ordering::Relaxed)
+    }
+
+    /// Replaces the AOF with a snapshot of the dataset. Does nothing without
+    /// an AOF.
+    ///
+    /// The caller holds the script lock exclusively, so no write may land
+    /// between taking the snapshot and swapping the file.
+    async fn compact_aof(&self) -> io::Result<()> {
+        let Some(aof_manager) = &self.aof_manager else {
+            return Ok(());
+        };
+        aof_manager
+            .compact(&self.snapshot(), self.config.aof_preamble())
+            .await
+    }
+
+    /// Replaces the dataset with the snapshot received in a full resync from
+    /// a primary, and the AOF with a snapshot of the result, so keys removed
+    /// on the primary meanwhile don't come back from the AOF on the next
+    /// start. The entries are forwarded to this instance's own replicas.
+    pub async fn load_resync(&self, snapshot: Snapshot) -> io::Result<()> {
+        let _guard = self.script_lock.write().await;
+        self.clear();
+        for (db, entries) in &snapshot {
+            for (key, entry) in entries {
+                self.replication.propagate(
+                    *db,
+                    &Operation::Set {
+                        key: key.clone(),
+                        value: entry.value.clone(),
+                        expires_at: entry.expires_at,
+                    },
+                );
+            }
+            self.dirty
+                .fetch_add(entries.len() as u64, Ordering::Relaxed);
+        }
+        self.load_entries(snapshot)?;
+        self.compact_aof().await
+    }
+
+    /// Drops the in-memory dataset of every database, as a full resync or
+    /// DEBUG RELOAD does.
+    fn clear(&self) {
+        for database in self.databases.iter() {
+            let mut volatile = database.volatile.lock().unwrap();
+            volatile.clear();
+            database.data.clear();
         }
+        self.used_memory.store(0, Ordering::Relaxed);
+        self.tracking.invalidate_all();
+    }
+
+    pub fn replication(&self) -> &Arc<Replication> {
+        &self.replication
+    }
+
+    pub fn acl(&self) -> &Acl {
+        &self.acl
+    }
+
+    /// Lock serializing scripts and multi-key check-and-set commands against
+    /// every other command: commands share it, those take it exclusively.
+    pub fn script_lock(&self) -> &RwLock<()> {
+        &self.script_lock
+    }
+
+    pub fn scripts(&self) -> &ScriptCache {
+        &self.scripts
+    }
+
+    pub fn stats(&self) -> &Arc<Stats> {
+        &self.stats
+    }
+
+    pub fn latency(&self) -> &LatencyMonitor {
+        &self.latency
+    }
+
+    pub fn pubsub(&self) -> &PubSub {
+        &self.pubsub
+    }
+
+    pub fn tracking(&self) -> &Tracking {
+        &self.tracking
+    }
+
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
+    /// The current time according to the storage's clock.
+    pub fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Whether write commands from clients must be rejected, either because
+    /// the server was started read-only or because it follows a primary.
+    pub fn is_read_only(&self) -> bool {
+        self.config.read_only || self.replication.is_replica()
+    }
+
+    pub fn replicate_from(&self, host: String, port: u16) {
+        self.replication.follow(self.clone(), host, port);
     }
 }
 
 impl Clone for Storage {
     fn clone(&self) -> Self {
         Self {
-            data: Arc::clone(&self.data),
-            aof_manager: Arc::clone(&self.aof_manager),
+            databases: Arc::clone(&self.databases),
+            db: self.db,
+            used_memory: Arc::clone(&self.used_memory),
+            peak_memory: Arc::clone(&self.peak_memory),
+            active_expire: Arc::clone(&self.active_expire),
+            memory_override: Arc::clone(&self.memory_override),
+            aof_manager: self.aof_manager.clone(),
+            aof_failing: Arc::clone(&self.aof_failing),
             rdb_manager: Arc::clone(&self.rdb_manager),
+            dirty: Arc::clone(&self.dirty),
+            last_save: Arc::clone(&self.last_save),
+            replication: Arc::clone(&self.replication),
+            acl: Arc::clone(&self.acl),
+            script_lock: Arc::clone(&self.script_lock),
+            scripts: Arc::clone(&self.scripts),
+            stats: Arc::clone(&self.stats),
+            latency: Arc::clone(&self.latency),
+            pubsub: Arc::clone(&self.pubsub),
+            tracking: Arc::clone(&self.tracking),
+            run_id: Arc::clone(&self.run_id),
+            clock: Arc::clone(&self.clock),
+            config: Arc::clone(&self.config),
+        }
+    }
+}
+
+/// Approximate memory a key with a `key_len`-byte name takes up: the key
+/// string, its entry and the per-slot overhead of the map holding it.
+fn footprint(key_len: usize, entry: &ValueEntry) -> usize {
+    mem::size_of::<(String, ValueEntry)>() + key_len + entry.size_of()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::Client,
+        clock::MockClock,
+        commands::Command,
+        persistence::{aof::AppendFsync, rdb::Compression},
+        resp::Resp,
+    };
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_active_expire_cycle_only_samples_volatile_keys() {
+        let clock = Arc::new(MockClock::new());
+        let storage = Storage::with_clock(
+            Config {
+                dir: tempfile::tempdir().unwrap().keep(),
+                ..Config::default()
+            },
+            clock.clone(),
+        )
+        .unwrap();
+
+        for i in 0..1000 {
+            storage
+                .set(format!("persistent:{}", i), "v".into(), None)
+                .await
+                .unwrap();
+        }
+        for i in 0..100 {
+            storage
+                .set(
+                    format!("volatile:{}", i),
+                    "v".into(),
+                    Some(Duration::from_millis(10)),
+                )
+                .await
+                .unwrap();
         }
+        assert_eq!(storage.database().volatile.lock().unwrap().len(), 100);
+
+        clock.advance(Duration::from_millis(20));
+        let mut deleted = 0;
+        for _ in 0..100 {
+            deleted += storage.active_expire_cycle(20, Duration::from_secs(1));
+            if storage.database().data.len() == 1000 {
+                break;
+            }
+        }
+
+        assert_eq!(storage.database().data.len(), 1000);
+        assert_eq!(storage.database().volatile.lock().unwrap().len(), 0);
+        assert!(deleted <= 100);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_mock_clock_expires_keys_without_sleeping() {
+        let clock = Arc::new(MockClock::new());
+        let storage = Storage::with_clock(
+            Config {
+                dir: tempfile::tempdir().unwrap().keep(),
+                ..Config::default()
+            },
+            clock.clone(),
+        )
+        .unwrap();
+
+        storage
+            .set("key".into(), "v".into(), Some(Duration::from_secs(60)))
+            .await
+            .unwrap();
+        clock.advance(Duration::from_secs(59));
+        assert!(storage.read("key", Value::clone).is_some());
+        assert_eq!(
+            storage.get_entry("key").unwrap().idle_time(storage.now()),
+            Duration::ZERO
+        );
+
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(storage.read("key", Value::clone), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_paused_active_expire_leaves_lazy_expiry() {
+        let storage = Storage::new(Config {
+            dir: tempfile::tempdir().unwrap().keep(),
+            ..Config::default()
+        })
+        .unwrap();
+        storage.set_active_expire(false);
+
+        storage
+            .set("key".into(), "v".into(), Some(Duration::from_millis(10)))
+            .await
+            .unwrap();
+        // Long enough for several cycles at the default frequency.
+        tokio::time::sleep(Duration::from_millis(350)).await;
+        assert!(storage.database().data.contains_key("key"));
+
+        assert_eq!(storage.read("key", Value::clone), None);
+        assert!(!storage.database().data.contains_key("key"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_shutdown_flushes_buffered_operations() {
+        let dir = tempfile::tempdir().unwrap().keep();
+        let storage = Storage::new(Config {
+            dir: dir.clone(),
+            ..Config::default()
+        })
+        .unwrap();
+        for i in 0..500 {
+            storage
+                .set(format!("key:{}", i), "v".into(), None)
+                .await
+                .unwrap();
+        }
+        storage.shutdown().await.unwrap();
+
+        let aof = AofManager::new(dir.join("appendonly.aof"), AppendFsync::EverySec).unwrap();
+        assert_eq!(aof.load().unwrap().operations.len(), 500);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_list_operations_replay_from_the_aof() {
+        let dir = tempfile::tempdir().unwrap().keep();
+        let config = Config {
+            dir: dir.clone(),
+            ..Config::default()
+        };
+        let storage = Storage::new(config.clone()).unwrap();
+        let elements = |elements: &[&str]| elements.iter().map(|e| e.to_string()).collect();
+        storage
+            .push("list".into(), End::Right, elements(&["a", "b", "c"]))
+            .await
+            .unwrap();
+        storage
+            .push("list".into(), End::Left, elements(&["z"]))
+            .await
+            .unwrap();
+        let popped = storage.pop("list".into(), End::Right, 2).await.unwrap();
+        assert_eq!(popped, Some(elements(&["c", "b"])));
+        storage
+            .push("emptied".into(), End::Left, elements(&["x"]))
+            .await
+            .unwrap();
+        storage.pop("emptied".into(), End::Left, 1).await.unwrap();
+        assert!(storage.get_entry("emptied").is_none());
+        let moved = storage
+            .lmove("list".into(), "moved".into(), End::Left, End::Right)
+            .await
+            .unwrap();
+        assert_eq!(moved.as_deref(), Some("z"));
+        storage.shutdown().await.unwrap();
+        drop(storage);
+
+        let storage = Storage::new(config).unwrap();
+        let list = storage.get_entry("list").unwrap().value;
+        assert_eq!(list, Value::List(VecDeque::from(elements(&["a"]))));
+        let moved = storage.get_entry("moved").unwrap().value;
+        assert_eq!(moved, Value::List(VecDeque::from(elements(&["z"]))));
+        assert!(storage.get_entry("emptied").is_none());
+        assert_eq!(
+            storage.pop("missing".into(), End::Left, 1).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_set_operations_replay_from_the_aof() {
+        let dir = tempfile::tempdir().unwrap().keep();
+        let config = Config {
+            dir: dir.clone(),
+            ..Config::default()
+        };
+        let storage = Storage::new(config.clone()).unwrap();
+        let members = |members: &[&str]| members.iter().map(|m| m.to_string()).collect();
+        let added = storage.sadd("set".into(), members(&["a", "b", "a"])).await;
+        assert_eq!(added.unwrap(), 2);
+        let removed = storage.srem("set".into(), members(&["a", "z"])).await;
+        assert_eq!(removed.unwrap(), 1);
+        storage
+            .sadd("emptied".into(), members(&["x"]))
+            .await
+            .unwrap();
+        storage
+            .srem("emptied".into(), members(&["x"]))
+            .await
+            .unwrap();
+        assert!(storage.get_entry("emptied").is_none());
+        storage.shutdown().await.unwrap();
+        drop(storage);
+
+        let storage = Storage::new(config).unwrap();
+        let set = storage.get_entry("set").unwrap().value;
+        assert_eq!(set, Value::Set(Set::HashTable(HashSet::from(["b".into()]))));
+        assert!(storage.get_entry("emptied").is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sorted_set_operations_replay_from_the_aof() {
+        let dir = tempfile::tempdir().unwrap().keep();
+        let config = Config {
+            dir: dir.clone(),
+            ..Config::default()
+        };
+        let storage = Storage::new(config.clone()).unwrap();
+        let scores = vec![("a".into(), 1.0), ("b".into(), 2.0), ("a".into(), 3.0)];
+        assert_eq!(storage.zadd("zset".into(), scores).await.unwrap(), 2);
+        let removed = storage
+            .zrem("zset".into(), vec!["b".into(), "z".into()])
+            .await;
+        assert_eq!(removed.unwrap(), 1);
+        storage
+            .zadd("emptied".into(), vec![("x".into(), 0.0)])
+            .await
+            .unwrap();
+        storage
+            .zrem("emptied".into(), vec!["x".into()])
+            .await
+            .unwrap();
+        assert!(storage.get_entry("emptied").is_none());
+        storage.shutdown().await.unwrap();
+        drop(storage);
+
+        let storage = Storage::new(config).unwrap();
+        let Value::SortedSet(zset) = storage.get_entry("zset").unwrap().value else {
+            panic!("expected a sorted set");
+        };
+        assert_eq!(zset.iter().collect::<Vec<_>>(), vec![("a", 3.0)]);
+        assert!(storage.get_entry("emptied").is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_keys_reload_into_their_database() {
+        let configs = [
+            (Persistence::Full, true),
+            (Persistence::Full, false),
+            (Persistence::OnDemand, false),
+        ];
+        for (persistence, aof_use_rdb_preamble) in configs {
+            let config = Config {
+                dir: tempfile::tempdir().unwrap().keep(),
+                persistence,
+                aof_use_rdb_preamble,
+                ..Config::default()
+            };
+            let storage = Storage::new(config.clone()).unwrap();
+            storage.set("zero".into(), "0".into(), None).await.unwrap();
+            storage.set("both".into(), "0".into(), None).await.unwrap();
+            let db1 = storage.select(1);
+            db1.set("one".into(), "1".into(), None).await.unwrap();
+            db1.set("both".into(), "1".into(), None).await.unwrap();
+            // The AOF holds a snapshot of both databases, then SELECT
+            // frames between the operations appended after it.
+            storage.compact_aof().await.unwrap();
+            storage.delete("zero".into()).await.unwrap();
+            db1.set("later".into(), "1".into(), None).await.unwrap();
+            storage.set("zero".into(), "0".into(), None).await.unwrap();
+            storage.save().await.unwrap();
+            storage.shutdown().await.unwrap();
+            drop(storage);
+
+            let restarted = Storage::new(config).unwrap();
+            let string =
+                |storage: &Storage, key: &str| storage.get_entry(key).map(|entry| entry.value);
+            let db1 = restarted.select(1);
+            assert_eq!(string(&restarted, "zero"), Some(Value::Int(0)));
+            assert_eq!(string(&restarted, "both"), Some(Value::Int(0)));
+            assert_eq!(string(&restarted, "one"), None);
+            assert_eq!(string(&restarted, "later"), None);
+            assert_eq!(string(&db1, "one"), Some(Value::Int(1)));
+            assert_eq!(string(&db1, "both"), Some(Value::Int(1)));
+            assert_eq!(string(&db1, "later"), Some(Value::Int(1)));
+            assert_eq!(string(&db1, "zero"), None);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_copy_is_journaled_into_the_destination_database() {
+        let config = Config {
+            dir: tempfile::tempdir().unwrap().keep(),
+            persistence: Persistence::Full,
+            ..Config::default()
+        };
+        let storage = Storage::new(config.clone()).unwrap();
+        let ttl = Some(Duration::from_secs(100));
+        storage.set("key".into(), "v".into(), ttl).await.unwrap();
+        let db1 = storage.select(1);
+        assert!(storage
+            .copy("key", &db1, "copy".into(), false)
+            .await
+            .unwrap());
+        assert!(!storage
+            .copy("key", &db1, "copy".into(), false)
+            .await
+            .unwrap());
+        assert!(!storage
+            .copy("missing", &db1, "x".into(), true)
+            .await
+            .unwrap());
+        let expires_at = storage.lookup("key", |entry| entry.expires_at).unwrap();
+        storage.shutdown().await.unwrap();
+        drop(db1);
+        drop(storage);
+
+        let restarted = Storage::new(config).unwrap();
+        let copy = restarted.select(1).get_entry("copy").unwrap();
+        assert_eq!(copy.value, Value::String("v".into()));
+        assert_eq!(copy.expires_at, expires_at);
+        assert!(restarted.get_entry("copy").is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_loading_into_a_missing_database_fails() {
+        let dir = tempfile::tempdir().unwrap().keep();
+        let storage = Storage::new(Config {
+            dir: dir.clone(),
+            ..Config::default()
+        })
+        .unwrap();
+        storage
+            .select(5)
+            .set("key".into(), "v".into(), None)
+            .await
+            .unwrap();
+        storage.shutdown().await.unwrap();
+        drop(storage);
+
+        let error = Storage::new(Config {
+            dir,
+            databases: 4,
+            ..Config::default()
+        })
+        .err()
+        .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_appendfsync_always_syncs_before_acknowledging() {
+        let dir = tempfile::tempdir().unwrap().keep();
+        let storage = Storage::new(Config {
+            dir: dir.clone(),
+            append_fsync: AppendFsync::Always,
+            ..Config::default()
+        })
+        .unwrap();
+        storage.set("key".into(), "v".into(), None).await.unwrap();
+
+        let aof = AofManager::new(dir.join("appendonly.aof"), AppendFsync::EverySec).unwrap();
+        assert_eq!(aof.load().unwrap().operations.len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_aof_is_preferred_over_a_stale_rdb() {
+        let dir = tempfile::tempdir().unwrap().keep();
+        let string = |value: &str| ValueEntry::new(Value::String(value.to_string().into()), None);
+        RdbManager::new(dir.join("dump.rdb"), Compression::None)
+            .save([(
+                0,
+                vec![
+                    ("key".to_string(), string("old")),
+                    ("deleted".to_string(), string("v")),
+                ],
+            )])
+            .await
+            .unwrap();
+        // As compacted after `deleted` was removed and `key` changed.
+        aof::write_snapshot(
+            &dir.join("appendonly.aof"),
+            &vec![(0, vec![("key".into(), string("new"))])],
+            None,
+        )
+        .unwrap();
+
+        let storage = Storage::new(Config {
+            dir,
+            ..Config::default()
+        })
+        .unwrap();
+        assert_eq!(
+            storage.read("key", Value::clone),
+            Some(Value::String("new".into()))
+        );
+        assert_eq!(storage.read("deleted", Value::clone), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_aof_compacted_with_rdb_preamble() {
+        let dir = tempfile::tempdir().unwrap().keep();
+        let config = Config {
+            dir: dir.clone(),
+            rdb_compression: Compression::Lz4,
+            ..Config::default()
+        };
+        let storage = Storage::new(config.clone()).unwrap();
+        for i in 0..1000 {
+            storage
+                .set(format!("key:{}", i), i.to_string().into(), None)
+                .await
+                .unwrap();
+        }
+        storage
+            .hset("hash".into(), vec![("f".into(), "v".into())])
+            .await
+            .unwrap();
+        let aof_manager = storage.aof_manager.clone().unwrap();
+        aof_manager
+            .compact(&storage.snapshot(), config.aof_preamble())
+            .await
+            .unwrap();
+        // Writes after the compaction are appended as operations.
+        storage
+            .set("key:0".into(), "changed".into(), None)
+            .await
+            .unwrap();
+        storage
+            .apply(Operation::Delete {
+                key: "key:1".into(),
+            })
+            .await
+            .unwrap();
+        storage.shutdown().await.unwrap();
+
+        let contents = aof_manager.load().unwrap();
+        assert!(matches!(&contents.preamble[..], [(0, entries)] if entries.len() == 1001));
+        assert_eq!(contents.operations.len(), 2);
+
+        fs::remove_file(dir.join("dump.rdb")).ok();
+        let storage = Storage::new(config).unwrap();
+        assert_eq!(storage.database().data.len(), 1000);
+        assert_eq!(
+            storage.read("key:0", Value::clone),
+            Some(Value::String("changed".into()))
+        );
+        assert_eq!(storage.read("key:1", Value::clone), None);
+        assert_eq!(storage.read("key:999", Value::clone), Some(Value::Int(999)));
+        assert!(storage.read("hash", Value::clone).is_some());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_missing_aof_is_created_from_the_rdb() {
+        let dir = tempfile::tempdir().unwrap().keep();
+        let storage = Storage::new(Config {
+            dir: dir.clone(),
+            persistence: Persistence::OnDemand,
+            ..Config::default()
+        })
+        .unwrap();
+        storage.set("key".into(), "v".into(), None).await.unwrap();
+        storage.save().await.unwrap();
+        drop(storage);
+
+        // Switching to full persistence loads the RDB file once, after which
+        // the AOF holds the dataset on its own.
+        let storage = Storage::new(Config {
+            dir: dir.clone(),
+            ..Config::default()
+        })
+        .unwrap();
+        assert!(storage.read("key", Value::clone).is_some());
+        storage.set("other".into(), "v".into(), None).await.unwrap();
+        storage.shutdown().await.unwrap();
+
+        fs::remove_file(dir.join("dump.rdb")).unwrap();
+        let storage = Storage::new(Config {
+            dir,
+            ..Config::default()
+        })
+        .unwrap();
+        assert!(storage.read("key", Value::clone).is_some());
+        assert!(storage.read("other", Value::clone).is_some());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_resync_replaces_the_aof() {
+        let dir = tempfile::tempdir().unwrap().keep();
+        let config = Config {
+            dir,
+            ..Config::default()
+        };
+        let storage = Storage::new(config.clone()).unwrap();
+        storage.set("key".into(), "old".into(), None).await.unwrap();
+        storage
+            .set("deleted".into(), "v".into(), None)
+            .await
+            .unwrap();
+        storage.shutdown().await.unwrap();
+
+        // The primary removed `deleted` while the replica was away.
+        let entry = ValueEntry::new(Value::String("new".into()), None);
+        storage
+            .load_resync(vec![(0, vec![("key".into(), entry)])])
+            .await
+            .unwrap();
+        drop(storage);
+
+        let storage = Storage::new(config).unwrap();
+        assert_eq!(
+            storage.read("key", Value::clone),
+            Some(Value::String("new".into()))
+        );
+        assert_eq!(storage.read("deleted", Value::clone), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_waitaof_syncs_the_aof() {
+        let dir = tempfile::tempdir().unwrap().keep();
+        let storage = Storage::new(Config {
+            dir: dir.clone(),
+            ..Config::default()
+        })
+        .unwrap();
+        storage.set("key".into(), "v".into(), None).await.unwrap();
+
+        let command = Command::from_resp(Resp::Array(vec![
+            Resp::BulkString("WAITAOF".into()),
+            Resp::BulkString("1".into()),
+            Resp::BulkString("0".into()),
+            Resp::BulkString("0".into()),
+        ]))
+        .unwrap();
+        let mut client = Client::new(storage.acl());
+        match command.execute(&storage, &mut client).await {
+            Resp::Array(acks) => {
+                assert!(matches!(acks[..], [Resp::Integer(1), Resp::Integer(0)]))
+            }
+            other => panic!("expected an array, got {:?}", other),
+        }
+
+        let aof = AofManager::new(dir.join("appendonly.aof"), AppendFsync::EverySec).unwrap();
+        let operations = aof.load().unwrap().operations;
+        assert!(
+            matches!(&operations[..], [(0, Operation::Set { key, .. })] if key == "key"),
+            "{:?}",
+            operations
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_waitaof_without_an_aof() {
+        let storage = Storage::new(Config {
+            dir: tempfile::tempdir().unwrap().keep(),
+            persistence: Persistence::OnDemand,
+            ..Config::default()
+        })
+        .unwrap();
+        let mut client = Client::new(storage.acl());
+        let waitaof = |numlocal: &str| {
+            Command::from_resp(Resp::Array(vec![
+                Resp::BulkString("WAITAOF".into()),
+                Resp::BulkString(numlocal.into()),
+                Resp::BulkString("0".into()),
+                Resp::BulkString("0".into()),
+            ]))
+            .unwrap()
+        };
+
+        assert!(matches!(
+            waitaof("1").execute(&storage, &mut client).await,
+            Resp::Error(e) if e == "ERR WAITAOF cannot be used when numlocal is set but appendonly is disabled."
+        ));
+        match waitaof("0").execute(&storage, &mut client).await {
+            Resp::Array(acks) => {
+                assert!(matches!(acks[..], [Resp::Integer(0), Resp::Integer(0)]))
+            }
+            other => panic!("expected an array, got {:?}", other),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_on_demand_persistence_only_saves_on_save() {
+        let dir = tempfile::tempdir().unwrap().keep();
+        let config = Config {
+            dir: dir.clone(),
+            persistence: Persistence::OnDemand,
+            ..Config::default()
+        };
+
+        let storage = Storage::new(config.clone()).unwrap();
+        storage.set("saved".into(), "v".into(), None).await.unwrap();
+        let command =
+            Command::from_resp(Resp::Array(vec![Resp::BulkString("SAVE".into())])).unwrap();
+        let mut client = Client::new(storage.acl());
+        assert!(matches!(
+            command.execute(&storage, &mut client).await,
+            Resp::SimpleString(ok) if ok == "OK"
+        ));
+        storage
+            .set("unsaved".into(), "v".into(), None)
+            .await
+            .unwrap();
+        storage.shutdown().await.unwrap();
+        assert!(!dir.join("appendonly.aof").exists());
+
+        let restarted = Storage::new(config).unwrap();
+        assert!(restarted.database().data.contains_key("saved"));
+        assert!(!restarted.database().data.contains_key("unsaved"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_large_dataset_round_trips_through_rdb() {
+        for rdb_compression in [Compression::None, Compression::Lz4, Compression::Zstd] {
+            let config = Config {
+                dir: tempfile::tempdir().unwrap().keep(),
+                persistence: Persistence::OnDemand,
+                rdb_compression,
+                ..Config::default()
+            };
+            let storage = Storage::new(config.clone()).unwrap();
+            for i in 0..50_000 {
+                storage
+                    .set(format!("key:{}", i), format!("value:{}", i).into(), None)
+                    .await
+                    .unwrap();
+            }
+            storage.save().await.unwrap();
+
+            let restarted = Storage::new(config).unwrap();
+            assert_eq!(restarted.database().data.len(), 50_000);
+            for i in [0, 12_345, 49_999] {
+                let entry = restarted.get_entry(&format!("key:{}", i)).unwrap();
+                assert_eq!(entry.value, Value::String(format!("value:{}", i).into()));
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_aof_write_failure_reaches_the_client() {
+        // On demand, so no periodic fsync of the real AOF clears the
+        // failure.
+        let mut storage = Storage::new(Config {
+            dir: tempfile::tempdir().unwrap().keep(),
+            persistence: Persistence::OnDemand,
+            ..Config::default()
+        })
+        .unwrap();
+        // Every write to /dev/full fails with ENOSPC once the AOF buffer is
+        // flushed, which happens at the latest after 1000 operations.
+        storage.aof_manager = Some(Arc::new(
+            AofManager::new("/dev/full".into(), AppendFsync::EverySec).unwrap(),
+        ));
+
+        let set = |key: String| {
+            Command::from_resp(Resp::Array(vec![
+                Resp::BulkString("SET".into()),
+                Resp::BulkString(key),
+                Resp::BulkString("value".into()),
+            ]))
+            .unwrap()
+        };
+        let mut client = Client::new(storage.acl());
+        let mut failure = None;
+        for i in 0..1000 {
+            let key = format!("key:{}", i);
+            if let Resp::Error(e) = set(key.clone()).execute(&storage, &mut client).await {
+                failure = Some((key, e));
+                break;
+            }
+        }
+        let (key, e) = failure.expect("no write failed");
+        assert!(
+            e.starts_with("ERR failed to set value (I/O error): "),
+            "{}",
+            e
+        );
+        assert!(e.contains("No space left on device"), "{}", e);
+        assert!(storage.get_entry(&key).is_none());
+
+        // Later writes are refused up front while reads keep working.
+        assert!(matches!(
+            set("key".into()).execute(&storage, &mut client).await,
+            Resp::Error(e) if e == "MISCONF Errors writing to the AOF file"
+        ));
+        assert!(storage.get_entry("key").is_none());
+        let get = Command::from_resp(Resp::Array(vec![
+            Resp::BulkString("GET".into()),
+            Resp::BulkString("key:0".into()),
+        ]))
+        .unwrap();
+        assert!(matches!(
+            get.execute(&storage, &mut client).await,
+            Resp::BulkString(value) if value == "value"
+        ));
     }
 }
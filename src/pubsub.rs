@@ -0,0 +1,348 @@
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::Mutex,
+};
+
+use crate::{
+    client::{Client, Mailbox},
+    glob,
+    resp::Resp,
+};
+
+/// Which keyspace notifications are published, parsed from the flags of
+/// `--notify-keyspace-events` as Redis spells them. Only expiration events
+/// exist so far, so the flags are `K` (publish to `__keyspace@<db>__:<key>`),
+/// `E` (publish to `__keyevent@<db>__:<event>`), `x` (expired events) and
+/// `A`, which stands for every event class.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyspaceEvents {
+    keyspace: bool,
+    keyevent: bool,
+    expired: bool,
+}
+
+impl FromStr for KeyspaceEvents {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut events = Self::default();
+        for flag in s.chars() {
+            match flag {
+                'K' => events.keyspace = true,
+                'E' => events.keyevent = true,
+                'x' | 'A' => events.expired = true,
+                _ => return Err(()),
+            }
+        }
+        Ok(events)
+    }
+}
+
+/// Subscribers of each channel or pattern, by client id.
+type Registry = Mutex<HashMap<String, HashMap<u64, Mailbox>>>;
+
+/// The channels and patterns clients are subscribed to, shared by every
+/// connection. Published messages are queued to each subscribed client's
+/// connection, which writes them out between commands.
+#[derive(Debug, Default)]
+pub struct PubSub {
+    channels: Registry,
+    patterns: Registry,
+    /// Subscribers of shard channels, which never see messages published
+    /// to regular channels or match patterns.
+    shard_channels: Registry,
+    /// Bytes of messages that may wait for a subscriber before it is
+    /// disconnected, or `None` for no limit.
+    output_buffer_limit: Option<usize>,
+    keyspace_events: KeyspaceEvents,
+}
+
+impl PubSub {
+    pub fn new(output_buffer_limit: Option<usize>, keyspace_events: KeyspaceEvents) -> Self {
+        Self {
+            output_buffer_limit,
+            keyspace_events,
+            ..Self::default()
+        }
+    }
+
+    /// Publishes the notifications of `key` in database `db` expiring, as
+    /// far as the keyspace events configured turn them on.
+    pub fn notify_expired(&self, db: usize, key: &str) {
+        let events = self.keyspace_events;
+        if !events.expired {
+            return;
+        }
+        if events.keyspace {
+            self.publish(&format!("__keyspace@{}__:{}", db, key), "expired");
+        }
+        if events.keyevent {
+            self.publish(&format!("__keyevent@{}__:expired", db), key);
+        }
+    }
+
+    /// Subscribes the client to a channel and returns the confirmation
+    /// reply, which carries its new subscription count.
+    pub fn subscribe(&self, client: &mut Client, channel: &str) -> Resp {
+        if client.channels.insert(channel.to_string()) {
+            add(&self.channels, channel, client);
+        }
+        confirmation(
+            "subscribe",
+            Some(channel),
+            client.subscription_count(),
+            client,
+        )
+    }
+
+    pub fn psubscribe(&self, client: &mut Client, pattern: &str) -> Resp {
+        if client.patterns.insert(pattern.to_string()) {
+            add(&self.patterns, pattern, client);
+        }
+        confirmation(
+            "psubscribe",
+            Some(pattern),
+            client.subscription_count(),
+            client,
+        )
+    }
+
+    /// Unsubscribes the client from the given channels, or from all of them
+    /// if none are given, returning one confirmation per channel.
+    pub fn unsubscribe(&self, client: &mut Client, channels: &[String]) -> Vec<Resp> {
+        let channels = targets(channels, &client.channels);
+        if channels.is_empty() {
+            return vec![confirmation("unsubscribe", None, 0, client)];
+        }
+        channels
+            .iter()
+            .map(|channel| {
+                if client.channels.remove(channel) {
+                    remove(&self.channels, channel, client.id);
+                }
+                confirmation(
+                    "unsubscribe",
+                    Some(channel),
+                    client.subscription_count(),
+                    client,
+                )
+            })
+            .collect()
+    }
+
+    pub fn punsubscribe(&self, client: &mut Client, patterns: &[String]) -> Vec<Resp> {
+        let patterns = targets(patterns, &client.patterns);
+        if patterns.is_empty() {
+            return vec![confirmation("punsubscribe", None, 0, client)];
+        }
+        patterns
+            .iter()
+            .map(|pattern| {
+                if client.patterns.remove(pattern) {
+                    remove(&self.patterns, pattern, client.id);
+                }
+                confirmation(
+                    "punsubscribe",
+                    Some(pattern),
+                    client.subscription_count(),
+                    client,
+                )
+            })
+            .collect()
+    }
+
+    /// Subscribes the client to a shard channel. The confirmation carries
+    /// its number of shard channel subscriptions only.
+    pub fn ssubscribe(&self, client: &mut Client, channel: &str) -> Resp {
+        if client.shard_channels.insert(channel.to_string()) {
+            add(&self.shard_channels, channel, client);
+        }
+        let count = client.shard_channels.len();
+        confirmation("ssubscribe", Some(channel), count, client)
+    }
+
+    pub fn sunsubscribe(&self, client: &mut Client, channels: &[String]) -> Vec<Resp> {
+        let channels = targets(channels, &client.shard_channels);
+        if channels.is_empty() {
+            return vec![confirmation("sunsubscribe", None, 0, client)];
+        }
+        channels
+            .iter()
+            .map(|channel| {
+                if client.shard_channels.remove(channel) {
+                    remove(&self.shard_channels, channel, client.id);
+                }
+                let count = client.shard_channels.len();
+                confirmation("sunsubscribe", Some(channel), count, client)
+            })
+            .collect()
+    }
+
+    /// Drops every subscription of a client that disconnects or resets.
+    pub fn unsubscribe_all(&self, client: &mut Client) {
+        for channel in client.channels.drain() {
+            remove(&self.channels, &channel, client.id);
+        }
+        for pattern in client.patterns.drain() {
+            remove(&self.patterns, &pattern, client.id);
+        }
+        for channel in client.shard_channels.drain() {
+            remove(&self.shard_channels, &channel, client.id);
+        }
+    }
+
+    /// Delivers a message to the subscribers of `channel` and of every
+    /// pattern matching it. Returns the number of deliveries, which leaves
+    /// out subscribers over the output buffer limit. Each subscriber
+    /// receives the messages of a channel in publish order.
+    pub fn publish(&self, channel: &str, message: &str) -> usize {
+        let mut delivered = 0;
+
+        if let Some(subscribers) = self.channels.lock().unwrap().get(channel) {
+            let reply = Encoded::new(vec![
+                Resp::BulkString("message".into()),
+                Resp::BulkString(channel.into()),
+                Resp::BulkString(message.into()),
+            ]);
+            delivered += self.deliver(subscribers, &reply);
+        }
+
+        for (pattern, subscribers) in self.patterns.lock().unwrap().iter() {
+            if !glob::matches(pattern, channel) {
+                continue;
+            }
+            let reply = Encoded::new(vec![
+                Resp::BulkString("pmessage".into()),
+                Resp::BulkString(pattern.clone()),
+                Resp::BulkString(channel.into()),
+                Resp::BulkString(message.into()),
+            ]);
+            delivered += self.deliver(subscribers, &reply);
+        }
+
+        delivered
+    }
+
+    /// Delivers a message to the subscribers of a shard channel, as an
+    /// `smessage`. Returns the number of deliveries.
+    pub fn spublish(&self, channel: &str, message: &str) -> usize {
+        let shard_channels = self.shard_channels.lock().unwrap();
+        let Some(subscribers) = shard_channels.get(channel) else {
+            return 0;
+        };
+        let reply = Encoded::new(vec![
+            Resp::BulkString("smessage".into()),
+            Resp::BulkString(channel.into()),
+            Resp::BulkString(message.into()),
+        ]);
+        self.deliver(subscribers, &reply)
+    }
+
+    fn deliver(&self, subscribers: &HashMap<u64, Mailbox>, reply: &Encoded) -> usize {
+        subscribers
+            .values()
+            .filter(|mailbox| mailbox.deliver(reply.to(mailbox), self.output_buffer_limit))
+            .count()
+    }
+
+    /// The channels with at least one subscriber, optionally filtered by a
+    /// glob pattern, for PUBSUB CHANNELS.
+    pub fn active_channels(&self, pattern: Option<&str>) -> Vec<String> {
+        let mut channels: Vec<String> = self
+            .channels
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|channel| pattern.is_none_or(|pattern| glob::matches(pattern, channel)))
+            .cloned()
+            .collect();
+        channels.sort();
+        channels
+    }
+
+    /// The number of subscribers of a channel, not counting patterns.
+    pub fn subscriber_count(&self, channel: &str) -> usize {
+        self.channels
+            .lock()
+            .unwrap()
+            .get(channel)
+            .map_or(0, HashMap::len)
+    }
+
+    /// The number of distinct patterns subscribed to, for PUBSUB NUMPAT.
+    pub fn pattern_count(&self) -> usize {
+        self.patterns.lock().unwrap().len()
+    }
+}
+
+/// A message encoded once for each protocol its subscribers may speak: an
+/// array for RESP2 and a push frame for RESP3.
+struct Encoded {
+    array: Vec<u8>,
+    push: Vec<u8>,
+}
+
+impl Encoded {
+    fn new(items: Vec<Resp>) -> Self {
+        Self {
+            array: Resp::Array(items.clone()).into_bytes(),
+            push: Resp::Push(items).into_bytes(),
+        }
+    }
+
+    fn to(&self, mailbox: &Mailbox) -> &[u8] {
+        if mailbox.resp3() {
+            &self.push
+        } else {
+            &self.array
+        }
+    }
+}
+
+fn add(registry: &Registry, name: &str, client: &Client) {
+    registry
+        .lock()
+        .unwrap()
+        .entry(name.to_string())
+        .or_default()
+        .insert(client.id, client.mailbox.clone());
+}
+
+fn remove(registry: &Registry, name: &str, id: u64) {
+    let mut registry = registry.lock().unwrap();
+    if let Some(subscribers) = registry.get_mut(name) {
+        subscribers.remove(&id);
+        if subscribers.is_empty() {
+            registry.remove(name);
+        }
+    }
+}
+
+/// The names an UNSUBSCRIBE-style command applies to: the given ones, or
+/// every current subscription if none are given.
+fn targets(names: &[String], subscribed: &HashSet<String>) -> Vec<String> {
+    if names.is_empty() {
+        let mut names: Vec<String> = subscribed.iter().cloned().collect();
+        names.sort();
+        names
+    } else {
+        names.to_vec()
+    }
+}
+
+/// Confirms a (un)subscription with the client's remaining `count` of
+/// subscriptions of its kind, as a push frame under RESP3 like the messages
+/// that follow it.
+fn confirmation(kind: &str, name: Option<&str>, count: usize, client: &Client) -> Resp {
+    let items = vec![
+        Resp::BulkString(kind.into()),
+        name.map_or(Resp::Null, |name| Resp::BulkString(name.into())),
+        Resp::Integer(count as i64),
+    ];
+    if client.protocol() == 3 {
+        Resp::Push(items)
+    } else {
+        Resp::Array(items)
+    }
+}
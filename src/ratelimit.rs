@@ -0,0 +1,36 @@
+use std::time::Instant;
+
+/// Token bucket limiting how many commands a connection runs per second.
+/// It holds up to a second's worth of tokens, so a client that has been
+/// quiet may burst up to the limit at once.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(per_second: u32) -> Self {
+        Self {
+            rate: per_second as f64,
+            tokens: per_second as f64,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// Takes a token if one is available.
+    pub fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.refilled_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
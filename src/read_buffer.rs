@@ -0,0 +1,184 @@
+use std::io;
+
+use bytes::{Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::resp::BIG_ARG;
+
+/// Bytes asked of the first read, and of every read once the connection is
+/// caught up, as Redis sizes its query buffer.
+const MIN_READ: usize = 16 * 1024;
+/// Bytes asked of a single read at most.
+const MAX_READ: usize = 1024 * 1024;
+/// Capacity past which a caught-up connection gives its buffer back rather
+/// than keep it while idle.
+const MAX_IDLE_CAPACITY: usize = 4 * MIN_READ;
+
+/// The bytes a connection received but hasn't parsed into requests yet.
+///
+/// Each read that fills the space it was given doubles the next one, up to
+/// `MAX_READ`, so a large value arrives in few reads rather than many small
+/// ones. Once every request received has been taken, reads shrink back and
+/// a buffer grown by a large request is released.
+///
+/// Requests are split off the front as [`Bytes`] sharing the buffer's
+/// memory, so their arguments are sliced out of it rather than copied. A
+/// read reuses that memory once every request split off it has been
+/// dropped, and otherwise moves the bytes left to a new allocation, leaving
+/// the old one to the requests still holding it. Small values are copied
+/// when stored, so only a large one can outlive its request, and a large
+/// request is only split off an allocation it makes up at least half of:
+/// it is copied out otherwise, so it never keeps more than twice its size
+/// alive.
+#[derive(Debug)]
+pub struct ReadBuffer {
+    bytes: BytesMut,
+    /// The size of the allocation `bytes` lies in.
+    allocated: usize,
+    read_size: usize,
+}
+
+impl ReadBuffer {
+    pub fn new() -> Self {
+        Self {
+            bytes: BytesMut::new(),
+            allocated: 0,
+            read_size: MIN_READ,
+        }
+    }
+
+    /// Reads once from `stream`, appending to the buffer. Returns the number
+    /// of bytes read, 0 at the end of the stream. Cancelling it loses
+    /// nothing.
+    pub async fn read_from<S: AsyncRead + Unpin>(&mut self, stream: &mut S) -> io::Result<usize> {
+        if self.bytes.capacity() - self.bytes.len() < self.read_size
+            && !self.bytes.try_reclaim(self.read_size)
+        {
+            // Twice what's left, so a large request arriving over many
+            // reads isn't copied over on every one of them.
+            self.allocated = 2 * self.bytes.len() + self.read_size;
+            let mut bytes = BytesMut::with_capacity(self.allocated);
+            bytes.extend_from_slice(&self.bytes);
+            self.bytes = bytes;
+        }
+        let n = stream.read_buf(&mut self.bytes).await?;
+        if n >= self.read_size {
+            self.read_size = (self.read_size * 2).min(MAX_READ);
+        }
+        Ok(n)
+    }
+
+    /// The bytes received and not taken yet.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Takes the first `len` bytes off the buffer, once they have been
+    /// parsed into a request.
+    pub fn take(&mut self, len: usize) -> Bytes {
+        let frame = self.bytes.split_to(len);
+        let frame = if len >= BIG_ARG && len * 2 < self.allocated {
+            Bytes::copy_from_slice(&frame)
+        } else {
+            frame.freeze()
+        };
+        if self.bytes.is_empty() {
+            self.read_size = MIN_READ;
+            if self.allocated > MAX_IDLE_CAPACITY {
+                self.bytes = BytesMut::new();
+                self.allocated = 0;
+            }
+        }
+        frame
+    }
+}
+
+#[cfg(test)]
+impl From<&[u8]> for ReadBuffer {
+    /// A buffer holding `bytes`, as if they had been read in one go.
+    fn from(bytes: &[u8]) -> Self {
+        let bytes = BytesMut::from(bytes);
+        Self {
+            allocated: bytes.capacity(),
+            bytes,
+            read_size: MIN_READ,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_grows_for_large_values_and_shrinks_after() {
+        let value = vec![b'x'; 8 * 1024 * 1024];
+        let mut stream = &value[..];
+        let mut buffer = ReadBuffer::new();
+
+        let mut reads = 0;
+        while buffer.bytes().len() < value.len() {
+            buffer.read_from(&mut stream).await.unwrap();
+            reads += 1;
+        }
+        assert_eq!(buffer.bytes(), &value[..]);
+        // 1024-byte reads would take 8192.
+        assert!(reads < 32, "{} reads", reads);
+        assert_eq!(buffer.read_size, MAX_READ);
+
+        // The value makes up most of the buffer, so it isn't copied.
+        let start = buffer.bytes().as_ptr();
+        let frame = buffer.take(value.len() - 1);
+        assert_eq!(frame.as_ptr(), start);
+        assert_eq!(buffer.bytes(), b"x");
+        buffer.take(1);
+        assert_eq!(buffer.allocated, 0);
+        assert_eq!(buffer.read_size, MIN_READ);
+    }
+
+    #[tokio::test]
+    async fn test_memory_is_reused_once_requests_are_dropped() {
+        let mut buffer = ReadBuffer::new();
+        buffer.read_from(&mut &b"onetwothr"[..]).await.unwrap();
+        let start = buffer.bytes().as_ptr();
+        let one = buffer.take(3);
+        assert_eq!(one, &b"one"[..]);
+        assert_eq!(one.as_ptr(), start);
+        let two = buffer.take(3);
+        assert_eq!(buffer.bytes(), b"thr");
+
+        // The requests still hold the memory, so the rest moves out.
+        buffer.read_from(&mut &b"ee"[..]).await.unwrap();
+        assert_eq!(buffer.bytes(), b"three");
+        let moved = buffer.bytes().as_ptr();
+        assert!(moved < start || moved >= start.wrapping_add(MIN_READ));
+        assert_eq!((&one[..], &two[..]), (&b"one"[..], &b"two"[..]));
+
+        // Once the last one is dropped, its memory is read into again.
+        let allocated = buffer.allocated;
+        drop(buffer.take(5));
+        buffer.read_from(&mut &b"four"[..]).await.unwrap();
+        assert_eq!(buffer.bytes(), b"four");
+        let offset = buffer.bytes().as_ptr() as usize - moved as usize;
+        assert!(offset < allocated, "{}", offset);
+    }
+
+    #[tokio::test]
+    async fn test_large_requests_only_share_an_allocation_they_fill() {
+        let mut input = vec![b'x'; BIG_ARG];
+        input.extend(vec![b'y'; 4 * BIG_ARG]);
+        let mut stream = &input[..];
+        let mut buffer = ReadBuffer::new();
+        while buffer.bytes().len() < input.len() {
+            buffer.read_from(&mut stream).await.unwrap();
+        }
+
+        // The first request would keep alive an allocation several times its
+        // size, so it is copied out; the second fills most of it.
+        let start = buffer.bytes().as_ptr();
+        let first = buffer.take(BIG_ARG);
+        assert_ne!(first.as_ptr(), start);
+        let second = buffer.take(4 * BIG_ARG);
+        assert_eq!(second.as_ptr(), start.wrapping_add(BIG_ARG));
+    }
+}
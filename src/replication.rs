@@ -0,0 +1,503 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use bincode::deserialize;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    sync::{
+        broadcast::{self, error::RecvError},
+        Notify,
+    },
+    task::JoinHandle,
+};
+use tracing::{error, info};
+
+use crate::{
+    persistence::{
+        aof::{encode_operation, SelectedDb},
+        Operation, Snapshot, ValueEntry,
+    },
+    resp::Resp,
+    Storage,
+};
+
+const FEED_CAPACITY: usize = 64 * 1024;
+/// Length in hex digits of run and replication IDs, as in Redis.
+const ID_LEN: usize = 40;
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// The longest reply to SYNC a replica reads before giving up on it.
+const MAX_REPLY_LINE: usize = 1024;
+/// The largest operation frame a replica accepts, as the largest bulk
+/// string a client may send.
+const MAX_FRAME_LEN: usize = 512 * 1024 * 1024;
+
+/// Replication state shared by every connection: the feed of applied write
+/// operations that connected replicas stream from, the offsets they have
+/// acknowledged, and, when this instance is itself a replica, the link to
+/// its primary.
+pub struct Replication {
+    /// The ID of the replication history this instance serves, reported as
+    /// `master_replid`.
+    replid: Mutex<String>,
+    /// Each operation with its offset and the database it applies to.
+    feed: broadcast::Sender<(u64, usize, Operation)>,
+    offset: AtomicU64,
+    replicas: Mutex<HashMap<u64, u64>>,
+    next_replica_id: AtomicU64,
+    acked: Notify,
+    primary: Mutex<Option<PrimaryLink>>,
+}
+
+struct PrimaryLink {
+    host: String,
+    port: u16,
+    task: JoinHandle<()>,
+}
+
+impl Replication {
+    pub fn new() -> Self {
+        let (feed, _) = broadcast::channel(FEED_CAPACITY);
+        Self {
+            replid: Mutex::new(random_id()),
+            feed,
+            offset: AtomicU64::new(0),
+            replicas: Mutex::new(HashMap::new()),
+            next_replica_id: AtomicU64::new(0),
+            acked: Notify::new(),
+            primary: Mutex::new(None),
+        }
+    }
+
+    /// Forwards an operation that has already been applied locally to
+    /// database `db` to every connected replica, advancing the replication
+    /// offset.
+    pub fn propagate(&self, db: usize, op: &Operation) {
+        let offset = self.offset.fetch_add(1, Ordering::SeqCst) + 1;
+        // Sending only fails when no replica is connected.
+        let _ = self.feed.send((offset, db, op.clone()));
+    }
+
+    pub fn replid(&self) -> String {
+        self.replid.lock().unwrap().clone()
+    }
+
+    /// Starts a new replication history, as DEBUG CHANGE-REPL-ID does.
+    pub fn change_replid(&self) {
+        *self.replid.lock().unwrap() = random_id();
+    }
+
+    /// The number of write operations propagated so far.
+    pub fn offset(&self) -> u64 {
+        self.offset.load(Ordering::SeqCst)
+    }
+
+    pub fn is_replica(&self) -> bool {
+        self.primary.lock().unwrap().is_some()
+    }
+
+    /// Starts following `host:port`, replacing any existing primary link.
+    pub fn follow(&self, storage: Storage, host: String, port: u16) {
+        let mut primary = self.primary.lock().unwrap();
+        if let Some(link) = primary.take() {
+            link.task.abort();
+        }
+
+        info!("Replicating from primary {}:{}", host, port);
+        let task = tokio::spawn({
+            let host = host.clone();
+            async move {
+                loop {
+                    match sync_with_primary(&storage, &host, port).await {
+                        Ok(()) => info!("Primary {}:{} closed the connection", host, port),
+                        Err(e) => error!("Replication from {}:{} failed: {}", host, port, e),
+                    }
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            }
+        });
+
+        *primary = Some(PrimaryLink { host, port, task });
+    }
+
+    /// Stops following the current primary, turning this instance back into
+    /// a primary that keeps its current dataset.
+    pub fn promote(&self) {
+        if let Some(link) = self.primary.lock().unwrap().take() {
+            info!("Stopped replicating from {}:{}", link.host, link.port);
+            link.task.abort();
+        }
+    }
+
+    /// Blocks until at least `numreplicas` replicas have acknowledged every
+    /// write propagated so far, or until `timeout` elapses (`None` waits
+    /// forever). Returns the number of replicas that acknowledged.
+    pub async fn wait(&self, numreplicas: usize, timeout: Option<Duration>) -> usize {
+        let target = self.offset.load(Ordering::SeqCst);
+
+        let caught_up = async {
+            loop {
+                let notified = self.acked.notified();
+                let count = self.acked_replicas(target);
+                if count >= numreplicas || self.replicas.lock().unwrap().is_empty() {
+                    return;
+                }
+                notified.await;
+            }
+        };
+
+        if let Some(timeout) = timeout {
+            let _ = tokio::time::timeout(timeout, caught_up).await;
+        } else {
+            caught_up.await;
+        }
+
+        self.acked_replicas(target)
+    }
+
+    fn acked_replicas(&self, offset: u64) -> usize {
+        self.replicas
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|acked| **acked >= offset)
+            .count()
+    }
+
+    fn register_replica(self: &Arc<Self>) -> ReplicaGuard {
+        let id = self.next_replica_id.fetch_add(1, Ordering::SeqCst);
+        self.replicas.lock().unwrap().insert(id, 0);
+        ReplicaGuard {
+            replication: Arc::clone(self),
+            id,
+        }
+    }
+
+    fn ack(&self, id: u64, offset: u64) {
+        if let Some(acked) = self.replicas.lock().unwrap().get_mut(&id) {
+            *acked = offset;
+        }
+        self.acked.notify_waiters();
+    }
+}
+
+/// Removes a replica from the acknowledgment registry once its stream ends.
+struct ReplicaGuard {
+    replication: Arc<Replication>,
+    id: u64,
+}
+
+impl Drop for ReplicaGuard {
+    fn drop(&mut self) {
+        self.replication.replicas.lock().unwrap().remove(&self.id);
+        self.replication.acked.notify_waiters();
+    }
+}
+
+/// A random ID of 40 hex digits, for run and replication IDs.
+pub fn random_id() -> String {
+    let bytes: Vec<u8> = (0..ID_LEN / 2).map(|_| fastrand::u8(..)).collect();
+    hex::encode(bytes)
+}
+
+/// Serves a replica that issued SYNC: replies `+FULLRESYNC <frames>` and
+/// sends the full dataset as that many SET operations, then streams every
+/// subsequent write operation. SELECT frames go in wherever the database
+/// changes; they aren't counted as frames.
+///
+/// The replica acknowledges by sending back the number of frames it has
+/// applied so far as a little-endian `u64`; those counts are mapped back to
+/// replication offsets for WAIT.
+pub async fn serve_replica<S>(stream: S, storage: &Storage) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let replication = storage.replication();
+
+    // Subscribe before taking the snapshot so no write can fall between the
+    // two. Operations replayed on top of a newer snapshot converge to the
+    // same state since each one overwrites or removes a whole key.
+    let mut feed = replication.feed.subscribe();
+    let base_offset = replication.offset.load(Ordering::SeqCst);
+
+    let guard = replication.register_replica();
+    let (mut reader, mut writer) = tokio::io::split(stream);
+
+    // Pairs of (frames sent, offset reached once those frames are applied).
+    let pending = Arc::new(Mutex::new(VecDeque::new()));
+
+    let acks = tokio::spawn({
+        let replication = Arc::clone(replication);
+        let pending = Arc::clone(&pending);
+        let id = guard.id;
+        async move {
+            let mut ack_bytes = [0u8; 8];
+            while reader.read_exact(&mut ack_bytes).await.is_ok() {
+                let applied = u64::from_le_bytes(ack_bytes);
+                let mut pending = pending.lock().unwrap();
+                let mut offset = None;
+                while let Some(&(frames, reached)) = pending.front() {
+                    if frames > applied {
+                        break;
+                    }
+                    offset = Some(reached);
+                    pending.pop_front();
+                }
+                drop(pending);
+
+                if let Some(offset) = offset {
+                    replication.ack(id, offset);
+                }
+            }
+        }
+    });
+
+    let result = async {
+        let snapshot = storage.snapshot();
+        let entries: usize = snapshot.iter().map(|(_, entries)| entries.len()).sum();
+        let reply = Resp::SimpleString(format!("FULLRESYNC {}", entries));
+        writer.write_all(&reply.into_bytes()).await?;
+        let mut selected = SelectedDb::start();
+        let mut frames = write_snapshot(&mut writer, snapshot, &mut selected).await?;
+        if frames == 0 {
+            replication.ack(guard.id, base_offset);
+        } else {
+            pending.lock().unwrap().push_back((frames, base_offset));
+        }
+
+        while let Some((offset, db, op)) = next_operation(&mut feed, base_offset).await? {
+            write_operation(&mut writer, db, &op, &mut selected).await?;
+            frames += 1;
+            pending.lock().unwrap().push_back((frames, offset));
+        }
+        Ok(())
+    }
+    .await;
+
+    acks.abort();
+    drop(guard);
+    result
+}
+
+/// Serves a CHANGEFEED consumer: the full dataset as a series of SET
+/// operations when `snapshot` is set, then every subsequent write operation,
+/// framed as in the AOF, SELECT frames included. Unlike a replica, a consumer sends nothing back and
+/// doesn't count towards WAIT. Returns once the consumer disconnects.
+pub async fn serve_changefeed<S>(stream: S, storage: &Storage, snapshot: bool) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let replication = storage.replication();
+    let mut feed = replication.feed.subscribe();
+    let base_offset = replication.offset.load(Ordering::SeqCst);
+    let (mut reader, mut writer) = tokio::io::split(stream);
+
+    let mut selected = SelectedDb::start();
+    if snapshot {
+        write_snapshot(&mut writer, storage.snapshot(), &mut selected).await?;
+    }
+
+    let mut buffer = [0u8; 64];
+    loop {
+        tokio::select! {
+            // Anything the consumer sends is ignored; reading only notices
+            // when it goes away.
+            read = reader.read(&mut buffer) => {
+                if read? == 0 {
+                    return Ok(());
+                }
+            }
+            op = next_operation(&mut feed, base_offset) => match op? {
+                Some((_, db, op)) => write_operation(&mut writer, db, &op, &mut selected).await?,
+                None => return Ok(()),
+            },
+        }
+    }
+}
+
+/// Writes a snapshot of the dataset as a series of SET operations. Returns
+/// the number of SET frames written.
+async fn write_snapshot<W>(
+    writer: &mut W,
+    snapshot: Snapshot,
+    selected: &mut SelectedDb,
+) -> io::Result<u64>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut frames = 0;
+    for (db, entries) in snapshot {
+        for (key, entry) in entries {
+            let op = Operation::Set {
+                key,
+                value: entry.value,
+                expires_at: entry.expires_at,
+            };
+            write_operation(writer, db, &op, selected).await?;
+            frames += 1;
+        }
+    }
+    Ok(frames)
+}
+
+/// Writes an operation on database `db`, preceded by a SELECT frame if the
+/// stream is in another database.
+async fn write_operation<W>(
+    writer: &mut W,
+    db: usize,
+    op: &Operation,
+    selected: &mut SelectedDb,
+) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    if let Some(select) = selected.switch_to(db)? {
+        writer.write_all(&select).await?;
+    }
+    writer.write_all(&encode_operation(op)?).await
+}
+
+/// The next operation from the feed that isn't already reflected in a
+/// snapshot taken at `base_offset`, with its offset, or `None` once the feed
+/// closes. Fails if the subscriber fell so far behind that operations were
+/// dropped.
+async fn next_operation(
+    feed: &mut broadcast::Receiver<(u64, usize, Operation)>,
+    base_offset: u64,
+) -> io::Result<Option<(u64, usize, Operation)>> {
+    loop {
+        match feed.recv().await {
+            Ok((offset, _, _)) if offset <= base_offset => continue,
+            Ok(entry) => return Ok(Some(entry)),
+            Err(RecvError::Lagged(skipped)) => {
+                return Err(io::Error::other(format!(
+                    "fell behind by {} operations",
+                    skipped
+                )))
+            }
+            Err(RecvError::Closed) => return Ok(None),
+        }
+    }
+}
+
+/// Follows the primary at `host:port` until the connection ends: the
+/// dataset is replaced once the whole snapshot has arrived, then every
+/// operation streamed after it is applied as it comes, to the database the
+/// last SELECT frame switched to.
+async fn sync_with_primary(storage: &Storage, host: &str, port: u16) -> io::Result<()> {
+    let mut stream = TcpStream::connect((host, port)).await?;
+    let sync = Resp::Array(vec![Resp::BulkString("SYNC".into())]);
+    stream.write_all(&sync.into_bytes()).await?;
+
+    // An error reply, such as NOAUTH or NOPERM, leaves the dataset as is.
+    let reply = read_reply_line(&mut stream).await?;
+    let frames = reply
+        .strip_prefix("+FULLRESYNC ")
+        .and_then(|frames| frames.parse::<u64>().ok())
+        .ok_or_else(|| io::Error::other(format!("primary refused SYNC: {}", reply)))?;
+    info!("Full resync with primary {}:{} started", host, port);
+
+    let mut snapshot: Snapshot = Vec::new();
+    let mut db = 0;
+    let mut received = 0;
+    while received < frames {
+        let Some(op) = read_operation(&mut stream).await? else {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        };
+        let (key, value, expires_at) = match op {
+            Operation::Select { db: selected } => {
+                db = checked_db(storage, selected)?;
+                continue;
+            }
+            Operation::Set {
+                key,
+                value,
+                expires_at,
+            } => (key, value, expires_at),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "snapshot holds an operation other than SET",
+                ))
+            }
+        };
+        match snapshot.last_mut() {
+            Some((last, entries)) if *last == db => {
+                entries.push((key, ValueEntry::new(value, expires_at)))
+            }
+            _ => snapshot.push((db, vec![(key, ValueEntry::new(value, expires_at))])),
+        }
+        received += 1;
+    }
+    storage.load_resync(snapshot).await?;
+
+    let mut applied = frames;
+    stream.write_all(&applied.to_le_bytes()).await?;
+    while let Some(op) = read_operation(&mut stream).await? {
+        if let Operation::Select { db: selected } = op {
+            db = checked_db(storage, selected)?;
+            continue;
+        }
+        storage.select(db).apply(op).await?;
+        applied += 1;
+        stream.write_all(&applied.to_le_bytes()).await?;
+    }
+    Ok(())
+}
+
+/// The database a SELECT frame from the primary switches to, which this
+/// instance must have too.
+fn checked_db(storage: &Storage, db: usize) -> io::Result<usize> {
+    if db >= storage.database_count() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("primary selected database {}, which doesn't exist here", db),
+        ));
+    }
+    Ok(db)
+}
+
+/// Reads the primary's reply to SYNC, up to its CRLF.
+async fn read_reply_line(stream: &mut TcpStream) -> io::Result<String> {
+    let mut line = Vec::new();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= MAX_REPLY_LINE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "reply to SYNC is too long",
+            ));
+        }
+        line.push(stream.read_u8().await?);
+    }
+    line.truncate(line.len() - 2);
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+/// Reads the next operation frame, or `None` once the primary closes the
+/// connection between frames.
+async fn read_operation(stream: &mut TcpStream) -> io::Result<Option<Operation>> {
+    let mut len_bytes = [0u8; 4];
+    match stream.read_exact(&mut len_bytes).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let len = u32::from_le_bytes(len_bytes) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("operation frame of {} bytes is too large", len),
+        ));
+    }
+    let mut buf = vec![0u8; len];
+    stream.read_exact(&mut buf).await?;
+    deserialize(&buf).map(Some).map_err(io::Error::other)
+}
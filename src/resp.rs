@@ -1,71 +1,367 @@
-use crate::{Error, Result};
+use std::ops::Range;
+
+use bytes::Bytes;
+
+use crate::{read_buffer::ReadBuffer, Error, Result};
+
+/// Most elements a request may have, as in Redis.
+const MAX_MULTIBULK_LEN: usize = 1024 * 1024;
+/// Longest argument a request may carry, Redis' default
+/// `proto-max-bulk-len`.
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+/// Most elements allocated for ahead of a request's arrival.
+const MAX_PREALLOCATED: usize = 1024;
+/// Longest length line accepted before its CRLF arrives.
+const MAX_LINE_LEN: usize = 64 * 1024;
+/// Length from which an argument is large enough to be kept as it arrived
+/// rather than copied, Redis' `PROTO_MBULK_BIG_ARG`.
+pub const BIG_ARG: usize = 32 * 1024;
 
 #[derive(Debug, Clone)]
 pub enum Resp {
     SimpleString(String),
     Error(String),
+    Integer(i64),
     BulkString(String),
+    /// A bulk string held as raw bytes: the arguments of a request, sliced
+    /// out of the bytes it arrived in, and replies that aren't valid UTF-8,
+    /// as string values may be. Replies that are UTF-8 are always
+    /// [`Resp::BulkString`].
+    BulkBytes(Bytes),
     Array(Vec<Resp>),
     Null,
+    /// An out-of-band RESP3 message, such as a published message, that
+    /// clients tell apart from command replies by its `>` prefix.
+    Push(Vec<Resp>),
+    /// Several replies sent back to back, for commands such as SUBSCRIBE
+    /// that answer once per argument.
+    Sequence(Vec<Resp>),
 }
 
 impl Resp {
+    /// A bulk string holding `bytes`, whichever variant they fit.
+    pub fn bulk(bytes: Vec<u8>) -> Resp {
+        match String::from_utf8(bytes) {
+            Ok(s) => Resp::BulkString(s),
+            Err(e) => Resp::BulkBytes(e.into_bytes().into()),
+        }
+    }
+
+    /// The payload of a bulk string of either variant.
+    pub fn into_bulk(self) -> Option<Bytes> {
+        match self {
+            Resp::BulkString(s) => Some(s.into()),
+            Resp::BulkBytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// The payload of a bulk string of either variant, if it is UTF-8.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Resp::BulkString(s) => Some(s),
+            Resp::BulkBytes(bytes) => std::str::from_utf8(bytes).ok(),
+            _ => None,
+        }
+    }
+
+    /// Like [`Resp::as_str`], but owned: a [`Resp::BulkString`] is moved
+    /// out, while raw bytes are copied.
+    pub fn into_string(self) -> Option<String> {
+        match self {
+            Resp::BulkString(s) => Some(s),
+            other => other.as_str().map(str::to_owned),
+        }
+    }
+
     pub fn into_bytes(self) -> Vec<u8> {
         match self {
             Resp::SimpleString(s) => format!("+{}\r\n", s).into_bytes(),
             Resp::Error(s) => format!("-{}\r\n", s).into_bytes(),
+            Resp::Integer(i) => format!(":{}\r\n", i).into_bytes(),
             Resp::BulkString(s) => format!("${}\r\n{}\r\n", s.len(), s).into_bytes(),
-            Resp::Array(arr) => {
-                let mut bytes = format!("*{}\r\n", arr.len()).into_bytes();
-                for item in arr {
-                    bytes.extend(item.into_bytes());
-                }
-                bytes
+            Resp::BulkBytes(bytes) => {
+                let mut encoded = format!("${}\r\n", bytes.len()).into_bytes();
+                encoded.extend_from_slice(&bytes);
+                encoded.extend(b"\r\n");
+                encoded
             }
+            Resp::Array(arr) => aggregate('*', arr),
+            Resp::Push(items) => aggregate('>', items),
             Resp::Null => "$-1\r\n".as_bytes().to_vec(),
+            Resp::Sequence(replies) => replies.into_iter().flat_map(Resp::into_bytes).collect(),
         }
     }
+}
+
+/// Parses requests out of a connection's read buffer. Where the elements of
+/// a request that hasn't fully arrived lie is kept, so each read only parses
+/// the bytes it added rather than the whole request again. Once it has all
+/// arrived, the request is taken off the buffer and its elements are sliced
+/// out of it, so no argument is copied on its own.
+#[derive(Debug, Default)]
+pub struct RequestParser {
+    partial: Option<PartialRequest>,
+}
+
+/// A multibulk whose header and first elements have been parsed.
+#[derive(Debug)]
+struct PartialRequest {
+    count: usize,
+    /// Where the payload of each parsed element lies.
+    elements: Vec<Range<usize>>,
+    /// Bytes the header and parsed elements take up.
+    len: usize,
+}
+
+impl RequestParser {
+    /// Parses the request at the start of `buffer` and takes it off, or
+    /// returns `None` if `buffer` doesn't hold all of it yet. Until a request
+    /// is returned, every call must be given the same bytes as the last, with
+    /// more appended. Errors mean the bytes can't be framed as a request at
+    /// all.
+    pub fn parse(&mut self, buffer: &mut ReadBuffer) -> Result<Option<Resp>> {
+        let input = buffer.bytes();
+        let mut request = match self.partial.take() {
+            Some(request) => request,
+            None => match parse_header(input)? {
+                Some(request) => request,
+                None => return Ok(None),
+            },
+        };
 
-    pub fn parse(input: &[u8]) -> Result<Option<Resp>> {
-        if input.is_empty() {
-            return Ok(None);
+        while request.elements.len() < request.count {
+            let Some((element, end)) = parse_bulk(input, request.len)? else {
+                self.partial = Some(request);
+                return Ok(None);
+            };
+            request.elements.push(element);
+            request.len = end;
         }
 
-        match input[0] as char {
-            '*' => {
-                let input_str = std::str::from_utf8(input)
-                    .map_err(|_| Error::Protocol("Invalid UTF-8".into()))?;
-                let lines: Vec<&str> = input_str.split("\r\n").collect();
-
-                let count = lines[0][1..]
-                    .parse::<usize>()
-                    .map_err(|_| Error::Protocol("Invalid array length".into()))?;
-
-                let mut array = Vec::with_capacity(count);
-                let mut current_line = 1;
-
-                for _ in 0..count {
-                    if current_line >= lines.len() {
-                        return Err(Error::Protocol("Incomplete array".into()));
-                    }
-
-                    match lines[current_line].chars().next() {
-                        Some('$') => {
-                            current_line += 1;
-                            if current_line >= lines.len() {
-                                return Err(Error::Protocol("Incomplete bulk string".into()));
-                            }
-
-                            array.push(Resp::BulkString(lines[current_line].to_string()));
-                            current_line += 1;
-                        }
-                        _ => return Err(Error::Protocol("Invalid array element".into())),
-                    }
-                }
-
-                Ok(Some(Resp::Array(array)))
-            }
-            _ => Err(Error::Protocol("Unsupported RESP type".into())),
+        let frame = buffer.take(request.len);
+        let elements = request
+            .elements
+            .into_iter()
+            .map(|payload| Resp::BulkBytes(frame.slice(payload)))
+            .collect();
+        Ok(Some(Resp::Array(elements)))
+    }
+}
+
+/// Parses the multibulk header at the start of `input`.
+fn parse_header(input: &[u8]) -> Result<Option<PartialRequest>> {
+    let Some(&first) = input.first() else {
+        return Ok(None);
+    };
+    // Inline commands aren't supported, so every request must be a
+    // multibulk.
+    if first != b'*' {
+        return Err(Error::Protocol(format!(
+            "expected '*', got '{}'",
+            first as char
+        )));
+    }
+
+    let Some((header, len)) = read_line(input, 0, "mbulk count")? else {
+        return Ok(None);
+    };
+    let count = header[1..]
+        .parse::<usize>()
+        .ok()
+        .filter(|&count| count <= MAX_MULTIBULK_LEN)
+        .ok_or_else(|| Error::Protocol("invalid multibulk length".into()))?;
+
+    Ok(Some(PartialRequest {
+        count,
+        // The count is only a claim until the elements arrive.
+        elements: Vec::with_capacity(count.min(MAX_PREALLOCATED)),
+        len,
+    }))
+}
+
+/// Parses the bulk string starting at `pos`, returning where its payload lies
+/// with the position just past it.
+fn parse_bulk(input: &[u8], pos: usize) -> Result<Option<(Range<usize>, usize)>> {
+    let Some(&marker) = input.get(pos) else {
+        return Ok(None);
+    };
+    if marker != b'$' {
+        return Err(Error::Protocol(format!(
+            "expected '$', got '{}'",
+            marker as char
+        )));
+    }
+
+    let Some((header, start)) = read_line(input, pos, "bulk count")? else {
+        return Ok(None);
+    };
+    let len = header[1..]
+        .parse::<usize>()
+        .ok()
+        .filter(|&len| len <= MAX_BULK_LEN)
+        .ok_or_else(|| Error::Protocol("invalid bulk length".into()))?;
+
+    // The payload is exactly `len` bytes and may itself contain CRLF, so it
+    // is sliced rather than split on.
+    let end = start + len;
+    if input.len() < end + 2 {
+        return Ok(None);
+    }
+    if &input[end..end + 2] != b"\r\n" {
+        return Err(Error::Protocol("invalid bulk length".into()));
+    }
+    Ok(Some((start..end, end + 2)))
+}
+
+/// Encodes an array-like type: its prefix and length, then each item.
+fn aggregate(prefix: char, items: Vec<Resp>) -> Vec<u8> {
+    let mut bytes = format!("{}{}\r\n", prefix, items.len()).into_bytes();
+    for item in items {
+        bytes.extend(item.into_bytes());
+    }
+    bytes
+}
+
+/// Reads the CRLF-terminated line starting at `pos`, returning it without the
+/// terminator along with the position just past it, or `None` if the line
+/// isn't complete yet. A line that grows past [`MAX_LINE_LEN`] without ending
+/// is an error, named after `what` it holds.
+fn read_line<'a>(input: &'a [u8], pos: usize, what: &str) -> Result<Option<(&'a str, usize)>> {
+    let Some(len) = input[pos..].windows(2).position(|window| window == b"\r\n") else {
+        if input.len() - pos > MAX_LINE_LEN {
+            return Err(Error::Protocol(format!("too big {} string", what)));
+        }
+        return Ok(None);
+    };
+    // A line that isn't UTF-8 can't hold a valid length either.
+    let line = std::str::from_utf8(&input[pos..pos + len]).unwrap_or_default();
+    Ok(Some((line, pos + len + 2)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(input: &str) -> String {
+        RequestParser::default()
+            .parse(&mut ReadBuffer::from(input.as_bytes()))
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn test_parse_waits_for_complete_request() {
+        let request = b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n";
+        for len in 0..request.len() {
+            let mut buffer = ReadBuffer::from(&request[..len]);
+            let parsed = RequestParser::default().parse(&mut buffer).unwrap();
+            assert!(parsed.is_none(), "{}", len);
+            assert_eq!(buffer.bytes().len(), len);
+        }
+
+        let mut parser = RequestParser::default();
+        let mut pipelined = request.to_vec();
+        pipelined.extend_from_slice(b"*1\r\n$4\r\nPING\r\n");
+        let mut buffer = ReadBuffer::from(&pipelined[..]);
+        let resp = parser.parse(&mut buffer).unwrap().unwrap();
+        assert!(matches!(resp, Resp::Array(items) if items.len() == 2));
+        assert_eq!(buffer.bytes(), b"*1\r\n$4\r\nPING\r\n");
+        let resp = parser.parse(&mut buffer).unwrap().unwrap();
+        assert!(matches!(resp, Resp::Array(items) if items.len() == 1));
+        assert!(buffer.bytes().is_empty());
+    }
+
+    #[test]
+    fn test_parse_resumes_after_parsed_elements() {
+        let request = b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n";
+        let mut parser = RequestParser::default();
+        for len in 0..request.len() {
+            let mut buffer = ReadBuffer::from(&request[..len]);
+            assert!(parser.parse(&mut buffer).unwrap().is_none(), "{}", len);
         }
+        // The elements already parsed aren't framed again.
+        let mut altered = request.to_vec();
+        altered[5] = b'X';
+        let mut buffer = ReadBuffer::from(&altered[..]);
+        let resp = parser.parse(&mut buffer).unwrap().unwrap();
+        assert!(buffer.bytes().is_empty());
+        assert!(matches!(&resp, Resp::Array(items)
+            if matches!(&items[..], [cmd, _] if cmd.as_str() == Some("GET"))));
+
+        // A huge count isn't allocated for up front.
+        let mut parser = RequestParser::default();
+        let mut buffer = ReadBuffer::from(&b"*1048576\r\n"[..]);
+        assert!(parser.parse(&mut buffer).unwrap().is_none());
+        let partial = parser.partial.as_ref().unwrap();
+        assert_eq!(partial.elements.capacity(), MAX_PREALLOCATED);
+    }
+
+    #[test]
+    fn test_bulk_strings_are_binary_safe() {
+        let request = b"*2\r\n$3\r\nGET\r\n$3\r\n\xff\r\n\r\n";
+        let mut buffer = ReadBuffer::from(&request[..]);
+        let resp = RequestParser::default()
+            .parse(&mut buffer)
+            .unwrap()
+            .unwrap();
+        let Resp::Array(items) = resp else {
+            panic!("not an array");
+        };
+        assert!(matches!(&items[1], Resp::BulkBytes(bytes) if bytes[..] == b"\xff\r\n"[..]));
+        assert_eq!(items[1].as_str(), None);
+
+        assert!(matches!(Resp::bulk(b"key".to_vec()), Resp::BulkString(s) if s == "key"));
+        assert_eq!(
+            Resp::bulk(vec![0xff, 0]).into_bytes(),
+            b"$2\r\n\xff\x00\r\n"
+        );
+    }
+
+    #[test]
+    fn test_arguments_are_sliced_out_of_the_request() {
+        let request = b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n";
+        let mut buffer = ReadBuffer::from(&request[..]);
+        let resp = RequestParser::default()
+            .parse(&mut buffer)
+            .unwrap()
+            .unwrap();
+        let Resp::Array(items) = resp else {
+            panic!("not an array");
+        };
+        let [Resp::BulkBytes(name), Resp::BulkBytes(key), Resp::BulkBytes(value)] = &items[..]
+        else {
+            panic!("not raw arguments");
+        };
+        assert_eq!(
+            (&name[..], &key[..], &value[..]),
+            (&b"SET"[..], &b"key"[..], &b"value"[..])
+        );
+        // `\r\n$5\r\n` lies between the key and the value.
+        assert_eq!(value.as_ptr(), key.as_ptr().wrapping_add(key.len() + 6));
+    }
+
+    #[test]
+    fn test_protocol_errors_match_redis() {
+        assert_eq!(
+            parse_error("*x\r\n$4\r\nPING\r\n"),
+            "ERR Protocol error: invalid multibulk length"
+        );
+        assert_eq!(
+            parse_error("*1\r\n:4\r\n"),
+            "ERR Protocol error: expected '$', got ':'"
+        );
+        assert_eq!(
+            parse_error("*1\r\n$x\r\nPING\r\n"),
+            "ERR Protocol error: invalid bulk length"
+        );
+        assert_eq!(
+            parse_error("+PING\r\n"),
+            "ERR Protocol error: expected '*', got '+'"
+        );
+        assert_eq!(
+            parse_error("*99999999\r\n"),
+            "ERR Protocol error: invalid multibulk length"
+        );
     }
 }
@@ -0,0 +1,191 @@
+use crc::{Crc, CRC_64_REDIS};
+
+use crate::{glob, Error, Result};
+
+const CRC64: Crc<u64> = Crc::<u64>::new(&CRC_64_REDIS);
+
+/// The arguments of a SCAN-family command after its key, if any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanOptions {
+    pub cursor: u64,
+    pub pattern: Option<String>,
+    pub count: usize,
+    /// Only keys holding this type are returned. SCAN only.
+    pub type_name: Option<String>,
+}
+
+impl ScanOptions {
+    /// Parses `<cursor> [MATCH pattern] [COUNT count] [TYPE type]`, the
+    /// options in any order, for the command `name`. `TYPE` is accepted only
+    /// when `with_type` is set, since only SCAN walks values of several types.
+    pub fn parse(
+        mut args: impl Iterator<Item = String>,
+        name: &str,
+        with_type: bool,
+    ) -> Result<Self> {
+        let cursor = args
+            .next()
+            .ok_or_else(|| Error::Command(format!("Wrong number of {} arguments", name)))?
+            .parse::<u64>()
+            .map_err(|_| Error::Command("invalid cursor".into()))?;
+
+        let mut options = Self {
+            cursor,
+            pattern: None,
+            count: 10,
+            type_name: None,
+        };
+        while let Some(option) = args.next() {
+            match option.to_uppercase().as_str() {
+                "MATCH" => {
+                    options.pattern = Some(
+                        args.next()
+                            .ok_or_else(|| Error::Command("MATCH requires a pattern".into()))?,
+                    );
+                }
+                "COUNT" => {
+                    options.count = args
+                        .next()
+                        .and_then(|count| count.parse::<usize>().ok())
+                        .filter(|count| *count > 0)
+                        .ok_or_else(|| Error::Command("Invalid COUNT value".into()))?;
+                }
+                "TYPE" if with_type => {
+                    options.type_name = Some(
+                        args.next()
+                            .ok_or_else(|| Error::Command("TYPE requires a type name".into()))?,
+                    );
+                }
+                _ => {
+                    return Err(Error::Command(format!(
+                        "Invalid {} option: {}",
+                        name, option
+                    )))
+                }
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Position of an element in the scan order.
+///
+/// The maps holding keys and hash fields have no stable iteration order, so
+/// SCAN-family cursors walk elements ordered by a checksum of their name
+/// instead: a cursor is the position to resume from. Every element present
+/// for a whole iteration is returned at least once no matter how the
+/// collection is modified in between.
+pub fn position(name: &str) -> u64 {
+    CRC64.checksum(name.as_bytes())
+}
+
+/// Selects the page of a SCAN-family iteration starting at `cursor`.
+///
+/// `elements` are the candidates at or past the cursor (callers may pass
+/// everything; earlier elements are dropped). Returns the cursor for the next
+/// call, 0 once the iteration is complete, and the page's elements whose name
+/// matches `pattern`. A page holds `count` elements before filtering, plus
+/// any that share the last one's position so that none are skipped.
+pub fn page<T>(
+    elements: impl IntoIterator<Item = T>,
+    cursor: u64,
+    count: usize,
+    pattern: Option<&str>,
+    name: impl Fn(&T) -> &str,
+) -> (u64, Vec<T>) {
+    let mut candidates: Vec<(u64, T)> = elements
+        .into_iter()
+        .map(|element| (position(name(&element)), element))
+        .filter(|(position, _)| *position >= cursor)
+        .collect();
+    candidates.sort_unstable_by_key(|(position, _)| *position);
+
+    let mut end = count.min(candidates.len());
+    while end > 0 && end < candidates.len() && candidates[end].0 == candidates[end - 1].0 {
+        end += 1;
+    }
+    let next = if end == candidates.len() {
+        0
+    } else {
+        candidates[end - 1].0 + 1
+    };
+
+    candidates.truncate(end);
+    let page = candidates
+        .into_iter()
+        .map(|(_, element)| element)
+        .filter(|element| pattern.is_none_or(|pattern| glob::matches(pattern, name(element))))
+        .collect();
+    (next, page)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str], with_type: bool) -> Result<ScanOptions> {
+        ScanOptions::parse(args.iter().map(|arg| arg.to_string()), "SCAN", with_type)
+    }
+
+    #[test]
+    fn test_options_in_any_order() {
+        let expected = ScanOptions {
+            cursor: 42,
+            pattern: Some("user:*".into()),
+            count: 100,
+            type_name: Some("hash".into()),
+        };
+        for args in [
+            ["42", "MATCH", "user:*", "COUNT", "100", "TYPE", "hash"],
+            ["42", "type", "hash", "count", "100", "match", "user:*"],
+            ["42", "COUNT", "100", "TYPE", "hash", "MATCH", "user:*"],
+        ] {
+            assert_eq!(parse(&args, true).unwrap(), expected);
+        }
+
+        let defaults = parse(&["0"], true).unwrap();
+        assert_eq!((defaults.pattern, defaults.count), (None, 10));
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        assert!(parse(&["0", "COUNT", "ten"], true).is_err());
+        assert!(parse(&["0", "COUNT", "0"], true).is_err());
+        assert!(parse(&["0", "MATCH"], true).is_err());
+        assert!(parse(&["zero"], true).is_err());
+        assert!(parse(&[], true).is_err());
+        // Only SCAN filters by type.
+        assert!(parse(&["0", "TYPE", "hash"], false).is_err());
+    }
+
+    #[test]
+    fn test_page_visits_every_element_once() {
+        let names: Vec<String> = (0..100).map(|i| format!("field:{}", i)).collect();
+
+        let mut seen = Vec::new();
+        let mut cursor = 0;
+        loop {
+            let (next, page) = page(names.iter(), cursor, 7, None, |name| name.as_str());
+            assert!(page.len() >= 7 || next == 0);
+            seen.extend(page);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+
+        seen.sort();
+        let mut expected: Vec<&String> = names.iter().collect();
+        expected.sort();
+        assert_eq!(seen, expected);
+    }
+
+    #[test]
+    fn test_page_filters_by_pattern() {
+        let names = ["user:1", "user:2", "session:1"];
+        let (next, page) = page(names, 0, 10, Some("user:*"), |name| name);
+        assert_eq!(next, 0);
+        assert_eq!(page.len(), 2);
+    }
+}
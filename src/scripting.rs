@@ -0,0 +1,193 @@
+use std::cell::RefCell;
+
+use dashmap::DashMap;
+use mlua::{Lua, LuaOptions, StdLib, Value, Variadic};
+use sha1::{Digest, Sha1};
+use tokio::runtime::Handle;
+
+use crate::{client::Client, commands::Command, resp::Resp, Storage};
+
+/// Scripts loaded with SCRIPT LOAD or run through EVAL, keyed by the
+/// lowercase hex SHA1 of their body.
+pub struct ScriptCache {
+    scripts: DashMap<String, String>,
+}
+
+impl ScriptCache {
+    pub fn new() -> Self {
+        Self {
+            scripts: DashMap::new(),
+        }
+    }
+
+    /// Caches the script and returns its SHA1 digest.
+    pub fn load(&self, script: &str) -> String {
+        let sha = hex::encode(Sha1::digest(script.as_bytes()));
+        self.scripts.insert(sha.clone(), script.to_string());
+        sha
+    }
+
+    pub fn get(&self, sha: &str) -> Option<String> {
+        self.scripts
+            .get(&sha.to_lowercase())
+            .map(|script| script.clone())
+    }
+
+    pub fn contains(&self, sha: &str) -> bool {
+        self.scripts.contains_key(&sha.to_lowercase())
+    }
+
+    pub fn flush(&self) {
+        self.scripts.clear();
+    }
+}
+
+/// Runs a Lua script with `KEYS` and `ARGV` populated and a `redis` table
+/// whose `call`/`pcall` functions dispatch back into the command layer on
+/// the same connection.
+///
+/// The interpreter runs synchronously on the current worker thread (through
+/// `block_in_place`), so this must be called from a multi-threaded runtime.
+/// Atomicity is provided by the caller holding the script lock.
+pub fn eval(
+    storage: &Storage,
+    client: &mut Client,
+    script: &str,
+    keys: &[String],
+    args: &[String],
+) -> Resp {
+    // As in Redis 7, a SELECT in the script doesn't outlast it.
+    let db = client.db;
+    let reply = tokio::task::block_in_place(|| match run(storage, client, script, keys, args) {
+        Ok(resp) => resp,
+        Err(e) => Resp::Error(format!("ERR Error running script: {}", e)),
+    });
+    client.db = db;
+    reply
+}
+
+fn run(
+    storage: &Storage,
+    client: &mut Client,
+    script: &str,
+    keys: &[String],
+    args: &[String],
+) -> mlua::Result<Resp> {
+    let lua = Lua::new_with(
+        StdLib::TABLE | StdLib::STRING | StdLib::MATH,
+        LuaOptions::default(),
+    )?;
+
+    let globals = lua.globals();
+    globals.set("KEYS", lua.create_sequence_from(keys.iter().cloned())?)?;
+    globals.set("ARGV", lua.create_sequence_from(args.iter().cloned())?)?;
+
+    let client = RefCell::new(client);
+    let handle = Handle::current();
+
+    let dispatch = |args: Variadic<mlua::String>| -> Resp {
+        let items = args
+            .into_iter()
+            .map(|arg| Resp::bulk(arg.as_bytes().to_vec()))
+            .collect();
+        match Command::from_resp(Resp::Array(items)) {
+            Ok(
+                Command::Eval { .. }
+                | Command::EvalSha { .. }
+                | Command::Sync
+                | Command::ChangeFeed { .. }
+                | Command::Reset
+                | Command::Quit
+                | Command::ClientTracking(_)
+                | Command::Subscribe(_)
+                | Command::Unsubscribe(_)
+                | Command::PSubscribe(_)
+                | Command::PUnsubscribe(_)
+                | Command::SSubscribe(_)
+                | Command::SUnsubscribe(_),
+            ) => Resp::Error("ERR This command is not allowed from script".into()),
+            Ok(cmd) => handle.block_on(cmd.run(storage, &mut client.borrow_mut())),
+            Err(e) => Resp::Error(format!("ERR {}", e)),
+        }
+    };
+
+    lua.scope(|scope| {
+        let redis = lua.create_table()?;
+        redis.set(
+            "call",
+            scope.create_function(|lua, args: Variadic<mlua::String>| match dispatch(args) {
+                Resp::Error(e) => Err(mlua::Error::RuntimeError(e)),
+                resp => resp_to_lua(lua, resp),
+            })?,
+        )?;
+        redis.set(
+            "pcall",
+            scope.create_function(|lua, args: Variadic<mlua::String>| {
+                resp_to_lua(lua, dispatch(args))
+            })?,
+        )?;
+        lua.globals().set("redis", redis)?;
+
+        let result = lua.load(script).set_name("script").eval::<Value>()?;
+        Ok(lua_to_resp(result))
+    })
+}
+
+/// Converts a command reply into a Lua value following the Redis
+/// conventions: status and error replies become `{ok = ...}`/`{err = ...}`
+/// tables and nil becomes `false`.
+fn resp_to_lua(lua: &Lua, resp: Resp) -> mlua::Result<Value<'_>> {
+    Ok(match resp {
+        Resp::SimpleString(s) => {
+            let table = lua.create_table()?;
+            table.set("ok", s)?;
+            Value::Table(table)
+        }
+        Resp::Error(e) => {
+            let table = lua.create_table()?;
+            table.set("err", e)?;
+            Value::Table(table)
+        }
+        Resp::Integer(i) => Value::Integer(i),
+        Resp::BulkString(s) => Value::String(lua.create_string(&s)?),
+        Resp::BulkBytes(bytes) => Value::String(lua.create_string(&bytes)?),
+        Resp::Array(items) | Resp::Push(items) | Resp::Sequence(items) => {
+            let table = lua.create_table()?;
+            for item in items {
+                table.push(resp_to_lua(lua, item)?)?;
+            }
+            Value::Table(table)
+        }
+        Resp::Null => Value::Boolean(false),
+    })
+}
+
+/// Converts a script's return value into a reply following the Redis
+/// conventions: numbers are truncated to integers, `true` becomes 1,
+/// `false`/nil become null, and tables are read as arrays up to the first
+/// nil unless they carry an `ok` or `err` field.
+fn lua_to_resp(value: Value) -> Resp {
+    match value {
+        Value::Nil | Value::Boolean(false) => Resp::Null,
+        Value::Boolean(true) => Resp::Integer(1),
+        Value::Integer(i) => Resp::Integer(i),
+        Value::Number(n) => Resp::Integer(n as i64),
+        Value::String(s) => Resp::bulk(s.as_bytes().to_vec()),
+        Value::Table(table) => {
+            if let Ok(Some(ok)) = table.get::<_, Option<String>>("ok") {
+                return Resp::SimpleString(ok);
+            }
+            if let Ok(Some(err)) = table.get::<_, Option<String>>("err") {
+                return Resp::Error(err);
+            }
+            Resp::Array(
+                table
+                    .sequence_values::<Value>()
+                    .map_while(|value| value.ok())
+                    .map(lua_to_resp)
+                    .collect(),
+            )
+        }
+        _ => Resp::Null,
+    }
+}
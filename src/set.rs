@@ -0,0 +1,275 @@
+use std::{borrow::Cow, collections::HashSet, mem};
+
+use serde::{Deserialize, Serialize};
+
+/// A set of strings, kept the way Redis keeps one: as a sorted array of
+/// integers while every member is the decimal form of an `i64` and there are
+/// few enough of them, and as a hash table otherwise. A set only ever moves
+/// to the hash table, even if the members that forced it are removed later.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Set {
+    IntSet(Vec<i64>),
+    HashTable(HashSet<String>),
+}
+
+impl Default for Set {
+    fn default() -> Self {
+        Set::IntSet(Vec::new())
+    }
+}
+
+impl Set {
+    pub fn len(&self) -> usize {
+        match self {
+            Set::IntSet(ints) => ints.len(),
+            Set::HashTable(members) => members.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, member: &str) -> bool {
+        match self {
+            Set::IntSet(ints) => integer(member).is_some_and(|i| ints.binary_search(&i).is_ok()),
+            Set::HashTable(members) => members.contains(member),
+        }
+    }
+
+    /// The members, in ascending order for an intset and in no particular
+    /// order otherwise.
+    pub fn iter(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        let (ints, members) = match self {
+            Set::IntSet(ints) => (Some(ints), None),
+            Set::HashTable(members) => (None, Some(members)),
+        };
+        let ints = ints
+            .into_iter()
+            .flatten()
+            .map(|i| Cow::Owned(i.to_string()));
+        let members = members
+            .into_iter()
+            .flatten()
+            .map(|member| Cow::Borrowed(member.as_str()));
+        ints.chain(members)
+    }
+
+    /// Adds `member`, first moving the set to a hash table if it isn't an
+    /// integer or the intset would grow past `max_intset_entries`. Returns
+    /// whether it wasn't in the set already.
+    pub fn insert(&mut self, member: String, max_intset_entries: usize) -> bool {
+        if let Set::IntSet(ints) = self {
+            match integer(&member).map(|i| (i, ints.binary_search(&i))) {
+                Some((_, Ok(_))) => return false,
+                Some((i, Err(index))) if ints.len() < max_intset_entries => {
+                    ints.insert(index, i);
+                    return true;
+                }
+                _ => self.upgrade(),
+            }
+        }
+        match self {
+            Set::HashTable(members) => members.insert(member),
+            Set::IntSet(_) => unreachable!("upgraded above"),
+        }
+    }
+
+    /// Removes `member`, returning whether it was in the set.
+    pub fn remove(&mut self, member: &str) -> bool {
+        match self {
+            Set::IntSet(ints) => match integer(member).map(|i| ints.binary_search(&i)) {
+                Some(Ok(index)) => {
+                    ints.remove(index);
+                    true
+                }
+                _ => false,
+            },
+            Set::HashTable(members) => members.remove(member),
+        }
+    }
+
+    /// The encoding OBJECT ENCODING reports: `intset` or `hashtable`.
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Set::IntSet(_) => "intset",
+            Set::HashTable(_) => "hashtable",
+        }
+    }
+
+    pub fn heap_size(&self) -> usize {
+        match self {
+            Set::IntSet(ints) => ints.capacity() * mem::size_of::<i64>(),
+            Set::HashTable(members) => {
+                members.capacity() * mem::size_of::<String>()
+                    + members.iter().map(String::capacity).sum::<usize>()
+            }
+        }
+    }
+
+    /// Like [`Set::heap_size`], but measuring only `samples` members of a
+    /// hash table and scaling their average to the whole set. 0 measures
+    /// every member; an intset's size is exact either way.
+    pub fn sampled_heap_size(&self, samples: usize) -> usize {
+        match self {
+            Set::HashTable(members) if samples > 0 && members.len() > samples => {
+                let sampled: usize = members.iter().take(samples).map(String::capacity).sum();
+                members.capacity() * mem::size_of::<String>() + sampled * members.len() / samples
+            }
+            _ => self.heap_size(),
+        }
+    }
+
+    fn upgrade(&mut self) {
+        if let Set::IntSet(ints) = self {
+            *self = Set::HashTable(ints.iter().map(i64::to_string).collect());
+        }
+    }
+}
+
+/// The operations SINTER, SUNION and SDIFF apply to their sets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Combine {
+    Inter,
+    Union,
+    Diff,
+}
+
+/// Combines `sets` as `combine` says, a missing set counting as empty: the
+/// members common to all of them, the members of any of them, or the
+/// members of the first that are in none of the others. The result is
+/// built like any set, so it is an intset if it qualifies.
+pub fn combine(combine: Combine, sets: &[Option<Set>], max_intset_entries: usize) -> Set {
+    let empty = Set::default();
+    let sets: Vec<&Set> = sets
+        .iter()
+        .map(|set| set.as_ref().unwrap_or(&empty))
+        .collect();
+    let mut result = Set::default();
+    let Some((first, others)) = sets.split_first() else {
+        return result;
+    };
+    let members: Box<dyn Iterator<Item = Cow<'_, str>>> = match combine {
+        Combine::Inter => {
+            // Probes every set for the members of the smallest one.
+            let smallest = sets.iter().min_by_key(|set| set.len()).unwrap_or(first);
+            Box::new(
+                smallest
+                    .iter()
+                    .filter(|member| sets.iter().all(|set| set.contains(member))),
+            )
+        }
+        Combine::Union => Box::new(sets.iter().flat_map(|set| set.iter())),
+        Combine::Diff => Box::new(
+            first
+                .iter()
+                .filter(|member| !others.iter().any(|set| set.contains(member))),
+        ),
+    };
+    for member in members {
+        result.insert(member.into_owned(), max_intset_entries);
+    }
+    result
+}
+
+/// `member` as an integer, if it is exactly the decimal form of one, so it
+/// reads back the same from an intset.
+fn integer(member: &str) -> Option<i64> {
+    member
+        .parse::<i64>()
+        .ok()
+        .filter(|i| i.to_string() == member)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn members(set: &Set) -> Vec<String> {
+        let mut members: Vec<String> = set.iter().map(Cow::into_owned).collect();
+        members.sort();
+        members
+    }
+
+    #[test]
+    fn test_intset_keeps_integers_sorted() {
+        let mut set = Set::default();
+        for member in ["3", "1", "2", "1"] {
+            set.insert(member.into(), 512);
+        }
+        assert_eq!(set, Set::IntSet(vec![1, 2, 3]));
+        assert!(set.contains("2"));
+        assert!(!set.contains("02"));
+        assert!(!set.contains("x"));
+
+        assert!(set.remove("2"));
+        assert!(!set.remove("2"));
+        assert!(!set.remove("+1"));
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec!["1", "3"]);
+    }
+
+    #[test]
+    fn test_upgrade_is_one_way() {
+        let mut set = Set::default();
+        set.insert("1".into(), 512);
+        assert!(set.insert("a".into(), 512));
+        assert_eq!(set.encoding(), "hashtable");
+        assert_eq!(members(&set), vec!["1", "a"]);
+
+        set.remove("a");
+        set.insert("2".into(), 512);
+        assert_eq!(set.encoding(), "hashtable");
+        assert_eq!(members(&set), vec!["1", "2"]);
+
+        // Non-canonical integers would not read back the same.
+        let mut set = Set::default();
+        set.insert("007".into(), 512);
+        assert_eq!(set.encoding(), "hashtable");
+    }
+
+    #[test]
+    fn test_combine() {
+        let set = |members: &[&str]| {
+            let mut set = Set::default();
+            for member in members {
+                set.insert(member.to_string(), 512);
+            }
+            Some(set)
+        };
+        let sets = [
+            set(&["1", "2", "3", "a"]),
+            set(&["2", "3", "4"]),
+            set(&["3", "a"]),
+        ];
+
+        let inter = combine(Combine::Inter, &sets, 512);
+        assert_eq!(inter, Set::IntSet(vec![3]));
+        assert_eq!(
+            members(&combine(Combine::Union, &sets, 512)),
+            vec!["1", "2", "3", "4", "a"]
+        );
+        assert_eq!(members(&combine(Combine::Diff, &sets, 512)), vec!["1"]);
+
+        // A missing set is empty.
+        let sets = [set(&["1", "2"]), None];
+        assert!(combine(Combine::Inter, &sets, 512).is_empty());
+        assert_eq!(
+            members(&combine(Combine::Union, &sets, 512)),
+            vec!["1", "2"]
+        );
+        assert_eq!(members(&combine(Combine::Diff, &sets, 512)), vec!["1", "2"]);
+        assert!(combine(Combine::Diff, &[None, set(&["1"])], 512).is_empty());
+    }
+
+    #[test]
+    fn test_upgrade_past_max_intset_entries() {
+        let mut set = Set::default();
+        set.insert("1".into(), 2);
+        set.insert("2".into(), 2);
+        assert!(!set.insert("2".into(), 2));
+        assert_eq!(set.encoding(), "intset");
+        set.insert("3".into(), 2);
+        assert_eq!(set.encoding(), "hashtable");
+        assert_eq!(members(&set), vec!["1", "2", "3"]);
+    }
+}
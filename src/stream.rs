@@ -0,0 +1,161 @@
+use std::{collections::BTreeMap, fmt, mem, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// The ID of a stream entry: the millisecond it was added at, plus a
+/// sequence number telling apart entries added within the same millisecond.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    /// The ID XADD generates for an entry added at `now_ms` after this one:
+    /// the current time if the clock has moved past it, or the next sequence
+    /// number otherwise, so IDs keep increasing even if the clock goes back.
+    /// Returns `None` once no greater ID exists.
+    pub fn successor(self, now_ms: u64) -> Option<StreamId> {
+        if now_ms > self.ms {
+            Some(StreamId { ms: now_ms, seq: 0 })
+        } else if self.seq < u64::MAX {
+            Some(StreamId {
+                ms: self.ms,
+                seq: self.seq + 1,
+            })
+        } else if self.ms < u64::MAX {
+            Some(StreamId {
+                ms: self.ms + 1,
+                seq: 0,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Parses `<ms>-<seq>`, or a bare `<ms>` with the sequence number
+    /// defaulting to `seq`, as range bounds do.
+    pub fn parse_with_seq(s: &str, seq: u64) -> Option<StreamId> {
+        match s.split_once('-') {
+            Some((ms, seq)) => Some(StreamId {
+                ms: ms.parse().ok()?,
+                seq: seq.parse().ok()?,
+            }),
+            None => Some(StreamId {
+                ms: s.parse().ok()?,
+                seq,
+            }),
+        }
+    }
+}
+
+impl FromStr for StreamId {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_with_seq(s, 0).ok_or(())
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// An append-only log of field/value entries ordered by ID.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Vec<(String, String)>>,
+    /// The greatest ID ever added, which new entries must exceed.
+    last_id: StreamId,
+}
+
+impl Stream {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// Appends an entry. Callers make sure `id` is greater than
+    /// [`Stream::last_id`].
+    pub fn insert(&mut self, id: StreamId, fields: Vec<(String, String)>) {
+        self.last_id = self.last_id.max(id);
+        self.entries.insert(id, fields);
+    }
+
+    /// The entries with IDs between `start` and `end` inclusive, in order.
+    pub fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+    ) -> impl Iterator<Item = (&StreamId, &Vec<(String, String)>)> {
+        let entries = (start <= end).then(|| self.entries.range(start..=end));
+        entries.into_iter().flatten()
+    }
+
+    pub fn heap_size(&self) -> usize {
+        self.entries.values().map(entry_size).sum()
+    }
+
+    /// Like [`Stream::heap_size`], but measuring only the first `samples`
+    /// entries and scaling their average to the whole stream. 0 measures
+    /// every entry.
+    pub fn sampled_heap_size(&self, samples: usize) -> usize {
+        if samples == 0 || self.entries.len() <= samples {
+            return self.heap_size();
+        }
+        let sampled: usize = self.entries.values().take(samples).map(entry_size).sum();
+        sampled * self.entries.len() / samples
+    }
+}
+
+/// Bytes taken by one entry: its slot in the tree and its fields.
+fn entry_size(fields: &Vec<(String, String)>) -> usize {
+    mem::size_of::<(StreamId, Vec<(String, String)>)>()
+        + fields.capacity() * mem::size_of::<(String, String)>()
+        + fields
+            .iter()
+            .map(|(field, value)| field.capacity() + value.capacity())
+            .sum::<usize>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_successor() {
+        let id = StreamId { ms: 5, seq: 3 };
+        assert_eq!(id.successor(10), Some(StreamId { ms: 10, seq: 0 }));
+        assert_eq!(id.successor(5), Some(StreamId { ms: 5, seq: 4 }));
+        assert_eq!(id.successor(1), Some(StreamId { ms: 5, seq: 4 }));
+        assert_eq!(StreamId::MAX.successor(0), None);
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        assert_eq!("7-2".parse(), Ok(StreamId { ms: 7, seq: 2 }));
+        assert_eq!("7".parse(), Ok(StreamId { ms: 7, seq: 0 }));
+        assert_eq!("7-x".parse::<StreamId>(), Err(()));
+        assert_eq!(
+            StreamId::parse_with_seq("7", u64::MAX),
+            Some(StreamId {
+                ms: 7,
+                seq: u64::MAX
+            })
+        );
+        assert_eq!(StreamId { ms: 7, seq: 2 }.to_string(), "7-2");
+    }
+}
@@ -0,0 +1,101 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use crate::{
+    client::{Client, Mailbox},
+    resp::Resp,
+};
+
+/// The keys read by connections with CLIENT TRACKING on, shared by every
+/// connection. When a tracked key is modified or expires, each connection
+/// that read it is sent an `invalidate` push and the key stops being tracked
+/// for it until it reads the key again.
+#[derive(Debug, Default)]
+pub struct Tracking {
+    /// Readers of each key, by client id.
+    keys: Mutex<HashMap<String, HashMap<u64, Mailbox>>>,
+    /// Connections with tracking on, so writes skip the table when there
+    /// are none.
+    clients: AtomicUsize,
+}
+
+impl Tracking {
+    /// Turns tracking on for the client.
+    pub fn enable(&self, client: &mut Client) {
+        if !client.tracking {
+            client.tracking = true;
+            self.clients.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Turns tracking off for the client and forgets the keys it read.
+    pub fn disable(&self, client: &mut Client) {
+        if !client.tracking {
+            return;
+        }
+        client.tracking = false;
+        self.clients.fetch_sub(1, Ordering::Relaxed);
+        self.keys.lock().unwrap().retain(|_, readers| {
+            readers.remove(&client.id);
+            !readers.is_empty()
+        });
+    }
+
+    /// The number of connections with tracking on.
+    pub fn clients(&self) -> usize {
+        self.clients.load(Ordering::Relaxed)
+    }
+
+    /// Records that the client read `key`, if it has tracking on.
+    pub fn track(&self, client: &Client, key: &str) {
+        if !client.tracking {
+            return;
+        }
+        self.keys
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .insert(client.id, client.mailbox.clone());
+    }
+
+    /// Tells every connection that read `key` that it changed.
+    pub fn invalidate(&self, key: &str) {
+        if self.clients.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let Some(readers) = self.keys.lock().unwrap().remove(key) else {
+            return;
+        };
+        let message = Resp::Push(vec![
+            Resp::BulkString("invalidate".into()),
+            Resp::Array(vec![Resp::BulkString(key.into())]),
+        ])
+        .into_bytes();
+        for mailbox in readers.values() {
+            mailbox.deliver(&message, None);
+        }
+    }
+
+    /// Tells every connection that read any key that the whole dataset was
+    /// dropped, with a null in place of the keys as Redis does on a flush.
+    pub fn invalidate_all(&self) {
+        if self.clients.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let mut readers = HashMap::new();
+        for (_, mailboxes) in self.keys.lock().unwrap().drain() {
+            readers.extend(mailboxes);
+        }
+        let message =
+            Resp::Push(vec![Resp::BulkString("invalidate".into()), Resp::Null]).into_bytes();
+        for mailbox in readers.values() {
+            mailbox.deliver(&message, None);
+        }
+    }
+}
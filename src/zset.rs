@@ -0,0 +1,297 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeSet, HashMap},
+    mem,
+    ops::Bound,
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// A score, ordered with [`f64::total_cmp`] so it can key the ordered index.
+/// Scores are never NaN, and -0 is stored as 0, so the order is the usual
+/// one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Score(f64);
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// A sorted set: unique members with a score each, ordered by score and
+/// then by member. The scores are kept both by member, for lookups, and in
+/// order, for ranges. Only the former is serialized; the order is rebuilt
+/// on load.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "HashMap<String, f64>", into = "HashMap<String, f64>")]
+pub struct SortedSet {
+    scores: HashMap<String, f64>,
+    ordered: BTreeSet<(Score, String)>,
+}
+
+impl From<HashMap<String, f64>> for SortedSet {
+    fn from(scores: HashMap<String, f64>) -> Self {
+        let ordered = scores
+            .iter()
+            .map(|(member, &score)| (Score(score), member.clone()))
+            .collect();
+        Self { scores, ordered }
+    }
+}
+
+impl From<SortedSet> for HashMap<String, f64> {
+    fn from(set: SortedSet) -> Self {
+        set.scores
+    }
+}
+
+impl SortedSet {
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &str) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Sets the score of `member`, adding it if needed. Returns whether it
+    /// wasn't in the set before.
+    pub fn insert(&mut self, member: String, score: f64) -> bool {
+        // Adding 0 turns -0 into 0, which `total_cmp` would order apart.
+        let score = score + 0.0;
+        let previous = self.scores.insert(member.clone(), score);
+        if let Some(previous) = previous {
+            self.ordered.remove(&(Score(previous), member.clone()));
+        }
+        self.ordered.insert((Score(score), member));
+        previous.is_none()
+    }
+
+    /// Removes `member`, returning whether it was in the set.
+    pub fn remove(&mut self, member: &str) -> bool {
+        match self.scores.remove_entry(member) {
+            Some((member, score)) => {
+                self.ordered.remove(&(Score(score), member));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The members and their scores, in order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&str, f64)> {
+        self.ordered
+            .iter()
+            .map(|(score, member)| (member.as_str(), score.0))
+    }
+
+    /// The members with a score between `min` and `max`, in order.
+    pub fn range_by_score(
+        &self,
+        min: ScoreBound,
+        max: ScoreBound,
+    ) -> impl Iterator<Item = (&str, f64)> {
+        // The empty string sorts first among members with the same score.
+        let start = (Score(min.score + 0.0), String::new());
+        self.ordered
+            .range((Bound::Included(start), Bound::Unbounded))
+            .skip_while(move |(score, _)| min.exclusive && score.0 == min.score)
+            .take_while(move |(score, _)| max.admits_from_above(score.0))
+            .map(|(score, member)| (member.as_str(), score.0))
+    }
+
+    /// How many members have a score between `min` and `max`.
+    pub fn count(&self, min: ScoreBound, max: ScoreBound) -> usize {
+        self.range_by_score(min, max).count()
+    }
+
+    pub fn heap_size(&self) -> usize {
+        self.scores.capacity() * mem::size_of::<(String, f64)>()
+            + self.len() * mem::size_of::<(Score, String)>()
+            + self
+                .scores
+                .keys()
+                .map(|member| 2 * member.capacity())
+                .sum::<usize>()
+    }
+
+    /// Like [`SortedSet::heap_size`], but measuring only `samples` members
+    /// and scaling their average to the whole set. 0 measures every member.
+    pub fn sampled_heap_size(&self, samples: usize) -> usize {
+        if samples == 0 || self.len() <= samples {
+            return self.heap_size();
+        }
+        let sampled: usize = self
+            .scores
+            .keys()
+            .take(samples)
+            .map(|member| 2 * member.capacity())
+            .sum();
+        self.scores.capacity() * mem::size_of::<(String, f64)>()
+            + self.len() * mem::size_of::<(Score, String)>()
+            + sampled * self.len() / samples
+    }
+}
+
+/// The end of a sorted set ZMPOP pops from, spelled `MIN` for the lowest
+/// scores and `MAX` for the highest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Extreme {
+    Min,
+    Max,
+}
+
+impl FromStr for Extreme {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "MIN" => Ok(Extreme::Min),
+            "MAX" => Ok(Extreme::Max),
+            _ => Err(()),
+        }
+    }
+}
+
+/// One end of a score range as ZRANGEBYSCORE and ZCOUNT take it: a score,
+/// `-inf` or `+inf`, excluded from the range when prefixed with `(`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreBound {
+    pub score: f64,
+    pub exclusive: bool,
+}
+
+impl ScoreBound {
+    /// Whether `score` is within the range this bound closes from above.
+    fn admits_from_above(&self, score: f64) -> bool {
+        if self.exclusive {
+            score < self.score
+        } else {
+            score <= self.score
+        }
+    }
+}
+
+impl FromStr for ScoreBound {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (score, exclusive) = match s.strip_prefix('(') {
+            Some(score) => (score, true),
+            None => (s, false),
+        };
+        Ok(Self {
+            score: parse_score(score)?,
+            exclusive,
+        })
+    }
+}
+
+/// Parses a score the way Redis does: a float, or `inf` or `infinity` with
+/// an optional sign in any case. NaN is not a score.
+pub fn parse_score(s: &str) -> Result<f64, ()> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|score| !score.is_nan())
+        .ok_or(())
+}
+
+/// Formats a score for a reply: the shortest form that reads back the same,
+/// and `inf` or `-inf` for the infinities, as Redis replies.
+pub fn format_score(score: f64) -> String {
+    score.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scores(set: &SortedSet) -> Vec<(&str, f64)> {
+        set.iter().collect()
+    }
+
+    fn bound(s: &str) -> ScoreBound {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_members_are_ordered_by_score_then_member() {
+        let mut set = SortedSet::default();
+        assert!(set.insert("b".into(), 2.0));
+        assert!(set.insert("a".into(), 2.0));
+        assert!(set.insert("c".into(), 1.0));
+        assert!(!set.insert("c".into(), 3.0));
+        assert_eq!(scores(&set), vec![("a", 2.0), ("b", 2.0), ("c", 3.0)]);
+        assert_eq!(set.score("c"), Some(3.0));
+
+        assert!(set.remove("a"));
+        assert!(!set.remove("a"));
+        assert_eq!(scores(&set), vec![("b", 2.0), ("c", 3.0)]);
+    }
+
+    #[test]
+    fn test_range_by_score() {
+        let mut set = SortedSet::default();
+        for (member, score) in [("a", 1.0), ("b", 2.0), ("c", 2.0), ("d", 3.0)] {
+            set.insert(member.into(), score);
+        }
+        let members = |min, max| -> Vec<&str> {
+            set.range_by_score(bound(min), bound(max))
+                .map(|(member, _)| member)
+                .collect()
+        };
+        assert_eq!(members("-inf", "+inf"), vec!["a", "b", "c", "d"]);
+        assert_eq!(members("2", "2"), vec!["b", "c"]);
+        assert_eq!(members("(1", "(3"), vec!["b", "c"]);
+        assert_eq!(members("(2", "inf"), vec!["d"]);
+        assert_eq!(members("3", "1"), Vec::<&str>::new());
+        assert_eq!(set.count(bound("1"), bound("(2")), 1);
+    }
+
+    #[test]
+    fn test_parse_score() {
+        assert_eq!(parse_score("1.5"), Ok(1.5));
+        assert_eq!(parse_score("-INF"), Ok(f64::NEG_INFINITY));
+        assert_eq!(parse_score("+inf"), Ok(f64::INFINITY));
+        assert!(parse_score("nan").is_err());
+        assert_eq!(parse_score("Infinity"), Ok(f64::INFINITY));
+        assert!(parse_score("x").is_err());
+        assert_eq!(
+            bound("(5"),
+            ScoreBound {
+                score: 5.0,
+                exclusive: true
+            }
+        );
+        assert!("(".parse::<ScoreBound>().is_err());
+
+        assert_eq!(format_score(3.0), "3");
+        assert_eq!(format_score(-0.5), "-0.5");
+        assert_eq!(format_score(f64::INFINITY), "inf");
+    }
+
+    #[test]
+    fn test_serialization_rebuilds_the_order() {
+        let mut set = SortedSet::default();
+        set.insert("b".into(), 1.0);
+        set.insert("a".into(), 2.0);
+        let bytes = bincode::serialize(&set).unwrap();
+        let loaded: SortedSet = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(loaded, set);
+        assert_eq!(scores(&loaded), vec![("b", 1.0), ("a", 2.0)]);
+    }
+}